@@ -0,0 +1,303 @@
+//! Alerts for humans: opportunities, failed legs, risk breaches and feed outages. Alerts are
+//! filtered by severity, deduplicated by key, rate limited and then delivered to every sink
+//! configured under `alerts.sinks` from a background task.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Severity::Info => "INFO",
+            Severity::Warning => "WARNING",
+            Severity::Critical => "CRITICAL",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Alert {
+    pub severity: Severity,
+    /// Alerts with the same key within `dedup_secs` are only sent once
+    pub key: String,
+    pub title: String,
+    pub message: String,
+}
+
+impl Alert {
+    pub fn new(severity: Severity, key: impl Into<String>, title: impl Into<String>) -> Self {
+        Self {
+            severity,
+            key: key.into(),
+            title: title.into(),
+            message: String::new(),
+        }
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    fn text(&self) -> String {
+        if self.message.is_empty() {
+            format!("[{}] {}", self.severity, self.title)
+        } else {
+            format!("[{}] {}\n{}", self.severity, self.title, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AlertSink {
+    /// Prints alerts to stdout
+    Stdout,
+    /// POSTs the alert as JSON
+    Webhook { url: String },
+    /// Discord (or Slack-compatible with `text_field: "text"`) incoming webhook
+    Discord {
+        url: String,
+        #[serde(default = "default_text_field")]
+        text_field: String,
+    },
+    /// Telegram bot API, `url` is `https://api.telegram.org/bot<token>/sendMessage`
+    Telegram { url: String, chat_id: String },
+}
+
+fn default_text_field() -> String {
+    "content".to_string()
+}
+
+impl AlertSink {
+    async fn deliver(&self, client: &reqwest::Client, alert: &Alert) -> anyhow::Result<()> {
+        let (url, body) = match self {
+            AlertSink::Stdout => {
+                println!("🚨 {}", alert.text());
+                return Ok(());
+            }
+            AlertSink::Webhook { url } => (url, serde_json::to_value(alert)?),
+            AlertSink::Discord { url, text_field } => {
+                let mut body = serde_json::Map::new();
+                body.insert(text_field.clone(), alert.text().into());
+                (url, body.into())
+            }
+            AlertSink::Telegram { url, chat_id } => {
+                (url, json!({ "chat_id": chat_id, "text": alert.text() }))
+            }
+        };
+        client
+            .post(url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AlertConfig {
+    pub enabled: bool,
+    /// Alerts below this are dropped
+    pub min_severity: Severity,
+    pub dedup_secs: u64,
+    pub max_per_minute: usize,
+    /// A feed without updates for this long raises an outage alert
+    pub feed_stale_secs: u64,
+    pub sinks: Vec<AlertSink>,
+}
+
+impl Default for AlertConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_severity: Severity::Warning,
+            dedup_secs: 300,
+            max_per_minute: 20,
+            feed_stale_secs: 30,
+            sinks: vec![AlertSink::Stdout],
+        }
+    }
+}
+
+/// Severity, dedup and rate limit checks, kept apart from delivery
+#[derive(Debug)]
+pub struct AlertFilter {
+    min_severity: Severity,
+    dedup: Duration,
+    max_per_minute: usize,
+    last_sent: HashMap<String, Instant>,
+    sent: VecDeque<Instant>,
+}
+
+impl AlertFilter {
+    pub fn new(config: &AlertConfig) -> Self {
+        Self {
+            min_severity: config.min_severity,
+            dedup: Duration::from_secs(config.dedup_secs),
+            max_per_minute: config.max_per_minute,
+            last_sent: HashMap::new(),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Whether `alert` should go out at `now`, counting it as sent if so
+    pub fn admit(&mut self, alert: &Alert, now: Instant) -> bool {
+        if alert.severity < self.min_severity {
+            return false;
+        }
+        if self
+            .last_sent
+            .get(&alert.key)
+            .is_some_and(|last| now.duration_since(*last) < self.dedup)
+        {
+            return false;
+        }
+        while self
+            .sent
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= RATE_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max_per_minute {
+            return false;
+        }
+        self.sent.push_back(now);
+        self.last_sent.insert(alert.key.clone(), now);
+        true
+    }
+}
+
+/// Handle used to raise alerts, cheap to clone. Does nothing when alerting is disabled.
+#[derive(Debug, Clone, Default)]
+pub struct AlertDispatcher {
+    tx: Option<mpsc::UnboundedSender<Alert>>,
+}
+
+impl AlertDispatcher {
+    /// Starts the delivery task if alerting is enabled
+    pub fn spawn(config: &AlertConfig) -> Self {
+        if !config.enabled || config.sinks.is_empty() {
+            return Self::default();
+        }
+        let mut filter = AlertFilter::new(config);
+        let sinks = config.sinks.clone();
+        let client = reqwest::Client::new();
+        let (tx, mut rx) = mpsc::unbounded_channel::<Alert>();
+        tokio::spawn(async move {
+            while let Some(alert) = rx.recv().await {
+                if !filter.admit(&alert, Instant::now()) {
+                    continue;
+                }
+                for sink in &sinks {
+                    if let Err(e) = sink.deliver(&client, &alert).await {
+                        error!(key = %alert.key, "Error delivering alert: {e}");
+                    }
+                }
+            }
+        });
+        Self { tx: Some(tx) }
+    }
+
+    pub fn send(&self, alert: Alert) {
+        if let Some(tx) = &self.tx
+            && tx.send(alert).is_err()
+        {
+            warn!("Alert dropped, dispatcher stopped");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Json, Router, extract::State, routing::post};
+    use tokio::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn filters_by_severity_dedup_and_rate() {
+        let mut filter = AlertFilter::new(&AlertConfig {
+            max_per_minute: 2,
+            ..Default::default()
+        });
+        let start = Instant::now();
+        let alert = |key: &str| Alert::new(Severity::Critical, key, "feed down");
+
+        assert!(!filter.admit(&Alert::new(Severity::Info, "info", "found"), start));
+        assert!(filter.admit(&alert("cex"), start));
+        assert!(!filter.admit(&alert("cex"), start + Duration::from_secs(10)));
+        assert!(filter.admit(&alert("dex"), start + Duration::from_secs(10)));
+        // rate limited until the first alert leaves the window
+        assert!(!filter.admit(&alert("other"), start + Duration::from_secs(20)));
+        assert!(filter.admit(&alert("other"), start + Duration::from_secs(61)));
+        assert!(filter.admit(&alert("cex"), start + Duration::from_secs(400)));
+    }
+
+    #[tokio::test]
+    async fn webhook_sinks_post_to_a_local_receiver() {
+        let received: Arc<Mutex<Vec<serde_json::Value>>> = Arc::default();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(
+                    |State(received): State<Arc<Mutex<Vec<serde_json::Value>>>>,
+                     Json(body): Json<serde_json::Value>| async move {
+                        received.lock().await.push(body);
+                    },
+                ),
+            )
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let alert = Alert::new(Severity::Critical, "leg", "Leg failed").message("slippage");
+        let client = reqwest::Client::new();
+        let sinks = [
+            AlertSink::Webhook { url: url.clone() },
+            AlertSink::Discord {
+                url: url.clone(),
+                text_field: default_text_field(),
+            },
+            AlertSink::Telegram {
+                url,
+                chat_id: "42".to_string(),
+            },
+        ];
+        for sink in &sinks {
+            sink.deliver(&client, &alert).await.unwrap();
+        }
+
+        let received = received.lock().await;
+        assert_eq!(received[0]["severity"], "critical");
+        assert_eq!(received[0]["key"], "leg");
+        assert_eq!(received[1]["content"], "[CRITICAL] Leg failed\nslippage");
+        assert_eq!(received[2]["chat_id"], "42");
+        assert_eq!(received[2]["text"], "[CRITICAL] Leg failed\nslippage");
+    }
+}
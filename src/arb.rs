@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    balances::{Asset, Balances, Venue},
+    config::Config,
+    market::{CexStruct, DexStruct},
+    rebalance,
+};
+
+/// Pair traded between the pool and the CEX
+pub const PAIR: &str = "SOL_USDC";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Buy SOL on the CEX asks, sell it into the pool
+    BuyCexSellDex,
+    /// Buy SOL from the pool, sell it on the CEX bids
+    BuyDexSellCex,
+}
+
+impl Direction {
+    /// Stable snake_case identifier, used as the metrics label
+    pub fn id(&self) -> &'static str {
+        match self {
+            Direction::BuyCexSellDex => "buy_cex_sell_dex",
+            Direction::BuyDexSellCex => "buy_dex_sell_cex",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Opportunity {
+    pub direction: Direction,
+    pub base_amount: u64, // lamports
+    pub cost: u64,        // micro-USDC paid on the buy leg
+    pub proceeds: u64,    // micro-USDC received on the sell leg
+    pub spread: i64,
+    pub cex_fee: u64, // taker fee on the CEX leg, micro-USDC
+    /// Lamports the DEX leg's transaction pays, priority fee included
+    #[serde(default)]
+    pub tx_fee: u64,
+    pub slot: u64,
+}
+
+impl Opportunity {
+    /// Spread after the CEX taker fee and the DEX transaction fee (the pool fee is already part
+    /// of the DEX quote)
+    pub fn net_profit(&self) -> i64 {
+        self.spread - self.cex_fee as i64 - self.tx_fee_quote() as i64
+    }
+
+    /// The DEX transaction fee in micro-USDC, at the price of the CEX leg
+    pub fn tx_fee_quote(&self) -> u64 {
+        let cex_quote = match self.direction {
+            Direction::BuyCexSellDex => self.cost,
+            Direction::BuyDexSellCex => self.proceeds,
+        };
+        lamports_to_quote(self.tx_fee, cex_quote, self.base_amount)
+    }
+
+    /// What the trade puts at stake while its legs are in flight, should only one of them fill:
+    /// the SOL traded and the USDC paid for the buy leg
+    pub fn exposure(&self) -> [(Asset, u64); 2] {
+        [(Asset::Sol, self.base_amount), (Asset::Usdc, self.cost)]
+    }
+}
+
+/// CEX taker fee on `quote` micro-USDC, rounded up
+pub fn taker_fee(quote: u64, fee_bps: u64) -> u64 {
+    (quote as u128 * fee_bps as u128).div_ceil(10_000) as u64
+}
+
+/// `lamports` in micro-USDC at the price of `quote` micro-USDC for `base` lamports, rounded up
+pub fn lamports_to_quote(lamports: u64, quote: u64, base: u64) -> u64 {
+    if base == 0 {
+        return 0;
+    }
+    (lamports as u128 * quote as u128).div_ceil(base as u128) as u64
+}
+
+/// Checks both directions for `config.trade_size` lamports (capped by the inventory available on
+/// each venue), walking the CEX book and simulating the pool swap across ticks. Only opportunities
+/// whose net profit clears `min_profit_bps` plus the amortized rebalancing cost are returned.
+pub fn detect(
+    dex: &DexStruct,
+    cex: &CexStruct,
+    balances: &Balances,
+    config: &Config,
+) -> Vec<Opportunity> {
+    let mut found = Vec::new();
+    let tx_fee = config.transaction.fee();
+
+    // buy on CEX, sell SOL into the pool
+    let size = capped_size(Direction::BuyCexSellDex, dex, cex, balances, config);
+    let fill = cex.book.buy_base(size);
+    if size > 0
+        && fill.base == size
+        && let Ok(swap) = dex.simulate(size, true, true)
+        && swap.complete
+    {
+        found.push(opportunity(
+            Direction::BuyCexSellDex,
+            size,
+            fill.quote,
+            swap.amount_out,
+            taker_fee(fill.quote, config.taker_fee_bps()),
+            tx_fee,
+            dex.slot,
+        ));
+    }
+
+    // buy exactly `size` SOL from the pool with USDC, sell it on CEX
+    let size = capped_size(Direction::BuyDexSellCex, dex, cex, balances, config);
+    let fill = cex.book.sell_base(size);
+    if size > 0
+        && fill.base == size
+        && let Ok(swap) = dex.simulate(size, false, false)
+        && swap.complete
+    {
+        found.push(opportunity(
+            Direction::BuyDexSellCex,
+            size,
+            swap.amount_in,
+            fill.quote,
+            taker_fee(fill.quote, config.taker_fee_bps()),
+            tx_fee,
+            dex.slot,
+        ));
+    }
+
+    let rebalance_bps = cex
+        .book
+        .mid()
+        .map(|price| rebalance::cost_bps(&config.rebalance, balances, price))
+        .unwrap_or_default();
+    let threshold_bps = (config.min_profit_bps + rebalance_bps) as i128;
+    found.retain(|opp| {
+        let net_profit = opp.net_profit() as i128;
+        net_profit > 0 && net_profit * 10_000 >= opp.cost as i128 * threshold_bps
+    });
+    found
+}
+
+/// Largest size up to `config.trade_size` that both venues can fund for `direction`. Venues
+/// without tracked balances don't cap the size.
+pub fn capped_size(
+    direction: Direction,
+    dex: &DexStruct,
+    cex: &CexStruct,
+    balances: &Balances,
+    config: &Config,
+) -> u64 {
+    let mut size = config.trade_size;
+    match direction {
+        Direction::BuyCexSellDex => {
+            if let Some(usdc) = balances.available(Venue::Cex, Asset::Usdc) {
+                let budget = usdc as u128 * 10_000 / (10_000 + config.taker_fee_bps() as u128);
+                size = size.min(cex.book.base_for_quote(budget as u64));
+            }
+            if let Some(sol) = balances.available(Venue::Wallet, Asset::Sol) {
+                size = size.min(sol);
+            }
+        }
+        Direction::BuyDexSellCex => {
+            if let Some(sol) = balances.available(Venue::Cex, Asset::Sol) {
+                size = size.min(sol);
+            }
+            if let Some(usdc) = balances.available(Venue::Wallet, Asset::Usdc) {
+                let affordable = dex
+                    .simulate(usdc, false, true)
+                    .map(|swap| swap.amount_out)
+                    .unwrap_or_default();
+                size = size.min(affordable);
+            }
+        }
+    }
+    size
+}
+
+fn opportunity(
+    direction: Direction,
+    base_amount: u64,
+    cost: u64,
+    proceeds: u64,
+    cex_fee: u64,
+    tx_fee: u64,
+    slot: u64,
+) -> Opportunity {
+    Opportunity {
+        direction,
+        base_amount,
+        cost,
+        proceeds,
+        spread: proceeds as i64 - cost as i64,
+        cex_fee,
+        tx_fee,
+        slot,
+    }
+}
@@ -0,0 +1,317 @@
+//! Backpack Exchange: REST API signed with the ed25519 API secret, and the public depth stream
+
+use std::collections::HashMap;
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::Deserialize;
+use solana_sdk::{signature::keypair_from_seed, signer::Signer};
+
+use crate::{
+    balances::{Asset, format_amount, parse_amount},
+    cex::{
+        BOOK_DEPTH, CexExchange, CexVenue, OrderRequest, OrderSide, missing_credentials,
+        order_decimal, order_price,
+    },
+    feed::{DepthProtocol, WsBookFeed},
+    order_book::{DepthBook, DepthUpdate, OrderBook},
+};
+
+const API_URL: &str = "https://api.backpack.exchange";
+const WS_URL: &str = "wss://ws.backpack.exchange";
+const SYMBOL: &str = "SOL_USDC";
+const WINDOW_MS: u64 = 5000;
+/// Lot size of SOL_USDC, in lamports
+const LOT_SIZE: u64 = 10_000_000;
+/// Tick size of SOL_USDC, in micro-USDC
+const TICK_SIZE: u64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct BackpackAuth {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+impl BackpackAuth {
+    /// Reads `BACKPACK_API_KEY` and `BACKPACK_API_SECRET`, `None` if either is unset
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            api_key: std::env::var("BACKPACK_API_KEY").ok()?,
+            api_secret: std::env::var("BACKPACK_API_SECRET").ok()?,
+        })
+    }
+
+    /// Headers for a signed request, `params` must already be sorted by key
+    fn headers(
+        &self,
+        instruction: &str,
+        params: &str,
+    ) -> anyhow::Result<Vec<(&'static str, String)>> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis();
+        let mut message = format!("instruction={instruction}");
+        if !params.is_empty() {
+            message.push('&');
+            message.push_str(params);
+        }
+        message.push_str(&format!("&timestamp={timestamp}&window={WINDOW_MS}"));
+
+        let seed = STANDARD.decode(&self.api_secret)?;
+        let keypair = keypair_from_seed(&seed).map_err(|e| anyhow::Error::msg(e.to_string()))?;
+        let signature = keypair.sign_message(message.as_bytes());
+
+        Ok(vec![
+            ("X-API-Key", self.api_key.clone()),
+            ("X-Signature", STANDARD.encode(signature.as_ref())),
+            ("X-Timestamp", timestamp.to_string()),
+            ("X-Window", WINDOW_MS.to_string()),
+        ])
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct CapitalEntry {
+    available: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OrderResponse {
+    id: String,
+}
+
+/// Available balances of the account, for the assets the bot knows about
+pub async fn fetch_balances(auth: &BackpackAuth) -> anyhow::Result<HashMap<Asset, u64>> {
+    let mut request = reqwest::Client::new().get(format!("{API_URL}/api/v1/capital"));
+    for (name, value) in auth.headers("balanceQuery", "")? {
+        request = request.header(name, value);
+    }
+    let capital: HashMap<String, CapitalEntry> =
+        request.send().await?.error_for_status()?.json().await?;
+
+    let mut balances = HashMap::new();
+    for (symbol, entry) in capital {
+        if let Some(asset) = Asset::from_symbol(&symbol) {
+            balances.insert(asset, parse_amount(&entry.available, asset)?);
+        }
+    }
+    Ok(balances)
+}
+
+/// Backpack as the CEX venue, orders and balances need `BackpackAuth`
+#[derive(Debug, Clone, Default)]
+pub struct BackpackVenue {
+    auth: Option<BackpackAuth>,
+}
+
+impl BackpackVenue {
+    pub fn from_env() -> Self {
+        Self {
+            auth: BackpackAuth::from_env(),
+        }
+    }
+
+    fn auth(&self) -> anyhow::Result<&BackpackAuth> {
+        self.auth
+            .as_ref()
+            .ok_or_else(|| missing_credentials(CexExchange::Backpack))
+    }
+}
+
+impl CexVenue for BackpackVenue {
+    type BookFeed = WsBookFeed<BackpackDepth>;
+
+    fn exchange(&self) -> CexExchange {
+        CexExchange::Backpack
+    }
+
+    fn book_feed(&self) -> Self::BookFeed {
+        WsBookFeed::new(BackpackDepth::default())
+    }
+
+    fn authenticated(&self) -> bool {
+        self.auth.is_some()
+    }
+
+    async fn place_order(&self, order: &OrderRequest) -> anyhow::Result<String> {
+        let auth = self.auth()?;
+        let side = match order.side {
+            OrderSide::Buy => "Bid",
+            OrderSide::Sell => "Ask",
+        };
+        let quantity = order_decimal(order.qty, LOT_SIZE, Asset::Sol, false);
+        let mut body = serde_json::json!({
+            "orderType": "Market",
+            "quantity": quantity,
+            "side": side,
+            "symbol": SYMBOL,
+        });
+        let params = match order_price(order, TICK_SIZE) {
+            Some(price) => {
+                body["orderType"] = "Limit".into();
+                body["price"] = price.clone().into();
+                body["timeInForce"] = "IOC".into();
+                format!(
+                    "orderType=Limit&price={price}&quantity={quantity}&side={side}&symbol={SYMBOL}&timeInForce=IOC"
+                )
+            }
+            None => format!("orderType=Market&quantity={quantity}&side={side}&symbol={SYMBOL}"),
+        };
+        let mut request = reqwest::Client::new()
+            .post(format!("{API_URL}/api/v1/order"))
+            .json(&body);
+        for (name, value) in auth.headers("orderExecute", &params)? {
+            request = request.header(name, value);
+        }
+        let response: OrderResponse = request.send().await?.error_for_status()?.json().await?;
+        Ok(response.id)
+    }
+
+    async fn cancel_order(&self, order_id: &str) -> anyhow::Result<()> {
+        let auth = self.auth()?;
+        let body = serde_json::json!({ "orderId": order_id, "symbol": SYMBOL });
+        let mut request = reqwest::Client::new()
+            .delete(format!("{API_URL}/api/v1/order"))
+            .json(&body);
+        let params = format!("orderId={order_id}&symbol={SYMBOL}");
+        for (name, value) in auth.headers("orderCancel", &params)? {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    async fn balances(&self) -> anyhow::Result<HashMap<Asset, u64>> {
+        fetch_balances(self.auth()?).await
+    }
+
+    async fn withdraw(&self, asset: Asset, amount: u64, address: &str) -> anyhow::Result<()> {
+        let auth = self.auth()?;
+        let quantity = format_amount(amount, asset);
+        let params = format!(
+            "address={address}&blockchain=Solana&quantity={quantity}&symbol={}",
+            asset.symbol()
+        );
+        let body = serde_json::json!({
+            "address": address,
+            "blockchain": "Solana",
+            "quantity": quantity,
+            "symbol": asset.symbol(),
+        });
+        let mut request = reqwest::Client::new()
+            .post(format!("{API_URL}/wapi/v1/capital/withdrawals"))
+            .json(&body);
+        for (name, value) in auth.headers("withdraw", &params)? {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: String,
+    bids: Vec<(String, String)>,
+    asks: Vec<(String, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    data: DepthEvent,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthEvent {
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    last_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<(String, String)>,
+    #[serde(rename = "a")]
+    asks: Vec<(String, String)>,
+}
+
+/// `depth.SOL_USDC` stream: diffs with update ids on top of the REST depth snapshot
+#[derive(Debug, Default)]
+pub struct BackpackDepth {
+    book: DepthBook,
+}
+
+impl DepthProtocol for BackpackDepth {
+    fn url(&self) -> String {
+        WS_URL.to_string()
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let message = serde_json::json!({
+            "method": "SUBSCRIBE",
+            "params": [format!("depth.{SYMBOL}")],
+        });
+        vec![message.to_string()]
+    }
+
+    fn snapshot_url(&self) -> Option<String> {
+        Some(format!("{API_URL}/api/v1/depth?symbol={SYMBOL}"))
+    }
+
+    fn apply_snapshot(&mut self, body: &str) -> anyhow::Result<()> {
+        let snapshot: DepthSnapshot = serde_json::from_str(body)?;
+        self.book.snapshot(
+            snapshot.last_update_id.parse()?,
+            &snapshot.bids,
+            &snapshot.asks,
+        )
+    }
+
+    fn apply_message(&mut self, message: &str) -> anyhow::Result<Option<OrderBook>> {
+        // subscription acks and other streams don't carry depth data
+        let Ok(StreamMessage { data }) = serde_json::from_str(message) else {
+            return Ok(None);
+        };
+        let changed = self.book.update(DepthUpdate {
+            first_update_id: data.first_update_id,
+            last_update_id: data.last_update_id,
+            bids: data.bids,
+            asks: data.asks,
+        })?;
+        Ok(changed.then(|| self.book.book(BOOK_DEPTH)).flatten())
+    }
+
+    fn reset(&mut self) {
+        self.book.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_book_from_recorded_depth_messages() {
+        let mut depth = BackpackDepth::default();
+        let mut books = Vec::new();
+        // the stream is opened before the snapshot is fetched
+        let messages = include_str!("../fixtures/cex/backpack_depth.jsonl");
+        let mut lines = messages.lines();
+        for message in lines.by_ref().take(2) {
+            assert_eq!(depth.apply_message(message).unwrap(), None);
+        }
+        depth
+            .apply_snapshot(include_str!("../fixtures/cex/backpack_snapshot.json"))
+            .unwrap();
+        for message in lines {
+            books.extend(depth.apply_message(message).unwrap());
+        }
+        let book = books.last().unwrap();
+        assert_eq!(book.best_bid(), Some(150_110_000));
+        assert_eq!(book.best_ask(), Some(150_130_000));
+        assert_eq!(book.bids[0].qty, 4_200_000_000);
+        assert_eq!(book.asks.len(), 2);
+
+        // a skipped update id means the book is out of sync
+        let gap = r#"{"stream":"depth.SOL_USDC","data":{"e":"depth","E":1729000000900000,"s":"SOL_USDC","a":[],"b":[["150.10","1.00"]],"U":2001000,"u":2001000,"T":1729000000899000}}"#;
+        assert!(depth.apply_message(gap).is_err());
+    }
+}
@@ -0,0 +1,395 @@
+//! Backtesting over recorded market data. Records are replayed into the same DEX/CEX state the
+//! live tasks maintain, detection runs on the live loop's interval and every opportunity goes
+//! through the live risk checks and paper execution. Each leg fills against the market as it
+//! was after that leg's configured latency.
+
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use tracing::warn;
+
+use crate::{
+    arb::{self, Opportunity},
+    balances::Balances,
+    config::Config,
+    execution::{Decision, ExecutionCoordinator, ExecutionMode},
+    ledger::RoundTrip,
+    market::{CexStruct, DexStruct},
+    paper::PaperBalances,
+    recorder::{Event, Record},
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct BacktestConfig {
+    /// Time from detection until the DEX leg lands
+    pub dex_latency_ms: u64,
+    /// Time from detection until the CEX order fills
+    pub cex_latency_ms: u64,
+    /// How often detection runs, the live loop sleeps 500ms between passes
+    pub detect_interval_ms: u64,
+    pub output_dir: String,
+}
+
+impl Default for BacktestConfig {
+    fn default() -> Self {
+        Self {
+            dex_latency_ms: 400,
+            cex_latency_ms: 50,
+            detect_interval_ms: 500,
+            output_dir: "backtest".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PnlPoint {
+    pub timestamp_us: u64,
+    pub realized_pnl: i64,
+    pub unrealized_pnl: i64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BacktestSummary {
+    pub start_us: u64,
+    pub end_us: u64,
+    pub records: u64,
+    pub opportunities: u64,
+    pub executed: u64,
+    pub rejected: u64,
+    pub failed: u64,
+    pub realized_pnl: i64,
+    pub unrealized_pnl: i64,
+    pub fees: u64,
+    pub volume: u64,
+    /// Share of executed round-trips with a positive realized PnL
+    pub win_rate: f64,
+    /// Largest drop of realized + unrealized PnL from a previous high
+    pub max_drawdown: i64,
+    pub final_balances: PaperBalances,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BacktestReport {
+    pub trades: Vec<RoundTrip>,
+    pub pnl_curve: Vec<PnlPoint>,
+    pub summary: BacktestSummary,
+}
+
+impl BacktestReport {
+    /// Writes `trades.jsonl`, `pnl.csv` and `summary.json` into `dir`
+    pub fn write(&self, dir: impl AsRef<Path>) -> anyhow::Result<PathBuf> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+
+        let mut trades = fs::File::create(dir.join("trades.jsonl"))?;
+        for trade in &self.trades {
+            writeln!(trades, "{}", serde_json::to_string(trade)?)?;
+        }
+        let mut pnl = fs::File::create(dir.join("pnl.csv"))?;
+        writeln!(pnl, "timestamp_us,realized_pnl,unrealized_pnl")?;
+        for point in &self.pnl_curve {
+            writeln!(
+                pnl,
+                "{},{},{}",
+                point.timestamp_us, point.realized_pnl, point.unrealized_pnl
+            )?;
+        }
+        fs::write(
+            dir.join("summary.json"),
+            serde_json::to_string_pretty(&self.summary)?,
+        )?;
+        Ok(dir.to_path_buf())
+    }
+}
+
+/// An approved opportunity waiting for its legs to reach the venues
+struct Pending {
+    opportunity: Opportunity,
+    dex_at_us: u64,
+    cex_at_us: u64,
+    dex: Option<DexStruct>,
+    cex: Option<CexStruct>,
+}
+
+pub struct Backtest {
+    config: Config,
+    pool_id: Pubkey,
+    dex: DexStruct,
+    cex: CexStruct,
+    balances: Balances,
+    coordinator: ExecutionCoordinator,
+    pending: Vec<Pending>,
+    next_detect_us: Option<u64>,
+    report: BacktestReport,
+}
+
+impl Backtest {
+    /// Backtest of the strategy in `config`, always executed in paper mode from
+    /// `config.paper_balances`
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let mut config = config.clone();
+        config.execution_mode = ExecutionMode::Paper;
+        let mut coordinator = ExecutionCoordinator::new(&config);
+        coordinator.log_fills = false;
+        Ok(Self {
+            pool_id: config.pool.parse()?,
+            dex: DexStruct::new(config.pool_type, config.pair_mints()?.0),
+            cex: CexStruct::default(),
+            balances: Balances::from(&config.paper_balances),
+            coordinator,
+            pending: Vec::new(),
+            next_detect_us: None,
+            report: BacktestReport::default(),
+            config,
+        })
+    }
+
+    pub fn run(
+        mut self,
+        records: impl IntoIterator<Item = anyhow::Result<Record>>,
+    ) -> anyhow::Result<BacktestReport> {
+        for record in records {
+            self.apply(record?)?;
+        }
+        // no more data to detect on, but trades still in flight land
+        self.next_detect_us = None;
+        let end = self
+            .pending
+            .iter()
+            .map(|p| p.dex_at_us.max(p.cex_at_us))
+            .max()
+            .unwrap_or_default();
+        self.advance(end, true);
+        Ok(self.finish())
+    }
+
+    fn apply(&mut self, record: Record) -> anyhow::Result<()> {
+        // timers due before this record see the market as it was before it
+        self.advance(record.recv_us, false);
+        let summary = &mut self.report.summary;
+        if summary.records == 0 {
+            summary.start_us = record.recv_us;
+        }
+        summary.records += 1;
+        summary.end_us = record.recv_us;
+
+        match record.event {
+            Event::Account {
+                slot, pubkey, data, ..
+            } => {
+                let pubkey = Pubkey::new_from_array(pubkey);
+                match self.dex.apply_account(&self.pool_id, &pubkey, slot, &data) {
+                    Ok(true) => self.dex.received_us = record.recv_us,
+                    Ok(false) => {}
+                    Err(e) => warn!(%pubkey, "Error decoding recorded account: {e}"),
+                }
+            }
+            Event::Book { bids, asks } => {
+                self.cex.book.bids = bids;
+                self.cex.book.asks = asks;
+                self.cex.best_bid = self.cex.book.best_bid().unwrap_or_default();
+                self.cex.best_ask = self.cex.book.best_ask().unwrap_or_default();
+                self.cex.received_us = record.recv_us;
+            }
+        }
+        if self.next_detect_us.is_none() && self.ready() {
+            self.next_detect_us = Some(record.recv_us);
+        }
+        Ok(())
+    }
+
+    /// Fires every detection pass and leg arrival due before `until_us` (or at it, if
+    /// `inclusive`) in time order
+    fn advance(&mut self, until_us: u64, inclusive: bool) {
+        let due = |at: u64| at < until_us || (inclusive && at == until_us);
+        loop {
+            let next_leg = self
+                .pending
+                .iter()
+                .flat_map(|p| {
+                    [
+                        p.dex.is_none().then_some(p.dex_at_us),
+                        p.cex.is_none().then_some(p.cex_at_us),
+                    ]
+                })
+                .flatten()
+                .min();
+            match (next_leg, self.next_detect_us) {
+                (Some(leg), detect) if due(leg) && detect.is_none_or(|d| leg <= d) => {
+                    self.land_legs(leg)
+                }
+                (_, Some(detect)) if due(detect) => {
+                    self.detect(detect);
+                    self.next_detect_us =
+                        Some(detect + self.config.backtest.detect_interval_ms.max(1) * 1000);
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn ready(&self) -> bool {
+        self.dex.price().is_some() && self.cex.book.mid().is_some()
+    }
+
+    fn detect(&mut self, now_us: u64) {
+        let dex_latency_us = self.config.backtest.dex_latency_ms * 1000;
+        let cex_latency_us = self.config.backtest.cex_latency_ms * 1000;
+        for opportunity in arb::detect(&self.dex, &self.cex, &self.balances, &self.config) {
+            self.report.summary.opportunities += 1;
+            match self.coordinator.approve(&opportunity, now_us) {
+                None => self.pending.push(Pending {
+                    opportunity,
+                    dex_at_us: now_us + dex_latency_us,
+                    cex_at_us: now_us + cex_latency_us,
+                    dex: None,
+                    cex: None,
+                }),
+                Some(decision) => self.book(decision, now_us),
+            }
+        }
+    }
+
+    /// Snapshots the market for every leg arriving at `at_us` and executes trades whose legs
+    /// have all arrived
+    fn land_legs(&mut self, at_us: u64) {
+        for pending in &mut self.pending {
+            if pending.dex.is_none() && pending.dex_at_us == at_us {
+                pending.dex = Some(self.dex.clone());
+            }
+            if pending.cex.is_none() && pending.cex_at_us == at_us {
+                pending.cex = Some(self.cex.clone());
+            }
+        }
+        let (landed, waiting) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|p| p.dex.is_some() && p.cex.is_some());
+        self.pending = waiting;
+
+        for pending in landed.into_iter() {
+            let (Some(dex), Some(cex)) = (&pending.dex, &pending.cex) else {
+                continue;
+            };
+            let decision =
+                self.coordinator
+                    .execute(&pending.opportunity, dex, cex, &mut self.balances, at_us);
+            self.book(decision, at_us);
+        }
+    }
+
+    fn book(&mut self, decision: Decision, now_us: u64) {
+        let summary = &mut self.report.summary;
+        match decision {
+            Decision::Executed { trade } => {
+                summary.executed += 1;
+                self.report.trades.push(trade);
+                let ledger = &self.coordinator.ledger;
+                self.report.pnl_curve.push(PnlPoint {
+                    timestamp_us: now_us,
+                    realized_pnl: ledger.realized_pnl,
+                    unrealized_pnl: ledger.unrealized_pnl(self.cex.book.mid().unwrap_or_default()),
+                });
+            }
+            Decision::Rejected { .. } => summary.rejected += 1,
+            Decision::Failed { .. } => summary.failed += 1,
+            Decision::Logged => {}
+        }
+    }
+
+    fn finish(mut self) -> BacktestReport {
+        let ledger = &self.coordinator.ledger;
+        let summary = &mut self.report.summary;
+        summary.realized_pnl = ledger.realized_pnl;
+        summary.unrealized_pnl = ledger.unrealized_pnl(self.cex.book.mid().unwrap_or_default());
+        for trade in &self.report.trades {
+            summary.fees += trade.fees;
+            summary.volume += trade.buy.quote;
+        }
+        let wins = self
+            .report
+            .trades
+            .iter()
+            .filter(|t| t.realized_pnl > 0)
+            .count();
+        summary.win_rate = if self.report.trades.is_empty() {
+            0.0
+        } else {
+            wins as f64 / self.report.trades.len() as f64
+        };
+        let mut peak = 0;
+        for point in &self.report.pnl_curve {
+            let equity = point.realized_pnl + point.unrealized_pnl;
+            peak = peak.max(equity);
+            summary.max_drawdown = summary.max_drawdown.max(peak - equity);
+        }
+        summary.final_balances = self.coordinator.paper.balances.clone();
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        clmm_simulator::tests::{pool, tick_array},
+        order_book::Level,
+        raydium_clmm::ClmmVenue,
+    };
+
+    fn book(recv_us: u64, bid: u64, ask: u64) -> anyhow::Result<Record> {
+        let level = |price| Level {
+            price,
+            qty: 100_000_000_000,
+        };
+        Ok(Record {
+            recv_us,
+            event: Event::Book {
+                bids: vec![level(bid)],
+                asks: vec![level(ask)],
+            },
+        })
+    }
+
+    /// Pool at ~150 USDC/SOL, CEX bid at 155 until it drops to 149 at 420ms
+    fn run(cex_latency_ms: u64) -> BacktestReport {
+        let mut config = Config::default();
+        config.backtest.cex_latency_ms = cex_latency_ms;
+        config.backtest.dex_latency_ms = 400;
+        let mut backtest = Backtest::new(&config).unwrap();
+        backtest.dex.pool = Box::new(ClmmVenue {
+            pool: Some(pool(-18_971, 1_000_000_000_000_000)),
+            tick_arrays: [(-18_980, tick_array(-18_980, &[]))].into(),
+            fee_rate: Some(0),
+        });
+        backtest
+            .run([
+                book(0, 155_000_000, 156_000_000),
+                book(420_000, 149_000_000, 150_000_000),
+            ])
+            .unwrap()
+    }
+
+    #[test]
+    fn legs_fill_against_the_market_after_their_latency() {
+        let fast = run(50);
+        assert_eq!(fast.summary.records, 2);
+        assert_eq!(fast.summary.opportunities, 1);
+        assert_eq!(fast.summary.executed, 1);
+        assert!(fast.summary.realized_pnl > 4_000_000);
+        assert_eq!(fast.pnl_curve.len(), 1);
+        assert_eq!(fast.pnl_curve[0].timestamp_us, 400_000);
+
+        // the CEX order arrives after the bid dropped
+        let slow = run(450);
+        assert_eq!(slow.summary.executed, 1);
+        assert!(slow.summary.realized_pnl < 0);
+        assert_eq!(slow.summary.max_drawdown, -slow.summary.realized_pnl);
+        assert_eq!(slow.trades[0].sell.price(), 149_000_000);
+    }
+}
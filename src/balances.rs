@@ -0,0 +1,254 @@
+//! Inventory per asset and venue. Wallet balances come from the gRPC account stream, CEX
+//! balances from polling the exchange.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{pubkey, pubkey::Pubkey};
+
+use crate::paper::PaperBalances;
+
+pub const TOKEN_PROGRAM_ID: Pubkey = pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+pub const TOKEN_2022_PROGRAM_ID: Pubkey = pubkey!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb");
+pub const ASSOCIATED_TOKEN_PROGRAM_ID: Pubkey =
+    pubkey!("ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL");
+pub const WSOL_MINT: Pubkey = pubkey!("So11111111111111111111111111111111111111112");
+pub const USDC_MINT: Pubkey = pubkey!("EPjFWdd5AufqSSqeM2qJ1K2rTZBHsoZ1VzUnE2BN2t1v");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Venue {
+    Wallet,
+    Cex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Asset {
+    Sol,  // lamports
+    Usdc, // micro-USDC
+}
+
+impl Asset {
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "SOL" => Some(Asset::Sol),
+            "USDC" => Some(Asset::Usdc),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Asset::Sol => "SOL",
+            Asset::Usdc => "USDC",
+        }
+    }
+
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Asset::Sol => 9,
+            Asset::Usdc => 6,
+        }
+    }
+}
+
+/// Wallet SOL is split across the system account and the wSOL ATA
+#[derive(Debug, Clone, Copy, Default)]
+struct WalletSol {
+    native: u64,
+    wrapped: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Balances {
+    amounts: HashMap<(Venue, Asset), u64>,
+    wallet_sol: WalletSol,
+    /// Lamports kept aside for transaction fees and rent
+    sol_fee_reserve: u64,
+}
+
+impl Balances {
+    pub fn new(sol_fee_reserve: u64) -> Self {
+        Self {
+            sol_fee_reserve,
+            ..Default::default()
+        }
+    }
+
+    /// Available amount of `asset` on `venue`, `None` when the venue isn't tracked
+    pub fn available(&self, venue: Venue, asset: Asset) -> Option<u64> {
+        self.amounts.get(&(venue, asset)).copied()
+    }
+
+    pub fn set(&mut self, venue: Venue, asset: Asset, amount: u64) {
+        self.amounts.insert((venue, asset), amount);
+    }
+
+    /// Every tracked (venue, asset) balance
+    pub fn iter(&self) -> impl Iterator<Item = (Venue, Asset, u64)> + '_ {
+        self.amounts
+            .iter()
+            .map(|(&(venue, asset), &amount)| (venue, asset, amount))
+    }
+
+    /// Wallet token account balance of `mint`, `None` for untracked mints
+    pub fn wallet_token(&self, mint: &Pubkey) -> Option<u64> {
+        if *mint == WSOL_MINT {
+            self.available(Venue::Wallet, Asset::Sol)
+                .map(|_| self.wallet_sol.wrapped)
+        } else if *mint == USDC_MINT {
+            self.available(Venue::Wallet, Asset::Usdc)
+        } else {
+            None
+        }
+    }
+
+    /// Wallet SOL outside the wSOL ATA, net of the fee reserve: what a system transfer can move
+    pub fn wallet_native_sol(&self) -> Option<u64> {
+        self.available(Venue::Wallet, Asset::Sol)
+            .map(|sol| sol.saturating_sub(self.wallet_sol.wrapped))
+    }
+
+    pub fn set_wallet_native_sol(&mut self, lamports: u64) {
+        self.wallet_sol.native = lamports;
+        self.update_wallet_sol();
+    }
+
+    pub fn set_wallet_wrapped_sol(&mut self, lamports: u64) {
+        self.wallet_sol.wrapped = lamports;
+        self.update_wallet_sol();
+    }
+
+    fn update_wallet_sol(&mut self) {
+        let native = self.wallet_sol.native.saturating_sub(self.sol_fee_reserve);
+        self.set(Venue::Wallet, Asset::Sol, native + self.wallet_sol.wrapped);
+    }
+}
+
+impl From<&PaperBalances> for Balances {
+    fn from(paper: &PaperBalances) -> Self {
+        let mut balances = Balances::default();
+        balances.set(Venue::Cex, Asset::Sol, paper.cex_sol);
+        balances.set(Venue::Cex, Asset::Usdc, paper.cex_usdc);
+        balances.set(Venue::Wallet, Asset::Sol, paper.wallet_sol);
+        balances.set(Venue::Wallet, Asset::Usdc, paper.wallet_usdc);
+        balances
+    }
+}
+
+/// Token accounts of the wallet that are tracked over gRPC
+#[derive(Debug, Clone, Copy)]
+pub struct WalletAccounts {
+    pub wallet: Pubkey,
+    pub wsol_ata: Pubkey,
+    pub usdc_ata: Pubkey,
+}
+
+impl WalletAccounts {
+    pub fn new(wallet: Pubkey) -> Self {
+        Self {
+            wallet,
+            wsol_ata: associated_token_address(&wallet, &WSOL_MINT),
+            usdc_ata: associated_token_address(&wallet, &USDC_MINT),
+        }
+    }
+
+    pub fn pubkeys(&self) -> [Pubkey; 3] {
+        [self.wallet, self.wsol_ata, self.usdc_ata]
+    }
+
+    /// Applies an account update to `balances`, returns false if `pubkey` isn't one of ours
+    pub fn apply(
+        &self,
+        balances: &mut Balances,
+        pubkey: &Pubkey,
+        lamports: u64,
+        data: &[u8],
+    ) -> bool {
+        if *pubkey == self.wallet {
+            balances.set_wallet_native_sol(lamports);
+        } else if *pubkey == self.wsol_ata {
+            balances.set_wallet_wrapped_sol(token_account_amount(data).unwrap_or_default());
+        } else if *pubkey == self.usdc_ata {
+            let amount = token_account_amount(data).unwrap_or_default();
+            balances.set(Venue::Wallet, Asset::Usdc, amount);
+        } else {
+            return false;
+        }
+        true
+    }
+}
+
+pub fn associated_token_address(wallet: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), TOKEN_PROGRAM_ID.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Reads the `amount` field of an SPL token account (mint, owner, amount, ...)
+pub fn token_account_amount(data: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(64..72)?.try_into().ok()?))
+}
+
+/// Converts a decimal string like "12.5" into base units of `asset`
+pub fn parse_amount(amount: &str, asset: Asset) -> anyhow::Result<u64> {
+    let value = amount.parse::<f64>()?;
+    Ok((value * 10f64.powi(asset.decimals() as i32)).round() as u64)
+}
+
+/// Formats base units of `asset` as a decimal string, the inverse of `parse_amount`
+pub fn format_amount(amount: u64, asset: Asset) -> String {
+    let scale = 10u64.pow(asset.decimals());
+    format!(
+        "{}.{:0width$}",
+        amount / scale,
+        amount % scale,
+        width = asset.decimals() as usize
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wallet_sol_keeps_fee_reserve() {
+        let mut balances = Balances::new(50_000_000);
+        balances.set_wallet_native_sol(1_000_000_000);
+        balances.set_wallet_wrapped_sol(500_000_000);
+        assert_eq!(
+            balances.available(Venue::Wallet, Asset::Sol),
+            Some(1_450_000_000)
+        );
+        assert_eq!(balances.wallet_native_sol(), Some(950_000_000));
+
+        balances.set_wallet_native_sol(10_000_000);
+        assert_eq!(
+            balances.available(Venue::Wallet, Asset::Sol),
+            Some(500_000_000)
+        );
+        assert_eq!(balances.wallet_native_sol(), Some(0));
+        assert_eq!(balances.available(Venue::Cex, Asset::Sol), None);
+    }
+
+    #[test]
+    fn reads_token_account_amount() {
+        let mut data = vec![0u8; 165];
+        data[64..72].copy_from_slice(&42_000_000u64.to_le_bytes());
+        assert_eq!(token_account_amount(&data), Some(42_000_000));
+        assert_eq!(token_account_amount(&data[..70]), None);
+    }
+
+    #[test]
+    fn amounts_round_trip_through_strings() {
+        assert_eq!(format_amount(1_500_000_000, Asset::Sol), "1.500000000");
+        assert_eq!(format_amount(42, Asset::Usdc), "0.000042");
+        assert_eq!(
+            parse_amount("1.500000000", Asset::Sol).unwrap(),
+            1_500_000_000
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Binance spot: REST API signed with an HMAC-SHA256 of the query string, and the public diff
+//! depth stream
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::{
+    balances::{Asset, format_amount, parse_amount},
+    cex::{
+        BOOK_DEPTH, CexExchange, CexVenue, OrderRequest, OrderSide, hmac_sha256,
+        missing_credentials, order_decimal, order_price,
+    },
+    feed::{DepthProtocol, WsBookFeed},
+    order_book::{DepthBook, DepthUpdate, OrderBook},
+};
+
+const API_URL: &str = "https://api.binance.com";
+const WS_URL: &str = "wss://stream.binance.com:9443/ws";
+const SYMBOL: &str = "SOLUSDC";
+const RECV_WINDOW_MS: u64 = 5000;
+/// Lot size of SOLUSDC, in lamports
+const LOT_SIZE: u64 = 1_000_000;
+/// Tick size of SOLUSDC, in micro-USDC
+const TICK_SIZE: u64 = 10_000;
+
+#[derive(Debug, Clone)]
+pub struct BinanceAuth {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+impl BinanceAuth {
+    /// Reads `BINANCE_API_KEY` and `BINANCE_API_SECRET`, `None` if either is unset
+    pub fn from_env() -> Option<Self> {
+        Some(Self {
+            api_key: std::env::var("BINANCE_API_KEY").ok()?,
+            api_secret: std::env::var("BINANCE_API_SECRET").ok()?,
+        })
+    }
+
+    /// `params` with the timestamp, receive window and signature appended
+    fn sign(&self, params: &str) -> anyhow::Result<String> {
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_millis();
+        let mut query = params.to_string();
+        if !query.is_empty() {
+            query.push('&');
+        }
+        query.push_str(&format!(
+            "timestamp={timestamp}&recvWindow={RECV_WINDOW_MS}"
+        ));
+        let signature = hex(&hmac_sha256(self.api_secret.as_bytes(), query.as_bytes()));
+        Ok(format!("{query}&signature={signature}"))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct OrderResponse {
+    #[serde(rename = "orderId")]
+    order_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountResponse {
+    balances: Vec<BalanceEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BalanceEntry {
+    asset: String,
+    free: String,
+}
+
+/// Binance spot as the CEX venue, orders and balances need `BinanceAuth`
+#[derive(Debug, Clone, Default)]
+pub struct BinanceVenue {
+    auth: Option<BinanceAuth>,
+}
+
+impl BinanceVenue {
+    pub fn from_env() -> Self {
+        Self {
+            auth: BinanceAuth::from_env(),
+        }
+    }
+
+    /// Sends a request for `path` with `params` signed
+    async fn signed(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        params: &str,
+    ) -> anyhow::Result<reqwest::Response> {
+        let auth = self
+            .auth
+            .as_ref()
+            .ok_or_else(|| missing_credentials(CexExchange::Binance))?;
+        let query = auth.sign(params)?;
+        let response = reqwest::Client::new()
+            .request(method, format!("{API_URL}{path}?{query}"))
+            .header("X-MBX-APIKEY", &auth.api_key)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response)
+    }
+}
+
+impl CexVenue for BinanceVenue {
+    type BookFeed = WsBookFeed<BinanceDepth>;
+
+    fn exchange(&self) -> CexExchange {
+        CexExchange::Binance
+    }
+
+    fn book_feed(&self) -> Self::BookFeed {
+        WsBookFeed::new(BinanceDepth::default())
+    }
+
+    fn authenticated(&self) -> bool {
+        self.auth.is_some()
+    }
+
+    async fn place_order(&self, order: &OrderRequest) -> anyhow::Result<String> {
+        let side = match order.side {
+            OrderSide::Buy => "BUY",
+            OrderSide::Sell => "SELL",
+        };
+        let quantity = order_decimal(order.qty, LOT_SIZE, Asset::Sol, false);
+        let params = match order_price(order, TICK_SIZE) {
+            Some(price) => format!(
+                "symbol={SYMBOL}&side={side}&type=LIMIT&timeInForce=IOC&quantity={quantity}&price={price}"
+            ),
+            None => format!("symbol={SYMBOL}&side={side}&type=MARKET&quantity={quantity}"),
+        };
+        let response: OrderResponse = self
+            .signed(reqwest::Method::POST, "/api/v3/order", &params)
+            .await?
+            .json()
+            .await?;
+        Ok(response.order_id.to_string())
+    }
+
+    async fn cancel_order(&self, order_id: &str) -> anyhow::Result<()> {
+        let params = format!("symbol={SYMBOL}&orderId={order_id}");
+        self.signed(reqwest::Method::DELETE, "/api/v3/order", &params)
+            .await?;
+        Ok(())
+    }
+
+    async fn balances(&self) -> anyhow::Result<HashMap<Asset, u64>> {
+        let account: AccountResponse = self
+            .signed(reqwest::Method::GET, "/api/v3/account", "")
+            .await?
+            .json()
+            .await?;
+        let mut balances = HashMap::new();
+        for entry in account.balances {
+            if let Some(asset) = Asset::from_symbol(&entry.asset) {
+                balances.insert(asset, parse_amount(&entry.free, asset)?);
+            }
+        }
+        Ok(balances)
+    }
+
+    async fn withdraw(&self, asset: Asset, amount: u64, address: &str) -> anyhow::Result<()> {
+        let params = format!(
+            "coin={}&network=SOL&address={address}&amount={}",
+            asset.symbol(),
+            format_amount(amount, asset)
+        );
+        self.signed(
+            reqwest::Method::POST,
+            "/sapi/v1/capital/withdraw/apply",
+            &params,
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
+    bids: Vec<(String, String)>,
+    asks: Vec<(String, String)>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DepthEvent {
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    #[serde(rename = "u")]
+    last_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<(String, String)>,
+    #[serde(rename = "a")]
+    asks: Vec<(String, String)>,
+}
+
+/// `solusdc@depth@100ms` stream: diffs with update ids on top of the REST depth snapshot. The
+/// first diff applied is the one spanning the snapshot's `lastUpdateId + 1`.
+#[derive(Debug, Default)]
+pub struct BinanceDepth {
+    book: DepthBook,
+}
+
+impl DepthProtocol for BinanceDepth {
+    fn url(&self) -> String {
+        format!("{WS_URL}/{}@depth@100ms", SYMBOL.to_lowercase())
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        // the stream is named in the URL
+        Vec::new()
+    }
+
+    fn snapshot_url(&self) -> Option<String> {
+        Some(format!("{API_URL}/api/v3/depth?symbol={SYMBOL}&limit=1000"))
+    }
+
+    fn apply_snapshot(&mut self, body: &str) -> anyhow::Result<()> {
+        let snapshot: DepthSnapshot = serde_json::from_str(body)?;
+        self.book
+            .snapshot(snapshot.last_update_id, &snapshot.bids, &snapshot.asks)
+    }
+
+    fn apply_message(&mut self, message: &str) -> anyhow::Result<Option<OrderBook>> {
+        let event: DepthEvent = serde_json::from_str(message)?;
+        let changed = self.book.update(DepthUpdate {
+            first_update_id: event.first_update_id,
+            last_update_id: event.last_update_id,
+            bids: event.bids,
+            asks: event.asks,
+        })?;
+        Ok(changed.then(|| self.book.book(BOOK_DEPTH)).flatten())
+    }
+
+    fn reset(&mut self) {
+        self.book.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_book_from_recorded_depth_messages() {
+        let mut depth = BinanceDepth::default();
+        let messages: Vec<&str> = include_str!("../fixtures/cex/binance_depth.jsonl")
+            .lines()
+            .collect();
+        // buffered until the snapshot, the first one is older than it
+        for message in &messages[..2] {
+            assert_eq!(depth.apply_message(message).unwrap(), None);
+        }
+        depth
+            .apply_snapshot(include_str!("../fixtures/cex/binance_snapshot.json"))
+            .unwrap();
+        let book = depth.apply_message(messages[2]).unwrap().unwrap();
+        assert_eq!(book.best_bid(), Some(150_020_000));
+        assert_eq!(book.best_ask(), Some(150_040_000));
+        assert_eq!(book.asks[0].qty, 12_345_000_000);
+        assert_eq!(book.bids.len(), 2);
+    }
+
+    #[test]
+    fn signs_the_query_string() {
+        // example from the Binance API docs
+        let secret = "NhqPtmdSJYdKjVHjA7PZj4Mge3R5YNiP1e3UZjInClVN65XAbvqqM6A7H5fATj0j";
+        let query = "symbol=LTCBTC&side=BUY&type=LIMIT&timeInForce=GTC&quantity=1&price=0.1&recvWindow=5000&timestamp=1499827319559";
+        assert_eq!(
+            hex(&hmac_sha256(secret.as_bytes(), query.as_bytes())),
+            "c8db56825ae71d6d79447849e617115f4a920fa2acdcab2b053c4b2838bd6b71"
+        );
+    }
+}
@@ -0,0 +1,170 @@
+//! CEX venues. Every exchange implements `CexVenue`: its order book stream, orders, balances and
+//! fees. Book streams go through `WsBookFeed` with the exchange's `DepthProtocol`, so the same
+//! pool can be arbitraged against whichever exchange is configured.
+
+use std::collections::HashMap;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    balances::{Asset, format_amount},
+    feed::CexFeed,
+};
+
+/// Levels kept on each side of the books built from depth streams
+pub const BOOK_DEPTH: usize = 50;
+
+/// Exchange the pool is arbitraged against
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CexExchange {
+    #[default]
+    Backpack,
+    Binance,
+    Okx,
+}
+
+impl CexExchange {
+    /// Base tier fees of the exchange
+    pub fn base_fees(self) -> CexFees {
+        match self {
+            CexExchange::Backpack | CexExchange::Okx => CexFees {
+                maker_bps: 8,
+                taker_bps: 10,
+            },
+            CexExchange::Binance => CexFees {
+                maker_bps: 10,
+                taker_bps: 10,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+/// Order for SOL against USDC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OrderRequest {
+    pub side: OrderSide,
+    /// Lamports, rounded down to the exchange's lot size
+    pub qty: u64,
+    /// Limit price in micro-USDC per SOL, rounded to the tick away from a worse fill. Limit
+    /// orders are immediate or cancel, `None` sends a market order.
+    pub price: Option<u64>,
+}
+
+/// Base tier fees of an exchange, in bps
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CexFees {
+    pub maker_bps: u64,
+    pub taker_bps: u64,
+}
+
+pub trait CexVenue {
+    type BookFeed: CexFeed + Send + 'static;
+
+    fn exchange(&self) -> CexExchange;
+
+    /// SOL/USDC order book from the exchange's public WebSocket depth stream
+    fn book_feed(&self) -> Self::BookFeed;
+
+    /// Whether API credentials are set, orders and balances fail without them
+    fn authenticated(&self) -> bool;
+
+    /// Places `order`, returns the exchange's order id
+    fn place_order(
+        &self,
+        order: &OrderRequest,
+    ) -> impl Future<Output = anyhow::Result<String>> + Send;
+
+    fn cancel_order(&self, order_id: &str) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    /// Available balances, for the assets the bot knows about
+    fn balances(&self) -> impl Future<Output = anyhow::Result<HashMap<Asset, u64>>> + Send;
+
+    /// Requests a withdrawal of `amount` base units of `asset` to `address` on Solana
+    fn withdraw(
+        &self,
+        asset: Asset,
+        amount: u64,
+        address: &str,
+    ) -> impl Future<Output = anyhow::Result<()>> + Send;
+
+    fn fees(&self) -> CexFees {
+        self.exchange().base_fees()
+    }
+}
+
+/// Decimal string of `amount` base units of `asset` rounded to a multiple of `step`, up or down,
+/// without trailing zeros
+pub fn order_decimal(amount: u64, step: u64, asset: Asset, round_up: bool) -> String {
+    let amount = if round_up {
+        amount.div_ceil(step) * step
+    } else {
+        amount / step * step
+    };
+    let decimal = format_amount(amount, asset);
+    decimal
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+/// Limit price of `order` as a decimal string: buys round the tick down, sells up
+pub fn order_price(order: &OrderRequest, tick: u64) -> Option<String> {
+    let round_up = order.side == OrderSide::Sell;
+    order
+        .price
+        .map(|price| order_decimal(price, tick, Asset::Usdc, round_up))
+}
+
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+pub fn missing_credentials(exchange: CexExchange) -> anyhow::Error {
+    anyhow::Error::msg(format!("Error: no API credentials for {exchange:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_orders_to_the_exchange_steps() {
+        // 1.23456789 SOL on a 0.001 lot
+        assert_eq!(
+            order_decimal(1_234_567_890, 1_000_000, Asset::Sol, false),
+            "1.234"
+        );
+        assert_eq!(
+            order_decimal(2_000_000_000, 1_000_000, Asset::Sol, false),
+            "2"
+        );
+        let buy = OrderRequest {
+            side: OrderSide::Buy,
+            qty: 1_000_000_000,
+            price: Some(150_123_456),
+        };
+        assert_eq!(order_price(&buy, 10_000).as_deref(), Some("150.12"));
+        let sell = OrderRequest {
+            side: OrderSide::Sell,
+            ..buy
+        };
+        assert_eq!(order_price(&sell, 10_000).as_deref(), Some("150.13"));
+        // RFC 4231 test case 2
+        assert_eq!(
+            hmac_sha256(b"Jefe", b"what do ya want for nothing?")[..4],
+            [0x5b, 0xdc, 0xc1, 0x46]
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Off-chain replica of the Raydium CLMM swap loop. Walks initialized ticks from the cached tick
+//! arrays, crossing liquidity as it goes, so quotes stay accurate for sizes larger than the
+//! current tick range. The loop itself (`walk_ticks`) is shared with the other Q64.64
+//! concentrated liquidity pools.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    raydium_clmm::{PoolState, TickArrayState},
+    raydium_math::{
+        liquidity_math, swap_math::compute_swap_step, tick_math, tick_math::MAX_TICK,
+        tick_math::MIN_TICK,
+    },
+};
+
+/// Tick arrays of a pool keyed by their start tick index
+pub type TickArrayCache = BTreeMap<i32, TickArrayState>;
+
+/// Upper bound on swap steps, mirrors the compute limit of the on-chain program
+const MAX_SWAP_STEPS: usize = 256;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SwapResult {
+    /// Amount paid into the pool, fees included
+    pub amount_in: u64,
+    pub amount_out: u64,
+    pub fee_amount: u64,
+    pub sqrt_price_x64: u128,
+    pub tick: i32,
+    pub ticks_crossed: u32,
+    /// False when the swap ran into a tick array that isn't cached (or hit the price limit)
+    /// before the whole amount was consumed
+    pub complete: bool,
+}
+
+/// Price state of a concentrated liquidity pool
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PoolPrice {
+    pub sqrt_price_x64: u128,
+    pub liquidity: u128,
+    pub tick: i32,
+}
+
+/// Next tick the swap can move to without leaving known liquidity
+pub struct NextTick {
+    pub tick: i32,
+    /// `None` at the edge of the known tick arrays
+    pub liquidity_net: Option<i128>,
+}
+
+/// Simulates a swap against `pool` using the ticks in `tick_arrays`.
+///
+/// `is_base_input` selects exact input (`amount_specified` is the amount in) or exact output
+/// (`amount_specified` is the amount out), like the on-chain instruction.
+pub fn simulate_swap(
+    pool: &PoolState,
+    tick_arrays: &TickArrayCache,
+    fee_rate: u32,
+    amount_specified: u64,
+    zero_for_one: bool,
+    is_base_input: bool,
+) -> anyhow::Result<SwapResult> {
+    let price = PoolPrice {
+        sqrt_price_x64: pool.sqrt_price_x64,
+        liquidity: pool.liquidity,
+        tick: pool.tick_current,
+    };
+    walk_ticks(
+        price,
+        fee_rate,
+        amount_specified,
+        zero_for_one,
+        is_base_input,
+        |tick| next_tick(pool, tick_arrays, tick, zero_for_one),
+    )
+}
+
+/// The swap loop: steps from `price` towards each tick returned by `next_tick` (called with the
+/// current tick), crossing its liquidity, until the amount is used up or no known tick is left.
+/// `fee_rate` is in hundredths of a bip.
+pub fn walk_ticks(
+    price: PoolPrice,
+    fee_rate: u32,
+    amount_specified: u64,
+    zero_for_one: bool,
+    is_base_input: bool,
+    mut next_tick: impl FnMut(i32) -> Option<NextTick>,
+) -> anyhow::Result<SwapResult> {
+    let mut result = SwapResult {
+        sqrt_price_x64: price.sqrt_price_x64,
+        tick: price.tick,
+        complete: true,
+        ..Default::default()
+    };
+    let mut amount_remaining = amount_specified;
+    let mut liquidity = price.liquidity;
+
+    for _ in 0..MAX_SWAP_STEPS {
+        if amount_remaining == 0 {
+            return Ok(result);
+        }
+        let next = match next_tick(result.tick) {
+            Some(next) => next,
+            None => break,
+        };
+        let sqrt_price_target = tick_math::get_sqrt_price_at_tick(next.tick)?;
+        if sqrt_price_target == result.sqrt_price_x64 && next.liquidity_net.is_none() {
+            break;
+        }
+
+        let step = compute_swap_step(
+            result.sqrt_price_x64,
+            sqrt_price_target,
+            liquidity,
+            amount_remaining,
+            fee_rate,
+            is_base_input,
+            zero_for_one,
+        )?;
+
+        let step_in = step.amount_in + step.fee_amount;
+        if is_base_input {
+            amount_remaining = amount_remaining.saturating_sub(step_in);
+        } else {
+            amount_remaining = amount_remaining.saturating_sub(step.amount_out);
+        }
+        result.amount_in += step_in;
+        result.amount_out += step.amount_out;
+        result.fee_amount += step.fee_amount;
+        result.sqrt_price_x64 = step.sqrt_price_next_x64;
+
+        if step.sqrt_price_next_x64 == sqrt_price_target {
+            match next.liquidity_net {
+                Some(liquidity_net) => {
+                    let liquidity_net = if zero_for_one {
+                        -liquidity_net
+                    } else {
+                        liquidity_net
+                    };
+                    liquidity = liquidity_math::add_delta(liquidity, liquidity_net)?;
+                    result.ticks_crossed += 1;
+                    result.tick = if zero_for_one {
+                        next.tick - 1
+                    } else {
+                        next.tick
+                    };
+                }
+                // reached the edge of the cached tick arrays
+                None => {
+                    result.tick = if zero_for_one {
+                        next.tick - 1
+                    } else {
+                        next.tick
+                    };
+                    if amount_remaining > 0 {
+                        break;
+                    }
+                }
+            }
+        } else {
+            result.tick = tick_math::get_tick_at_sqrt_price(result.sqrt_price_x64)?;
+        }
+    }
+
+    result.complete = amount_remaining == 0;
+    Ok(result)
+}
+
+/// Start indexes of the initialized tick arrays within `window` arrays of the current tick that
+/// aren't cached yet
+pub fn missing_tick_arrays(
+    pool: &PoolState,
+    tick_arrays: &TickArrayCache,
+    window: i32,
+) -> Vec<i32> {
+    let ticks_in_array = TickArrayState::tick_count(pool.tick_spacing);
+    let current = TickArrayState::get_array_start_index(pool.tick_current, pool.tick_spacing);
+    (-window..=window)
+        .map(|offset| current + offset * ticks_in_array)
+        .filter(|start| !tick_arrays.contains_key(start))
+        .filter(|start| pool.is_tick_array_initialized(*start) == Some(true))
+        .collect()
+}
+
+/// Finds the next initialized tick in the swap direction, or the boundary of the first tick array
+/// that should hold liquidity but isn't cached.
+fn next_tick(
+    pool: &PoolState,
+    tick_arrays: &TickArrayCache,
+    tick_current: i32,
+    zero_for_one: bool,
+) -> Option<NextTick> {
+    let ticks_in_array = TickArrayState::tick_count(pool.tick_spacing);
+    let mut start_index = TickArrayState::get_array_start_index(tick_current, pool.tick_spacing);
+
+    loop {
+        if start_index + ticks_in_array <= MIN_TICK || start_index > MAX_TICK {
+            return None;
+        }
+        match tick_arrays.get(&start_index) {
+            Some(tick_array) => {
+                let initialized = tick_array.ticks.iter().filter(|t| t.liquidity_gross > 0);
+                let found = if zero_for_one {
+                    initialized
+                        .filter(|t| t.tick <= tick_current)
+                        .max_by_key(|t| t.tick)
+                } else {
+                    initialized
+                        .filter(|t| t.tick > tick_current)
+                        .min_by_key(|t| t.tick)
+                };
+                if let Some(t) = found {
+                    return Some(NextTick {
+                        tick: t.tick,
+                        liquidity_net: Some(t.liquidity_net),
+                    });
+                }
+            }
+            None => {
+                if pool.is_tick_array_initialized(start_index) != Some(false) {
+                    // entering an array we know nothing about, stop at its edge
+                    let edge = if zero_for_one {
+                        start_index + ticks_in_array
+                    } else {
+                        start_index
+                    };
+                    if (zero_for_one && edge > tick_current)
+                        || (!zero_for_one && edge <= tick_current)
+                    {
+                        return None;
+                    }
+                    return Some(NextTick {
+                        tick: edge.clamp(MIN_TICK, MAX_TICK),
+                        liquidity_net: None,
+                    });
+                }
+            }
+        }
+        start_index += if zero_for_one {
+            -ticks_in_array
+        } else {
+            ticks_in_array
+        };
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        raydium_clmm::{RewardInfo, TickState},
+    };
+
+    pub(crate) fn pool(tick_current: i32, liquidity: u128) -> PoolState {
+        PoolState {
+            bump: [0],
+            amm_config: Default::default(),
+            owner: Default::default(),
+            token_mint0: WSOL_MINT,
+            token_mint1: USDC_MINT,
+            token_vault0: Default::default(),
+            token_vault1: Default::default(),
+            observation_key: Default::default(),
+            mint_decimals0: 9,
+            mint_decimals1: 6,
+            tick_spacing: 1,
+            liquidity,
+            sqrt_price_x64: tick_math::get_sqrt_price_at_tick(tick_current).unwrap(),
+            tick_current,
+            observation_index: 0,
+            observation_update_duration: 0,
+            fee_growth_global0_x64: 0,
+            fee_growth_global1_x64: 0,
+            protocol_fees_token0: 0,
+            protocol_fees_token1: 0,
+            swap_in_amount_token0: 0,
+            swap_out_amount_token1: 0,
+            swap_in_amount_token1: 0,
+            swap_out_amount_token0: 0,
+            status: 0,
+            padding: [0; 7],
+            reward_infos: [
+                RewardInfo::default(),
+                RewardInfo::default(),
+                RewardInfo::default(),
+            ],
+            tick_array_bitmap: [0; 16],
+            total_fees_token0: 0,
+            total_fees_claimed_token0: 0,
+            total_fees_token1: 0,
+            total_fees_claimed_token1: 0,
+            fund_fees_token0: 0,
+            fund_fees_token1: 0,
+            open_time: 0,
+            padding1: [0; 25],
+            padding2: [0; 32],
+        }
+    }
+
+    pub(crate) fn tick_array(start_tick_index: i32, ticks: &[(i32, i128)]) -> TickArrayState {
+        TickArrayState {
+            pool_id: Default::default(),
+            start_tick_index,
+            ticks: ticks
+                .iter()
+                .map(|&(tick, liquidity_net)| TickState {
+                    tick,
+                    liquidity_net,
+                    liquidity_gross: liquidity_net.unsigned_abs(),
+                    ..Default::default()
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stays_in_range_for_small_swaps() {
+        let pool = pool(10, 1_000_000_000_000);
+        let mut cache = TickArrayCache::new();
+        cache.insert(0, tick_array(0, &[(0, 1_000_000_000_000)]));
+
+        let swap = simulate_swap(&pool, &cache, 0, 1_000, true, true).unwrap();
+        assert!(swap.complete);
+        assert_eq!(swap.amount_in, 1_000);
+        assert_eq!(swap.ticks_crossed, 0);
+        assert!(swap.amount_out > 990);
+    }
+
+    #[test]
+    fn crosses_ticks_and_runs_out_of_liquidity() {
+        let pool = pool(10, 1_000_000);
+        let mut cache = TickArrayCache::new();
+        cache.insert(0, tick_array(0, &[(0, 1_000_000)]));
+        cache.insert(-60, tick_array(-60, &[]));
+
+        let swap = simulate_swap(&pool, &cache, 0, u64::MAX / 2, true, true).unwrap();
+        assert_eq!(swap.ticks_crossed, 1);
+        // no liquidity left below tick 0, so the swap can't complete
+        assert!(!swap.complete);
+        assert!(swap.amount_out > 0);
+    }
+
+    #[test]
+    fn exact_output_charges_fees_on_input() {
+        let pool = pool(10, 1_000_000_000_000);
+        let mut cache = TickArrayCache::new();
+        cache.insert(0, tick_array(0, &[(0, 1_000_000_000_000), (50, -1)]));
+
+        let no_fee = simulate_swap(&pool, &cache, 0, 10_000, false, false).unwrap();
+        let with_fee = simulate_swap(&pool, &cache, 2_500, 10_000, false, false).unwrap();
+        assert_eq!(no_fee.amount_out, 10_000);
+        assert_eq!(with_fee.amount_out, 10_000);
+        assert!(with_fee.amount_in > no_fee.amount_in);
+        assert!(with_fee.fee_amount > 0);
+    }
+}
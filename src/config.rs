@@ -0,0 +1,308 @@
+use std::{str::FromStr, time::SystemTime};
+
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use tracing::error;
+
+use crate::{
+    alerts::AlertConfig,
+    backtest::BacktestConfig,
+    balances::{USDC_MINT, WSOL_MINT},
+    cex::CexExchange,
+    control::ControlConfig,
+    cross_dex::CrossDexConfig,
+    execution::ExecutionMode,
+    journal::JournalConfig,
+    logging::LoggingConfig,
+    lookup_tables::LookupTableConfig,
+    metrics::MetricsConfig,
+    paper::PaperBalances,
+    rebalance::RebalanceConfig,
+    recorder::RecorderConfig,
+    risk::RiskLimits,
+    route::RouteConfig,
+    supervisor::SupervisorConfig,
+    tx_builder::TransactionConfig,
+    venue::PoolType,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub grpc_url: String,
+    pub rpc_url: String,
+    /// Pool to watch
+    pub pool: String,
+    pub pool_type: PoolType,
+    /// Mint of the pair's base token, SOL
+    pub base_mint: String,
+    /// Mint of the pair's quote token, USDC
+    pub quote_mint: String,
+    /// Exchange the pool is arbitraged against
+    pub cex_exchange: CexExchange,
+    /// Size checked for each opportunity, in lamports
+    pub trade_size: u64,
+    /// Taker fee charged on the CEX leg, in bps. Unset, the venue's base tier fee is used; set it
+    /// for a discounted tier.
+    pub cex_taker_fee_bps: Option<u64>,
+    /// Spread an opportunity must clear on top of the rebalancing cost, in bps of its cost
+    pub min_profit_bps: u64,
+    /// Wallet whose SOL, wSOL and USDC balances are tracked
+    pub wallet: Option<String>,
+    /// Lamports of wallet SOL never used for trading (fees, rent)
+    pub sol_fee_reserve: u64,
+    pub cex_balance_poll_secs: u64,
+    pub execution_mode: ExecutionMode,
+    /// Starting balances when `execution_mode` is `paper`
+    pub paper_balances: PaperBalances,
+    pub rebalance: RebalanceConfig,
+    pub cross_dex: CrossDexConfig,
+    pub routes: RouteConfig,
+    /// Compute budget of the atomic DEX transactions
+    pub transaction: TransactionConfig,
+    pub lookup_tables: LookupTableConfig,
+    pub risk: RiskLimits,
+    pub journal: JournalConfig,
+    pub recorder: RecorderConfig,
+    pub backtest: BacktestConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub control: ControlConfig,
+    pub alerts: AlertConfig,
+    pub supervisor: SupervisorConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            grpc_url: "https://solana-yellowstone-grpc.publicnode.com:443".to_string(),
+            rpc_url: "https://api.mainnet-beta.solana.com".to_string(),
+            pool: "3ucNos4NbumPLZNWztqGHNFFgkHeRMBQAVemeeomsUxv".to_string(),
+            pool_type: PoolType::RaydiumClmm,
+            base_mint: WSOL_MINT.to_string(),
+            quote_mint: USDC_MINT.to_string(),
+            cex_exchange: CexExchange::Backpack,
+            trade_size: 1_000_000_000,
+            cex_taker_fee_bps: None,
+            min_profit_bps: 0,
+            wallet: None,
+            sol_fee_reserve: 50_000_000,
+            cex_balance_poll_secs: 10,
+            execution_mode: ExecutionMode::LogOnly,
+            paper_balances: PaperBalances {
+                cex_sol: 10_000_000_000,
+                cex_usdc: 2_000_000_000,
+                wallet_sol: 10_000_000_000,
+                wallet_usdc: 2_000_000_000,
+            },
+            rebalance: RebalanceConfig::default(),
+            cross_dex: CrossDexConfig::default(),
+            routes: RouteConfig::default(),
+            transaction: TransactionConfig::default(),
+            lookup_tables: LookupTableConfig::default(),
+            risk: RiskLimits::default(),
+            journal: JournalConfig::default(),
+            recorder: RecorderConfig::default(),
+            backtest: BacktestConfig::default(),
+            logging: LoggingConfig::default(),
+            metrics: MetricsConfig::default(),
+            control: ControlConfig::default(),
+            alerts: AlertConfig::default(),
+            supervisor: SupervisorConfig::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Base and quote mints of the pair
+    pub fn pair_mints(&self) -> anyhow::Result<(Pubkey, Pubkey)> {
+        Ok((
+            Pubkey::from_str(&self.base_mint)?,
+            Pubkey::from_str(&self.quote_mint)?,
+        ))
+    }
+
+    /// Taker fee detection, sizing and paper fills charge on the CEX leg
+    pub fn taker_fee_bps(&self) -> u64 {
+        self.cex_taker_fee_bps
+            .unwrap_or_else(|| self.cex_exchange.base_fees().taker_bps)
+    }
+
+    /// Takes the risk limits of `new`, the only settings that apply while the bot runs. Returns
+    /// the other settings that differ, they're left as they were until a restart.
+    pub fn reload(&mut self, new: Config) -> Vec<&'static str> {
+        let restart = self.restart_changes(&new);
+        self.risk = new.risk;
+        restart
+    }
+
+    /// Settings of `new` that differ from these but only take effect on restart. Every task
+    /// reads them once at startup.
+    pub fn restart_changes(&self, new: &Config) -> Vec<&'static str> {
+        let Config {
+            grpc_url,
+            rpc_url,
+            pool,
+            pool_type,
+            base_mint,
+            quote_mint,
+            cex_exchange,
+            trade_size,
+            cex_taker_fee_bps,
+            min_profit_bps,
+            wallet,
+            sol_fee_reserve,
+            cex_balance_poll_secs,
+            execution_mode,
+            paper_balances,
+            rebalance,
+            cross_dex,
+            routes,
+            transaction,
+            lookup_tables,
+            risk: _,
+            journal,
+            recorder,
+            backtest,
+            logging,
+            metrics,
+            control,
+            alerts,
+            supervisor,
+        } = self;
+        [
+            ("grpc_url", *grpc_url != new.grpc_url),
+            ("rpc_url", *rpc_url != new.rpc_url),
+            ("pool", *pool != new.pool),
+            ("pool_type", *pool_type != new.pool_type),
+            ("base_mint", *base_mint != new.base_mint),
+            ("quote_mint", *quote_mint != new.quote_mint),
+            ("cex_exchange", *cex_exchange != new.cex_exchange),
+            ("trade_size", *trade_size != new.trade_size),
+            (
+                "cex_taker_fee_bps",
+                *cex_taker_fee_bps != new.cex_taker_fee_bps,
+            ),
+            ("min_profit_bps", *min_profit_bps != new.min_profit_bps),
+            ("wallet", *wallet != new.wallet),
+            ("sol_fee_reserve", *sol_fee_reserve != new.sol_fee_reserve),
+            (
+                "cex_balance_poll_secs",
+                *cex_balance_poll_secs != new.cex_balance_poll_secs,
+            ),
+            ("execution_mode", *execution_mode != new.execution_mode),
+            ("paper_balances", *paper_balances != new.paper_balances),
+            ("rebalance", *rebalance != new.rebalance),
+            ("cross_dex", *cross_dex != new.cross_dex),
+            ("routes", *routes != new.routes),
+            ("transaction", *transaction != new.transaction),
+            ("lookup_tables", *lookup_tables != new.lookup_tables),
+            ("journal", *journal != new.journal),
+            ("recorder", *recorder != new.recorder),
+            ("backtest", *backtest != new.backtest),
+            ("logging", *logging != new.logging),
+            ("metrics", *metrics != new.metrics),
+            ("control", *control != new.control),
+            ("alerts", *alerts != new.alerts),
+            ("supervisor", *supervisor != new.supervisor),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+
+    /// Loads the JSON config from `$ARB_CONFIG` (default `config.json`), missing fields and a
+    /// missing file fall back to the defaults
+    pub fn load() -> anyhow::Result<Self> {
+        dotenv::dotenv().ok();
+        Self::load_from(&Self::path())
+    }
+
+    pub fn path() -> String {
+        std::env::var("ARB_CONFIG").unwrap_or_else(|_| "config.json".to_string())
+    }
+
+    pub fn load_from(path: &str) -> anyhow::Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(raw) => Ok(serde_json::from_str(&raw)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Picks up edits to the config file so risk limits can be changed without a restart
+pub struct ConfigWatcher {
+    path: String,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new() -> Self {
+        let path = Config::path();
+        let modified = modified(&path);
+        Self { path, modified }
+    }
+
+    /// Returns the new config if the file changed since the last call. A file that fails to
+    /// parse is reported and skipped, the running config stays in place.
+    pub fn poll(&mut self) -> Option<Config> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+        match Config::load_from(&self.path) {
+            Ok(config) => Some(config),
+            Err(e) => {
+                error!(path = %self.path, "Error reloading config: {e}");
+                None
+            }
+        }
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reload_only_takes_the_risk_limits() {
+        let mut config = Config::default();
+        let mut new = Config {
+            risk: RiskLimits {
+                kill_switch: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert!(config.restart_changes(&new).is_empty());
+        assert!(config.clone().reload(new.clone()).is_empty());
+
+        // other settings are reported and kept until a restart
+        new.trade_size *= 2;
+        new.cross_dex.max_size = 1;
+        assert_eq!(config.restart_changes(&new), ["trade_size", "cross_dex"]);
+        assert_eq!(config.reload(new), ["trade_size", "cross_dex"]);
+        assert!(config.risk.kill_switch);
+        assert_eq!(config.trade_size, Config::default().trade_size);
+    }
+
+    #[test]
+    fn strategy_fee_follows_the_venue_unless_overridden() {
+        let mut config = Config {
+            cex_exchange: CexExchange::Okx,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.taker_fee_bps(),
+            CexExchange::Okx.base_fees().taker_bps
+        );
+        config.cex_taker_fee_bps = Some(7);
+        assert_eq!(config.taker_fee_bps(), 7);
+    }
+}
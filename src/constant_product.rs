@@ -0,0 +1,62 @@
+//! Constant product (x * y = k) curve shared by the constant product pools. Fees are taken by
+//! the callers, each program rounds and splits them its own way.
+
+use crate::{clmm_simulator::PoolPrice, raydium_math::tick_math};
+
+/// Output of swapping `amount_in` (fees already taken) into the pool, rounded down
+pub fn amount_out(amount_in: u64, reserve_in: u64, reserve_out: u64) -> u64 {
+    let reserve_in = u128::from(reserve_in) + u128::from(amount_in);
+    if reserve_in == 0 {
+        return 0;
+    }
+    // never more than reserve_out, so it fits
+    (u128::from(reserve_out) * u128::from(amount_in) / reserve_in) as u64
+}
+
+/// Input (before fees) needed to take `amount_out` out of the pool, rounded up. `None` when the
+/// pool doesn't hold that much.
+pub fn amount_in(amount_out: u64, reserve_in: u64, reserve_out: u64) -> Option<u64> {
+    if amount_out >= reserve_out {
+        return None;
+    }
+    let numerator = u128::from(reserve_in) * u128::from(amount_out);
+    u64::try_from(numerator.div_ceil(u128::from(reserve_out - amount_out))).ok()
+}
+
+/// Price of a pool holding `reserve_0` / `reserve_1` in the concentrated liquidity terms used by
+/// logs and the journal: sqrt(reserve_1 / reserve_0) as Q64.64, liquidity sqrt(x * y) and the
+/// matching tick. `None` for an empty pool.
+pub fn price(reserve_0: u64, reserve_1: u64) -> Option<PoolPrice> {
+    if reserve_0 == 0 || reserve_1 == 0 {
+        return None;
+    }
+    let (reserve_0, reserve_1) = (u128::from(reserve_0), u128::from(reserve_1));
+    // sqrt of the Q64.64 ratio is Q32.32, good to 32 fractional bits
+    let sqrt_price_x64 = ((reserve_1 << 64) / reserve_0).isqrt() << 32;
+    let tick = tick_math::get_tick_at_sqrt_price(sqrt_price_x64).ok()?;
+    Some(PoolPrice {
+        sqrt_price_x64,
+        liquidity: (reserve_0 * reserve_1).isqrt(),
+        tick,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_round_against_the_trader() {
+        // 1_000 SOL / 150_000 USDC
+        let (sol, usdc) = (1_000_000_000_000, 150_000_000_000);
+        let out = amount_out(1_000_000_000, sol, usdc);
+        assert_eq!(out, 149_850_149);
+        // buying that output back takes at least what was paid
+        assert_eq!(amount_in(out, sol, usdc), Some(999_999_995));
+        assert_eq!(amount_in(usdc, sol, usdc), None);
+
+        let price = price(sol, usdc).unwrap();
+        assert_eq!(price.tick, -18_973);
+        assert_eq!(price.liquidity, 387_298_334_620);
+    }
+}
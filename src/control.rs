@@ -0,0 +1,401 @@
+//! Local HTTP/JSON control API: quotes, opportunities, balances and risk limits of the running
+//! bot, trading toggles per pair, the kill switch and config reloads. Changes are picked up by
+//! the arb loop on its next pass.
+
+use std::collections::HashSet;
+
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    arb::{self, Opportunity},
+    balances::{Asset, Venue},
+    config::Config,
+    execution::Decision,
+    ledger,
+    pipeline::SharedState,
+    risk::RiskLimits,
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ControlConfig {
+    pub enabled: bool,
+    /// Keep this on localhost, the API is unauthenticated
+    pub listen: String,
+}
+
+impl Default for ControlConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: "127.0.0.1:9200".to_string(),
+        }
+    }
+}
+
+/// State shared between the control API and the arb loop
+#[derive(Debug, Default)]
+pub struct Controls {
+    /// Pairs trading was switched off for, their opportunities are only logged
+    pub paused: HashSet<String>,
+    /// Kill switch engaged over the API, on top of the one in the config
+    pub kill_switch: bool,
+    /// Config loaded over the API, taken by the arb loop on its next pass
+    pub pending_config: Option<Config>,
+    /// Limits in force, published by the arb loop
+    pub risk_limits: RiskLimits,
+    /// Opportunities of the last detection pass
+    pub opportunities: Vec<(Opportunity, Decision)>,
+}
+
+impl Controls {
+    pub fn trading_enabled(&self, pair: &str) -> bool {
+        !self.paused.contains(pair)
+    }
+
+    /// `limits` with the API kill switch applied
+    pub fn effective_limits(&self, limits: &RiskLimits) -> RiskLimits {
+        RiskLimits {
+            kill_switch: limits.kill_switch || self.kill_switch,
+            ..limits.clone()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairStatus {
+    pub pair: String,
+    pub pool: String,
+    pub trading: bool,
+    pub slot: u64,
+    /// Quote for `dex_in` lamports, in micro-USDC
+    pub dex_in: u64,
+    pub dex_out: Option<u64>,
+    pub best_bid: u64,
+    pub best_ask: u64,
+    pub cex_age_ms: Option<u64>,
+    /// Other tracked pools of the pair, compared by cross-DEX detection
+    pub pools: Vec<PoolStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PoolStatus {
+    pub pool: String,
+    pub slot: u64,
+    /// Quote for `dex_in` lamports, in micro-USDC
+    pub dex_in: u64,
+    pub dex_out: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OpportunityStatus {
+    pub opportunity: Opportunity,
+    pub decision: Decision,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceStatus {
+    pub venue: Venue,
+    pub asset: Asset,
+    pub amount: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradingToggle {
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KillSwitch {
+    pub engaged: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigReload {
+    /// Limits the arb loop takes on its next pass
+    pub risk: RiskLimits,
+    /// Changed settings left as they were until a restart
+    pub restart: Vec<String>,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    state: SharedState,
+    /// Config the bot started with, reloads may only change its risk limits
+    config: Config,
+    /// Config file reloads read
+    config_path: String,
+}
+
+type ApiError = (StatusCode, String);
+
+pub fn router(state: SharedState, config: &Config) -> Router {
+    routes(ApiState {
+        state,
+        config: config.clone(),
+        config_path: Config::path(),
+    })
+}
+
+fn routes(api: ApiState) -> Router {
+    Router::new()
+        .route("/pairs", get(pairs))
+        .route("/pairs/{pair}/trading", post(set_trading))
+        .route("/opportunities", get(opportunities))
+        .route("/balances", get(balances))
+        .route("/risk", get(risk))
+        .route("/kill-switch", post(kill_switch))
+        .route("/config/reload", post(reload_config))
+        .with_state(api)
+}
+
+/// Serves the control API on `config.control.listen` until `token` is cancelled
+pub async fn serve(
+    state: SharedState,
+    config: Config,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(&config.control.listen).await?;
+    info!(listen = %config.control.listen, "Serving control API");
+    axum::serve(listener, router(state, &config))
+        .with_graceful_shutdown(token.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+async fn pairs(State(api): State<ApiState>) -> Json<Vec<PairStatus>> {
+    let trading = api.state.controls.lock().await.trading_enabled(arb::PAIR);
+    let snapshot = api.state.market.load();
+    let (dex, cex) = (&snapshot.dex, &snapshot.cex);
+    let cex_age_ms = (cex.received_us > 0)
+        .then(|| ledger::unix_time_us().saturating_sub(cex.received_us) / 1_000);
+    let pools = match api.config.pair_mints() {
+        Ok(pair) => snapshot
+            .pools
+            .iter()
+            .filter(|(id, dex)| id.to_string() != api.config.pool && dex.trades(pair))
+            .map(|(id, dex)| PoolStatus {
+                pool: id.to_string(),
+                slot: dex.slot,
+                dex_in: dex.token_in,
+                dex_out: dex.token_out,
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    Json(vec![PairStatus {
+        pair: arb::PAIR.to_string(),
+        pool: api.config.pool.clone(),
+        trading,
+        slot: dex.slot,
+        dex_in: dex.token_in,
+        dex_out: dex.token_out,
+        best_bid: cex.best_bid,
+        best_ask: cex.best_ask,
+        cex_age_ms,
+        pools,
+    }])
+}
+
+async fn set_trading(
+    State(api): State<ApiState>,
+    Path(pair): Path<String>,
+    Json(toggle): Json<TradingToggle>,
+) -> Result<Json<TradingToggle>, ApiError> {
+    if pair != arb::PAIR {
+        return Err((StatusCode::NOT_FOUND, format!("Error: unknown pair {pair}")));
+    }
+    let mut controls = api.state.controls.lock().await;
+    if toggle.enabled {
+        controls.paused.remove(&pair);
+    } else {
+        controls.paused.insert(pair.clone());
+    }
+    info!(%pair, enabled = toggle.enabled, "Trading toggled over the control API");
+    Ok(Json(toggle))
+}
+
+async fn opportunities(State(api): State<ApiState>) -> Json<Vec<OpportunityStatus>> {
+    let controls = api.state.controls.lock().await;
+    Json(
+        controls
+            .opportunities
+            .iter()
+            .map(|(opportunity, decision)| OpportunityStatus {
+                opportunity: opportunity.clone(),
+                decision: decision.clone(),
+            })
+            .collect(),
+    )
+}
+
+async fn balances(State(api): State<ApiState>) -> Json<Vec<BalanceStatus>> {
+    let balances = api.state.balances.lock().await;
+    let mut entries: Vec<BalanceStatus> = balances
+        .iter()
+        .map(|(venue, asset, amount)| BalanceStatus {
+            venue,
+            asset,
+            amount,
+        })
+        .collect();
+    entries.sort_by_key(|b| (b.venue as u8, b.asset as u8));
+    Json(entries)
+}
+
+async fn risk(State(api): State<ApiState>) -> Json<RiskLimits> {
+    Json(api.state.controls.lock().await.risk_limits.clone())
+}
+
+async fn kill_switch(
+    State(api): State<ApiState>,
+    Json(kill_switch): Json<KillSwitch>,
+) -> Json<KillSwitch> {
+    api.state.controls.lock().await.kill_switch = kill_switch.engaged;
+    info!(
+        engaged = kill_switch.engaged,
+        "Kill switch set over the control API"
+    );
+    Json(kill_switch)
+}
+
+/// Reloads the config file the way the arb loop reloads it on edits: only its risk limits are
+/// taken, the other changed settings are listed and wait for a restart.
+async fn reload_config(State(api): State<ApiState>) -> Result<Json<ConfigReload>, ApiError> {
+    let config = Config::load_from(&api.config_path)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut running = api.config.clone();
+    let restart = running.reload(config.clone());
+    api.state.controls.lock().await.pending_config = Some(config);
+    info!(?restart, "Config reload requested over the control API");
+    Ok(Json(ConfigReload {
+        risk: running.risk,
+        restart: restart.into_iter().map(String::from).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        raydium_amm::tests::amm_dex,
+    };
+
+    #[tokio::test]
+    async fn kill_switch_and_pause_reach_the_arb_loop() {
+        let state = SharedState::default();
+        // a tracked pool of the pair at 150 USDC/SOL and one of another pair
+        let other_id = Pubkey::new_unique();
+        let mut other = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 150_000_000_000),
+            7,
+        );
+        (other.token_in, other.token_out) = (1_000_000_000, Some(149_475_897));
+        state.market.publish_pool(other_id, Arc::new(other));
+        state.market.publish_pool(
+            Pubkey::new_unique(),
+            Arc::new(amm_dex((WSOL_MINT, Pubkey::new_unique()), (1, 1), 7)),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = router(state.clone(), &Config::default());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let client = reqwest::Client::new();
+        let resp = client
+            .post(format!("{url}/kill-switch"))
+            .json(&KillSwitch { engaged: true })
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let resp = client
+            .post(format!("{url}/pairs/{}/trading", arb::PAIR))
+            .json(&TradingToggle { enabled: false })
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let resp = client
+            .post(format!("{url}/pairs/BTC_USDC/trading"))
+            .json(&TradingToggle { enabled: false })
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::NOT_FOUND);
+
+        let controls = state.controls.lock().await;
+        assert!(!controls.trading_enabled(arb::PAIR));
+        assert!(
+            controls
+                .effective_limits(&RiskLimits::default())
+                .kill_switch
+        );
+        drop(controls);
+
+        let pairs: Vec<PairStatus> = client
+            .get(format!("{url}/pairs"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert!(!pairs[0].trading);
+        assert_eq!(pairs[0].pools.len(), 1);
+        assert_eq!(pairs[0].pools[0].pool, other_id.to_string());
+        assert_eq!(pairs[0].pools[0].slot, 7);
+        assert_eq!(pairs[0].pools[0].dex_out, Some(149_475_897));
+    }
+
+    #[tokio::test]
+    async fn reload_takes_the_limits_and_lists_restart_changes() {
+        let state = SharedState::default();
+        let config_path = std::env::temp_dir()
+            .join(format!("config-test-{}.json", uuid::Uuid::new_v4()))
+            .to_string_lossy()
+            .into_owned();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = routes(ApiState {
+            state: state.clone(),
+            config: Config::default(),
+            config_path: config_path.clone(),
+        });
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        std::fs::write(
+            &config_path,
+            r#"{"trade_size": 2000000000, "risk": {"kill_switch": true}}"#,
+        )
+        .unwrap();
+        let resp = reqwest::Client::new()
+            .post(format!("{url}/config/reload"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), reqwest::StatusCode::OK);
+        let reload: ConfigReload = resp.json().await.unwrap();
+        assert!(reload.risk.kill_switch);
+        assert_eq!(reload.restart, ["trade_size"]);
+
+        let pending = state.controls.lock().await.pending_config.take().unwrap();
+        assert!(pending.risk.kill_switch);
+        std::fs::remove_file(&config_path).unwrap();
+    }
+}
@@ -0,0 +1,275 @@
+//! Cross-DEX arbitrage between pools of the same pair: buy SOL from the pool where it's cheapest
+//! and sell it into the one where it's dearest. Both swaps go into one transaction, so either
+//! both land or neither does and there's no leg risk.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+
+use crate::{
+    balances::{Asset, Balances, Venue},
+    market::DexStruct,
+    venue::{PoolType, SwapParams},
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct CrossDexConfig {
+    /// Other pools to track. Those of the configured pair are compared with each other and the
+    /// configured pool, all of them feed route search.
+    pub pools: Vec<TrackedPool>,
+    /// Largest size searched, in lamports
+    pub max_size: u64,
+    /// Resolution of the size search, in lamports
+    pub size_step: u64,
+}
+
+impl Default for CrossDexConfig {
+    fn default() -> Self {
+        Self {
+            pools: Vec::new(),
+            max_size: 10_000_000_000,
+            size_step: 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TrackedPool {
+    pub pool: String,
+    #[serde(default)]
+    pub pool_type: PoolType,
+}
+
+/// Buy `base_amount` lamports from `buy_pool` and sell them into `sell_pool`, in one transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrossDexOpportunity {
+    pub buy_pool: Pubkey,
+    pub sell_pool: Pubkey,
+    pub base_amount: u64, // lamports
+    pub cost: u64,        // micro-USDC paid into the buy pool
+    pub proceeds: u64,    // micro-USDC out of the sell pool
+    pub spread: i64,
+    /// Minimum output of the sell swap: the cost plus the required profit, so the transaction
+    /// fails on chain rather than land at a loss
+    pub min_proceeds: u64,
+    /// Latest slot of the two pools
+    pub slot: u64,
+}
+
+impl CrossDexOpportunity {
+    /// The two swaps signed by `user`: an exact out buy from `buy` paying at most `cost`, then an
+    /// exact in sell of the SOL bought into `sell` for at least `min_proceeds`
+    pub fn swaps(&self, buy: &DexStruct, sell: &DexStruct, user: Pubkey) -> [SwapParams; 2] {
+        [
+            SwapParams {
+                user,
+                amount: self.base_amount,
+                other_amount_threshold: self.cost,
+                zero_for_one: buy.zero_for_one(false),
+                is_base_input: false,
+            },
+            SwapParams {
+                user,
+                amount: self.base_amount,
+                other_amount_threshold: self.min_proceeds,
+                zero_for_one: sell.zero_for_one(true),
+                is_base_input: true,
+            },
+        ]
+    }
+
+    /// Instructions of the atomic transaction candidate, buy swap first
+    pub fn instructions(
+        &self,
+        buy: &DexStruct,
+        sell: &DexStruct,
+        user: Pubkey,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        let [buy_swap, sell_swap] = self.swaps(buy, sell, user);
+        Ok(vec![
+            buy.pool.swap_instruction(&self.buy_pool, &buy_swap)?,
+            sell.pool.swap_instruction(&self.sell_pool, &sell_swap)?,
+        ])
+    }
+}
+
+/// Checks every ordered pair of `pools` for a profitable round trip. The size is searched on
+/// both curves together, up to `config.max_size` and what the wallet's USDC can buy. Only
+/// opportunities whose spread clears `min_profit_bps` of their cost are returned, best first.
+pub fn detect(
+    pools: &[(Pubkey, &DexStruct)],
+    balances: &Balances,
+    config: &CrossDexConfig,
+    min_profit_bps: u64,
+) -> Vec<CrossDexOpportunity> {
+    let mut found = Vec::new();
+    for (buy_pool, buy) in pools {
+        for (sell_pool, sell) in pools {
+            if buy_pool == sell_pool {
+                continue;
+            }
+            let Some((size, cost, proceeds)) = best_size(buy, sell, balances, config) else {
+                continue;
+            };
+            let min_proceeds =
+                cost + (cost as u128 * min_profit_bps as u128).div_ceil(10_000) as u64;
+            if proceeds <= cost || proceeds < min_proceeds {
+                continue;
+            }
+            found.push(CrossDexOpportunity {
+                buy_pool: *buy_pool,
+                sell_pool: *sell_pool,
+                base_amount: size,
+                cost,
+                proceeds,
+                spread: proceeds as i64 - cost as i64,
+                min_proceeds,
+                slot: buy.slot.max(sell.slot),
+            });
+        }
+    }
+    found.sort_by_key(|opp| std::cmp::Reverse(opp.spread));
+    found
+}
+
+/// Size with the largest spread buying from `buy` and selling into `sell`, with its cost and
+/// proceeds. The spread is concave in the size (each swap moves its pool's price against the
+/// trade), so a ternary search over multiples of `size_step` finds it.
+fn best_size(
+    buy: &DexStruct,
+    sell: &DexStruct,
+    balances: &Balances,
+    config: &CrossDexConfig,
+) -> Option<(u64, u64, u64)> {
+    let step = config.size_step.max(1);
+    let mut max_size = config.max_size;
+    if let Some(usdc) = balances.available(Venue::Wallet, Asset::Usdc) {
+        let affordable = buy.simulate(usdc, false, true).ok()?.amount_out;
+        max_size = max_size.min(affordable);
+    }
+    // spread of `steps` size steps, `None` past the liquidity either pool has cached
+    let quote = |steps: u64| -> Option<(i128, u64, u64)> {
+        let size = steps * step;
+        let bought = buy
+            .simulate(size, false, false)
+            .ok()
+            .filter(|s| s.complete)?;
+        let sold = sell
+            .simulate(size, true, true)
+            .ok()
+            .filter(|s| s.complete)?;
+        let spread = sold.amount_out as i128 - bought.amount_in as i128;
+        Some((spread, bought.amount_in, sold.amount_out))
+    };
+    let spread = |steps| quote(steps).map_or(i128::MIN, |(spread, _, _)| spread);
+
+    let (mut lo, mut hi) = (1, max_size / step);
+    if hi < lo || spread(lo) <= 0 {
+        return None;
+    }
+    while hi - lo > 2 {
+        let third = (hi - lo) / 3;
+        let (m1, m2) = (lo + third, hi - third);
+        if spread(m1) < spread(m2) {
+            lo = m1 + 1;
+        } else {
+            hi = m2;
+        }
+    }
+    let best = (lo..=hi).max_by_key(|&steps| spread(steps))?;
+    let (_, cost, proceeds) = quote(best)?;
+    Some((best * step, cost, proceeds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        raydium_amm::{AMM_V4_PROGRAM_ID, tests::amm_dex},
+    };
+
+    #[test]
+    fn sizes_the_round_trip_on_both_curves() {
+        let (cheap_id, dear_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        // 1000 SOL at 150 and at 153 USDC
+        let cheap = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 150_000_000_000),
+            10,
+        );
+        let dear = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 153_000_000_000),
+            12,
+        );
+        let pools = [(cheap_id, &cheap), (dear_id, &dear)];
+        let config = CrossDexConfig {
+            max_size: 100_000_000_000,
+            ..Default::default()
+        };
+        let found = detect(&pools, &Balances::default(), &config, 10);
+        assert_eq!(found.len(), 1);
+        let opp = &found[0];
+        assert_eq!(
+            (opp.buy_pool, opp.sell_pool, opp.slot),
+            (cheap_id, dear_id, 12)
+        );
+        assert_eq!(opp.spread, opp.proceeds as i64 - opp.cost as i64);
+        assert!(opp.min_proceeds > opp.cost && opp.min_proceeds <= opp.proceeds);
+
+        // the marginal prices meet net of both fees at about 3.7 SOL
+        assert!((3_500_000_000..4_000_000_000).contains(&opp.base_amount));
+        let round_trip = |size| {
+            let cost = cheap.simulate(size, false, false).unwrap().amount_in;
+            dear.simulate(size, true, true).unwrap().amount_out as i64 - cost as i64
+        };
+        assert!(opp.spread >= round_trip(opp.base_amount - config.size_step));
+        assert!(opp.spread >= round_trip(opp.base_amount + config.size_step));
+
+        // a wallet short of USDC caps the size
+        let mut balances = Balances::default();
+        balances.set(Venue::Wallet, Asset::Usdc, 300_000_000);
+        let capped = &detect(&pools, &balances, &config, 10)[0];
+        assert!(capped.base_amount < 2_000_000_000 && capped.cost <= 300_000_000);
+    }
+
+    #[test]
+    fn builds_both_swaps_into_one_candidate() {
+        let cheap = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 150_000_000_000),
+            1,
+        );
+        let dear = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 153_000_000_000),
+            1,
+        );
+        let opp = CrossDexOpportunity {
+            buy_pool: Pubkey::new_unique(),
+            sell_pool: Pubkey::new_unique(),
+            base_amount: 1_000_000_000,
+            cost: 150_500_000,
+            proceeds: 152_600_000,
+            spread: 2_100_000,
+            min_proceeds: 150_651_000,
+            slot: 1,
+        };
+        let user = Pubkey::new_unique();
+        let instructions = opp.instructions(&cheap, &dear, user).unwrap();
+        assert_eq!(instructions.len(), 2);
+        assert!(
+            instructions
+                .iter()
+                .all(|ix| ix.program_id == AMM_V4_PROGRAM_ID)
+        );
+        assert_eq!(instructions[0].accounts[1].pubkey, opp.buy_pool);
+        assert_eq!(instructions[1].accounts[1].pubkey, opp.sell_pool);
+        // swap_base_out(max in, amount out), then swap_base_in(amount in, min out)
+        assert_eq!(instructions[0].data[1..9], 150_500_000u64.to_le_bytes());
+        assert_eq!(instructions[0].data[9..17], 1_000_000_000u64.to_le_bytes());
+        assert_eq!(instructions[1].data[1..9], 1_000_000_000u64.to_le_bytes());
+        assert_eq!(instructions[1].data[9..17], 150_651_000u64.to_le_bytes());
+    }
+}
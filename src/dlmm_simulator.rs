@@ -0,0 +1,311 @@
+//! Meteora DLMM swap simulation. A swap fills the active bin at its fixed price, then moves one
+//! bin at a time in the swap direction. Every bin is charged the base fee plus the variable fee
+//! for its distance from the reference bin, like the program does.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    clmm_simulator::{PoolPrice, SwapResult},
+    meteora_dlmm::{Bin, BinArray, FEE_PRECISION, LbPair, get_price_from_id},
+    raydium_math::tick_math,
+};
+
+/// Bin arrays of a pair keyed by their index
+pub type DlmmBinArrays = BTreeMap<i64, BinArray>;
+
+/// How many bin arrays on each side of the active one are kept cached
+pub const BIN_ARRAY_WINDOW: i64 = 2;
+
+/// Simulates a swap against `pair` using the bins in `bin_arrays` at `now` (unix seconds),
+/// `swap_for_y` sells token X. Swaps that run into a bin array with liquidity that isn't cached
+/// (or past the pair's bin range) are incomplete.
+pub fn simulate_swap(
+    pair: &LbPair,
+    bin_arrays: &DlmmBinArrays,
+    amount_specified: u64,
+    swap_for_y: bool,
+    is_base_input: bool,
+    now: i64,
+) -> anyhow::Result<SwapResult> {
+    if pair.status != 0 {
+        return Err(anyhow::Error::msg("Error: swaps are disabled on the pair"));
+    }
+    let (volatility_reference, index_reference) = pair.references_at(now);
+    let mut active_id = pair.active_id;
+    let mut remaining = amount_specified;
+    let (mut amount_in, mut amount_out, mut fee_amount) = (0u64, 0u64, 0u64);
+    let mut bins_crossed = 0;
+    let mut complete = true;
+
+    while remaining > 0 {
+        let bin = match find_bin(pair, bin_arrays, active_id) {
+            Lookup::Bin(bin) => Some(bin),
+            Lookup::Empty => None,
+            Lookup::Unknown => {
+                complete = false;
+                break;
+            }
+        };
+        if let Some(bin) = bin.filter(|bin| output_reserve(bin, swap_for_y) > 0) {
+            let price = get_price_from_id(active_id, pair.bin_step)
+                .ok_or_else(|| anyhow::Error::msg("Error: bin price out of range"))?;
+            let accumulator =
+                pair.volatility_accumulator(volatility_reference, index_reference, active_id);
+            let fee_rate = pair.total_fee_rate(accumulator);
+            let step = if is_base_input {
+                swap_exact_in(bin, price, fee_rate, remaining, swap_for_y)
+            } else {
+                swap_exact_out(bin, price, fee_rate, remaining, swap_for_y)
+            };
+            amount_in += step.amount_in + step.fee;
+            amount_out += step.amount_out;
+            fee_amount += step.fee;
+            remaining -= if is_base_input {
+                step.amount_in + step.fee
+            } else {
+                step.amount_out
+            };
+        }
+        if remaining == 0 {
+            break;
+        }
+        active_id += if swap_for_y { -1 } else { 1 };
+        bins_crossed += 1;
+    }
+
+    let price = get_price_from_id(active_id, pair.bin_step).unwrap_or_default();
+    let sqrt_price_x64 = price.isqrt() << 32;
+    Ok(SwapResult {
+        amount_in,
+        amount_out,
+        fee_amount,
+        sqrt_price_x64,
+        tick: tick_math::get_tick_at_sqrt_price(sqrt_price_x64).unwrap_or_default(),
+        ticks_crossed: bins_crossed,
+        complete,
+    })
+}
+
+/// Current price of `pair` in concentrated liquidity terms for logs and the journal. The
+/// liquidity is the value of the active bin in token Y, zero when its array isn't cached.
+pub fn pair_price(pair: &LbPair, bin_arrays: &DlmmBinArrays) -> Option<PoolPrice> {
+    let price = get_price_from_id(pair.active_id, pair.bin_step)?;
+    let sqrt_price_x64 = price.isqrt() << 32;
+    let liquidity = match find_bin(pair, bin_arrays, pair.active_id) {
+        Lookup::Bin(bin) => ((u128::from(bin.amount_x) * price) >> 64) + u128::from(bin.amount_y),
+        _ => 0,
+    };
+    Some(PoolPrice {
+        sqrt_price_x64,
+        liquidity,
+        tick: tick_math::get_tick_at_sqrt_price(sqrt_price_x64).ok()?,
+    })
+}
+
+/// Indexes of the bin arrays around the active one that hold liquidity and aren't cached yet
+pub fn missing_bin_arrays(pair: &LbPair, bin_arrays: &DlmmBinArrays) -> Vec<i64> {
+    let active = BinArray::bin_id_to_index(pair.active_id);
+    (active - BIN_ARRAY_WINDOW..=active + BIN_ARRAY_WINDOW)
+        .filter(|index| pair.bin_array_has_liquidity(*index) == Some(true))
+        .filter(|index| !bin_arrays.contains_key(index))
+        .collect()
+}
+
+enum Lookup<'a> {
+    Bin(&'a Bin),
+    /// In a bin array the pair marks as empty
+    Empty,
+    /// Not cached, or out of the pair's range
+    Unknown,
+}
+
+fn find_bin<'a>(pair: &LbPair, bin_arrays: &'a DlmmBinArrays, bin_id: i32) -> Lookup<'a> {
+    if bin_id < pair.parameters.min_bin_id || bin_id > pair.parameters.max_bin_id {
+        return Lookup::Unknown;
+    }
+    let index = BinArray::bin_id_to_index(bin_id);
+    match bin_arrays.get(&index).and_then(|array| array.bin(bin_id)) {
+        Some(bin) => Lookup::Bin(bin),
+        None if pair.bin_array_has_liquidity(index) == Some(false) => Lookup::Empty,
+        None => Lookup::Unknown,
+    }
+}
+
+fn output_reserve(bin: &Bin, swap_for_y: bool) -> u64 {
+    if swap_for_y {
+        bin.amount_y
+    } else {
+        bin.amount_x
+    }
+}
+
+/// Amounts of one bin: `amount_in` excludes the fee
+struct BinStep {
+    amount_in: u64,
+    amount_out: u64,
+    fee: u64,
+}
+
+/// Input that takes `amount_out` out of a bin at `price`, rounded up
+fn amount_in_for(amount_out: u64, price: u128, swap_for_y: bool) -> u64 {
+    let amount_in = if swap_for_y {
+        (u128::from(amount_out) << 64).div_ceil(price)
+    } else {
+        (u128::from(amount_out) * price).div_ceil(1 << 64)
+    };
+    amount_in.min(u128::from(u64::MAX)) as u64
+}
+
+/// Output of `amount_in` (fees taken) in a bin at `price`, rounded down
+fn amount_out_for(amount_in: u64, price: u128, swap_for_y: bool) -> u64 {
+    let amount_out = if swap_for_y {
+        (u128::from(amount_in) * price) >> 64
+    } else {
+        (u128::from(amount_in) << 64) / price
+    };
+    amount_out.min(u128::from(u64::MAX)) as u64
+}
+
+/// Fee on top of `amount`, rounded up
+fn fee_on(amount: u64, fee_rate: u64) -> u64 {
+    (u128::from(amount) * u128::from(fee_rate)).div_ceil(u128::from(FEE_PRECISION - fee_rate))
+        as u64
+}
+
+/// Fee included in `amount`, rounded up
+fn fee_of(amount: u64, fee_rate: u64) -> u64 {
+    (u128::from(amount) * u128::from(fee_rate)).div_ceil(u128::from(FEE_PRECISION)) as u64
+}
+
+fn swap_exact_in(bin: &Bin, price: u128, fee_rate: u64, amount: u64, swap_for_y: bool) -> BinStep {
+    let max_out = output_reserve(bin, swap_for_y);
+    let max_in = amount_in_for(max_out, price, swap_for_y);
+    let max_fee = fee_on(max_in, fee_rate);
+    if u128::from(amount) > u128::from(max_in) + u128::from(max_fee) {
+        return BinStep {
+            amount_in: max_in,
+            amount_out: max_out,
+            fee: max_fee,
+        };
+    }
+    let fee = fee_of(amount, fee_rate);
+    let amount_out = amount_out_for(amount - fee, price, swap_for_y).min(max_out);
+    BinStep {
+        amount_in: amount - fee,
+        amount_out,
+        fee,
+    }
+}
+
+fn swap_exact_out(bin: &Bin, price: u128, fee_rate: u64, amount: u64, swap_for_y: bool) -> BinStep {
+    let amount_out = amount.min(output_reserve(bin, swap_for_y));
+    let amount_in = amount_in_for(amount_out, price, swap_for_y);
+    BinStep {
+        amount_in,
+        amount_out,
+        fee: fee_on(amount_in, fee_rate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::meteora_dlmm::tests::{bin_array_data, lb_pair};
+
+    /// One cached bin array with `bins` (bin id, amount x, amount y) filled
+    fn cached(index: i64, bins: &[(i32, u64, u64)]) -> DlmmBinArrays {
+        let data = bin_array_data(&Pubkey::default(), index, bins);
+        DlmmBinArrays::from([(index, BinArray::decode(&data).unwrap())])
+    }
+
+    #[test]
+    fn walks_bins_with_the_dynamic_fee() {
+        // 1_000 USDC in each of the active bin and the next two bins down
+        let pair = lb_pair(-1_897, 10, &[-28]);
+        let usdc = 1_000_000_000;
+        let bin_arrays = cached(
+            -28,
+            &[(-1_897, 0, usdc), (-1_898, 0, usdc), (-1_899, 0, usdc)],
+        );
+
+        // inside the active bin only the base fee applies
+        let small = simulate_swap(&pair, &bin_arrays, 1_000_000_000, true, true, 0).unwrap();
+        assert!(small.complete);
+        assert_eq!(small.ticks_crossed, 0);
+        assert_eq!(small.fee_amount, 1_000_000);
+        let price = get_price_from_id(-1_897, 10).unwrap();
+        assert_eq!(small.amount_out, ((999_000_000u128 * price) >> 64) as u64);
+
+        // crossing into lower bins adds the variable fee of their distance to the reference
+        let large = simulate_swap(&pair, &bin_arrays, 15_000_000_000, true, true, 0).unwrap();
+        assert!(large.complete);
+        assert_eq!(large.ticks_crossed, 2);
+        assert!(large.fee_amount > 15_000_000);
+        let exact_out =
+            simulate_swap(&pair, &bin_arrays, large.amount_out, true, false, 0).unwrap();
+        assert_eq!(exact_out.amount_out, large.amount_out);
+        // the exact in output is rounded down, by less than a micro-USDC worth of lamports
+        assert!(exact_out.amount_in <= large.amount_in);
+        assert!(large.amount_in - exact_out.amount_in < 7);
+
+        // more than the cached bins hold: the next array has liquidity but isn't cached
+        let pair = lb_pair(-1_897, 10, &[-28, -29]);
+        let too_large = simulate_swap(&pair, &bin_arrays, 100_000_000_000, true, true, 0).unwrap();
+        assert!(!too_large.complete);
+        assert_eq!(too_large.amount_out, 3 * usdc);
+        assert_eq!(missing_bin_arrays(&pair, &bin_arrays), vec![-29]);
+    }
+
+    #[test]
+    fn buys_x_walking_bins_up() {
+        // 10 SOL in each of the active bin and the next two bins up
+        let pair = lb_pair(-1_897, 10, &[-28, -27]);
+        let sol = 10_000_000_000;
+        let bin_arrays = cached(-28, &[(-1_897, sol, 0), (-1_896, sol, 0), (-1_895, sol, 0)]);
+
+        let small = simulate_swap(&pair, &bin_arrays, 150_000_000, false, true, 0).unwrap();
+        assert!(small.complete);
+        assert_eq!(small.ticks_crossed, 0);
+        assert_eq!(small.fee_amount, 150_000);
+        let price = get_price_from_id(-1_897, 10).unwrap();
+        assert_eq!(small.amount_out, ((149_850_000u128 << 64) / price) as u64);
+
+        // crossing upwards the price rises, so each lamport costs more
+        let large = simulate_swap(&pair, &bin_arrays, 3_000_000_000, false, true, 0).unwrap();
+        assert!(large.complete);
+        assert_eq!(large.ticks_crossed, 1);
+        assert!(large.amount_out < 20 * small.amount_out);
+        assert!(large.sqrt_price_x64 > pair_price(&pair, &bin_arrays).unwrap().sqrt_price_x64);
+        let exact_out =
+            simulate_swap(&pair, &bin_arrays, large.amount_out, false, false, 0).unwrap();
+        assert_eq!(exact_out.amount_out, large.amount_out);
+        assert!(exact_out.amount_in <= large.amount_in);
+        assert!(large.amount_in - exact_out.amount_in < 2);
+
+        // bins past the cached array can't be quoted
+        let too_large = simulate_swap(&pair, &bin_arrays, 10_000_000_000, false, true, 0).unwrap();
+        assert!(!too_large.complete);
+        assert_eq!(too_large.amount_out, 3 * sol);
+    }
+
+    #[test]
+    fn stale_volatility_decays_before_the_swap() {
+        let mut pair = lb_pair(-1_897, 10, &[-28]);
+        pair.v_parameters.volatility_accumulator = 100_000;
+        pair.v_parameters.volatility_reference = 100_000;
+        pair.v_parameters.last_update_timestamp = 1_000;
+        let usdc = 1_000_000_000;
+        let bin_arrays = cached(-28, &[(-1_897, 0, usdc)]);
+
+        // within the filter period the reference still counts, after the decay period it's gone
+        let fresh = simulate_swap(&pair, &bin_arrays, 1_000_000_000, true, true, 1_010).unwrap();
+        let stale = simulate_swap(&pair, &bin_arrays, 1_000_000_000, true, true, 2_000).unwrap();
+        assert!(fresh.fee_amount > stale.fee_amount);
+        assert_eq!(stale.fee_amount, 1_000_000);
+
+        pair.status = 1;
+        assert!(simulate_swap(&pair, &bin_arrays, 1, true, true, 2_000).is_err());
+    }
+}
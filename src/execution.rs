@@ -0,0 +1,196 @@
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    arb::Opportunity,
+    balances::Balances,
+    config::Config,
+    ledger::{self, Ledger, RoundTrip, SECS_PER_DAY},
+    market::{CexStruct, DexStruct},
+    paper::PaperExecutor,
+    risk::{RejectReason, RiskEngine},
+};
+
+/// Legs of a CEX-DEX trade, one per venue
+const CEX_DEX_LEGS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    /// Only log opportunities
+    #[default]
+    LogOnly,
+    /// Fill opportunities against local market state with virtual balances
+    Paper,
+}
+
+/// What was done with an opportunity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "outcome", rename_all = "snake_case")]
+pub enum Decision {
+    /// Log-only mode, nothing was executed
+    Logged,
+    Rejected {
+        reason: RejectReason,
+    },
+    Executed {
+        trade: RoundTrip,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Logged,
+    Rejected,
+    Executed,
+    Failed,
+}
+
+impl Decision {
+    pub fn outcome(&self) -> Outcome {
+        match self {
+            Decision::Logged => Outcome::Logged,
+            Decision::Rejected { .. } => Outcome::Rejected,
+            Decision::Executed { .. } => Outcome::Executed,
+            Decision::Failed { .. } => Outcome::Failed,
+        }
+    }
+}
+
+/// Decides what happens to each opportunity coming out of detection
+pub struct ExecutionCoordinator {
+    pub mode: ExecutionMode,
+    pub paper: PaperExecutor,
+    pub risk: RiskEngine,
+    pub ledger: Ledger,
+    /// Print fills and rejections as they happen
+    pub log_fills: bool,
+    /// Stamp of the snapshot the last detection pass ran on
+    pub last_pass: Option<(u64, u64, u64)>,
+}
+
+impl ExecutionCoordinator {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            mode: config.execution_mode,
+            paper: PaperExecutor::new(config.paper_balances.clone(), config.taker_fee_bps()),
+            risk: RiskEngine::new(config.risk.clone()),
+            ledger: Ledger::default(),
+            log_fills: true,
+            last_pass: None,
+        }
+    }
+
+    /// Starts a detection pass on the snapshot with `stamp`. False when the last pass already
+    /// ran on it, its opportunities were handled then.
+    pub fn new_pass(&mut self, stamp: (u64, u64, u64)) -> bool {
+        self.last_pass.replace(stamp) != Some(stamp)
+    }
+
+    pub fn handle(
+        &mut self,
+        opp: &Opportunity,
+        dex: &DexStruct,
+        cex: &CexStruct,
+        balances: &mut Balances,
+    ) -> Decision {
+        let now_us = ledger::unix_time_us();
+        match self.approve(opp, now_us) {
+            None => self.execute(opp, dex, cex, balances, now_us),
+            Some(decision) => decision,
+        }
+    }
+
+    /// Runs `opp` through the risk checks and marks its legs as in flight. Returns the final
+    /// decision for opportunities that won't be executed, `None` once approved.
+    pub fn approve(&mut self, opp: &Opportunity, now_us: u64) -> Option<Decision> {
+        if self.mode == ExecutionMode::LogOnly {
+            return Some(Decision::Logged);
+        }
+        if let Err(reason) = self.risk.check(opp, CEX_DEX_LEGS, &opp.exposure(), now_us) {
+            if self.log_fills {
+                warn!(
+                    direction = ?opp.direction,
+                    %reason,
+                    rejections = self.risk.rejections[&reason],
+                    "⛔ Risk rejected"
+                );
+            }
+            return Some(Decision::Rejected { reason });
+        }
+        self.risk.open_legs(CEX_DEX_LEGS, now_us);
+        None
+    }
+
+    /// Executes an approved opportunity against `dex` and `cex` and books the result
+    pub fn execute(
+        &mut self,
+        opp: &Opportunity,
+        dex: &DexStruct,
+        cex: &CexStruct,
+        balances: &mut Balances,
+        now_us: u64,
+    ) -> Decision {
+        let decision = match self.paper.execute(opp, dex, cex) {
+            Ok(trade) => {
+                *balances = Balances::from(&self.paper.balances);
+                let now = now_us / 1_000_000;
+                self.report_finished_day(now);
+
+                let mid = cex.book.mid().unwrap_or_default();
+                let (buy, sell) = trade.legs();
+                let trip = self
+                    .ledger
+                    .record(trade.direction, buy, sell, mid, now)
+                    .clone();
+                self.risk
+                    .on_fill(trip.realized_pnl, &trade.imbalances(), now_us);
+                if self.log_fills {
+                    info!(
+                        direction = ?trade.direction,
+                        size = trade.base_amount,
+                        buy_price = trip.buy.price(),
+                        sell_price = trip.sell.price(),
+                        fees = trip.fees,
+                        realized_pnl = trip.realized_pnl,
+                        total_realized_pnl = self.ledger.realized_pnl,
+                        unrealized_pnl = self.ledger.unrealized_pnl(mid),
+                        balances = ?self.paper.balances,
+                        "📝 Paper fill"
+                    );
+                }
+                Decision::Executed { trade: trip }
+            }
+            Err(e) => {
+                if self.log_fills {
+                    warn!("Paper fill rejected: {e}");
+                }
+                Decision::Failed {
+                    error: e.to_string(),
+                }
+            }
+        };
+        self.risk.close_legs(CEX_DEX_LEGS);
+        decision
+    }
+
+    /// Prints the summary of the last trading day once a fill lands on a new day
+    fn report_finished_day(&self, now: u64) {
+        if !self.log_fills {
+            return;
+        }
+        let Some(last) = self.ledger.round_trips.last() else {
+            return;
+        };
+        if last.timestamp / SECS_PER_DAY == now / SECS_PER_DAY {
+            return;
+        }
+        if let Some(summary) = self.ledger.daily_summaries().pop() {
+            info!(?summary, "📊 Daily summary");
+        }
+    }
+}
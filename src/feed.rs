@@ -0,0 +1,346 @@
+//! Market data feeds. The pipeline only talks to these traits, so it runs the same way on the
+//! Yellowstone stream and the exchange API as on a scripted sequence in tests.
+
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+#[cfg(test)]
+use std::collections::VecDeque;
+
+use futures::{Sink, SinkExt, Stream, StreamExt, channel::mpsc::SendError};
+use solana_sdk::pubkey::Pubkey;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, tungstenite::Message};
+use tracing::debug;
+use yellowstone_grpc_client::{ClientTlsConfig, GeyserGrpcClient};
+use yellowstone_grpc_proto::geyser::{
+    SubscribeRequest, SubscribeRequestFilterAccounts, SubscribeRequestFilterAccountsFilter,
+    SubscribeRequestFilterAccountsFilterMemcmp, SubscribeUpdate,
+    subscribe_request_filter_accounts_filter::Filter,
+    subscribe_request_filter_accounts_filter_memcmp::Data, subscribe_update::UpdateOneof,
+};
+
+use crate::{
+    balances::WalletAccounts,
+    order_book::OrderBook,
+    rpc::{self, Account},
+    venue::AccountFilter,
+};
+
+/// Wait before reconnecting a book stream that couldn't connect
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Prefix of the subscription filters of the pools, replaced together on each `subscribe`
+const POOL_FILTER_PREFIX: &str = "pool";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountUpdate {
+    pub slot: u64,
+    pub pubkey: Pubkey,
+    /// Program owning the account, to route the update to the pools subscribed to it
+    pub owner: Pubkey,
+    pub write_version: u64,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+}
+
+pub trait DexFeed {
+    /// Next account update, `None` once the feed has ended
+    async fn next_update(&mut self) -> Option<anyhow::Result<AccountUpdate>>;
+
+    /// Current state of `pubkeys`, for accounts the stream only reports when they change
+    async fn fetch_accounts(&mut self, pubkeys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>>;
+
+    /// Streams the accounts matching `filters` from now on, replacing the previous pool filters.
+    /// Several pools share one stream by subscribing to the union of their filters.
+    async fn subscribe(&mut self, filters: &[AccountFilter]) -> anyhow::Result<()>;
+}
+
+pub trait CexFeed {
+    /// Next full order book, `None` once the feed has ended. The future is `Send` so feeds of
+    /// any exchange can be run from generic code.
+    fn next_book(&mut self) -> impl Future<Output = Option<anyhow::Result<OrderBook>>> + Send;
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, tonic::Status>> + Send>>;
+type RequestSink = Pin<Box<dyn Sink<SubscribeRequest, Error = SendError> + Send>>;
+
+/// Yellowstone gRPC account stream for the wallet and the accounts the pools' venues subscribe
+/// to, with RPC for snapshots
+pub struct GeyserFeed {
+    rpc_url: String,
+    // the subscription is closed once the request side is dropped
+    requests: RequestSink,
+    /// Current subscription, a new request replaces it as a whole
+    subscription: SubscribeRequest,
+    updates: UpdateStream,
+}
+
+impl GeyserFeed {
+    pub async fn connect(
+        grpc_url: &str,
+        rpc_url: &str,
+        wallet: Option<&WalletAccounts>,
+    ) -> anyhow::Result<Self> {
+        let tls_config = ClientTlsConfig::new().with_native_roots();
+        let mut client = GeyserGrpcClient::build_from_shared(grpc_url.to_string())?
+            .keep_alive_while_idle(true)
+            .tls_config(tls_config)?
+            .connect()
+            .await?;
+
+        let mut accounts: HashMap<String, SubscribeRequestFilterAccounts> = HashMap::new();
+        if let Some(wallet) = wallet {
+            let wallet_filter = SubscribeRequestFilterAccounts {
+                account: wallet.pubkeys().iter().map(|k| k.to_string()).collect(),
+                ..Default::default()
+            };
+            accounts.insert("wallet".to_string(), wallet_filter);
+        }
+        let subscription = SubscribeRequest {
+            accounts,
+            ..Default::default()
+        };
+        let (requests, updates) = client
+            .subscribe_with_request(Some(subscription.clone()))
+            .await?;
+
+        Ok(Self {
+            rpc_url: rpc_url.to_string(),
+            requests: Box::pin(requests),
+            subscription,
+            updates: Box::pin(updates),
+        })
+    }
+}
+
+impl DexFeed for GeyserFeed {
+    async fn next_update(&mut self) -> Option<anyhow::Result<AccountUpdate>> {
+        loop {
+            let message = match self.updates.next().await? {
+                Ok(message) => message,
+                Err(e) => return Some(Err(e.into())),
+            };
+            let Some(UpdateOneof::Account(update)) = message.update_oneof else {
+                continue;
+            };
+            let Some(account) = update.account else {
+                continue;
+            };
+            let (Ok(pubkey), Ok(owner)) = (
+                Pubkey::try_from(account.pubkey.as_slice()),
+                Pubkey::try_from(account.owner.as_slice()),
+            ) else {
+                continue;
+            };
+            return Some(Ok(AccountUpdate {
+                slot: update.slot,
+                pubkey,
+                owner,
+                write_version: account.write_version,
+                lamports: account.lamports,
+                data: account.data,
+            }));
+        }
+    }
+
+    async fn fetch_accounts(&mut self, pubkeys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>> {
+        rpc::get_multiple_accounts(&self.rpc_url, pubkeys).await
+    }
+
+    async fn subscribe(&mut self, filters: &[AccountFilter]) -> anyhow::Result<()> {
+        let mut subscription = self.subscription.clone();
+        subscription
+            .accounts
+            .retain(|name, _| !name.starts_with(POOL_FILTER_PREFIX));
+        let accounts: Vec<String> = filters
+            .iter()
+            .filter_map(|filter| match filter {
+                AccountFilter::Account(pubkey) => Some(pubkey.to_string()),
+                AccountFilter::PoolArrays { .. } => None,
+            })
+            .collect();
+        if !accounts.is_empty() {
+            let filter = SubscribeRequestFilterAccounts {
+                account: accounts,
+                ..Default::default()
+            };
+            subscription
+                .accounts
+                .insert(format!("{POOL_FILTER_PREFIX}_accounts"), filter);
+        }
+        for (i, filter) in filters.iter().enumerate() {
+            let AccountFilter::PoolArrays {
+                owner,
+                data_size,
+                pool_offset,
+                pool,
+            } = filter
+            else {
+                continue;
+            };
+            // every tick (or bin) array of the pool: right size, pool id at its offset
+            let filter = SubscribeRequestFilterAccounts {
+                owner: vec![owner.to_string()],
+                filters: vec![
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(Filter::Datasize(*data_size as u64)),
+                    },
+                    SubscribeRequestFilterAccountsFilter {
+                        filter: Some(Filter::Memcmp(SubscribeRequestFilterAccountsFilterMemcmp {
+                            offset: *pool_offset as u64,
+                            data: Some(Data::Base58(pool.to_string())),
+                        })),
+                    },
+                ],
+                ..Default::default()
+            };
+            subscription
+                .accounts
+                .insert(format!("{POOL_FILTER_PREFIX}_arrays_{i}"), filter);
+        }
+        if subscription != self.subscription {
+            self.requests.send(subscription.clone()).await?;
+            self.subscription = subscription;
+        }
+        Ok(())
+    }
+}
+
+/// One exchange's public WebSocket depth protocol. Implementations only turn raw messages into
+/// books, the connection is handled by `WsBookFeed`, so they can be tested on recorded messages.
+pub trait DepthProtocol {
+    fn url(&self) -> String;
+
+    /// Messages sent once connected
+    fn subscribe_messages(&self) -> Vec<String>;
+
+    /// REST snapshot the stream's updates apply to, `None` when the stream sends its own
+    fn snapshot_url(&self) -> Option<String>;
+
+    /// Seeds the book from the body of `snapshot_url`
+    fn apply_snapshot(&mut self, body: &str) -> anyhow::Result<()>;
+
+    /// Applies one stream message, returns the book when it changed. An error means the book
+    /// can't be trusted anymore and the stream has to be resynced.
+    fn apply_message(&mut self, message: &str) -> anyhow::Result<Option<OrderBook>>;
+
+    /// Forgets the book before a reconnect
+    fn reset(&mut self);
+}
+
+type BookSocket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Exchange order book kept from its WebSocket depth stream. The stream is reconnected and the
+/// book rebuilt whenever it falls out of sync.
+pub struct WsBookFeed<P> {
+    protocol: P,
+    socket: Option<BookSocket>,
+}
+
+impl<P: DepthProtocol> WsBookFeed<P> {
+    pub fn new(protocol: P) -> Self {
+        Self {
+            protocol,
+            socket: None,
+        }
+    }
+
+    async fn connect(&mut self) -> anyhow::Result<BookSocket> {
+        self.protocol.reset();
+        let (mut socket, _) = tokio_tungstenite::connect_async(self.protocol.url()).await?;
+        for message in self.protocol.subscribe_messages() {
+            socket.send(Message::text(message)).await?;
+        }
+        // the stream is opened first so no update between the snapshot and the stream is lost
+        if let Some(url) = self.protocol.snapshot_url() {
+            let started = Instant::now();
+            let body = reqwest::get(&url).await?.error_for_status()?.text().await?;
+            debug!(
+                latency_ms = started.elapsed().as_millis() as u64,
+                "CEX depth snapshot fetched"
+            );
+            self.protocol.apply_snapshot(&body)?;
+        }
+        Ok(socket)
+    }
+}
+
+impl<P: DepthProtocol + Send> CexFeed for WsBookFeed<P> {
+    async fn next_book(&mut self) -> Option<anyhow::Result<OrderBook>> {
+        loop {
+            let socket = match &mut self.socket {
+                Some(socket) => socket,
+                None => match self.connect().await {
+                    Ok(socket) => self.socket.insert(socket),
+                    Err(e) => {
+                        tokio::time::sleep(RECONNECT_DELAY).await;
+                        return Some(Err(e));
+                    }
+                },
+            };
+            let text = match socket.next().await {
+                Some(Ok(Message::Text(text))) => text,
+                // pings are answered by the socket itself
+                Some(Ok(Message::Close(_))) | None => return None,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    self.socket = None;
+                    return Some(Err(e.into()));
+                }
+            };
+            match self.protocol.apply_message(&text) {
+                Ok(Some(book)) => return Some(Ok(book)),
+                Ok(None) => {}
+                Err(e) => {
+                    self.socket = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Plays back a fixed sequence of account updates, snapshots are served from `accounts`
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedDexFeed {
+    pub updates: VecDeque<AccountUpdate>,
+    pub accounts: HashMap<Pubkey, Account>,
+    /// Latest filters the pipeline subscribed to
+    pub subscriptions: Vec<AccountFilter>,
+}
+
+#[cfg(test)]
+impl DexFeed for ScriptedDexFeed {
+    async fn next_update(&mut self) -> Option<anyhow::Result<AccountUpdate>> {
+        self.updates.pop_front().map(Ok)
+    }
+
+    async fn fetch_accounts(&mut self, pubkeys: &[Pubkey]) -> anyhow::Result<Vec<Option<Account>>> {
+        Ok(pubkeys
+            .iter()
+            .map(|k| self.accounts.get(k).cloned())
+            .collect())
+    }
+
+    async fn subscribe(&mut self, filters: &[AccountFilter]) -> anyhow::Result<()> {
+        self.subscriptions = filters.to_vec();
+        Ok(())
+    }
+}
+
+/// Plays back a fixed sequence of order books
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct ScriptedCexFeed {
+    pub books: VecDeque<OrderBook>,
+}
+
+#[cfg(test)]
+impl CexFeed for ScriptedCexFeed {
+    async fn next_book(&mut self) -> Option<anyhow::Result<OrderBook>> {
+        self.books.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,343 @@
+//! Persistent journal of every detected opportunity: the market state it was detected on, what
+//! was decided and the resulting trades. Stored as append-only JSONL files that rotate by size,
+//! so they can be grepped as well as queried.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    arb::Opportunity,
+    execution::{Decision, Outcome},
+    market::{CexStruct, DexStruct},
+    order_book::Level,
+};
+
+const FILE_PREFIX: &str = "journal-";
+const FILE_EXTENSION: &str = "jsonl";
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    pub enabled: bool,
+    pub dir: String,
+    /// A new file is started once the current one grows past this
+    pub max_file_bytes: u64,
+    /// Book levels kept per side in each entry
+    pub book_depth: usize,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            dir: "journal".to_string(),
+            max_file_bytes: 64 * 1024 * 1024,
+            book_depth: 10,
+        }
+    }
+}
+
+/// Market state an opportunity was detected on
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub slot: u64,
+    pub sqrt_price_x64: u128,
+    pub liquidity: u128,
+    pub tick: i32,
+    pub bids: Vec<Level>,
+    pub asks: Vec<Level>,
+}
+
+impl MarketSnapshot {
+    pub fn capture(dex: &DexStruct, cex: &CexStruct, book_depth: usize) -> Self {
+        let price = dex.price().unwrap_or_default();
+        Self {
+            slot: dex.slot,
+            sqrt_price_x64: price.sqrt_price_x64,
+            liquidity: price.liquidity,
+            tick: price.tick,
+            bids: cex.book.bids.iter().take(book_depth).copied().collect(),
+            asks: cex.book.asks.iter().take(book_depth).copied().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp_ms: u64,
+    pub pair: String,
+    pub pool: String,
+    pub opportunity: Opportunity,
+    pub market: MarketSnapshot,
+    pub decision: Decision,
+}
+
+/// Filter for `query`, unset fields match everything
+#[derive(Debug, Clone, Default)]
+pub struct JournalQuery {
+    pub pair: Option<String>,
+    pub from_ms: Option<u64>,
+    pub to_ms: Option<u64>,
+    pub outcome: Option<Outcome>,
+}
+
+impl JournalQuery {
+    /// Parses `--pair`, `--from`, `--to` (unix ms) and `--outcome` command line flags
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let mut query = JournalQuery::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::Error::msg(format!("Error: missing value for {flag}")))?;
+            match flag.as_str() {
+                "--pair" => query.pair = Some(value.clone()),
+                "--from" => query.from_ms = Some(value.parse()?),
+                "--to" => query.to_ms = Some(value.parse()?),
+                "--outcome" => {
+                    query.outcome = Some(serde_json::from_value(serde_json::Value::String(
+                        value.clone(),
+                    ))?)
+                }
+                _ => return Err(anyhow::Error::msg(format!("Error: unknown flag {flag}"))),
+            }
+        }
+        Ok(query)
+    }
+
+    fn matches(&self, entry: &JournalEntry) -> bool {
+        self.pair.as_ref().is_none_or(|pair| *pair == entry.pair)
+            && self.from_ms.is_none_or(|from| entry.timestamp_ms >= from)
+            && self.to_ms.is_none_or(|to| entry.timestamp_ms < to)
+            && self
+                .outcome
+                .is_none_or(|outcome| outcome == entry.decision.outcome())
+    }
+}
+
+pub struct Journal {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    file: Option<File>,
+    written: u64,
+}
+
+impl Journal {
+    pub fn open(config: &JournalConfig) -> anyhow::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(Self {
+            dir: PathBuf::from(&config.dir),
+            max_file_bytes: config.max_file_bytes,
+            file: None,
+            written: 0,
+        })
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        if self.file.is_none() || self.written + line.len() as u64 > self.max_file_bytes {
+            self.rotate(entry.timestamp_ms)?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(&line)?;
+            self.written += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, timestamp_ms: u64) -> anyhow::Result<()> {
+        // zero padded to the full u32 width, sequence included, so files sort by name in time
+        // order even when several are started within a millisecond
+        let mut n = 0u32;
+        let mut path;
+        loop {
+            path = self.dir.join(format!(
+                "{FILE_PREFIX}{timestamp_ms:015}-{n:010}.{FILE_EXTENSION}"
+            ));
+            if !path.exists() {
+                break;
+            }
+            n += 1;
+        }
+        self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Entries in `dir` matching `query`, oldest first. Lines that don't parse, like one cut short
+/// by a crash mid-write, are reported and skipped.
+pub fn query(dir: impl AsRef<Path>, query: &JournalQuery) -> anyhow::Result<Vec<JournalEntry>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_EXTENSION))
+        })
+        .collect();
+    paths.sort();
+
+    let mut entries = Vec::new();
+    for path in paths {
+        for (n, line) in BufReader::new(File::open(&path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry: JournalEntry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!(path = %path.display(), line = n + 1, "Skipping journal line: {e}");
+                    continue;
+                }
+            };
+            if query.matches(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arb::Direction, risk::RejectReason};
+
+    fn entry(timestamp_ms: u64, decision: Decision) -> JournalEntry {
+        JournalEntry {
+            timestamp_ms,
+            pair: "SOL_USDC".to_string(),
+            pool: "pool".to_string(),
+            opportunity: Opportunity {
+                direction: Direction::BuyCexSellDex,
+                base_amount: 1_000_000_000,
+                cost: 150_000_000,
+                proceeds: 150_500_000,
+                spread: 500_000,
+                cex_fee: 150_000,
+                tx_fee: 5_000,
+                slot: 1,
+            },
+            market: MarketSnapshot::default(),
+            decision,
+        }
+    }
+
+    #[test]
+    fn rotates_and_queries_by_time_and_outcome() {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", uuid::Uuid::new_v4()));
+        let mut journal = Journal::open(&JournalConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            max_file_bytes: 600,
+            ..Default::default()
+        })
+        .unwrap();
+        for ts in 0..4 {
+            journal.append(&entry(ts, Decision::Logged)).unwrap();
+        }
+        journal
+            .append(&entry(
+                4,
+                Decision::Rejected {
+                    reason: RejectReason::MaxNotional,
+                },
+            ))
+            .unwrap();
+        assert!(fs::read_dir(&dir).unwrap().count() > 1);
+
+        let all = query(&dir, &JournalQuery::default()).unwrap();
+        assert_eq!(
+            all.iter().map(|e| e.timestamp_ms).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        let rejected = query(
+            &dir,
+            &JournalQuery {
+                outcome: Some(Outcome::Rejected),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(rejected.len(), 1);
+        let window = query(
+            &dir,
+            &JournalQuery {
+                pair: Some("SOL_USDC".to_string()),
+                from_ms: Some(1),
+                to_ms: Some(3),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(window.len(), 2);
+        let args = ["--outcome", "rejected", "--from", "4"].map(String::from);
+        assert_eq!(
+            query(&dir, &JournalQuery::from_args(&args).unwrap())
+                .unwrap()
+                .len(),
+            1
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_started_within_a_millisecond_keep_their_order() {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", uuid::Uuid::new_v4()));
+        let mut journal = Journal::open(&JournalConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            max_file_bytes: 1,
+            ..Default::default()
+        })
+        .unwrap();
+        // one file per entry, all in the same millisecond
+        for slot in 0..12 {
+            let mut entry = entry(7, Decision::Logged);
+            entry.opportunity.slot = slot;
+            journal.append(&entry).unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 12);
+
+        let all = query(&dir, &JournalQuery::default()).unwrap();
+        assert_eq!(
+            all.iter().map(|e| e.opportunity.slot).collect::<Vec<_>>(),
+            (0..12).collect::<Vec<_>>()
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_partial_line_is_skipped() {
+        let dir = std::env::temp_dir().join(format!("journal-test-{}", uuid::Uuid::new_v4()));
+        let mut journal = Journal::open(&JournalConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        journal.append(&entry(1, Decision::Logged)).unwrap();
+        // a write cut short by a crash, then the entries of the next run
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"timestamp_ms":2,"pair":"SOL"#).unwrap();
+        file.write_all(b"\n").unwrap();
+        journal.append(&entry(3, Decision::Logged)).unwrap();
+
+        let all = query(&dir, &JournalQuery::default()).unwrap();
+        assert_eq!(
+            all.iter().map(|e| e.timestamp_ms).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
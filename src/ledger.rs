@@ -0,0 +1,252 @@
+//! PnL accounting. Every fill is recorded with all of its costs; each arb round-trip realizes the
+//! PnL of the matched quantity, whatever one leg filled beyond the other is kept as residual
+//! inventory and marked to mid.
+
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{arb::Direction, balances::Venue};
+
+pub const SECS_PER_DAY: u64 = 86_400;
+/// Base fee of a transaction with one signature, in lamports
+pub const SIGNATURE_FEE: u64 = 5_000;
+const LAMPORTS_PER_SOL: i128 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fill {
+    pub venue: Venue,
+    pub base: u64,         // lamports
+    pub quote: u64,        // micro-USDC, before `fee`
+    pub fee: u64,          // micro-USDC charged on top of (buy) or taken from (sell) `quote`
+    pub tx_fee: u64,       // lamports
+    pub priority_fee: u64, // lamports
+    pub tip: u64,          // lamports
+}
+
+impl Fill {
+    /// Average price in micro-USDC per SOL
+    pub fn price(&self) -> u64 {
+        if self.base == 0 {
+            return 0;
+        }
+        (self.quote as i128 * LAMPORTS_PER_SOL / self.base as i128) as u64
+    }
+
+    /// Costs paid in SOL (transaction fee, priority fee, tip)
+    pub fn lamport_costs(&self) -> u64 {
+        self.tx_fee + self.priority_fee + self.tip
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoundTrip {
+    pub timestamp: u64, // unix seconds
+    pub direction: Direction,
+    pub buy: Fill,
+    pub sell: Fill,
+    /// All fees of both legs, SOL costs converted at the mid, in micro-USDC
+    pub fees: u64,
+    /// PnL of the matched quantity after fees, plus anything realized against residual inventory
+    pub realized_pnl: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DailySummary {
+    pub day: u64, // days since the unix epoch (UTC)
+    pub round_trips: u64,
+    pub volume: u64, // micro-USDC paid on buy legs
+    pub fees: u64,
+    pub realized_pnl: i64,
+}
+
+#[derive(Debug, Default)]
+pub struct Ledger {
+    pub round_trips: Vec<RoundTrip>,
+    /// Residual SOL position, lamports (negative when short)
+    pub residual: i64,
+    /// Cost basis of `residual`, micro-USDC
+    residual_cost: i64,
+    pub realized_pnl: i64,
+}
+
+impl Ledger {
+    /// Records both legs of a round-trip. `sol_price` (micro-USDC per SOL) converts SOL-denominated
+    /// costs. Returns the recorded round-trip.
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        buy: Fill,
+        sell: Fill,
+        sol_price: u64,
+        timestamp: u64,
+    ) -> &RoundTrip {
+        let matched = buy.base.min(sell.base);
+        let buy_matched = pro_rata(buy.quote, matched, buy.base);
+        let sell_matched = pro_rata(sell.quote, matched, sell.base);
+        let lamport_costs = buy.lamport_costs() + sell.lamport_costs();
+        let fees = buy.fee
+            + sell.fee
+            + (lamport_costs as i128 * sol_price as i128 / LAMPORTS_PER_SOL) as u64;
+
+        let mut realized_pnl = sell_matched as i64 - buy_matched as i64 - fees as i64;
+        if buy.base > matched {
+            realized_pnl += self.add_residual(
+                (buy.base - matched) as i64,
+                (buy.quote - buy_matched) as i64,
+            );
+        } else if sell.base > matched {
+            realized_pnl += self.add_residual(
+                -((sell.base - matched) as i64),
+                -((sell.quote - sell_matched) as i64),
+            );
+        }
+        self.realized_pnl += realized_pnl;
+
+        self.round_trips.push(RoundTrip {
+            timestamp,
+            direction,
+            buy,
+            sell,
+            fees,
+            realized_pnl,
+        });
+        self.round_trips.last().unwrap()
+    }
+
+    /// Residual inventory marked to `mid` (micro-USDC per SOL) minus its cost basis
+    pub fn unrealized_pnl(&self, mid: u64) -> i64 {
+        (self.residual as i128 * mid as i128 / LAMPORTS_PER_SOL) as i64 - self.residual_cost
+    }
+
+    /// Totals per UTC day, oldest first
+    pub fn daily_summaries(&self) -> Vec<DailySummary> {
+        let mut days: BTreeMap<u64, DailySummary> = BTreeMap::new();
+        for trip in &self.round_trips {
+            let day = trip.timestamp / SECS_PER_DAY;
+            let summary = days.entry(day).or_insert(DailySummary {
+                day,
+                ..Default::default()
+            });
+            summary.round_trips += 1;
+            summary.volume += trip.buy.quote;
+            summary.fees += trip.fees;
+            summary.realized_pnl += trip.realized_pnl;
+        }
+        days.into_values().collect()
+    }
+
+    /// Adds `lamports` at a cost of `cost` to the residual position (average cost). Returns the
+    /// PnL realized when it offsets an existing position.
+    fn add_residual(&mut self, lamports: i64, cost: i64) -> i64 {
+        if self.residual == 0 || self.residual.signum() == lamports.signum() {
+            self.residual += lamports;
+            self.residual_cost += cost;
+            return 0;
+        }
+
+        let closed = lamports.abs().min(self.residual.abs());
+        let closed_cost =
+            (self.residual_cost as i128 * closed as i128 / self.residual.abs() as i128) as i64;
+        let closed_value = (cost as i128 * closed as i128 / lamports.abs() as i128) as i64;
+        // closing a long sells at the new price, closing a short buys back at it
+        let realized = -(closed_value + closed_cost);
+
+        self.residual -= self.residual.signum() * closed;
+        self.residual_cost -= closed_cost;
+        let remaining = lamports.abs() - closed;
+        if remaining > 0 {
+            self.residual = lamports.signum() * remaining;
+            self.residual_cost = cost - closed_value;
+        }
+        realized
+    }
+}
+
+fn pro_rata(amount: u64, part: u64, total: u64) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+    (amount as u128 * part as u128 / total as u128) as u64
+}
+
+pub fn unix_time_ms() -> u64 {
+    unix_time_us() / 1000
+}
+
+pub fn unix_time_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(venue: Venue, base: u64, quote: u64, fee: u64, tx_fee: u64) -> Fill {
+        Fill {
+            venue,
+            base,
+            quote,
+            fee,
+            tx_fee,
+            priority_fee: 0,
+            tip: 0,
+        }
+    }
+
+    #[test]
+    fn realizes_matched_quantity_after_all_costs() {
+        let mut ledger = Ledger::default();
+        let trip = ledger.record(
+            Direction::BuyCexSellDex,
+            fill(Venue::Cex, 1_000_000_000, 150_000_000, 150_000, 0),
+            fill(Venue::Wallet, 1_000_000_000, 150_500_000, 0, 1_000_000),
+            150_000_000,
+            0,
+        );
+        // 0.5 USDC spread - 0.15 taker fee - 0.001 SOL of tx fees at 150
+        assert_eq!(trip.fees, 300_000);
+        assert_eq!(trip.realized_pnl, 200_000);
+        assert_eq!(ledger.residual, 0);
+        assert_eq!(ledger.unrealized_pnl(200_000_000), 0);
+    }
+
+    #[test]
+    fn residual_inventory_is_marked_to_mid_and_realized_when_offset() {
+        let mut ledger = Ledger::default();
+        // bought 1.1 SOL at 150, only 1 SOL sold
+        ledger.record(
+            Direction::BuyDexSellCex,
+            fill(Venue::Wallet, 1_100_000_000, 165_000_000, 0, 0),
+            fill(Venue::Cex, 1_000_000_000, 151_000_000, 0, 0),
+            150_000_000,
+            0,
+        );
+        assert_eq!(ledger.realized_pnl, 1_000_000);
+        assert_eq!(ledger.residual, 100_000_000);
+        assert_eq!(ledger.unrealized_pnl(160_000_000), 1_000_000);
+
+        // sold 0.1 SOL more than bought at 155, closes the residual
+        let trip = ledger.record(
+            Direction::BuyCexSellDex,
+            fill(Venue::Cex, 1_000_000_000, 150_000_000, 0, 0),
+            fill(Venue::Wallet, 1_100_000_000, 170_500_000, 0, 0),
+            150_000_000,
+            SECS_PER_DAY,
+        );
+        assert_eq!(trip.realized_pnl, 5_000_000 + 500_000);
+        assert_eq!(ledger.residual, 0);
+        assert_eq!(ledger.unrealized_pnl(160_000_000), 0);
+
+        let days = ledger.daily_summaries();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[1].day, 1);
+        assert_eq!(days[1].realized_pnl, 5_500_000);
+    }
+}
@@ -0,0 +1,84 @@
+//! Structured logging. Human readable lines go to stderr, JSON lines (with their spans) to files
+//! rotated by `tracing-appender`. Verbosity comes from `RUST_LOG` when set, `logging.level`
+//! otherwise, e.g. `RUST_LOG=info,arb_bot_solana_100x::pipeline=debug`.
+
+use serde::Deserialize;
+use tracing_appender::{non_blocking::WorkerGuard, rolling::Rotation};
+use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+impl From<LogRotation> for Rotation {
+    fn from(rotation: LogRotation) -> Self {
+        match rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Filter used when `RUST_LOG` isn't set
+    pub level: String,
+    /// JSON log files are written here, none when unset
+    pub dir: Option<String>,
+    pub file_prefix: String,
+    pub rotation: LogRotation,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            dir: Some("logs".to_string()),
+            file_prefix: "arb-bot".to_string(),
+            rotation: LogRotation::Hourly,
+        }
+    }
+}
+
+/// Installs the global subscriber. The returned guard flushes the file writer when dropped, so
+/// it has to live as long as the program.
+pub fn init(config: &LoggingConfig) -> anyhow::Result<Option<WorkerGuard>> {
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(&config.level)?,
+    };
+    let console = fmt::layer().with_writer(std::io::stderr);
+
+    let (file, guard) = match &config.dir {
+        Some(dir) => {
+            let appender = tracing_appender::rolling::Builder::new()
+                .rotation(config.rotation.into())
+                .filename_prefix(&config.file_prefix)
+                .filename_suffix("jsonl")
+                .build(dir)?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(file)
+        .try_init()?;
+    Ok(guard)
+}
@@ -0,0 +1,481 @@
+//! Address lookup tables for the DEX transactions. A CLMM swap with a few tick arrays plus a
+//! second venue is already past the account limit of a legacy transaction, so the accounts our
+//! candidates use most (pools, vaults, tick arrays, token accounts) are kept in tables owned by
+//! the wallet. Table contents are cached from RPC and the tables covering a transaction best are
+//! picked when it's compiled.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use solana_address_lookup_table_interface::{
+    instruction::{create_lookup_table, extend_lookup_table},
+    state::{AddressLookupTable, LOOKUP_TABLE_MAX_ADDRESSES},
+};
+use solana_sdk::{
+    instruction::Instruction,
+    message::AddressLookupTableAccount,
+    pubkey::Pubkey,
+    signature::{Keypair, read_keypair_file},
+    signer::Signer,
+    transaction::Transaction,
+};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::{config::Config, execution::ExecutionMode, rpc};
+
+/// Addresses added per extend instruction, keeps its transaction well under the packet limit
+const EXTEND_BATCH: usize = 20;
+/// A table covering fewer of a transaction's accounts costs more bytes than it saves: its key and
+/// index counts against 31 bytes saved per address
+const MIN_COVERED: usize = 2;
+/// Slots a created table stays pending without showing up over RPC. Its derivation slot has to be
+/// in the `SlotHashes` sysvar (the last 512 slots) for the create to land, after that it never will.
+const PENDING_SLOTS: u64 = 512;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct LookupTableConfig {
+    /// Existing tables to use, owned by the wallet or not
+    pub tables: Vec<String>,
+    /// Create and extend tables owned by the wallet (`WALLET_KEYPAIR`) for the accounts
+    /// candidates use most. Each table holds rent, reclaimed only by closing it.
+    pub manage: bool,
+    /// Candidates an account must appear in before it's added to a table
+    pub min_uses: u64,
+    /// Seconds between table refreshes
+    pub interval_secs: u64,
+    /// Tables one transaction loads at most
+    pub max_tables: usize,
+}
+
+impl Default for LookupTableConfig {
+    fn default() -> Self {
+        Self {
+            tables: Vec::new(),
+            manage: false,
+            min_uses: 3,
+            interval_secs: 30,
+            max_tables: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedTable {
+    authority: Option<Pubkey>,
+    addresses: Vec<Pubkey>,
+    /// False once deactivated, its addresses can't be looked up much longer
+    active: bool,
+}
+
+/// Cached lookup tables and how often candidates used each account
+#[derive(Debug, Default)]
+pub struct LookupTableManager {
+    /// Tables by address, `None` until fetched
+    tables: BTreeMap<Pubkey, Option<CachedTable>>,
+    /// Tables we created that RPC doesn't return yet, with the slot they were derived from
+    pending: HashMap<Pubkey, u64>,
+    uses: HashMap<Pubkey, u64>,
+}
+
+impl LookupTableManager {
+    /// Adds tables to fetch and use
+    pub fn track(&mut self, tables: impl IntoIterator<Item = Pubkey>) {
+        for table in tables {
+            self.tables.entry(table).or_default();
+        }
+    }
+
+    /// Tables to fetch
+    pub fn table_keys(&self) -> Vec<Pubkey> {
+        self.tables.keys().copied().collect()
+    }
+
+    /// Caches a fetched table. A table that doesn't exist is dropped, unless we just created it
+    /// and it isn't confirmed yet.
+    pub fn apply_account(&mut self, table: Pubkey, data: Option<&[u8]>) -> anyhow::Result<()> {
+        let Some(data) = data else {
+            if self.pending.contains_key(&table) {
+                return Ok(());
+            }
+            self.tables.remove(&table);
+            return Err(anyhow::Error::msg(format!(
+                "Error: lookup table {table} doesn't exist"
+            )));
+        };
+        let parsed = AddressLookupTable::deserialize(data)
+            .map_err(|e| anyhow::Error::msg(format!("Error: invalid lookup table {table}: {e}")))?;
+        self.pending.remove(&table);
+        self.tables.insert(
+            table,
+            Some(CachedTable {
+                authority: parsed.meta.authority,
+                addresses: parsed.addresses.to_vec(),
+                active: parsed.meta.deactivation_slot == u64::MAX,
+            }),
+        );
+        Ok(())
+    }
+
+    /// Counts the accounts of a candidate's swaps towards the ones worth a table entry
+    pub fn record(&mut self, instructions: &[Instruction]) {
+        for account in lookupable(instructions) {
+            *self.uses.entry(account).or_default() += 1;
+        }
+    }
+
+    /// Tables to compile `instructions` with, at most `max_tables`. Tables are picked greedily by
+    /// how many of the remaining accounts they cover.
+    pub fn select(
+        &self,
+        instructions: &[Instruction],
+        max_tables: usize,
+    ) -> Vec<AddressLookupTableAccount> {
+        let mut wanted = lookupable(instructions);
+        let mut selected: Vec<AddressLookupTableAccount> = Vec::new();
+        while selected.len() < max_tables && !wanted.is_empty() {
+            let best = self
+                .active_tables()
+                .filter(|(key, _)| selected.iter().all(|s| s.key != **key))
+                .map(|(key, table)| {
+                    let covered: HashSet<Pubkey> = table
+                        .addresses
+                        .iter()
+                        .filter(|address| wanted.contains(address))
+                        .copied()
+                        .collect();
+                    (key, table, covered)
+                })
+                .max_by_key(|(_, _, covered)| covered.len());
+            let Some((key, table, covered)) = best else {
+                break;
+            };
+            if covered.len() < MIN_COVERED {
+                break;
+            }
+            wanted.retain(|account| !covered.contains(account));
+            selected.push(AddressLookupTableAccount {
+                key: *key,
+                addresses: table.addresses.clone(),
+            });
+        }
+        selected
+    }
+
+    /// Instructions that put the accounts used at least `min_uses` times into tables owned by
+    /// `authority`, each sent in its own transaction. Tables with room are extended; when none
+    /// has room, one table is created at `recent_slot` and extended once it's been fetched. No
+    /// other table is created while one is pending, unless it can no longer land.
+    pub fn plan(&mut self, authority: Pubkey, recent_slot: u64, min_uses: u64) -> Vec<Instruction> {
+        let expired: Vec<Pubkey> = self
+            .pending
+            .iter()
+            .filter(|(_, slot)| recent_slot > **slot + PENDING_SLOTS)
+            .map(|(table, _)| *table)
+            .collect();
+        for table in expired {
+            warn!(%table, "Created lookup table never confirmed");
+            self.pending.remove(&table);
+            self.tables.remove(&table);
+        }
+
+        let covered: HashSet<Pubkey> = self
+            .active_tables()
+            .flat_map(|(_, table)| table.addresses.iter().copied())
+            .collect();
+        let mut wanted: Vec<(Pubkey, u64)> = self
+            .uses
+            .iter()
+            .filter(|(account, uses)| **uses >= min_uses && !covered.contains(account))
+            .map(|(account, uses)| (*account, *uses))
+            .collect();
+        if wanted.is_empty() {
+            return Vec::new();
+        }
+        // most used first, so they land in a table before the rest
+        wanted.sort_by_key(|(account, uses)| (std::cmp::Reverse(*uses), *account));
+        let mut wanted = wanted.into_iter().map(|(account, _)| account).peekable();
+
+        let mut instructions = Vec::new();
+        let owned = self
+            .active_tables()
+            .filter(|(_, table)| table.authority == Some(authority));
+        for (key, table) in owned {
+            let room = LOOKUP_TABLE_MAX_ADDRESSES - table.addresses.len();
+            let added: Vec<Pubkey> = wanted.by_ref().take(room).collect();
+            for batch in added.chunks(EXTEND_BATCH) {
+                instructions.push(extend_lookup_table(
+                    *key,
+                    authority,
+                    Some(authority),
+                    batch.to_vec(),
+                ));
+            }
+        }
+        if wanted.peek().is_some() && self.pending.is_empty() {
+            let (instruction, table) = create_lookup_table(authority, authority, recent_slot);
+            self.tables.insert(table, None);
+            self.pending.insert(table, recent_slot);
+            instructions.push(instruction);
+        }
+        instructions
+    }
+
+    fn active_tables(&self) -> impl Iterator<Item = (&Pubkey, &CachedTable)> {
+        self.tables
+            .iter()
+            .filter_map(|(key, table)| Some((key, table.as_ref()?)))
+            .filter(|(_, table)| table.active)
+    }
+}
+
+/// Accounts of `instructions` a table can supply: signers and invoked programs must be in the
+/// message itself
+fn lookupable(instructions: &[Instruction]) -> HashSet<Pubkey> {
+    let programs: HashSet<Pubkey> = instructions.iter().map(|ix| ix.program_id).collect();
+    instructions
+        .iter()
+        .flat_map(|ix| &ix.accounts)
+        .filter(|meta| !meta.is_signer && !programs.contains(&meta.pubkey))
+        .map(|meta| meta.pubkey)
+        .collect()
+}
+
+/// Refreshes the cached tables every `interval_secs` and, when managing them, creates and extends
+/// the wallet's tables
+pub async fn run(
+    manager: Arc<Mutex<LookupTableManager>>,
+    config: Config,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let tables = config
+        .lookup_tables
+        .tables
+        .iter()
+        .map(|table| table.parse::<Pubkey>())
+        .collect::<Result<Vec<_>, _>>()?;
+    manager.lock().await.track(tables);
+    // tables are never created with paper balances
+    let keypair = if config.lookup_tables.manage && config.execution_mode != ExecutionMode::Paper {
+        let keypair = std::env::var("WALLET_KEYPAIR")
+            .ok()
+            .and_then(|path| read_keypair_file(path).ok());
+        if keypair.is_none() {
+            warn!("Lookup tables not managed: WALLET_KEYPAIR missing");
+        }
+        keypair
+    } else {
+        None
+    };
+    loop {
+        if let Err(e) = refresh(&manager, &config.rpc_url).await {
+            error!("Error refreshing lookup tables: {e}");
+        } else if let Some(keypair) = &keypair
+            && let Err(e) = update(&manager, &config, keypair).await
+        {
+            error!("Error updating lookup tables: {e}");
+        }
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(Duration::from_secs(config.lookup_tables.interval_secs)) => {}
+        }
+    }
+}
+
+async fn refresh(manager: &Mutex<LookupTableManager>, rpc_url: &str) -> anyhow::Result<()> {
+    let keys = manager.lock().await.table_keys();
+    if keys.is_empty() {
+        return Ok(());
+    }
+    let accounts = rpc::get_multiple_accounts(rpc_url, &keys).await?;
+    let mut manager = manager.lock().await;
+    for (key, account) in keys.into_iter().zip(accounts) {
+        if let Err(e) = manager.apply_account(key, account.as_ref().map(|a| a.data.as_slice())) {
+            warn!("{e}");
+        }
+    }
+    Ok(())
+}
+
+async fn update(
+    manager: &Mutex<LookupTableManager>,
+    config: &Config,
+    keypair: &Keypair,
+) -> anyhow::Result<()> {
+    let slot = rpc::get_slot(&config.rpc_url).await?;
+    let planned = manager
+        .lock()
+        .await
+        .plan(keypair.pubkey(), slot, config.lookup_tables.min_uses);
+    for instruction in planned {
+        let table = instruction.accounts[0].pubkey;
+        let blockhash = rpc::get_latest_blockhash(&config.rpc_url).await?;
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&keypair.pubkey()),
+            &[keypair],
+            blockhash,
+        );
+        let signature = rpc::send_transaction(&config.rpc_url, &transaction).await?;
+        info!(%table, %signature, "Lookup table updated");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use solana_address_lookup_table_interface::state::LookupTableMeta;
+    use solana_sdk::{hash::Hash, instruction::AccountMeta};
+
+    use super::*;
+    use crate::tx_builder::{self, TransactionConfig};
+
+    fn table_data(authority: Pubkey, addresses: &[Pubkey], active: bool) -> Vec<u8> {
+        let mut meta = LookupTableMeta::new(authority);
+        if !active {
+            meta.deactivation_slot = 100;
+        }
+        AddressLookupTable {
+            meta,
+            addresses: Cow::Borrowed(addresses),
+        }
+        .serialize_for_tests()
+        .unwrap()
+    }
+
+    /// Swap touching `accounts` and signed by `user`
+    fn swap(program_id: Pubkey, user: Pubkey, accounts: &[Pubkey]) -> Instruction {
+        let mut metas = vec![AccountMeta::new_readonly(user, true)];
+        metas.extend(
+            accounts
+                .iter()
+                .map(|account| AccountMeta::new(*account, false)),
+        );
+        Instruction::new_with_bytes(program_id, &[1; 17], metas)
+    }
+
+    fn unique(n: usize) -> Vec<Pubkey> {
+        (0..n).map(|_| Pubkey::new_unique()).collect()
+    }
+
+    #[test]
+    fn picks_the_tables_covering_a_transaction() {
+        let (user, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (clmm, amm) = (unique(22), unique(18));
+        let swaps = [swap(program_id, user, &clmm), swap(program_id, user, &amm)];
+        let config = TransactionConfig::default();
+        assert!(tx_builder::build(&user, &swaps, None, &[], Hash::default(), &config).is_err());
+
+        let (clmm_table, amm_table, stale_table, other_table) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut manager = LookupTableManager::default();
+        manager.track([clmm_table, amm_table, stale_table, other_table]);
+        let authority = Pubkey::new_unique();
+        let mut with_program = clmm.clone();
+        with_program.push(program_id);
+        let tables = [
+            (clmm_table, table_data(authority, &with_program, true)),
+            (amm_table, table_data(authority, &amm[..10], true)),
+            (stale_table, table_data(authority, &amm, false)),
+            // a single account isn't worth its table
+            (other_table, table_data(authority, &[amm[17]], true)),
+        ];
+        for (key, data) in &tables {
+            manager.apply_account(*key, Some(data)).unwrap();
+        }
+        assert!(manager.apply_account(Pubkey::new_unique(), None).is_err());
+        assert!(
+            manager
+                .apply_account(Pubkey::new_unique(), Some(&[1, 2, 3]))
+                .is_err()
+        );
+
+        let selected = manager.select(&swaps, 3);
+        let keys: Vec<Pubkey> = selected.iter().map(|table| table.key).collect();
+        assert_eq!(keys, vec![clmm_table, amm_table]);
+        let tx =
+            tx_builder::build(&user, &swaps, None, &selected, Hash::default(), &config).unwrap();
+        // the invoked program stays in the message even though the table has it
+        assert_eq!(tx.looked_up(), 22 + 10);
+        assert_eq!(manager.select(&swaps, 1).len(), 1);
+    }
+
+    #[test]
+    fn extends_owned_tables_with_frequently_used_accounts() {
+        let (user, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let authority = Pubkey::new_unique();
+        let (hot, cold) = (unique(30), unique(5));
+        let mut manager = LookupTableManager::default();
+        for _ in 0..3 {
+            manager.record(&[swap(program_id, user, &hot)]);
+        }
+        manager.record(&[swap(program_id, user, &cold)]);
+        assert_eq!(manager.uses.len(), 35);
+
+        // nothing owned yet: one table is created and nothing else until it's fetched
+        let planned = manager.plan(authority, 1_000, 3);
+        assert_eq!(planned.len(), 1);
+        let created = planned[0].accounts[0].pubkey;
+        assert_eq!(manager.table_keys(), vec![created]);
+        assert!(manager.plan(authority, 1_001, 3).is_empty());
+        // not confirmed yet: RPC doesn't return it, but it isn't created again
+        manager.apply_account(created, None).unwrap();
+        assert_eq!(manager.table_keys(), vec![created]);
+        assert!(manager.plan(authority, 1_100, 3).is_empty());
+
+        // fetched with 250 addresses: 6 fit, the other 24 need a new table
+        let full = unique(250);
+        let data = table_data(authority, &full, true);
+        manager.apply_account(created, Some(&data)).unwrap();
+        let planned = manager.plan(authority, 1_002, 3);
+        assert_eq!(planned.len(), 2);
+        assert_eq!(planned[0].accounts[0].pubkey, created);
+        assert_ne!(planned[1].accounts[0].pubkey, created);
+        assert_eq!(manager.table_keys().len(), 2);
+
+        // with all hot accounts in a fetched table there is nothing left to do
+        let mut manager = LookupTableManager::default();
+        for _ in 0..3 {
+            manager.record(&[swap(program_id, user, &hot)]);
+        }
+        let table = Pubkey::new_unique();
+        manager.track([table]);
+        let data = table_data(authority, &hot, true);
+        manager.apply_account(table, Some(&data)).unwrap();
+        assert!(manager.plan(authority, 1_003, 3).is_empty());
+    }
+
+    #[test]
+    fn recreates_a_table_that_never_confirmed() {
+        let (user, program_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let authority = Pubkey::new_unique();
+        let mut manager = LookupTableManager::default();
+        for _ in 0..3 {
+            manager.record(&[swap(program_id, user, &unique(4))]);
+        }
+        let created = manager.plan(authority, 1_000, 1)[0].accounts[0].pubkey;
+        manager.apply_account(created, None).unwrap();
+        assert!(manager.plan(authority, 1_000 + PENDING_SLOTS, 1).is_empty());
+
+        // its derivation slot left `SlotHashes`, the create can't land anymore
+        let planned = manager.plan(authority, 1_001 + PENDING_SLOTS, 1);
+        assert_eq!(planned.len(), 1);
+        let recreated = planned[0].accounts[0].pubkey;
+        assert_ne!(recreated, created);
+        assert_eq!(manager.table_keys(), vec![recreated]);
+        // a table nobody created is still dropped
+        assert!(manager.apply_account(Pubkey::new_unique(), None).is_err());
+    }
+}
@@ -1,181 +1,436 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{str::FromStr, sync::Arc};
+mod alerts;
+mod arb;
+mod backpack;
+mod backtest;
+mod balances;
+mod binance;
+mod cex;
+mod clmm_simulator;
+mod config;
+mod constant_product;
+mod control;
+mod cross_dex;
+mod dlmm_simulator;
+mod execution;
+mod feed;
+mod journal;
+mod ledger;
+mod logging;
+mod lookup_tables;
+mod market;
+mod meteora_dlmm;
+mod metrics;
+mod okx;
+mod orca_whirlpool;
+mod order_book;
+mod paper;
+mod pipeline;
+mod raydium_amm;
 mod raydium_clmm;
+mod raydium_cpmm;
 mod raydium_math;
+mod rebalance;
+mod recorder;
+mod risk;
+mod route;
+mod rpc;
+mod supervisor;
+mod transfer_fee;
+mod tx_builder;
+mod venue;
+mod whirlpool_simulator;
 
-use futures_util::StreamExt;
-use reqwest;
-use serde::Deserialize;
+use solana_sdk::{pubkey::Pubkey, signature::read_keypair_file};
 use tokio::sync::Mutex;
-use yellowstone_grpc_client::{ClientTlsConfig, GeyserGrpcClient};
-use yellowstone_grpc_proto::geyser::{
-    SubscribeRequest, SubscribeRequestFilterAccounts, subscribe_update::UpdateOneof,
-};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 use crate::{
-    raydium_clmm::PoolState,
-    raydium_math::{sqrt_price_math::get_next_sqrt_price_from_input, swap_math::compute_swap_step},
+    alerts::{Alert, AlertDispatcher, Severity},
+    backpack::BackpackVenue,
+    backtest::Backtest,
+    balances::{Balances, Venue, WalletAccounts},
+    binance::BinanceVenue,
+    cex::{CexExchange, CexVenue, OrderRequest, OrderSide},
+    config::Config,
+    execution::{ExecutionCoordinator, ExecutionMode},
+    feed::GeyserFeed,
+    journal::JournalQuery,
+    market::MarketStore,
+    metrics::METRICS,
+    okx::OkxVenue,
+    pipeline::SharedState,
+    rebalance::RebalancePlanner,
+    recorder::{RecordReader, Recorder},
+    supervisor::Supervisor,
 };
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct DexStruct {
-    token_in: u64,          // sol
-    token_out: Option<u64>, // usdc, None until first update
+// ------------------- Balances -------------------
+async fn fetch_cex_balances(
+    balances: Arc<Mutex<Balances>>,
+    venue: impl CexVenue,
+    poll_secs: u64,
+    token: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    loop {
+        match venue.balances().await {
+            Ok(cex_balances) => {
+                let mut balances = balances.lock().await;
+                for (asset, amount) in cex_balances {
+                    balances.set(Venue::Cex, asset, amount);
+                }
+            }
+            Err(e) => error!("Error fetching CEX balances: {e}"),
+        }
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(std::time::Duration::from_secs(poll_secs)) => {}
+        }
+    }
 }
 
-#[derive(Debug)]
-pub struct CexStruct {
-    best_bid: u64,
-    best_ask: u64,
+// ------------------- CEX -------------------
+/// Streams the book of `venue` into the market, and polls its balances when trading live
+fn spawn_cex<V: CexVenue + Clone + Send + Sync + 'static>(
+    supervisor: &mut Supervisor,
+    venue: V,
+    state: &SharedState,
+    recorder: Recorder,
+    config: &Config,
+) {
+    let fees = venue.fees();
+    info!(
+        exchange = ?venue.exchange(),
+        maker_bps = fees.maker_bps,
+        taker_bps = fees.taker_bps,
+        strategy_taker_bps = config.taker_fee_bps(),
+        "CEX venue"
+    );
+    if config.taker_fee_bps() != fees.taker_bps {
+        warn!(
+            configured_taker_bps = config.taker_fee_bps(),
+            venue_taker_bps = fees.taker_bps,
+            "cex_taker_fee_bps overrides the venue's taker fee"
+        );
+    }
+    let (cex_state, book_venue) = (state.clone(), venue.clone());
+    supervisor.spawn("cex", move |token| {
+        let feed = book_venue.book_feed();
+        let (state, recorder) = (cex_state.clone(), recorder.clone());
+        async move {
+            tokio::select! {
+                _ = pipeline::run_cex(feed, state, recorder) => {}
+                _ = token.cancelled() => {}
+            }
+            Ok(())
+        }
+    });
+    if venue.authenticated() && config.execution_mode != ExecutionMode::Paper {
+        let (balances, poll_secs) = (state.balances.clone(), config.cex_balance_poll_secs);
+        supervisor.spawn("cex_balances", move |token| {
+            fetch_cex_balances(balances.clone(), venue.clone(), poll_secs, token)
+        });
+    }
 }
 
-#[derive(Debug, Deserialize)]
-struct DepthResponse {
-    bids: Vec<(String, String)>,
-    asks: Vec<(String, String)>,
+/// `cex balances`, `cex order <buy|sell> <lamports> [micro-USDC price]` or `cex cancel <id>`
+/// against the configured exchange, to check credentials and order handling by hand
+async fn run_cex_command(venue: impl CexVenue, args: &[String]) -> anyhow::Result<()> {
+    let usage = || {
+        anyhow::Error::msg(
+            "Error: usage: cex balances | cex order <buy|sell> <lamports> [price] | cex cancel <id>",
+        )
+    };
+    match args.first().map(String::as_str) {
+        Some("balances") => println!("{:?}", venue.balances().await?),
+        Some("order") => {
+            let side = match args.get(1).map(String::as_str) {
+                Some("buy") => OrderSide::Buy,
+                Some("sell") => OrderSide::Sell,
+                _ => return Err(usage()),
+            };
+            let order = OrderRequest {
+                side,
+                qty: args.get(2).ok_or_else(usage)?.parse()?,
+                price: args.get(3).map(|price| price.parse()).transpose()?,
+            };
+            println!("{}", venue.place_order(&order).await?);
+        }
+        Some("cancel") => venue.cancel_order(args.get(1).ok_or_else(usage)?).await?,
+        _ => return Err(usage()),
+    }
+    Ok(())
 }
-// ------------------- CEX fetch -------------------
-async fn fetch_cex_data(cex_struct: Arc<Mutex<CexStruct>>) -> Result<(), anyhow::Error> {
-    let url = "https://api.backpack.exchange/api/v1/depth?symbol=SOL_USDC";
-    loop {
-        let resp: DepthResponse = reqwest::get(url).await?.json().await?;
 
-        let best_bid = resp.bids.first().unwrap().0.parse::<f64>().unwrap();
-        let best_ask = resp.asks.first().unwrap().0.parse::<f64>().unwrap();
+// ------------------- Rebalancing -------------------
+/// Plans rebalancing between the wallet and `venue`, and executes it when enabled
+fn spawn_rebalancer<V: CexVenue + Clone + Send + Sync + 'static>(
+    supervisor: &mut Supervisor,
+    venue: V,
+    state: &SharedState,
+    config: &Config,
+) {
+    let (rebalance_state, rebalance_config) = (state.clone(), config.clone());
+    supervisor.spawn("rebalancer", move |token| {
+        run_rebalancer(
+            venue.clone(),
+            rebalance_state.balances.clone(),
+            rebalance_state.market.clone(),
+            rebalance_config.clone(),
+            token,
+        )
+    });
+}
 
-        let mut cex = cex_struct.lock().await;
-        cex.best_bid = (best_bid * 1_000_000.0) as u64;
-        cex.best_ask = (best_ask * 1_000_000.0) as u64;
+async fn run_rebalancer(
+    venue: impl CexVenue,
+    balances: Arc<Mutex<Balances>>,
+    market: Arc<MarketStore>,
+    config: Config,
+    token: CancellationToken,
+) -> Result<(), anyhow::Error> {
+    let mut planner = RebalancePlanner::new(config.rebalance.clone());
+    // transfers are only ever executed with real balances
+    let execute = config.rebalance.enabled && config.execution_mode != ExecutionMode::Paper;
+    let keypair = std::env::var("WALLET_KEYPAIR")
+        .ok()
+        .and_then(|path| read_keypair_file(path).ok());
 
-        println!("CEX updated: {:?}", *cex);
-        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-    }
-}
-// ------------------- Arb logic -------------------
-async fn handle_arb_txs(dex_struct: Arc<Mutex<DexStruct>>, cex_struct: Arc<Mutex<CexStruct>>) {
     loop {
-        let dex = dex_struct.lock().await;
+        // transfers in flight are finished, shutdown only interrupts the wait between plans
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(std::time::Duration::from_secs(config.rebalance.interval_secs)) => {}
+        }
+        let Some(sol_price) = market.load().cex.book.mid() else {
+            continue;
+        };
+        let planned = planner.plan(
+            &*balances.lock().await,
+            sol_price,
+            std::time::Instant::now(),
+        );
 
-        // Wait until DEX has a valid token_out value
-        let token_out = match dex.token_out {
-            Some(val) => val,
-            None => {
-                drop(dex);
-                tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        for rebalance in planned {
+            info!(action = ?rebalance.action, cost = rebalance.cost, "⚖️ Rebalance proposed");
+            if !execute {
+                planner.record(&rebalance, std::time::Instant::now());
                 continue;
             }
-        };
-
-        // Lock CEX to read best_bid / best_ask
-        let cex = cex_struct.lock().await;
-
-        let spread = token_out as i64 - cex.best_ask as i64;
-
-        // Arb check
-        if spread > 0 {
-            println!(
-                "💸 Arb found: Buy on CEX at {} USDC, Sell on DEX for {} USDC (spread: {})",
-                cex.best_ask, token_out, spread
-            );
+            let (Some(keypair), true) = (&keypair, venue.authenticated()) else {
+                warn!(
+                    exchange = ?venue.exchange(),
+                    "Rebalance skipped: WALLET_KEYPAIR or CEX credentials missing"
+                );
+                continue;
+            };
+            match rebalance::execute(
+                &rebalance,
+                &config.rebalance,
+                &config.rpc_url,
+                keypair,
+                &venue,
+            )
+            .await
+            {
+                Ok(()) => planner.record(&rebalance, std::time::Instant::now()),
+                Err(e) => error!("Error executing rebalance: {e}"),
+            }
         }
-
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
     }
 }
 // ------------------- Main -------------------
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    let dex_struct = Arc::new(Mutex::new(DexStruct {
-        token_in: 0,
-        token_out: None,
-    }));
-    let cex_struct = Arc::new(Mutex::new(CexStruct {
-        best_bid: 0,
-        best_ask: 0,
-    }));
-
-    let dex_clone = dex_struct.clone();
-    let cex_clone = cex_struct.clone();
-    let dex_grpc_clone = dex_struct.clone();
-    let cex_grpc_clone = cex_struct.clone();
-    // -------- DEX streaming task --------
-    let j1 = tokio::spawn(async move {
-        let tls_config = ClientTlsConfig::new().with_native_roots();
-        if let Ok(mut client) = GeyserGrpcClient::build_from_shared(
-            "https://solana-yellowstone-grpc.publicnode.com:443",
-        )
-        .unwrap()
-        .keep_alive_while_idle(true)
-        .tls_config(tls_config)
-        .unwrap()
-        .connect()
-        .await
-        {
-            let mut accounts: HashMap<String, SubscribeRequestFilterAccounts> = HashMap::new();
-            let filter = SubscribeRequestFilterAccounts {
-                owner: vec![],
-                account: vec!["3ucNos4NbumPLZNWztqGHNFFgkHeRMBQAVemeeomsUxv".to_string()],
-                ..Default::default()
+    let config = Config::load()?;
+    let _log_guard = logging::init(&config.logging)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        // `journal [--pair P] [--from MS] [--to MS] [--outcome O]` prints matching entries
+        Some("journal") => {
+            let query = JournalQuery::from_args(&args[1..])?;
+            for entry in journal::query(&config.journal.dir, &query)? {
+                println!("{}", serde_json::to_string(&entry)?);
+            }
+            return Ok(());
+        }
+        // `backtest [--from US] [--to US]` replays recorded market data through the strategy
+        Some("backtest") => {
+            let records = RecordReader::from_args(&config.recorder.dir, &args[1..])?;
+            let report = Backtest::new(&config)?.run(records)?;
+            let dir = report.write(&config.backtest.output_dir)?;
+            println!("{}", serde_json::to_string_pretty(&report.summary)?);
+            println!("Trades and PnL curve written to {}", dir.display());
+            return Ok(());
+        }
+        // `market-data [--from US] [--to US]` prints recorded events in receive order
+        Some("market-data") => {
+            for record in RecordReader::from_args(&config.recorder.dir, &args[1..])? {
+                println!("{}", record?);
+            }
+            return Ok(());
+        }
+        Some("cex") => {
+            return match config.cex_exchange {
+                CexExchange::Backpack => {
+                    run_cex_command(BackpackVenue::from_env(), &args[1..]).await
+                }
+                CexExchange::Binance => run_cex_command(BinanceVenue::from_env(), &args[1..]).await,
+                CexExchange::Okx => run_cex_command(OkxVenue::from_env(), &args[1..]).await,
             };
-            accounts.insert("client".to_string(), filter);
-            let (_tx, mut stream) = client
-                .subscribe_with_request(Some(SubscribeRequest {
-                    accounts,
-                    ..Default::default()
-                }))
-                .await
-                .expect("Error: unable to make grpc connection request");
-
-            while let Some(message) = stream.next().await {
-                match message {
-                    Ok(r) => {
-                        if let Some(UpdateOneof::Account(r)) = r.update_oneof {
-                            if let Some(account) = r.account {
-                                let pool: PoolState =
-                                    bincode::deserialize(&account.data[8..]).unwrap();
-                                let sqrt_price_current = pool.sqrt_price_x64;
-                                let liquidity = pool.liquidity;
-                                let amount_remaining: u64 = 1_000_000_000;
-                                let zero_for_one = true;
-                                let is_base_input = true;
-
-                                let sqrt_price_target = get_next_sqrt_price_from_input(
-                                    sqrt_price_current,
-                                    liquidity,
-                                    amount_remaining,
-                                    zero_for_one,
-                                );
+        }
+        _ => {}
+    }
+    let pool_id = Pubkey::from_str(&config.pool)?;
+    let pair = config.pair_mints()?;
+    pipeline::check_pool_pair(&config.rpc_url, &pool_id, config.pool_type, pair).await?;
+    // paper mode trades against virtual balances, real ones are only tracked when live
+    let paper = config.execution_mode == ExecutionMode::Paper;
+    let alerts = AlertDispatcher::spawn(&config.alerts);
+    let state = SharedState::new(
+        config.pool_type,
+        pair.0,
+        if paper {
+            Balances::from(&config.paper_balances)
+        } else {
+            Balances::new(config.sol_fee_reserve)
+        },
+        alerts.clone(),
+    );
+    let wallet = match &config.wallet {
+        Some(wallet) if !paper => Some(WalletAccounts::new(Pubkey::from_str(wallet)?)),
+        _ => None,
+    };
+    let recorder = Recorder::spawn(&config.recorder)?;
 
-                                let swap = compute_swap_step(
-                                    sqrt_price_current,
-                                    sqrt_price_target,
-                                    liquidity,
-                                    amount_remaining,
-                                    0,
-                                    is_base_input,
-                                    zero_for_one,
-                                );
+    let mut supervisor = Supervisor::new(&config.supervisor);
 
-                                let mut dex = dex_grpc_clone.lock().await;
-                                let swap = swap.unwrap();
-                                dex.token_in = swap.amount_in;
-                                dex.token_out = Some(swap.amount_out);
-                                println!("DEX swap state: {:?}", dex);
-                            }
-                        }
-                    }
-                    Err(_) => eprintln!("Error parsing DEX message"),
+    // -------- DEX streaming task --------
+    let dex_state = state.clone();
+    let dex_recorder = recorder.clone();
+    let dex_config = config.clone();
+    let mut connects = 0u64;
+    supervisor.spawn("dex", move |token| {
+        if connects > 0 {
+            METRICS.grpc_reconnects.inc();
+        }
+        connects += 1;
+        let (config, state, recorder) = (dex_config.clone(), dex_state.clone(), dex_recorder.clone());
+        async move {
+            let feed = match GeyserFeed::connect(&config.grpc_url, &config.rpc_url, wallet.as_ref())
+                .await
+            {
+                Ok(feed) => feed,
+                Err(e) => {
+                    state.alerts.send(
+                        Alert::new(Severity::Critical, "feed:grpc", "gRPC connection failed")
+                            .message(e.to_string()),
+                    );
+                    return Err(anyhow::Error::msg(format!(
+                        "Error: unable to make grpc connection request: {e}"
+                    )));
                 }
+            };
+            let alerts = state.alerts.clone();
+            tokio::select! {
+                _ = pipeline::run_dex(feed, state, pool_id, wallet, recorder, config.trade_size) => {
+                    alerts.send(Alert::new(Severity::Warning, "feed:grpc", "gRPC stream ended"));
+                }
+                _ = token.cancelled() => {}
             }
+            Ok(())
         }
     });
-    // -------- CEX fetch task --------
-    let j2 = tokio::spawn(fetch_cex_data(cex_clone));
+    // -------- Cross-DEX pool streams --------
+    // all tracked pools share one stream, reconnected as a whole when it ends
+    let tracked_pools = config
+        .cross_dex
+        .pools
+        .iter()
+        .map(|tracked| Ok((Pubkey::from_str(&tracked.pool)?, tracked.pool_type)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if !tracked_pools.is_empty() {
+        let (pools_state, pools_recorder, pools_config) =
+            (state.clone(), recorder.clone(), config.clone());
+        let mut connects = 0u64;
+        supervisor.spawn("pools", move |token| {
+            if connects > 0 {
+                METRICS.grpc_reconnects.inc();
+            }
+            connects += 1;
+            let (config, state, recorder) = (
+                pools_config.clone(),
+                pools_state.clone(),
+                pools_recorder.clone(),
+            );
+            let tracked_pools = tracked_pools.clone();
+            async move {
+                let feed = GeyserFeed::connect(&config.grpc_url, &config.rpc_url, None).await?;
+                tokio::select! {
+                    _ = pipeline::run_pools(feed, state, tracked_pools, recorder, config.trade_size) => {}
+                    _ = token.cancelled() => {}
+                }
+                Ok(())
+            }
+        });
+    }
+    // -------- Route search --------
+    if config.routes.enabled {
+        let (routes_state, routes_config) = (state.clone(), config.clone());
+        supervisor.spawn("routes", move |token| {
+            pipeline::run_routes(routes_state.clone(), routes_config.clone(), token)
+        });
+    }
+    // -------- Address lookup tables --------
+    if config.lookup_tables.manage || !config.lookup_tables.tables.is_empty() {
+        let (tables_state, tables_config) = (state.clone(), config.clone());
+        supervisor.spawn("lookup_tables", move |token| {
+            lookup_tables::run(
+                tables_state.lookup_tables.clone(),
+                tables_config.clone(),
+                token,
+            )
+        });
+    }
+    // -------- Metrics endpoint --------
+    if config.metrics.enabled {
+        let metrics_config = config.metrics.clone();
+        supervisor.spawn("metrics", move |token| {
+            metrics::serve(metrics_config.clone(), token)
+        });
+    }
+    // -------- Control API --------
+    if config.control.enabled {
+        let (control_state, control_config) = (state.clone(), config.clone());
+        supervisor.spawn("control", move |token| {
+            control::serve(control_state.clone(), control_config.clone(), token)
+        });
+    }
+    // -------- CEX book, balance and rebalancing tasks --------
+    match config.cex_exchange {
+        CexExchange::Backpack => {
+            let venue = BackpackVenue::from_env();
+            spawn_cex(&mut supervisor, venue.clone(), &state, recorder, &config);
+            spawn_rebalancer(&mut supervisor, venue, &state, &config);
+        }
+        CexExchange::Binance => {
+            let venue = BinanceVenue::from_env();
+            spawn_cex(&mut supervisor, venue.clone(), &state, recorder, &config);
+            spawn_rebalancer(&mut supervisor, venue, &state, &config);
+        }
+        CexExchange::Okx => {
+            let venue = OkxVenue::from_env();
+            spawn_cex(&mut supervisor, venue.clone(), &state, recorder, &config);
+            spawn_rebalancer(&mut supervisor, venue, &state, &config);
+        }
+    }
     // -------- Arb logic task --------
-    let j3 = tokio::spawn(handle_arb_txs(dex_clone, cex_grpc_clone));
-    // Wait for all
-    j1.await?;
-    j2.await?;
-    j3.await?;
+    // kept across restarts so the ledger and risk state survive
+    let coordinator = Arc::new(Mutex::new(ExecutionCoordinator::new(&config)));
+    supervisor.spawn("arb", move |token| {
+        pipeline::run_arb(state.clone(), coordinator.clone(), config.clone(), token)
+    });
 
-    Ok(())
+    supervisor.run().await
 }
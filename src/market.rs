@@ -0,0 +1,426 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use arc_swap::ArcSwap;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::Notify;
+
+use crate::{
+    clmm_simulator::{PoolPrice, SwapResult},
+    order_book::OrderBook,
+    venue::{AccountFilter, DexVenue, PoolType},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct DexStruct {
+    pub token_in: u64,          // sol
+    pub token_out: Option<u64>, // usdc, None until first update
+    pub slot: u64,
+    pub pool: Box<dyn DexVenue>,
+    /// Mint of SOL, which may be either token of the pool. `None` takes it to be the first.
+    pub base_mint: Option<Pubkey>,
+    /// Local receive time of the latest pool update, unix µs
+    pub received_us: u64,
+}
+
+impl DexStruct {
+    pub fn new(pool_type: PoolType, base_mint: Pubkey) -> Self {
+        Self {
+            pool: pool_type.venue(),
+            base_mint: Some(base_mint),
+            ..Default::default()
+        }
+    }
+
+    /// Accounts to stream for the pool, more once the pool account is loaded on some venues
+    pub fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        self.pool.subscriptions(pool_id)
+    }
+
+    /// Applies a raw account update: the pool itself or one of the accounts it depends on.
+    /// Returns false for accounts that don't belong to `pool_id`.
+    pub fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        slot: u64,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        let applied = self.pool.apply_account(pool_id, pubkey, data)?;
+        if applied {
+            // accounts bootstrapped over RPC come without a slot
+            self.slot = self.slot.max(slot);
+        }
+        Ok(applied)
+    }
+
+    /// Accounts the pool needs for quoting that aren't cached yet: its config, tick or bin
+    /// arrays near the current price, token vaults
+    pub fn missing_accounts(&self, pool_id: &Pubkey) -> Vec<Pubkey> {
+        self.pool.missing_accounts(pool_id)
+    }
+
+    /// Records that an account from `missing_accounts` doesn't exist on chain
+    pub fn mark_absent(&mut self, pubkey: &Pubkey) {
+        self.pool.mark_absent(pubkey);
+    }
+
+    /// Current price of the pool, `None` until the pool account is loaded
+    pub fn price(&self) -> Option<PoolPrice> {
+        self.pool.price()
+    }
+
+    /// Mints of the pool's first and second token, `None` until the pool account is loaded
+    pub fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        self.pool.mints()
+    }
+
+    /// Whether the pool trades `pair`, its base and quote mints in either order
+    pub fn trades(&self, (base, quote): (Pubkey, Pubkey)) -> bool {
+        self.mints()
+            .is_some_and(|mints| mints == (base, quote) || mints == (quote, base))
+    }
+
+    /// Direction of a swap selling SOL (`sell_base`) or buying it, whichever token of the pool
+    /// SOL is
+    pub fn zero_for_one(&self, sell_base: bool) -> bool {
+        let base_is_token_0 = match (self.base_mint, self.mints()) {
+            (Some(base), Some((mint_0, _))) => mint_0 == base,
+            _ => true,
+        };
+        sell_base == base_is_token_0
+    }
+
+    /// Simulates a swap against the latest pool state and cached tick arrays. `sell_base` sells
+    /// SOL, buys it otherwise.
+    pub fn simulate(
+        &self,
+        amount: u64,
+        sell_base: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<SwapResult> {
+        // time dependent fees use the latest update's time so replays match
+        let now = (self.received_us / 1_000_000) as i64;
+        let zero_for_one = self.zero_for_one(sell_base);
+        if is_base_input {
+            self.pool.quote_exact_in(amount, zero_for_one, now)
+        } else {
+            self.pool.quote_exact_out(amount, zero_for_one, now)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CexStruct {
+    pub best_bid: u64,
+    pub best_ask: u64,
+    pub book: OrderBook,
+    /// Local receive time of `book`, unix µs
+    pub received_us: u64,
+}
+
+/// Consistent DEX and CEX state at one point in time, never mutated once published
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    pub dex: Arc<DexStruct>,
+    pub cex: Arc<CexStruct>,
+    /// Other tracked pools, by pool id
+    pub pools: Arc<BTreeMap<Pubkey, Arc<DexStruct>>>,
+}
+
+impl Snapshot {
+    /// Identifies the feed updates the snapshot holds: DEX slot, DEX and CEX receive times
+    pub fn stamp(&self) -> (u64, u64, u64) {
+        (self.dex.slot, self.dex.received_us, self.cex.received_us)
+    }
+}
+
+/// Latest market snapshot. Feed tasks publish new immutable snapshots and readers load the
+/// current one without locking, so a slow detection pass never holds up the feeds.
+#[derive(Debug, Default)]
+pub struct MarketStore {
+    current: ArcSwap<Snapshot>,
+    pool_updated: Notify,
+}
+
+impl MarketStore {
+    /// The latest snapshot, kept alive for as long as the caller holds it
+    pub fn load(&self) -> Arc<Snapshot> {
+        self.current.load_full()
+    }
+
+    /// Waits for the next pool update published after the last wait returned. Updates published
+    /// in between wake one waiter once.
+    pub async fn pool_updated(&self) {
+        self.pool_updated.notified().await
+    }
+
+    /// Publishes new DEX state alongside the latest CEX state. The state is shared, not copied:
+    /// writers keep the `Arc` and only copy it when they next change it while readers hold it.
+    pub fn publish_dex(&self, dex: Arc<DexStruct>) {
+        self.current.rcu(|current| Snapshot {
+            dex: dex.clone(),
+            ..Snapshot::clone(current)
+        });
+        self.pool_updated.notify_one();
+    }
+
+    /// Publishes new CEX state alongside the latest DEX state
+    pub fn publish_cex(&self, cex: CexStruct) {
+        let cex = Arc::new(cex);
+        self.current.rcu(|current| Snapshot {
+            cex: cex.clone(),
+            ..Snapshot::clone(current)
+        });
+    }
+
+    /// Publishes new state of a tracked pool other than the configured one
+    pub fn publish_pool(&self, pool_id: Pubkey, dex: Arc<DexStruct>) {
+        self.current.rcu(|current| {
+            let mut pools = BTreeMap::clone(&current.pools);
+            pools.insert(pool_id, dex.clone());
+            Snapshot {
+                pools: Arc::new(pools),
+                ..Snapshot::clone(current)
+            }
+        });
+        self.pool_updated.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use tokio::sync::Mutex;
+
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        clmm_simulator::tests::{pool, tick_array},
+        journal::MarketSnapshot,
+        order_book::Level,
+        raydium_amm::tests::amm_dex,
+        raydium_clmm::ClmmVenue,
+    };
+
+    #[test]
+    fn swaps_follow_the_pool_token_order() {
+        // 1000 SOL at 150 USDC, SOL first or second
+        let straight = DexStruct {
+            base_mint: Some(WSOL_MINT),
+            ..amm_dex(
+                (WSOL_MINT, USDC_MINT),
+                (1_000_000_000_000, 150_000_000_000),
+                0,
+            )
+        };
+        let reversed = DexStruct {
+            base_mint: Some(WSOL_MINT),
+            ..amm_dex(
+                (USDC_MINT, WSOL_MINT),
+                (150_000_000_000, 1_000_000_000_000),
+                0,
+            )
+        };
+        assert!(straight.trades((WSOL_MINT, USDC_MINT)));
+        assert!(reversed.trades((WSOL_MINT, USDC_MINT)));
+        assert!(!reversed.trades((WSOL_MINT, Pubkey::new_unique())));
+        assert!(straight.zero_for_one(true) && !straight.zero_for_one(false));
+        assert!(!reversed.zero_for_one(true) && reversed.zero_for_one(false));
+
+        // selling and buying SOL quote the same whichever token it is
+        for (sell_base, is_base_input) in
+            [(true, true), (true, false), (false, true), (false, false)]
+        {
+            let amount = if sell_base == is_base_input {
+                1_000_000_000
+            } else {
+                150_000_000
+            };
+            let straight = straight.simulate(amount, sell_base, is_base_input).unwrap();
+            let reversed = reversed.simulate(amount, sell_base, is_base_input).unwrap();
+            assert_eq!(
+                (straight.amount_in, straight.amount_out),
+                (reversed.amount_in, reversed.amount_out)
+            );
+        }
+    }
+
+    #[test]
+    fn loaded_snapshots_stay_consistent() {
+        let store = MarketStore::default();
+        store.publish_dex(Arc::new(DexStruct {
+            slot: 1,
+            ..Default::default()
+        }));
+        let before = store.load();
+        store.publish_cex(CexStruct {
+            best_bid: 100,
+            ..Default::default()
+        });
+        store.publish_dex(Arc::new(DexStruct {
+            slot: 2,
+            ..Default::default()
+        }));
+
+        assert_eq!((before.dex.slot, before.cex.best_bid), (1, 0));
+        let after = store.load();
+        assert_eq!((after.dex.slot, after.cex.best_bid), (2, 100));
+    }
+
+    const READERS: usize = 3;
+    const RUN: Duration = Duration::from_millis(500);
+    /// Account updates arrive at arbitrary points of a detection pass, not back to back
+    const UPDATE_INTERVAL: Duration = Duration::from_micros(100);
+
+    fn wait_until(deadline: Instant) {
+        while Instant::now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn bench_market() -> (DexStruct, CexStruct) {
+        let tick = -18_971;
+        let dex = DexStruct {
+            pool: Box::new(ClmmVenue {
+                pool: Some(pool(tick, 1_000_000_000_000_000)),
+                tick_arrays: (-5..=5)
+                    .map(|i| {
+                        let start = (tick.div_euclid(60) + i) * 60;
+                        (
+                            start,
+                            tick_array(start, &[(start, 1_000), (start + 30, -1_000)]),
+                        )
+                    })
+                    .collect(),
+                fee_rate: Some(500),
+            }),
+            ..Default::default()
+        };
+        let levels = |from: u64| {
+            (0..50)
+                .map(|i| Level {
+                    price: from + i * 10_000,
+                    qty: 1_000_000_000,
+                })
+                .collect()
+        };
+        let cex = CexStruct {
+            book: OrderBook {
+                bids: levels(149_000_000),
+                asks: levels(150_000_000),
+            },
+            ..Default::default()
+        };
+        (dex, cex)
+    }
+
+    /// Stand-in for a detection pass: quotes both directions on the DEX, then journals the
+    /// market like an opportunity would
+    fn read_pass(dex: &DexStruct, cex: &CexStruct) -> usize {
+        let sell = dex.simulate(1_000_000_000, true, true).unwrap();
+        let buy = dex.simulate(1_000_000_000, false, false).unwrap();
+        let market = MarketSnapshot::capture(dex, cex, 10);
+        let journaled = serde_json::to_vec(&(sell.amount_out, buy.amount_in, market)).unwrap();
+        journaled.len()
+    }
+
+    fn report(design: &str, mut latencies: Vec<Duration>, reads: usize, elapsed: Duration) {
+        latencies.sort();
+        let at = |q: f64| latencies[((latencies.len() - 1) as f64 * q) as usize];
+        println!(
+            "{design:>8}: {} writes, p50 {:?} p99 {:?} p99.9 {:?} max {:?}, {:.0} reads/s",
+            latencies.len(),
+            at(0.5),
+            at(0.99),
+            at(0.999),
+            at(1.0),
+            reads as f64 / elapsed.as_secs_f64()
+        );
+    }
+
+    /// Writer latency with readers hammering the state, for the previous design (one mutex per
+    /// venue) and the snapshot store. Run with
+    /// `cargo test --release bench_market_state -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_market_state() {
+        let (dex, cex) = bench_market();
+
+        let dex_lock = Arc::new(Mutex::new(dex.clone()));
+        let cex_lock = Arc::new(Mutex::new(cex.clone()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let (dex, cex, stop) = (dex_lock.clone(), cex_lock.clone(), stop.clone());
+                tokio::spawn(async move {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        // the arb loop used to hold the DEX lock while waiting on the CEX one
+                        let dex = dex.lock().await;
+                        let cex = cex.lock().await;
+                        std::hint::black_box(read_pass(&dex, &cex));
+                        drop((dex, cex));
+                        reads += 1;
+                        tokio::task::yield_now().await;
+                    }
+                    reads
+                })
+            })
+            .collect();
+        let mut latencies = Vec::new();
+        let started = Instant::now();
+        while started.elapsed() < RUN {
+            wait_until(started + UPDATE_INTERVAL * latencies.len() as u32);
+            let update = Instant::now();
+            dex_lock.lock().await.slot += 1;
+            latencies.push(update.elapsed());
+        }
+        stop.store(true, Ordering::Relaxed);
+        let mut reads = 0;
+        for reader in readers {
+            reads += reader.await.unwrap();
+        }
+        report("mutex", latencies, reads, started.elapsed());
+
+        let store = Arc::new(MarketStore::default());
+        store.publish_dex(Arc::new(dex.clone()));
+        store.publish_cex(cex);
+        let stop = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..READERS)
+            .map(|_| {
+                let (store, stop) = (store.clone(), stop.clone());
+                tokio::spawn(async move {
+                    let mut reads = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        let snapshot = store.load();
+                        std::hint::black_box(read_pass(&snapshot.dex, &snapshot.cex));
+                        reads += 1;
+                        tokio::task::yield_now().await;
+                    }
+                    reads
+                })
+            })
+            .collect();
+        let mut latencies = Vec::new();
+        // the stream's path: the published state is still shared, so changing it copies it once
+        let mut working = store.load().dex.clone();
+        let started = Instant::now();
+        while started.elapsed() < RUN {
+            wait_until(started + UPDATE_INTERVAL * latencies.len() as u32);
+            let update = Instant::now();
+            Arc::make_mut(&mut working).slot += 1;
+            store.publish_dex(working.clone());
+            latencies.push(update.elapsed());
+        }
+        stop.store(true, Ordering::Relaxed);
+        let mut reads = 0;
+        for reader in readers {
+            reads += reader.await.unwrap();
+        }
+        report("snapshot", latencies, reads, started.elapsed());
+    }
+}
@@ -0,0 +1,628 @@
+//! Meteora DLMM account layouts, bin prices and fees. Liquidity sits in discrete bins, each at a
+//! fixed price of `(1 + bin_step / 10_000) ^ bin_id` token Y per token X, grouped 70 to a bin
+//! array. The swap fee is a base fee plus a variable fee that grows with how far the active bin
+//! moved recently (the volatility accumulator).
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    balances::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID},
+    clmm_simulator::{PoolPrice, SwapResult},
+    dlmm_simulator::{self, BIN_ARRAY_WINDOW, DlmmBinArrays},
+    venue::{self, AccountFilter, DexVenue, PoolType, SwapParams},
+};
+
+pub const DLMM_PROGRAM_ID: Pubkey = pubkey!("LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo");
+pub const BIN_ARRAY_SEED: &[u8] = b"bin_array";
+const EVENT_AUTHORITY_SEED: &[u8] = b"__event_authority";
+/// Bins per bin array
+pub const MAX_BIN_PER_ARRAY: i32 = 70;
+/// Size of an LbPair account, including the 8 byte anchor discriminator
+pub const LB_PAIR_ACCOUNT_LEN: usize = 904;
+/// Size of a bin array account, including the 8 byte anchor discriminator
+pub const BIN_ARRAY_ACCOUNT_LEN: usize = 10136;
+/// Offset of the pair address in a bin array account
+pub const BIN_ARRAY_LB_PAIR_OFFSET: usize = 24;
+/// Bin arrays covered by the bitmap stored in the pair, the rest need the bitmap extension
+pub const BIN_ARRAY_BITMAP_SIZE: i64 = 512;
+/// Fee rates are in 10^-9
+pub const FEE_PRECISION: u64 = 1_000_000_000;
+/// Fees are capped at 10%
+pub const MAX_FEE_RATE: u64 = 100_000_000;
+pub const BASIS_POINT_MAX: u64 = 10_000;
+/// Size of a packed `Bin` inside a bin array account
+const BIN_LEN: usize = 144;
+const SCALE_OFFSET: u32 = 64;
+const ONE: u128 = 1 << SCALE_OFFSET;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StaticParameters {
+    pub base_factor: u16,
+    /// Seconds after a swap during which the volatility reference stays put
+    pub filter_period: u16,
+    /// Seconds after which the volatility reference decays to zero
+    pub decay_period: u16,
+    /// Share of the volatility accumulator kept as reference, in bps
+    pub reduction_factor: u16,
+    pub variable_fee_control: u32,
+    pub max_volatility_accumulator: u32,
+    pub min_bin_id: i32,
+    pub max_bin_id: i32,
+    /// Share of the fee taken by the protocol, in bps
+    pub protocol_share: u16,
+    pub base_fee_power_factor: u8,
+    pub padding: [u8; 5],
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VariableParameters {
+    pub volatility_accumulator: u32,
+    pub volatility_reference: u32,
+    pub index_reference: i32,
+    pub padding: [u8; 4],
+    pub last_update_timestamp: i64,
+    pub padding1: [u8; 8],
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProtocolFee {
+    pub amount_x: u64,
+    pub amount_y: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RewardInfo {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub funder: Pubkey,
+    pub reward_duration: u64,
+    pub reward_duration_end: u64,
+    pub reward_rate: u128,
+    pub last_update_time: u64,
+    pub cumulative_seconds_with_empty_liquidity_reward: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LbPair {
+    pub parameters: StaticParameters,
+    pub v_parameters: VariableParameters,
+    pub bump_seed: [u8; 1],
+    pub bin_step_seed: [u8; 2],
+    pub pair_type: u8,
+    /// Bin the price is currently in
+    pub active_id: i32,
+    /// Price increment between bins, in bps
+    pub bin_step: u16,
+    /// 0 enabled, 1 disabled
+    pub status: u8,
+    pub require_base_factor_seed: u8,
+    pub base_factor_seed: [u8; 2],
+    pub activation_type: u8,
+    pub creator_pool_on_off_control: u8,
+    pub token_x_mint: Pubkey,
+    pub token_y_mint: Pubkey,
+    pub reserve_x: Pubkey,
+    pub reserve_y: Pubkey,
+    pub protocol_fee: ProtocolFee,
+    pub padding1: [u8; 32],
+    pub reward_infos: [RewardInfo; 2],
+    pub oracle: Pubkey,
+    /// Bin arrays with liquidity, bit `index + 512`
+    pub bin_array_bitmap: [u64; 16],
+    pub last_updated_at: i64,
+    pub padding2: [u8; 32],
+    pub pre_activation_swap_address: Pubkey,
+    pub base_key: Pubkey,
+    pub activation_point: u64,
+    pub pre_activation_duration: u64,
+    pub padding3: [u8; 8],
+    pub padding4: u64,
+    pub creator: Pubkey,
+    pub token_mint_x_program_flag: u8,
+    pub token_mint_y_program_flag: u8,
+    pub reserved: [u8; 22],
+}
+
+impl LbPair {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != LB_PAIR_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: invalid LbPair account size"));
+        }
+        Ok(bincode::deserialize(&data[8..])?)
+    }
+
+    /// Base fee rate, in 10^-9
+    pub fn base_fee_rate(&self) -> u128 {
+        u128::from(self.parameters.base_factor)
+            * u128::from(self.bin_step)
+            * 10
+            * 10u128.pow(u32::from(self.parameters.base_fee_power_factor))
+    }
+
+    /// Variable fee rate for `volatility_accumulator`, in 10^-9
+    pub fn variable_fee_rate(&self, volatility_accumulator: u32) -> u128 {
+        let control = u128::from(self.parameters.variable_fee_control);
+        if control == 0 {
+            return 0;
+        }
+        let square = (u128::from(volatility_accumulator) * u128::from(self.bin_step)).pow(2);
+        (control * square).div_ceil(100_000_000_000)
+    }
+
+    /// Total fee rate for `volatility_accumulator`, capped at `MAX_FEE_RATE`
+    pub fn total_fee_rate(&self, volatility_accumulator: u32) -> u64 {
+        let rate = self.base_fee_rate() + self.variable_fee_rate(volatility_accumulator);
+        rate.min(u128::from(MAX_FEE_RATE)) as u64
+    }
+
+    /// Volatility reference and index reference at `now` (unix seconds), as the program sets
+    /// them before a swap
+    pub fn references_at(&self, now: i64) -> (u32, i32) {
+        let v = &self.v_parameters;
+        let elapsed = now.saturating_sub(v.last_update_timestamp);
+        if elapsed < i64::from(self.parameters.filter_period) {
+            return (v.volatility_reference, v.index_reference);
+        }
+        let reference = if elapsed < i64::from(self.parameters.decay_period) {
+            (u64::from(v.volatility_accumulator) * u64::from(self.parameters.reduction_factor)
+                / BASIS_POINT_MAX) as u32
+        } else {
+            0
+        };
+        (reference, self.active_id)
+    }
+
+    /// Volatility accumulator once the active bin reaches `active_id`
+    pub fn volatility_accumulator(
+        &self,
+        volatility_reference: u32,
+        index_reference: i32,
+        active_id: i32,
+    ) -> u32 {
+        let delta = u64::from(index_reference.abs_diff(active_id));
+        let accumulator = u64::from(volatility_reference) + delta * BASIS_POINT_MAX;
+        accumulator.min(u64::from(self.parameters.max_volatility_accumulator)) as u32
+    }
+
+    /// Whether the bitmap in the pair has bin array `index` marked as holding liquidity. `None`
+    /// outside its range.
+    pub fn bin_array_has_liquidity(&self, index: i64) -> Option<bool> {
+        if !(-BIN_ARRAY_BITMAP_SIZE..BIN_ARRAY_BITMAP_SIZE).contains(&index) {
+            return None;
+        }
+        let bit = (index + BIN_ARRAY_BITMAP_SIZE) as usize;
+        Some(self.bin_array_bitmap[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Bin {
+    pub amount_x: u64,
+    pub amount_y: u64,
+    /// Q64.64 price, set once the bin is used
+    pub price: u128,
+    pub liquidity_supply: u128,
+    pub reward_per_token_stored: [u128; 2],
+    pub fee_amount_x_per_token_stored: u128,
+    pub fee_amount_y_per_token_stored: u128,
+    pub amount_x_in: u128,
+    pub amount_y_in: u128,
+}
+
+#[derive(Clone, Debug)]
+pub struct BinArray {
+    pub index: i64,
+    pub lb_pair: Pubkey,
+    /// Bin `i` has id `index * 70 + i`
+    pub bins: Vec<Bin>,
+}
+
+impl BinArray {
+    /// Decodes a raw bin array account, bins are read one by one like tick arrays
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != BIN_ARRAY_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: invalid bin array account size"));
+        }
+        let index = i64::from_le_bytes(data[8..16].try_into()?);
+        let lb_pair =
+            Pubkey::try_from(&data[BIN_ARRAY_LB_PAIR_OFFSET..BIN_ARRAY_LB_PAIR_OFFSET + 32])?;
+        let bins = data[BIN_ARRAY_LB_PAIR_OFFSET + 32..]
+            .chunks_exact(BIN_LEN)
+            .map(bincode::deserialize::<Bin>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            index,
+            lb_pair,
+            bins,
+        })
+    }
+
+    /// Index of the bin array holding `bin_id`
+    pub fn bin_id_to_index(bin_id: i32) -> i64 {
+        i64::from(bin_id.div_euclid(MAX_BIN_PER_ARRAY))
+    }
+
+    pub fn bin(&self, bin_id: i32) -> Option<&Bin> {
+        let offset = i64::from(bin_id) - self.index * i64::from(MAX_BIN_PER_ARRAY);
+        usize::try_from(offset).ok().and_then(|i| self.bins.get(i))
+    }
+
+    /// The index is part of the seeds as little endian i64
+    pub fn address(lb_pair: &Pubkey, index: i64) -> Pubkey {
+        Pubkey::find_program_address(
+            &[BIN_ARRAY_SEED, lb_pair.as_ref(), &index.to_le_bytes()],
+            &DLMM_PROGRAM_ID,
+        )
+        .0
+    }
+}
+
+/// Q64.64 price of bin `bin_id`, computed like the program: `base ^ |id|` by squaring with the
+/// base kept below one so nothing overflows, inverted as needed
+pub fn get_price_from_id(bin_id: i32, bin_step: u16) -> Option<u128> {
+    let base = ONE + (u128::from(bin_step) << SCALE_OFFSET) / u128::from(BASIS_POINT_MAX);
+    pow(base, bin_id)
+}
+
+fn pow(base: u128, exp: i32) -> Option<u128> {
+    if exp == 0 {
+        return Some(ONE);
+    }
+    let mut invert = exp < 0;
+    let mut exp = exp.unsigned_abs();
+    if exp >= 0x80000 {
+        return None;
+    }
+    let mut squared_base = base;
+    if squared_base >= ONE {
+        squared_base = u128::MAX / squared_base;
+        invert = !invert;
+    }
+    let mut result = ONE;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.checked_mul(squared_base)? >> SCALE_OFFSET;
+        }
+        squared_base = squared_base.checked_mul(squared_base)? >> SCALE_OFFSET;
+        exp >>= 1;
+    }
+    if result == 0 {
+        return None;
+    }
+    Some(if invert { u128::MAX / result } else { result })
+}
+
+/// Meteora DLMM pair and its cached bin arrays, fees are part of the pair account
+#[derive(Debug, Clone, Default)]
+pub struct DlmmVenue {
+    pub pool: Option<LbPair>,
+    pub bin_arrays: DlmmBinArrays,
+}
+
+impl DlmmVenue {
+    fn quote(
+        &self,
+        amount: u64,
+        swap_for_y: bool,
+        is_base_input: bool,
+        now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        let pair = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        dlmm_simulator::simulate_swap(
+            pair,
+            &self.bin_arrays,
+            amount,
+            swap_for_y,
+            is_base_input,
+            now,
+        )
+    }
+}
+
+/// Token program of a mint from the pair's flag, 0 SPL Token and 1 Token-2022
+fn token_program(flag: u8) -> Pubkey {
+    if flag == 1 {
+        TOKEN_2022_PROGRAM_ID
+    } else {
+        TOKEN_PROGRAM_ID
+    }
+}
+
+impl DexVenue for DlmmVenue {
+    fn pool_type(&self) -> PoolType {
+        PoolType::MeteoraDlmm
+    }
+
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        vec![
+            AccountFilter::Account(*pool_id),
+            AccountFilter::PoolArrays {
+                owner: DLMM_PROGRAM_ID,
+                data_size: BIN_ARRAY_ACCOUNT_LEN,
+                pool_offset: BIN_ARRAY_LB_PAIR_OFFSET,
+                pool: *pool_id,
+            },
+        ]
+    }
+
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        if pubkey == pool_id {
+            self.pool = Some(LbPair::decode(data)?);
+        } else if data.len() == BIN_ARRAY_ACCOUNT_LEN {
+            let bin_array = BinArray::decode(data)?;
+            if bin_array.lb_pair != *pool_id {
+                return Ok(false);
+            }
+            self.bin_arrays.insert(bin_array.index, bin_array);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn missing_accounts(&self, pool_id: &Pubkey) -> Vec<Pubkey> {
+        let Some(pair) = &self.pool else {
+            return Vec::new();
+        };
+        dlmm_simulator::missing_bin_arrays(pair, &self.bin_arrays)
+            .into_iter()
+            .map(|index| BinArray::address(pool_id, index))
+            .collect()
+    }
+
+    fn price(&self) -> Option<PoolPrice> {
+        let pair = self.pool.as_ref()?;
+        dlmm_simulator::pair_price(pair, &self.bin_arrays)
+    }
+
+    fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        let pair = self.pool.as_ref()?;
+        Some((pair.token_x_mint, pair.token_y_mint))
+    }
+
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        swap_for_y: bool,
+        now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_in, swap_for_y, true, now)
+    }
+
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        swap_for_y: bool,
+        now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_out, swap_for_y, false, now)
+    }
+
+    /// `swap` / `swap_exact_out` without the bitmap extension and host fee accounts. The bin
+    /// arrays with liquidity the swap goes through, plus the next one, are the remaining
+    /// accounts.
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction> {
+        let pair = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        // the dynamic fee doesn't change which bins a swap reaches enough to matter here
+        let quote = self.quote(
+            swap.amount,
+            swap.zero_for_one,
+            swap.is_base_input,
+            pair.v_parameters.last_update_timestamp,
+        )?;
+        let bins_crossed = quote.ticks_crossed as i32;
+        let (step, end_bin) = if swap.zero_for_one {
+            (-1, pair.active_id - bins_crossed)
+        } else {
+            (1, pair.active_id + bins_crossed)
+        };
+        let active = BinArray::bin_id_to_index(pair.active_id);
+        let end = BinArray::bin_id_to_index(end_bin);
+        let mut indexes: Vec<i64> = (0..=BIN_ARRAY_WINDOW)
+            .map(|offset| active + offset * step)
+            .filter(|index| pair.bin_array_has_liquidity(*index) == Some(true))
+            .collect();
+        let reached = indexes
+            .iter()
+            .filter(|index| (**index - end) * step <= 0)
+            .count();
+        indexes.truncate(reached + 1);
+
+        let (program_x, program_y) = (
+            token_program(pair.token_mint_x_program_flag),
+            token_program(pair.token_mint_y_program_flag),
+        );
+        let user_x = venue::user_token_account(&swap.user, &pair.token_x_mint, &program_x);
+        let user_y = venue::user_token_account(&swap.user, &pair.token_y_mint, &program_y);
+        let (user_in, user_out) = if swap.zero_for_one {
+            (user_x, user_y)
+        } else {
+            (user_y, user_x)
+        };
+        let event_authority =
+            Pubkey::find_program_address(&[EVENT_AUTHORITY_SEED], &DLMM_PROGRAM_ID).0;
+        // optional accounts left out are passed as the program id
+        let mut accounts = vec![
+            AccountMeta::new(*pool_id, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+            AccountMeta::new(pair.reserve_x, false),
+            AccountMeta::new(pair.reserve_y, false),
+            AccountMeta::new(user_in, false),
+            AccountMeta::new(user_out, false),
+            AccountMeta::new_readonly(pair.token_x_mint, false),
+            AccountMeta::new_readonly(pair.token_y_mint, false),
+            AccountMeta::new(pair.oracle, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+            AccountMeta::new_readonly(swap.user, true),
+            AccountMeta::new_readonly(program_x, false),
+            AccountMeta::new_readonly(program_y, false),
+            AccountMeta::new_readonly(event_authority, false),
+            AccountMeta::new_readonly(DLMM_PROGRAM_ID, false),
+        ];
+        accounts.extend(
+            indexes
+                .into_iter()
+                .map(|index| AccountMeta::new(BinArray::address(pool_id, index), false)),
+        );
+
+        let (name, first, second) = if swap.is_base_input {
+            ("swap", swap.amount, swap.other_amount_threshold)
+        } else {
+            ("swap_exact_out", swap.other_amount_threshold, swap.amount)
+        };
+        let mut data = venue::anchor_discriminator(name).to_vec();
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&second.to_le_bytes());
+        Ok(Instruction {
+            program_id: DLMM_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn DexVenue> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Pair at `active_id` with a 0.1% base fee and the bitmap bits of `bin_arrays` set
+    pub(crate) fn lb_pair(active_id: i32, bin_step: u16, bin_arrays: &[i64]) -> LbPair {
+        let mut pair = LbPair {
+            parameters: StaticParameters {
+                base_factor: 10_000,
+                filter_period: 30,
+                decay_period: 600,
+                reduction_factor: 5_000,
+                variable_fee_control: 7_500,
+                max_volatility_accumulator: 150_000,
+                min_bin_id: -443_636,
+                max_bin_id: 443_636,
+                protocol_share: 500,
+                ..Default::default()
+            },
+            v_parameters: VariableParameters {
+                index_reference: active_id,
+                ..Default::default()
+            },
+            active_id,
+            bin_step,
+            ..Default::default()
+        };
+        for index in bin_arrays {
+            let bit = (index + BIN_ARRAY_BITMAP_SIZE) as usize;
+            pair.bin_array_bitmap[bit / 64] |= 1 << (bit % 64);
+        }
+        pair
+    }
+
+    /// Raw bin array account with `bins` (bin id, amount x, amount y) filled
+    pub(crate) fn bin_array_data(
+        lb_pair: &Pubkey,
+        index: i64,
+        bins: &[(i32, u64, u64)],
+    ) -> Vec<u8> {
+        let mut data = vec![0; BIN_ARRAY_ACCOUNT_LEN];
+        data[8..16].copy_from_slice(&index.to_le_bytes());
+        data[BIN_ARRAY_LB_PAIR_OFFSET..BIN_ARRAY_LB_PAIR_OFFSET + 32]
+            .copy_from_slice(lb_pair.as_ref());
+        for &(bin_id, amount_x, amount_y) in bins {
+            let bin = Bin {
+                amount_x,
+                amount_y,
+                ..Default::default()
+            };
+            let offset = (i64::from(bin_id) - index * i64::from(MAX_BIN_PER_ARRAY)) as usize;
+            let at = BIN_ARRAY_LB_PAIR_OFFSET + 32 + offset * BIN_LEN;
+            data[at..at + BIN_LEN].copy_from_slice(&bincode::serialize(&bin).unwrap());
+        }
+        data
+    }
+
+    #[test]
+    fn decodes_accounts_and_prices_bins() {
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(&lb_pair(-1_897, 10, &[-28])).unwrap());
+        assert_eq!(data.len(), LB_PAIR_ACCOUNT_LEN);
+        let pair = LbPair::decode(&data).unwrap();
+        assert_eq!(pair.active_id, -1_897);
+        assert_eq!(pair.bin_array_has_liquidity(-28), Some(true));
+        assert_eq!(pair.bin_array_has_liquidity(-27), Some(false));
+        assert_eq!(pair.bin_array_has_liquidity(512), None);
+
+        let id = Pubkey::new_unique();
+        let bin_array = BinArray::decode(&bin_array_data(&id, -28, &[(-1_897, 5, 7)])).unwrap();
+        assert_eq!(bin_array.lb_pair, id);
+        assert_eq!(bin_array.bins.len(), MAX_BIN_PER_ARRAY as usize);
+        assert_eq!(BinArray::bin_id_to_index(-1_897), -28);
+        assert_eq!(bin_array.bin(-1_897).unwrap().amount_y, 7);
+        assert!(bin_array.bin(-1_890).is_none());
+
+        // 1.001 ^ -1897 ~ 0.15 USDC per lamport-scaled SOL
+        let price = get_price_from_id(-1_897, 10).unwrap();
+        assert!((price as f64 / ONE as f64 - 0.150_2).abs() < 1e-4);
+        assert_eq!(get_price_from_id(0, 10), Some(ONE));
+        let up = get_price_from_id(1, 10).unwrap();
+        assert_eq!(up / (ONE / 10_000), 10_010);
+
+        // 0.1% base fee, the variable part grows with the accumulator
+        assert_eq!(pair.total_fee_rate(0), 1_000_000);
+        assert_eq!(pair.total_fee_rate(10_000), 1_000_750);
+    }
+
+    #[test]
+    fn venue_quotes_once_pair_and_bin_arrays_are_cached() {
+        let pool_id = Pubkey::new_unique();
+        let mut venue = DlmmVenue::default();
+        assert!(venue.quote_exact_in(1_000_000_000, true, 0).is_err());
+        assert!(venue.missing_accounts(&pool_id).is_empty());
+
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(&lb_pair(-1_897, 10, &[-28])).unwrap());
+        assert!(venue.apply_account(&pool_id, &pool_id, &data).unwrap());
+        assert_eq!(
+            venue.missing_accounts(&pool_id),
+            vec![BinArray::address(&pool_id, -28)]
+        );
+        let uncached = venue.quote_exact_in(1_000_000_000, true, 0).unwrap();
+        assert!(!uncached.complete);
+        assert_eq!(uncached.amount_out, 0);
+
+        // a bin array of another pair is ignored
+        let bins = [(-1_897, 10_000_000_000, 1_000_000_000)];
+        let other = bin_array_data(&Pubkey::new_unique(), -28, &bins);
+        assert!(
+            !venue
+                .apply_account(&pool_id, &Pubkey::new_unique(), &other)
+                .unwrap()
+        );
+        assert_eq!(venue.missing_accounts(&pool_id).len(), 1);
+
+        let ours = bin_array_data(&pool_id, -28, &bins);
+        assert!(
+            venue
+                .apply_account(&pool_id, &Pubkey::new_unique(), &ours)
+                .unwrap()
+        );
+        assert!(venue.missing_accounts(&pool_id).is_empty());
+        let sell = venue.quote_exact_in(1_000_000_000, true, 0).unwrap();
+        let buy = venue.quote_exact_in(150_000_000, false, 0).unwrap();
+        assert!(sell.complete && buy.complete);
+        assert!(sell.amount_out > 149_000_000 && sell.amount_out < 150_300_000);
+        assert!(buy.amount_out > 990_000_000 && buy.amount_out < 1_000_000_000);
+        assert!(venue.price().unwrap().liquidity > 0);
+    }
+}
@@ -0,0 +1,275 @@
+//! Prometheus metrics, served as text on `/metrics`. Amounts are reported in whole SOL / USDC,
+//! durations in seconds.
+
+use std::sync::LazyLock;
+
+use axum::{Router, http::header, response::IntoResponse, routing::get};
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, Opts, Registry,
+    TextEncoder,
+};
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    arb::Opportunity,
+    execution::{Decision, ExecutionCoordinator},
+    ledger,
+    market::{CexStruct, DexStruct},
+};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub listen: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: "127.0.0.1:9100".to_string(),
+        }
+    }
+}
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    pub account_updates: IntCounterVec,
+    pub grpc_reconnects: IntCounter,
+    pub task_restarts: IntCounterVec,
+    pub cex_book_age: Gauge,
+    pub detection_latency: HistogramVec,
+    pub opportunities: IntCounterVec,
+    pub spread_bps: HistogramVec,
+    pub trades_executed: IntCounterVec,
+    pub risk_rejections: IntCounterVec,
+    pub realized_pnl: Gauge,
+    pub unrealized_pnl: Gauge,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("arb".to_string()), None).unwrap();
+        let metrics = Self {
+            account_updates: IntCounterVec::new(
+                Opts::new("account_updates_total", "Account updates received"),
+                &["pool"],
+            )
+            .unwrap(),
+            grpc_reconnects: IntCounter::new("grpc_reconnects_total", "gRPC stream reconnects")
+                .unwrap(),
+            task_restarts: IntCounterVec::new(
+                Opts::new("task_restarts_total", "Supervised task restarts"),
+                &["task"],
+            )
+            .unwrap(),
+            cex_book_age: Gauge::new(
+                "cex_book_age_seconds",
+                "Age of the CEX book at the last detection pass",
+            )
+            .unwrap(),
+            detection_latency: HistogramVec::new(
+                HistogramOpts::new(
+                    "detection_latency_seconds",
+                    "Latest market update receipt to decision",
+                )
+                .buckets(vec![
+                    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+                ]),
+                &["pair"],
+            )
+            .unwrap(),
+            opportunities: IntCounterVec::new(
+                Opts::new("opportunities_total", "Opportunities detected"),
+                &["pair", "direction"],
+            )
+            .unwrap(),
+            spread_bps: HistogramVec::new(
+                HistogramOpts::new("spread_bps", "Opportunity spread in bps of its cost")
+                    .buckets(vec![1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0]),
+                &["pair", "direction"],
+            )
+            .unwrap(),
+            trades_executed: IntCounterVec::new(
+                Opts::new("trades_executed_total", "Opportunities executed"),
+                &["pair", "direction"],
+            )
+            .unwrap(),
+            risk_rejections: IntCounterVec::new(
+                Opts::new(
+                    "risk_rejections_total",
+                    "Opportunities rejected by the risk engine",
+                ),
+                &["reason"],
+            )
+            .unwrap(),
+            realized_pnl: Gauge::new("realized_pnl_usdc", "Realized PnL").unwrap(),
+            unrealized_pnl: Gauge::new("unrealized_pnl_usdc", "Unrealized PnL at the CEX mid")
+                .unwrap(),
+            registry,
+        };
+        metrics.register().unwrap();
+        metrics
+    }
+
+    fn register(&self) -> prometheus::Result<()> {
+        self.registry
+            .register(Box::new(self.account_updates.clone()))?;
+        self.registry
+            .register(Box::new(self.grpc_reconnects.clone()))?;
+        self.registry
+            .register(Box::new(self.task_restarts.clone()))?;
+        self.registry
+            .register(Box::new(self.cex_book_age.clone()))?;
+        self.registry
+            .register(Box::new(self.detection_latency.clone()))?;
+        self.registry
+            .register(Box::new(self.opportunities.clone()))?;
+        self.registry.register(Box::new(self.spread_bps.clone()))?;
+        self.registry
+            .register(Box::new(self.trades_executed.clone()))?;
+        self.registry
+            .register(Box::new(self.risk_rejections.clone()))?;
+        self.registry
+            .register(Box::new(self.realized_pnl.clone()))?;
+        self.registry
+            .register(Box::new(self.unrealized_pnl.clone()))?;
+        Ok(())
+    }
+
+    /// Records a detection pass on a new snapshot: how long after its latest update the pass
+    /// finished, then each opportunity and its decision
+    pub fn observe_pass(
+        &self,
+        pair: &str,
+        dex: &DexStruct,
+        cex: &CexStruct,
+        decisions: &[(Opportunity, Decision)],
+        coordinator: &ExecutionCoordinator,
+    ) {
+        let received_us = dex.received_us.max(cex.received_us);
+        if received_us > 0 {
+            self.detection_latency
+                .with_label_values(&[pair])
+                .observe(ledger::unix_time_us().saturating_sub(received_us) as f64 / 1e6);
+        }
+        for (opp, decision) in decisions {
+            let labels = [pair, opp.direction.id()];
+            self.opportunities.with_label_values(&labels).inc();
+            if opp.cost > 0 {
+                self.spread_bps
+                    .with_label_values(&labels)
+                    .observe(opp.spread as f64 * 10_000.0 / opp.cost as f64);
+            }
+            match decision {
+                Decision::Executed { .. } => {
+                    self.trades_executed.with_label_values(&labels).inc();
+                }
+                Decision::Rejected { reason } => {
+                    self.risk_rejections.with_label_values(&[reason.id()]).inc();
+                }
+                Decision::Logged | Decision::Failed { .. } => {}
+            }
+        }
+        self.observe_state(cex, coordinator);
+    }
+
+    /// Records how stale the book is and the PnL, on every pass whether the snapshot is new or not
+    pub fn observe_state(&self, cex: &CexStruct, coordinator: &ExecutionCoordinator) {
+        if cex.received_us > 0 {
+            self.cex_book_age
+                .set(ledger::unix_time_us().saturating_sub(cex.received_us) as f64 / 1e6);
+        }
+        self.realized_pnl
+            .set(coordinator.ledger.realized_pnl as f64 / 1e6);
+        if let Some(mid) = cex.book.mid() {
+            self.unrealized_pnl
+                .set(coordinator.ledger.unrealized_pnl(mid) as f64 / 1e6);
+        }
+    }
+
+    /// Text exposition of every metric
+    pub fn render(&self) -> String {
+        let mut buf = Vec::new();
+        // encoding into a Vec can't fail
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buf);
+        String::from_utf8(buf).unwrap_or_default()
+    }
+}
+
+async fn metrics_handler() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        METRICS.render(),
+    )
+}
+
+/// Serves `/metrics` on `config.listen` until `token` is cancelled
+pub async fn serve(config: MetricsConfig, token: CancellationToken) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(&config.listen).await?;
+    info!(listen = %config.listen, "Serving metrics");
+    let app = Router::new().route("/metrics", get(metrics_handler));
+    axum::serve(listener, app)
+        .with_graceful_shutdown(token.cancelled_owned())
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{arb::Direction, config::Config, risk::RejectReason};
+
+    #[test]
+    fn pass_is_rendered_with_labels() {
+        let metrics = Metrics::new();
+        let opp = Opportunity {
+            direction: Direction::BuyCexSellDex,
+            base_amount: 1_000_000_000,
+            cost: 150_000_000,
+            proceeds: 150_300_000,
+            spread: 300_000,
+            cex_fee: 150_000,
+            tx_fee: 5_000,
+            slot: 1,
+        };
+        let decisions = [
+            (opp.clone(), Decision::Logged),
+            (
+                opp,
+                Decision::Rejected {
+                    reason: RejectReason::MaxNotional,
+                },
+            ),
+        ];
+        let coordinator = ExecutionCoordinator::new(&Config::default());
+        let cex = CexStruct {
+            received_us: ledger::unix_time_us(),
+            ..Default::default()
+        };
+        metrics.observe_pass(
+            "SOL_USDC",
+            &DexStruct::default(),
+            &cex,
+            &decisions,
+            &coordinator,
+        );
+
+        let text = metrics.render();
+        assert!(text.contains(
+            r#"arb_opportunities_total{direction="buy_cex_sell_dex",pair="SOL_USDC"} 2"#
+        ));
+        assert!(text.contains(
+            r#"arb_spread_bps_bucket{direction="buy_cex_sell_dex",pair="SOL_USDC",le="20"} 2"#
+        ));
+        assert!(text.contains(r#"arb_risk_rejections_total{reason="max_notional"} 1"#));
+        assert!(text.contains("arb_realized_pnl_usdc 0"));
+        // one latency sample per pass, however many opportunities it found
+        assert!(text.contains(r#"arb_detection_latency_seconds_count{pair="SOL_USDC"} 1"#));
+    }
+}
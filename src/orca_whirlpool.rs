@@ -0,0 +1,381 @@
+//! Orca Whirlpool account layouts. Whirlpools use the same Q64.64 sqrt price and tick math as
+//! Raydium CLMM; the differences are the fee rate living in the pool account and tick arrays
+//! of 88 ticks that don't store their tick index.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    balances::{TOKEN_PROGRAM_ID, associated_token_address},
+    clmm_simulator::{PoolPrice, SwapResult},
+    venue::{self, AccountFilter, DexVenue, PoolType, SwapParams},
+    whirlpool_simulator::{self, MAX_TICK_ARRAYS, WhirlpoolTickArrays},
+};
+
+pub const WHIRLPOOL_PROGRAM_ID: Pubkey = pubkey!("whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc");
+pub const TICK_ARRAY_SEED: &[u8] = b"tick_array";
+pub const ORACLE_SEED: &[u8] = b"oracle";
+/// Ticks per tick array
+pub const TICK_ARRAY_SIZE: i32 = 88;
+/// Size of a whirlpool account, including the 8 byte anchor discriminator
+pub const WHIRLPOOL_ACCOUNT_LEN: usize = 653;
+/// Size of a (fixed) tick array account, including the 8 byte anchor discriminator
+pub const TICK_ARRAY_ACCOUNT_LEN: usize = 9988;
+/// Offset of the whirlpool address in a tick array account, after the ticks
+pub const TICK_ARRAY_WHIRLPOOL_OFFSET: usize = TICK_ARRAY_ACCOUNT_LEN - 32;
+/// Size of a packed `Tick` inside a tick array account
+const TICK_LEN: usize = 113;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Whirlpool {
+    pub whirlpools_config: Pubkey,
+    pub whirlpool_bump: [u8; 1],
+    pub tick_spacing: u16,
+    pub fee_tier_index_seed: [u8; 2],
+    /// Swap fee, denominated in hundredths of a bip (10^-6)
+    pub fee_rate: u16,
+    /// Share of the swap fee taken by the protocol, in bps of the fee
+    pub protocol_fee_rate: u16,
+    /// The currently in range liquidity available to the pool.
+    pub liquidity: u128,
+    /// The current price of the pool as a sqrt(token_b/token_a) Q64.64 value
+    pub sqrt_price: u128,
+    pub tick_current_index: i32,
+    pub protocol_fee_owed_a: u64,
+    pub protocol_fee_owed_b: u64,
+    pub token_mint_a: Pubkey,
+    pub token_vault_a: Pubkey,
+    pub fee_growth_global_a: u128,
+    pub token_mint_b: Pubkey,
+    pub token_vault_b: Pubkey,
+    pub fee_growth_global_b: u128,
+    pub reward_last_updated_timestamp: u64,
+    pub reward_infos: [WhirlpoolRewardInfo; 3],
+}
+
+impl Whirlpool {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < WHIRLPOOL_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: whirlpool account too short"));
+        }
+        Ok(bincode::deserialize(&data[8..])?)
+    }
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct WhirlpoolRewardInfo {
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    pub authority: Pubkey,
+    pub emissions_per_second_x64: u128,
+    pub growth_global_x64: u128,
+}
+
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+pub struct Tick {
+    pub initialized: bool,
+    pub liquidity_net: i128,
+    pub liquidity_gross: u128,
+    pub fee_growth_outside_a: u128,
+    pub fee_growth_outside_b: u128,
+    pub reward_growths_outside: [u128; 3],
+}
+
+#[derive(Clone, Debug)]
+pub struct WhirlpoolTickArray {
+    pub start_tick_index: i32,
+    /// Tick `i` sits at `start_tick_index + i * tick_spacing`
+    pub ticks: Vec<Tick>,
+    pub whirlpool: Pubkey,
+}
+
+impl WhirlpoolTickArray {
+    /// Decodes a raw tick array account, ticks are read one by one like Raydium's. Only the fixed
+    /// size layout is supported.
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != TICK_ARRAY_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: invalid tick array account size"));
+        }
+        let start_tick_index = i32::from_le_bytes(data[8..12].try_into()?);
+        let ticks = data[12..TICK_ARRAY_WHIRLPOOL_OFFSET]
+            .chunks_exact(TICK_LEN)
+            .map(bincode::deserialize::<Tick>)
+            .collect::<Result<Vec<_>, _>>()?;
+        let whirlpool = Pubkey::try_from(&data[TICK_ARRAY_WHIRLPOOL_OFFSET..])?;
+        Ok(Self {
+            start_tick_index,
+            ticks,
+            whirlpool,
+        })
+    }
+
+    /// Number of ticks covered by one tick array
+    pub fn tick_count(tick_spacing: u16) -> i32 {
+        TICK_ARRAY_SIZE * i32::from(tick_spacing)
+    }
+
+    /// Start index of the tick array containing `tick_index`
+    pub fn get_array_start_index(tick_index: i32, tick_spacing: u16) -> i32 {
+        let ticks_in_array = Self::tick_count(tick_spacing);
+        tick_index.div_euclid(ticks_in_array) * ticks_in_array
+    }
+
+    /// Unlike Raydium the start index is part of the seeds as a decimal string
+    pub fn address(whirlpool: &Pubkey, start_tick_index: i32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                TICK_ARRAY_SEED,
+                whirlpool.as_ref(),
+                start_tick_index.to_string().as_bytes(),
+            ],
+            &WHIRLPOOL_PROGRAM_ID,
+        )
+        .0
+    }
+}
+
+/// Orca whirlpool and its cached tick arrays, the fee rate is part of the pool account
+#[derive(Debug, Clone, Default)]
+pub struct WhirlpoolVenue {
+    pub pool: Option<Whirlpool>,
+    pub tick_arrays: WhirlpoolTickArrays,
+    /// Tick arrays near the price that were never initialized on chain, not requested again
+    pub absent: HashSet<Pubkey>,
+}
+
+impl WhirlpoolVenue {
+    fn quote(&self, amount: u64, a_to_b: bool, is_base_input: bool) -> anyhow::Result<SwapResult> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        whirlpool_simulator::simulate_swap(pool, &self.tick_arrays, amount, a_to_b, is_base_input)
+    }
+}
+
+impl DexVenue for WhirlpoolVenue {
+    fn pool_type(&self) -> PoolType {
+        PoolType::OrcaWhirlpool
+    }
+
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        vec![
+            AccountFilter::Account(*pool_id),
+            AccountFilter::PoolArrays {
+                owner: WHIRLPOOL_PROGRAM_ID,
+                data_size: TICK_ARRAY_ACCOUNT_LEN,
+                pool_offset: TICK_ARRAY_WHIRLPOOL_OFFSET,
+                pool: *pool_id,
+            },
+        ]
+    }
+
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        if pubkey == pool_id {
+            self.pool = Some(Whirlpool::decode(data)?);
+        } else if data.len() == TICK_ARRAY_ACCOUNT_LEN {
+            let tick_array = WhirlpoolTickArray::decode(data)?;
+            if tick_array.whirlpool != *pool_id {
+                return Ok(false);
+            }
+            self.absent.remove(pubkey);
+            self.tick_arrays
+                .insert(tick_array.start_tick_index, tick_array);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn missing_accounts(&self, pool_id: &Pubkey) -> Vec<Pubkey> {
+        let Some(pool) = &self.pool else {
+            return Vec::new();
+        };
+        whirlpool_simulator::missing_tick_arrays(pool, &self.tick_arrays)
+            .into_iter()
+            .map(|start| WhirlpoolTickArray::address(pool_id, start))
+            .filter(|address| !self.absent.contains(address))
+            .collect()
+    }
+
+    fn mark_absent(&mut self, pubkey: &Pubkey) {
+        self.absent.insert(*pubkey);
+    }
+
+    fn price(&self) -> Option<PoolPrice> {
+        self.pool.as_ref().map(|pool| PoolPrice {
+            sqrt_price_x64: pool.sqrt_price,
+            liquidity: pool.liquidity,
+            tick: pool.tick_current_index,
+        })
+    }
+
+    fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        let pool = self.pool.as_ref()?;
+        Some((pool.token_mint_a, pool.token_mint_b))
+    }
+
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        a_to_b: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_in, a_to_b, true)
+    }
+
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        a_to_b: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_out, a_to_b, false)
+    }
+
+    /// `swap` with the current tick array and the next two in the swap direction. Arrays that
+    /// don't exist on chain repeat the previous one, which the program accepts past the first.
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let ticks_in_array = WhirlpoolTickArray::tick_count(pool.tick_spacing);
+        let step = if swap.zero_for_one {
+            -ticks_in_array
+        } else {
+            ticks_in_array
+        };
+        let current =
+            WhirlpoolTickArray::get_array_start_index(pool.tick_current_index, pool.tick_spacing);
+        let mut tick_arrays: Vec<Pubkey> = Vec::new();
+        for offset in 0..MAX_TICK_ARRAYS {
+            let address = WhirlpoolTickArray::address(pool_id, current + offset * step);
+            match tick_arrays.last() {
+                Some(previous) if self.absent.contains(&address) => tick_arrays.push(*previous),
+                _ => tick_arrays.push(address),
+            }
+        }
+        let oracle =
+            Pubkey::find_program_address(&[ORACLE_SEED, pool_id.as_ref()], &WHIRLPOOL_PROGRAM_ID).0;
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(swap.user, true),
+            AccountMeta::new(*pool_id, false),
+            AccountMeta::new(
+                associated_token_address(&swap.user, &pool.token_mint_a),
+                false,
+            ),
+            AccountMeta::new(pool.token_vault_a, false),
+            AccountMeta::new(
+                associated_token_address(&swap.user, &pool.token_mint_b),
+                false,
+            ),
+            AccountMeta::new(pool.token_vault_b, false),
+        ];
+        accounts.extend(
+            tick_arrays
+                .into_iter()
+                .map(|address| AccountMeta::new(address, false)),
+        );
+        accounts.push(AccountMeta::new(oracle, false));
+
+        let mut data = venue::anchor_discriminator("swap").to_vec();
+        data.extend_from_slice(&swap.amount.to_le_bytes());
+        data.extend_from_slice(&swap.other_amount_threshold.to_le_bytes());
+        // no price limit, the threshold protects the swap
+        data.extend_from_slice(&0u128.to_le_bytes());
+        data.push(u8::from(swap.is_base_input));
+        data.push(u8::from(swap.zero_for_one));
+        Ok(Instruction {
+            program_id: WHIRLPOOL_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn DexVenue> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::raydium_math::tick_math;
+
+    pub(crate) fn whirlpool(tick_current: i32, liquidity: u128, fee_rate: u16) -> Whirlpool {
+        Whirlpool {
+            whirlpools_config: Pubkey::default(),
+            whirlpool_bump: [0],
+            tick_spacing: 1,
+            fee_tier_index_seed: [1, 0],
+            fee_rate,
+            protocol_fee_rate: 1_300,
+            liquidity,
+            sqrt_price: tick_math::get_sqrt_price_at_tick(tick_current).unwrap(),
+            tick_current_index: tick_current,
+            protocol_fee_owed_a: 0,
+            protocol_fee_owed_b: 0,
+            token_mint_a: Pubkey::default(),
+            token_vault_a: Pubkey::default(),
+            fee_growth_global_a: 0,
+            token_mint_b: Pubkey::default(),
+            token_vault_b: Pubkey::default(),
+            fee_growth_global_b: 0,
+            reward_last_updated_timestamp: 0,
+            reward_infos: Default::default(),
+        }
+    }
+
+    /// Raw tick array account with `ticks` (offset in the array, liquidity net) initialized
+    pub(crate) fn tick_array_data(
+        whirlpool: &Pubkey,
+        start: i32,
+        ticks: &[(usize, i128)],
+    ) -> Vec<u8> {
+        let mut data = vec![0; TICK_ARRAY_ACCOUNT_LEN];
+        data[8..12].copy_from_slice(&start.to_le_bytes());
+        for &(offset, liquidity_net) in ticks {
+            let tick = Tick {
+                initialized: true,
+                liquidity_net,
+                liquidity_gross: liquidity_net.unsigned_abs(),
+                ..Default::default()
+            };
+            let at = 12 + offset * TICK_LEN;
+            data[at..at + TICK_LEN].copy_from_slice(&bincode::serialize(&tick).unwrap());
+        }
+        data[TICK_ARRAY_WHIRLPOOL_OFFSET..].copy_from_slice(whirlpool.as_ref());
+        data
+    }
+
+    #[test]
+    fn decodes_accounts_at_their_on_chain_size() {
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(&whirlpool(-18_971, 1_000, 400)).unwrap());
+        assert_eq!(data.len(), WHIRLPOOL_ACCOUNT_LEN);
+        let pool = Whirlpool::decode(&data).unwrap();
+        assert_eq!(pool.tick_current_index, -18_971);
+        assert_eq!(pool.fee_rate, 400);
+
+        let id = Pubkey::new_unique();
+        let tick_array =
+            WhirlpoolTickArray::decode(&tick_array_data(&id, -19_008, &[(87, -5)])).unwrap();
+        assert_eq!(tick_array.start_tick_index, -19_008);
+        assert_eq!(tick_array.ticks.len(), TICK_ARRAY_SIZE as usize);
+        assert!(tick_array.ticks[87].initialized);
+        assert_eq!(tick_array.ticks[87].liquidity_net, -5);
+        assert_eq!(tick_array.whirlpool, id);
+        assert_eq!(
+            WhirlpoolTickArray::get_array_start_index(-18_971, 1),
+            -19_008
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! Local copy of the CEX order book.
+//!
+//! Prices are kept in micro-USDC per SOL and sizes in lamports so they line up with the amounts
+//! coming out of the DEX math.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+pub const PRICE_SCALE: f64 = 1_000_000.0; // USDC decimals
+pub const QTY_SCALE: f64 = 1_000_000_000.0; // SOL decimals
+const LAMPORTS_PER_SOL: u128 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Level {
+    pub price: u64, // micro-USDC per SOL
+    pub qty: u64,   // lamports
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OrderBook {
+    /// Sorted best (highest) first
+    pub bids: Vec<Level>,
+    /// Sorted best (lowest) first
+    pub asks: Vec<Level>,
+}
+
+/// Result of walking the book for a given base amount
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BookFill {
+    pub base: u64,  // lamports filled
+    pub quote: u64, // micro-USDC paid or received
+}
+
+impl OrderBook {
+    pub fn best_bid(&self) -> Option<u64> {
+        self.bids.first().map(|l| l.price)
+    }
+
+    pub fn best_ask(&self) -> Option<u64> {
+        self.asks.first().map(|l| l.price)
+    }
+
+    /// Market buy of `base` lamports against the asks, cost rounded up
+    pub fn buy_base(&self, base: u64) -> BookFill {
+        walk(&self.asks, base, true)
+    }
+
+    pub fn mid(&self) -> Option<u64> {
+        Some((self.best_bid()? + self.best_ask()?) / 2)
+    }
+
+    /// Lamports that can be bought from the asks while spending at most `quote` micro-USDC
+    pub fn base_for_quote(&self, quote: u64) -> u64 {
+        let mut base = 0;
+        let mut remaining = quote as u128;
+        for level in &self.asks {
+            let cost = (level.qty as u128 * level.price as u128).div_ceil(LAMPORTS_PER_SOL);
+            if cost > remaining {
+                base += (remaining * LAMPORTS_PER_SOL / level.price as u128) as u64;
+                break;
+            }
+            base += level.qty;
+            remaining -= cost;
+        }
+        base
+    }
+
+    /// Market sell of `base` lamports against the bids, proceeds rounded down
+    pub fn sell_base(&self, base: u64) -> BookFill {
+        walk(&self.bids, base, false)
+    }
+}
+
+/// Incremental update of a depth stream, covering update ids `first_update_id..=last_update_id`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DepthUpdate {
+    pub first_update_id: u64,
+    pub last_update_id: u64,
+    /// `(price, qty)`, a zero quantity removes the level
+    pub bids: Vec<(String, String)>,
+    pub asks: Vec<(String, String)>,
+}
+
+/// Book kept from a snapshot plus the incremental updates of a depth stream. Updates that arrive
+/// before the snapshot are held back, a gap in the update ids means the book must be rebuilt.
+#[derive(Debug, Clone, Default)]
+pub struct DepthBook {
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+    /// Last update applied, `None` until the snapshot arrives
+    last_update_id: Option<u64>,
+    pending: Vec<DepthUpdate>,
+}
+
+impl DepthBook {
+    /// Replaces the book with a snapshot as of `last_update_id`, then applies the updates held
+    /// back that are newer
+    pub fn snapshot(
+        &mut self,
+        last_update_id: u64,
+        bids: &[(String, String)],
+        asks: &[(String, String)],
+    ) -> anyhow::Result<()> {
+        self.bids.clear();
+        self.asks.clear();
+        apply_levels(&mut self.bids, bids)?;
+        apply_levels(&mut self.asks, asks)?;
+        self.last_update_id = Some(last_update_id);
+        for update in std::mem::take(&mut self.pending) {
+            self.update(update)?;
+        }
+        Ok(())
+    }
+
+    /// Applies `update`, returns whether the book changed. Updates already covered by the book
+    /// are skipped.
+    pub fn update(&mut self, update: DepthUpdate) -> anyhow::Result<bool> {
+        let Some(last_update_id) = self.last_update_id else {
+            self.pending.push(update);
+            return Ok(false);
+        };
+        if update.last_update_id <= last_update_id {
+            return Ok(false);
+        }
+        if update.first_update_id > last_update_id + 1 {
+            return Err(anyhow::Error::msg(format!(
+                "Error: depth stream gap, expected update {} got {}",
+                last_update_id + 1,
+                update.first_update_id
+            )));
+        }
+        apply_levels(&mut self.bids, &update.bids)?;
+        apply_levels(&mut self.asks, &update.asks)?;
+        self.last_update_id = Some(update.last_update_id);
+        Ok(true)
+    }
+
+    /// Drops the book and anything held back, until the next snapshot
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Best `depth` levels on each side, `None` until the snapshot arrives
+    pub fn book(&self, depth: usize) -> Option<OrderBook> {
+        self.last_update_id?;
+        let level = |(&price, &qty)| Level { price, qty };
+        Some(OrderBook {
+            bids: self.bids.iter().rev().take(depth).map(level).collect(),
+            asks: self.asks.iter().take(depth).map(level).collect(),
+        })
+    }
+}
+
+fn apply_levels(side: &mut BTreeMap<u64, u64>, levels: &[(String, String)]) -> anyhow::Result<()> {
+    for level in parse_levels(levels)? {
+        if level.qty == 0 {
+            side.remove(&level.price);
+        } else {
+            side.insert(level.price, level.qty);
+        }
+    }
+    Ok(())
+}
+
+fn parse_levels(levels: &[(String, String)]) -> anyhow::Result<Vec<Level>> {
+    levels
+        .iter()
+        .map(|(price, qty)| {
+            Ok(Level {
+                price: (price.parse::<f64>()? * PRICE_SCALE).round() as u64,
+                qty: (qty.parse::<f64>()? * QTY_SCALE).round() as u64,
+            })
+        })
+        .collect()
+}
+
+fn walk(levels: &[Level], base: u64, round_up: bool) -> BookFill {
+    let mut fill = BookFill::default();
+    let mut remaining = base;
+    for level in levels {
+        if remaining == 0 {
+            break;
+        }
+        let qty = remaining.min(level.qty);
+        let notional = qty as u128 * level.price as u128;
+        let quote = if round_up {
+            notional.div_ceil(LAMPORTS_PER_SOL)
+        } else {
+            notional / LAMPORTS_PER_SOL
+        };
+        fill.base += qty;
+        fill.quote += quote as u64;
+        remaining -= qty;
+    }
+    fill
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn s(price: &str, qty: &str) -> (String, String) {
+        (price.to_string(), qty.to_string())
+    }
+
+    fn book() -> OrderBook {
+        let mut depth = DepthBook::default();
+        depth
+            .snapshot(
+                1,
+                &[s("149.90", "1"), s("150.00", "0.5")],
+                &[s("150.20", "2"), s("150.10", "0.5")],
+            )
+            .unwrap();
+        depth.book(10).unwrap()
+    }
+
+    #[test]
+    fn sorts_levels_best_first() {
+        let book = book();
+        assert_eq!(book.best_bid(), Some(150_000_000));
+        assert_eq!(book.best_ask(), Some(150_100_000));
+        assert_eq!(book.mid(), Some(150_050_000));
+    }
+
+    #[test]
+    fn walks_multiple_levels() {
+        let fill = book().buy_base(1_000_000_000);
+        assert_eq!(fill.base, 1_000_000_000);
+        assert_eq!(fill.quote, 75_050_000 + 75_100_000);
+    }
+
+    #[test]
+    fn base_for_quote_matches_buy_cost() {
+        let book = book();
+        let base = book.base_for_quote(150_150_000);
+        assert_eq!(base, 1_000_000_000);
+        assert!(book.buy_base(base).quote <= 150_150_000);
+    }
+
+    #[test]
+    fn partial_fill_when_book_is_thin() {
+        let fill = book().sell_base(2_000_000_000);
+        assert_eq!(fill.base, 1_500_000_000);
+        assert_eq!(fill.quote, 75_000_000 + 149_900_000);
+    }
+
+    #[test]
+    fn depth_book_replays_updates_newer_than_the_snapshot() {
+        let mut depth = DepthBook::default();
+        let update = |first, last, bids: Vec<(String, String)>| DepthUpdate {
+            first_update_id: first,
+            last_update_id: last,
+            bids,
+            asks: Vec::new(),
+        };
+        // held back until the snapshot, the first one is already part of it
+        assert!(!depth.update(update(8, 10, vec![s("150.00", "9")])).unwrap());
+        assert!(
+            !depth
+                .update(update(11, 12, vec![s("149.90", "0")]))
+                .unwrap()
+        );
+        assert_eq!(depth.book(10), None);
+        depth
+            .snapshot(10, &[s("150.00", "1"), s("149.90", "2")], &[])
+            .unwrap();
+        let book = depth.book(10).unwrap();
+        assert_eq!(
+            book.bids,
+            vec![Level {
+                price: 150_000_000,
+                qty: 1_000_000_000
+            }]
+        );
+
+        assert!(
+            depth
+                .update(update(13, 13, vec![s("150.05", "3")]))
+                .unwrap()
+        );
+        assert_eq!(depth.book(1).unwrap().best_bid(), Some(150_050_000));
+        assert!(depth.update(update(15, 16, Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Paper trading: fills opportunities against the local order book and the CLMM simulator and
+//! keeps virtual balances, so strategy changes can be checked without touching funds.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    arb::{Direction, Opportunity, lamports_to_quote, taker_fee},
+    balances::{Asset, Venue},
+    ledger::{Fill, SIGNATURE_FEE},
+    market::{CexStruct, DexStruct},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaperBalances {
+    pub cex_sol: u64,     // lamports
+    pub cex_usdc: u64,    // micro-USDC
+    pub wallet_sol: u64,  // lamports
+    pub wallet_usdc: u64, // micro-USDC
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaperTrade {
+    pub direction: Direction,
+    pub base_amount: u64,
+    pub cex_quote: u64, // micro-USDC paid or received on the CEX, before fees
+    pub cex_fee: u64,
+    pub dex_quote: u64, // micro-USDC paid or received on the DEX, pool fee included
+    pub sol_delta: i64, // net lamports left over when the legs don't match exactly
+    pub tx_fee: u64,    // lamports paid by the DEX transaction, priority fee included
+    pub pnl: i64,
+}
+
+impl PaperTrade {
+    /// SOL left over when the legs don't match exactly, the only position a filled trade leaves
+    /// open. The USDC difference between the legs is realized PnL.
+    pub fn imbalances(&self) -> [(Asset, i64); 1] {
+        [(Asset::Sol, self.sol_delta)]
+    }
+
+    /// The buy and sell leg as ledger fills. The pool fee is part of the DEX quote, the DEX leg
+    /// pays the transaction fee.
+    pub fn legs(&self) -> (Fill, Fill) {
+        let cex = Fill {
+            venue: Venue::Cex,
+            base: self.base_amount,
+            quote: self.cex_quote,
+            fee: self.cex_fee,
+            tx_fee: 0,
+            priority_fee: 0,
+            tip: 0,
+        };
+        // `sol_delta` is whatever the buy leg got beyond what the sell leg sold
+        let dex_base = match self.direction {
+            Direction::BuyCexSellDex => self.base_amount as i64 - self.sol_delta,
+            Direction::BuyDexSellCex => self.base_amount as i64 + self.sol_delta,
+        };
+        let dex = Fill {
+            venue: Venue::Wallet,
+            base: dex_base as u64,
+            quote: self.dex_quote,
+            fee: 0,
+            tx_fee: self.tx_fee.min(SIGNATURE_FEE),
+            priority_fee: self.tx_fee.saturating_sub(SIGNATURE_FEE),
+            tip: 0,
+        };
+        match self.direction {
+            Direction::BuyCexSellDex => (cex, dex),
+            Direction::BuyDexSellCex => (dex, cex),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct PaperExecutor {
+    pub balances: PaperBalances,
+    pub trades: Vec<PaperTrade>,
+    pub realized_pnl: i64,
+    cex_taker_fee_bps: u64,
+}
+
+impl PaperExecutor {
+    pub fn new(balances: PaperBalances, cex_taker_fee_bps: u64) -> Self {
+        Self {
+            balances,
+            trades: Vec::new(),
+            realized_pnl: 0,
+            cex_taker_fee_bps,
+        }
+    }
+
+    /// Fills both legs of `opp` against the current market state. Rejects the trade without
+    /// touching balances if either venue can't cover its leg or can't fill all of it. The DEX
+    /// leg pays the opportunity's transaction fee from the wallet's SOL.
+    pub fn execute(
+        &mut self,
+        opp: &Opportunity,
+        dex: &DexStruct,
+        cex: &CexStruct,
+    ) -> anyhow::Result<PaperTrade> {
+        let mut balances = self.balances.clone();
+        let trade = match opp.direction {
+            Direction::BuyCexSellDex => {
+                let fill = cex.book.buy_base(opp.base_amount);
+                if fill.base < opp.base_amount {
+                    return Err(anyhow::Error::msg(
+                        "Error: CEX book too thin to fill the buy leg",
+                    ));
+                }
+                let cex_fee = taker_fee(fill.quote, self.cex_taker_fee_bps);
+                balances.cex_usdc = balances
+                    .cex_usdc
+                    .checked_sub(fill.quote + cex_fee)
+                    .ok_or_else(|| anyhow::Error::msg("Error: insufficient CEX USDC"))?;
+                balances.cex_sol += fill.base;
+
+                let swap = dex.simulate(fill.base, true, true)?;
+                if !swap.complete {
+                    return Err(anyhow::Error::msg(
+                        "Error: DEX swap incomplete for the sell leg",
+                    ));
+                }
+                let tx_fee = lamports_to_quote(opp.tx_fee, fill.quote, fill.base) as i64;
+                balances.wallet_sol = balances
+                    .wallet_sol
+                    .checked_sub(swap.amount_in + opp.tx_fee)
+                    .ok_or_else(|| anyhow::Error::msg("Error: insufficient wallet SOL"))?;
+                balances.wallet_usdc += swap.amount_out;
+
+                PaperTrade {
+                    direction: opp.direction,
+                    base_amount: fill.base,
+                    cex_quote: fill.quote,
+                    cex_fee,
+                    dex_quote: swap.amount_out,
+                    sol_delta: fill.base as i64 - swap.amount_in as i64,
+                    tx_fee: opp.tx_fee,
+                    pnl: swap.amount_out as i64 - (fill.quote + cex_fee) as i64 - tx_fee,
+                }
+            }
+            Direction::BuyDexSellCex => {
+                let swap = dex.simulate(opp.base_amount, false, false)?;
+                if !swap.complete {
+                    return Err(anyhow::Error::msg(
+                        "Error: DEX swap incomplete for the buy leg",
+                    ));
+                }
+                balances.wallet_sol = balances
+                    .wallet_sol
+                    .checked_sub(opp.tx_fee)
+                    .ok_or_else(|| anyhow::Error::msg("Error: insufficient wallet SOL"))?;
+                balances.wallet_usdc = balances
+                    .wallet_usdc
+                    .checked_sub(swap.amount_in)
+                    .ok_or_else(|| anyhow::Error::msg("Error: insufficient wallet USDC"))?;
+                balances.wallet_sol += swap.amount_out;
+
+                let fill = cex.book.sell_base(swap.amount_out);
+                if fill.base < swap.amount_out {
+                    return Err(anyhow::Error::msg(
+                        "Error: CEX book too thin to fill the sell leg",
+                    ));
+                }
+                let cex_fee = taker_fee(fill.quote, self.cex_taker_fee_bps);
+                let tx_fee = lamports_to_quote(opp.tx_fee, fill.quote, fill.base) as i64;
+                balances.cex_sol = balances
+                    .cex_sol
+                    .checked_sub(fill.base)
+                    .ok_or_else(|| anyhow::Error::msg("Error: insufficient CEX SOL"))?;
+                balances.cex_usdc += fill.quote - cex_fee;
+
+                PaperTrade {
+                    direction: opp.direction,
+                    base_amount: fill.base,
+                    cex_quote: fill.quote,
+                    cex_fee,
+                    dex_quote: swap.amount_in,
+                    sol_delta: swap.amount_out as i64 - fill.base as i64,
+                    tx_fee: opp.tx_fee,
+                    pnl: (fill.quote - cex_fee) as i64 - swap.amount_in as i64 - tx_fee,
+                }
+            }
+        };
+
+        self.balances = balances;
+        self.realized_pnl += trade.pnl;
+        self.trades.push(trade.clone());
+        Ok(trade)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        clmm_simulator::tests::pool,
+        order_book::{Level, OrderBook},
+        raydium_amm::tests::amm_dex,
+        raydium_clmm::ClmmVenue,
+    };
+
+    const SOL: u64 = 1_000_000_000;
+    /// Lamports the DEX transaction pays
+    const TX_FEE: u64 = 10_000;
+
+    fn balances() -> PaperBalances {
+        PaperBalances {
+            cex_sol: 10 * SOL,
+            cex_usdc: 2_000_000_000,
+            wallet_sol: 10 * SOL,
+            wallet_usdc: 2_000_000_000,
+        }
+    }
+
+    /// 1000 SOL at 150 USDC
+    fn dex() -> DexStruct {
+        amm_dex((WSOL_MINT, USDC_MINT), (1_000 * SOL, 150_000_000_000), 1)
+    }
+
+    /// `qty` lamports bid at 151 USDC and asked at 149
+    fn cex(qty: u64) -> CexStruct {
+        CexStruct {
+            book: OrderBook {
+                bids: vec![Level {
+                    price: 151_000_000,
+                    qty,
+                }],
+                asks: vec![Level {
+                    price: 149_000_000,
+                    qty,
+                }],
+            },
+            ..Default::default()
+        }
+    }
+
+    fn opp(direction: Direction) -> Opportunity {
+        Opportunity {
+            direction,
+            base_amount: SOL,
+            cost: 0,
+            proceeds: 0,
+            spread: 0,
+            cex_fee: 0,
+            tx_fee: TX_FEE,
+            slot: 1,
+        }
+    }
+
+    /// Fails `direction` and checks the error and that no balance moved
+    fn assert_rejected(
+        mut executor: PaperExecutor,
+        direction: Direction,
+        dex: &DexStruct,
+        cex: &CexStruct,
+        error: &str,
+    ) {
+        let before = executor.balances.clone();
+        let e = executor.execute(&opp(direction), dex, cex).unwrap_err();
+        assert_eq!(e.to_string(), error);
+        assert_eq!(executor.balances, before);
+        assert!(executor.trades.is_empty());
+        assert_eq!(executor.realized_pnl, 0);
+    }
+
+    #[test]
+    fn buy_cex_sell_dex_fills_both_legs() {
+        let mut executor = PaperExecutor::new(balances(), 10);
+        let trade = executor
+            .execute(&opp(Direction::BuyCexSellDex), &dex(), &cex(SOL))
+            .unwrap();
+
+        // 149 USDC plus 10 bps on the CEX, 149.475897 USDC out of the pool
+        assert_eq!((trade.cex_quote, trade.cex_fee), (149_000_000, 149_000));
+        assert_eq!(trade.dex_quote, 149_475_897);
+        assert_eq!(trade.tx_fee, TX_FEE);
+        // the transaction fee at 149 USDC per SOL is 1490 micro-USDC
+        assert_eq!(trade.pnl, 149_475_897 - 149_149_000 - 1_490);
+        assert_eq!(executor.realized_pnl, trade.pnl);
+        let start = balances();
+        assert_eq!(
+            executor.balances,
+            PaperBalances {
+                cex_sol: start.cex_sol + SOL,
+                cex_usdc: start.cex_usdc - 149_149_000,
+                wallet_sol: start.wallet_sol - SOL - TX_FEE,
+                wallet_usdc: start.wallet_usdc + 149_475_897,
+            }
+        );
+    }
+
+    #[test]
+    fn buy_dex_sell_cex_fills_both_legs() {
+        let mut executor = PaperExecutor::new(balances(), 10);
+        let dex = dex();
+        let cost = dex.simulate(SOL, false, false).unwrap().amount_in;
+        let trade = executor
+            .execute(&opp(Direction::BuyDexSellCex), &dex, &cex(SOL))
+            .unwrap();
+
+        assert_eq!((trade.cex_quote, trade.cex_fee), (151_000_000, 151_000));
+        assert_eq!(trade.dex_quote, cost);
+        assert_eq!(trade.sol_delta, 0);
+        // the transaction fee at 151 USDC per SOL is 1510 micro-USDC
+        assert_eq!(trade.pnl, 151_000_000 - 151_000 - cost as i64 - 1_510);
+        let start = balances();
+        assert_eq!(
+            executor.balances,
+            PaperBalances {
+                cex_sol: start.cex_sol - SOL,
+                cex_usdc: start.cex_usdc + 150_849_000,
+                wallet_sol: start.wallet_sol + SOL - TX_FEE,
+                wallet_usdc: start.wallet_usdc - cost,
+            }
+        );
+    }
+
+    #[test]
+    fn thin_books_and_incomplete_swaps_are_rejected() {
+        let executor = || PaperExecutor::new(balances(), 10);
+        let thin = cex(SOL / 2);
+        assert_rejected(
+            executor(),
+            Direction::BuyCexSellDex,
+            &dex(),
+            &thin,
+            "Error: CEX book too thin to fill the buy leg",
+        );
+        assert_rejected(
+            executor(),
+            Direction::BuyDexSellCex,
+            &dex(),
+            &thin,
+            "Error: CEX book too thin to fill the sell leg",
+        );
+
+        // no tick arrays cached, the swap runs out of liquidity
+        let dry = DexStruct {
+            pool: Box::new(ClmmVenue {
+                pool: Some(pool(-18_971, 1_000)),
+                tick_arrays: Default::default(),
+                fee_rate: Some(500),
+            }),
+            ..Default::default()
+        };
+        assert_rejected(
+            executor(),
+            Direction::BuyCexSellDex,
+            &dry,
+            &cex(SOL),
+            "Error: DEX swap incomplete for the sell leg",
+        );
+        assert_rejected(
+            executor(),
+            Direction::BuyDexSellCex,
+            &dry,
+            &cex(SOL),
+            "Error: DEX swap incomplete for the buy leg",
+        );
+    }
+
+    #[test]
+    fn short_balances_are_rejected() {
+        let short = |change: fn(&mut PaperBalances)| {
+            let mut balances = balances();
+            change(&mut balances);
+            PaperExecutor::new(balances, 10)
+        };
+        let (dex, cex) = (dex(), cex(SOL));
+        let rejected = |change: fn(&mut PaperBalances), direction, error| {
+            assert_rejected(short(change), direction, &dex, &cex, error)
+        };
+        rejected(
+            |b| b.cex_usdc = 149_148_999,
+            Direction::BuyCexSellDex,
+            "Error: insufficient CEX USDC",
+        );
+        rejected(
+            |b| b.wallet_sol = SOL + TX_FEE - 1,
+            Direction::BuyCexSellDex,
+            "Error: insufficient wallet SOL",
+        );
+        rejected(
+            |b| b.wallet_sol = TX_FEE - 1,
+            Direction::BuyDexSellCex,
+            "Error: insufficient wallet SOL",
+        );
+        rejected(
+            |b| b.wallet_usdc = 150_000_000,
+            Direction::BuyDexSellCex,
+            "Error: insufficient wallet USDC",
+        );
+        rejected(
+            |b| b.cex_sol = SOL - 1,
+            Direction::BuyDexSellCex,
+            "Error: insufficient CEX SOL",
+        );
+    }
+}
@@ -0,0 +1,1283 @@
+//! The arb pipeline: DEX and CEX feeds keep the shared market state current and the arb loop runs
+//! detection and execution on it. Feeds come in through `DexFeed` / `CexFeed`, so the same code
+//! runs live and under a scripted test harness.
+
+use std::{
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use solana_sdk::{hash::Hash, instruction::Instruction, pubkey::Pubkey};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, instrument, warn};
+
+use crate::{
+    alerts::{Alert, AlertDispatcher, Severity},
+    arb::{self, Opportunity},
+    balances::{Balances, WalletAccounts},
+    config::{Config, ConfigWatcher},
+    control::Controls,
+    cross_dex::{self, CrossDexOpportunity},
+    execution::{Decision, ExecutionCoordinator},
+    feed::{AccountUpdate, CexFeed, DexFeed},
+    journal::{Journal, JournalEntry, MarketSnapshot},
+    ledger,
+    lookup_tables::LookupTableManager,
+    market::{CexStruct, DexStruct, MarketStore, Snapshot},
+    metrics::METRICS,
+    recorder::Recorder,
+    route::{self, Route},
+    rpc,
+    tx_builder::{self, ArbTransaction, MinOutputCheck},
+    venue::{AccountFilter, PoolType},
+};
+
+const ARB_INTERVAL: Duration = Duration::from_millis(500);
+
+/// State shared between the feed tasks and the arb loop
+#[derive(Debug, Clone, Default)]
+pub struct SharedState {
+    pub market: Arc<MarketStore>,
+    pub balances: Arc<Mutex<Balances>>,
+    pub controls: Arc<Mutex<Controls>>,
+    pub alerts: AlertDispatcher,
+    pub lookup_tables: Arc<Mutex<LookupTableManager>>,
+}
+
+impl SharedState {
+    pub fn new(
+        pool_type: PoolType,
+        base_mint: Pubkey,
+        balances: Balances,
+        alerts: AlertDispatcher,
+    ) -> Self {
+        let state = Self {
+            balances: Arc::new(Mutex::new(balances)),
+            alerts,
+            ..Default::default()
+        };
+        state
+            .market
+            .publish_dex(Arc::new(DexStruct::new(pool_type, base_mint)));
+        state
+    }
+}
+
+/// Fails unless the pool trades `pair`, its base and quote mints in either order. Quotes and
+/// swaps follow the pool's token order, any other pair would be traded as if it were SOL/USDC.
+pub async fn check_pool_pair(
+    rpc_url: &str,
+    pool_id: &Pubkey,
+    pool_type: PoolType,
+    pair: (Pubkey, Pubkey),
+) -> anyhow::Result<()> {
+    let account = rpc::get_multiple_accounts(rpc_url, &[*pool_id])
+        .await?
+        .pop()
+        .flatten()
+        .ok_or_else(|| anyhow::Error::msg("Error: pool account not found"))?;
+    let mut dex = DexStruct::new(pool_type, pair.0);
+    dex.apply_account(pool_id, pool_id, 0, &account.data)?;
+    match dex.mints() {
+        Some(_) if dex.trades(pair) => Ok(()),
+        mints => Err(anyhow::Error::msg(format!(
+            "Error: pool {pool_id} trades {mints:?}, not base mint {} and quote mint {}",
+            pair.0, pair.1
+        ))),
+    }
+}
+
+/// Keeps the pool and wallet state current from `feed` until it ends
+#[instrument(name = "dex", skip_all, fields(pair = arb::PAIR, pool = %pool_id))]
+pub async fn run_dex(
+    mut feed: impl DexFeed,
+    state: SharedState,
+    pool_id: Pubkey,
+    wallet: Option<WalletAccounts>,
+    recorder: Recorder,
+    trade_size: u64,
+) {
+    let wallet = wallet.as_ref();
+    bootstrap_dex(&mut feed, &state, &pool_id, wallet, &recorder, trade_size).await;
+    stream_dex(&mut feed, &state, &pool_id, wallet, &recorder, trade_size).await;
+}
+
+/// Loads the pool and wallet snapshots the stream won't send until they change
+pub async fn bootstrap_dex(
+    feed: &mut impl DexFeed,
+    state: &SharedState,
+    pool_id: &Pubkey,
+    wallet: Option<&WalletAccounts>,
+    recorder: &Recorder,
+    trade_size: u64,
+) {
+    let mut pools = [StreamedPool::new(
+        &state.market,
+        *pool_id,
+        PoolRole::Configured,
+    )];
+    bootstrap_pools(feed, state, &mut pools, recorder, trade_size).await;
+    if let Some(wallet) = wallet
+        && let Err(e) = bootstrap_wallet(feed, wallet, &state.balances).await
+    {
+        error!("Error bootstrapping wallet balances over RPC: {e}");
+    }
+}
+
+/// Applies account updates from `feed` until it ends. This is the only writer of the DEX state:
+/// it works on its own copy and publishes a snapshot after every change.
+pub async fn stream_dex(
+    feed: &mut impl DexFeed,
+    state: &SharedState,
+    pool_id: &Pubkey,
+    wallet: Option<&WalletAccounts>,
+    recorder: &Recorder,
+    trade_size: u64,
+) {
+    let mut pools = [StreamedPool::new(
+        &state.market,
+        *pool_id,
+        PoolRole::Configured,
+    )];
+    stream_pools(feed, state, &mut pools, wallet, recorder, trade_size).await;
+}
+
+/// Keeps the other pools of the pair current from `feed` until it ends, for cross-DEX
+/// detection. They all share the one stream, its updates are routed to the pools they match.
+#[instrument(name = "pools", skip_all, fields(pair = arb::PAIR, pools = pools.len()))]
+pub async fn run_pools(
+    mut feed: impl DexFeed,
+    state: SharedState,
+    pools: Vec<(Pubkey, PoolType)>,
+    recorder: Recorder,
+    trade_size: u64,
+) {
+    let mut pools: Vec<StreamedPool> = pools
+        .into_iter()
+        .map(|(pool_id, pool_type)| {
+            StreamedPool::new(&state.market, pool_id, PoolRole::Tracked(pool_type))
+        })
+        .collect();
+    bootstrap_pools(&mut feed, &state, &mut pools, &recorder, trade_size).await;
+    stream_pools(&mut feed, &state, &mut pools, None, &recorder, trade_size).await;
+}
+
+/// Where the state of a streamed pool is published
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PoolRole {
+    /// The configured pool, arbitraged against the CEX
+    Configured,
+    /// Another pool of the pair, tracked for cross-DEX arbitrage
+    Tracked(PoolType),
+}
+
+impl PoolRole {
+    /// Latest published state of the pool, an empty one for a tracked pool not seen yet
+    fn load(self, market: &MarketStore, pool_id: &Pubkey) -> Arc<DexStruct> {
+        let snapshot = market.load();
+        match self {
+            PoolRole::Configured => snapshot.dex.clone(),
+            PoolRole::Tracked(pool_type) => {
+                snapshot.pools.get(pool_id).cloned().unwrap_or_else(|| {
+                    Arc::new(DexStruct {
+                        pool: pool_type.venue(),
+                        base_mint: snapshot.dex.base_mint,
+                        ..Default::default()
+                    })
+                })
+            }
+        }
+    }
+
+    fn publish(self, market: &MarketStore, pool_id: &Pubkey, dex: Arc<DexStruct>) {
+        match self {
+            PoolRole::Configured => market.publish_dex(dex),
+            PoolRole::Tracked(_) => market.publish_pool(*pool_id, dex),
+        }
+    }
+}
+
+/// A pool on a shared stream: its latest state and the filters it subscribed to, which route the
+/// stream's updates to it. The state is shared with the published snapshot and copied on the
+/// next change, once per update.
+struct StreamedPool {
+    id: Pubkey,
+    role: PoolRole,
+    dex: Arc<DexStruct>,
+    filters: Vec<AccountFilter>,
+}
+
+impl StreamedPool {
+    fn new(market: &MarketStore, id: Pubkey, role: PoolRole) -> Self {
+        let dex = role.load(market, &id);
+        let filters = dex.subscriptions(&id);
+        Self {
+            id,
+            role,
+            dex,
+            filters,
+        }
+    }
+
+    fn publish(&self, market: &MarketStore) {
+        self.role.publish(market, &self.id, self.dex.clone());
+    }
+
+    /// Whether `update` is for one of the accounts the pool subscribed to
+    fn routes(&self, update: &AccountUpdate) -> bool {
+        self.filters
+            .iter()
+            .any(|filter| filter.matches(&update.pubkey, &update.owner, &update.data))
+    }
+}
+
+async fn stream_pools(
+    feed: &mut impl DexFeed,
+    state: &SharedState,
+    pools: &mut [StreamedPool],
+    wallet: Option<&WalletAccounts>,
+    recorder: &Recorder,
+    trade_size: u64,
+) {
+    let updates: Vec<_> = pools
+        .iter()
+        .map(|pool| {
+            METRICS
+                .account_updates
+                .with_label_values(&[pool.id.to_string().as_str()])
+        })
+        .collect();
+    while let Some(update) = feed.next_update().await {
+        let update = match update {
+            Ok(update) => update,
+            Err(e) => {
+                warn!("Error parsing DEX message: {e}");
+                continue;
+            }
+        };
+        recorder.account(
+            update.slot,
+            update.pubkey.as_ref(),
+            update.write_version,
+            update.lamports,
+            &update.data,
+        );
+        if let Some(wallet) = wallet {
+            let mut balances = state.balances.lock().await;
+            if wallet.apply(&mut balances, &update.pubkey, update.lamports, &update.data) {
+                continue;
+            }
+        }
+        // pools of one program may share accounts (a fee config), so every match gets it
+        for (pool, updates) in pools.iter_mut().zip(&updates) {
+            if !pool.routes(&update) {
+                continue;
+            }
+            updates.inc();
+            if !apply_pool_update(&update, Arc::make_mut(&mut pool.dex), &pool.id, trade_size) {
+                continue;
+            }
+            pool.publish(&state.market);
+            match refresh_accounts(feed, &pool.id, &mut pool.dex, recorder).await {
+                Ok(0) => {}
+                Ok(_) => pool.publish(&state.market),
+                Err(e) => error!(pool = %pool.id, "Error fetching pool accounts: {e}"),
+            }
+        }
+    }
+}
+
+/// Applies an update to the pool or an account it depends on and re-quotes. Returns false for accounts
+/// that don't belong to the pool.
+fn apply_pool_update(
+    update: &AccountUpdate,
+    dex: &mut DexStruct,
+    pool_id: &Pubkey,
+    trade_size: u64,
+) -> bool {
+    let started = Instant::now();
+    match dex.apply_account(pool_id, &update.pubkey, update.slot, &update.data) {
+        Ok(true) => {}
+        Ok(false) => return false,
+        Err(e) => {
+            warn!(pubkey = %update.pubkey, "Error decoding account: {e}");
+            return false;
+        }
+    }
+    dex.received_us = ledger::unix_time_us();
+    refresh_quote(dex, trade_size);
+    debug!(
+        pool = %pool_id,
+        slot = dex.slot,
+        tick = dex.price().map(|p| p.tick),
+        token_in = dex.token_in,
+        token_out = dex.token_out,
+        apply_us = started.elapsed().as_micros() as u64,
+        "DEX swap state"
+    );
+    true
+}
+
+/// Keeps the CEX book current from `feed` until it ends
+#[instrument(name = "cex", skip_all, fields(pair = arb::PAIR))]
+pub async fn run_cex(mut feed: impl CexFeed, state: SharedState, recorder: Recorder) {
+    while let Some(book) = feed.next_book().await {
+        let book = match book {
+            Ok(book) => book,
+            Err(e) => {
+                warn!("Error fetching CEX book: {e}");
+                continue;
+            }
+        };
+        recorder.book(&book);
+
+        let cex = CexStruct {
+            best_bid: book.best_bid().unwrap_or_default(),
+            best_ask: book.best_ask().unwrap_or_default(),
+            book,
+            received_us: ledger::unix_time_us(),
+        };
+        debug!(
+            best_bid = cex.best_bid,
+            best_ask = cex.best_ask,
+            "CEX updated"
+        );
+        state.market.publish_cex(cex);
+    }
+}
+
+/// Runs a detection pass every `ARB_INTERVAL`, picking up risk limit and control API changes
+/// between passes. Cancellation is only checked between passes, so executions in flight always finish.
+#[instrument(name = "arb", skip_all, fields(pair = arb::PAIR, pool = %config.pool))]
+pub async fn run_arb(
+    state: SharedState,
+    coordinator: Arc<Mutex<ExecutionCoordinator>>,
+    mut config: Config,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut coordinator = coordinator.lock().await;
+    let mut watcher = ConfigWatcher::new();
+    let started_us = ledger::unix_time_us();
+    let mut journal = if config.journal.enabled {
+        Journal::open(&config.journal)
+            .map_err(|e| error!(dir = %config.journal.dir, "Error opening journal: {e}"))
+            .ok()
+    } else {
+        None
+    };
+    loop {
+        let mut controls = state.controls.lock().await;
+        for new_config in [watcher.poll(), controls.pending_config.take()]
+            .into_iter()
+            .flatten()
+        {
+            let restart = config.reload(new_config);
+            if !restart.is_empty() {
+                warn!(?restart, "Config changes left for the next restart");
+            }
+        }
+        let limits = controls.effective_limits(&config.risk);
+        if limits != *coordinator.risk.limits() {
+            info!(?limits, "Risk limits updated");
+            coordinator.risk.set_limits(limits.clone());
+        }
+        controls.risk_limits = limits;
+        drop(controls);
+
+        check_feeds(&state, &config, started_us).await;
+        detect_pass(&state, &mut coordinator, &config, journal.as_mut()).await;
+        if !config.cross_dex.pools.is_empty() {
+            cross_dex_pass(&state, &config).await;
+        }
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = tokio::time::sleep(ARB_INTERVAL) => {}
+        }
+    }
+}
+
+/// One detection pass over the current market state. Every opportunity is handed to the
+/// coordinator (or only logged while its pair is paused) and journaled; returns them with the
+/// decision taken.
+pub async fn detect_pass(
+    state: &SharedState,
+    coordinator: &mut ExecutionCoordinator,
+    config: &Config,
+    mut journal: Option<&mut Journal>,
+) -> Vec<(Opportunity, Decision)> {
+    let trading = state.controls.lock().await.trading_enabled(arb::PAIR);
+    // one consistent snapshot for the whole pass, the feeds keep publishing meanwhile
+    let snapshot = state.market.load();
+    let (dex, cex) = (&*snapshot.dex, &*snapshot.cex);
+    // Wait until DEX has a valid token_out value
+    if dex.token_out.is_none() {
+        return Vec::new();
+    }
+    // nothing was published since the last pass, don't fill the same opportunity twice
+    if !coordinator.new_pass(snapshot.stamp()) {
+        METRICS.observe_state(cex, coordinator);
+        return Vec::new();
+    }
+    let mut balances = state.balances.lock().await;
+
+    let started = Instant::now();
+    let opportunities = arb::detect(dex, cex, &balances, config);
+    let detect_us = started.elapsed().as_micros() as u64;
+
+    let mut emitted = Vec::new();
+    for opp in opportunities {
+        let span = info_span!(
+            "opportunity",
+            direction = ?opp.direction,
+            slot = opp.slot,
+            size = opp.base_amount
+        );
+        let _entered = span.enter();
+        let market = MarketSnapshot::capture(dex, cex, config.journal.book_depth);
+        info!(
+            cost = opp.cost,
+            proceeds = opp.proceeds,
+            spread = opp.spread,
+            cex_fee = opp.cex_fee,
+            net_profit = opp.net_profit(),
+            tick = market.tick,
+            best_bid = cex.best_bid,
+            best_ask = cex.best_ask,
+            detect_us,
+            "💸 Arb found"
+        );
+        let started = Instant::now();
+        let decision = if trading {
+            coordinator.handle(&opp, dex, cex, &mut balances)
+        } else {
+            Decision::Logged
+        };
+        info!(
+            outcome = ?decision.outcome(),
+            handle_us = started.elapsed().as_micros() as u64,
+            "Decision"
+        );
+        if let Some(journal) = journal.as_deref_mut() {
+            let entry = JournalEntry {
+                timestamp_ms: ledger::unix_time_ms(),
+                pair: arb::PAIR.to_string(),
+                pool: config.pool.clone(),
+                opportunity: opp.clone(),
+                market,
+                decision: decision.clone(),
+            };
+            if let Err(e) = journal.append(&entry) {
+                error!("Error writing journal: {e}");
+            }
+        }
+        alert_decision(&state.alerts, &opp, &decision);
+        emitted.push((opp, decision));
+    }
+    METRICS.observe_pass(arb::PAIR, dex, cex, &emitted, coordinator);
+    state.controls.lock().await.opportunities = emitted.clone();
+    emitted
+}
+
+/// Looks for round trips between the configured pool and the other tracked pools. Each one is
+/// logged with its transaction candidate, they aren't executed yet.
+pub async fn cross_dex_pass(state: &SharedState, config: &Config) -> Vec<CrossDexOpportunity> {
+    let (Ok(pool_id), Ok(pair)) = (Pubkey::from_str(&config.pool), config.pair_mints()) else {
+        return Vec::new();
+    };
+    let quote_mint = pair.1;
+    let snapshot = state.market.load();
+    // tracked pools of other pairs only take part in route search, those of the pair are compared
+    // whichever of their tokens SOL is
+    let pools: Vec<(Pubkey, &DexStruct)> = tracked_pools(&snapshot, pool_id)
+        .into_iter()
+        .filter(|(_, dex)| dex.token_out.is_some() && dex.trades(pair))
+        .collect();
+    if pools.len() < 2 {
+        return Vec::new();
+    }
+    let balances = state.balances.lock().await;
+    let mut tables = state.lookup_tables.lock().await;
+    let started = Instant::now();
+    let found = cross_dex::detect(&pools, &balances, &config.cross_dex, config.min_profit_bps);
+    let detect_us = started.elapsed().as_micros() as u64;
+
+    let user = wallet_or_default(config);
+    let pool = |id: &Pubkey| pools.iter().find(|(p, _)| p == id).map(|(_, dex)| *dex);
+    let mut candidates = Vec::new();
+    for opp in found {
+        let (Some(buy), Some(sell)) = (pool(&opp.buy_pool), pool(&opp.sell_pool)) else {
+            continue;
+        };
+        let span = info_span!("cross_dex", buy_pool = %opp.buy_pool, sell_pool = %opp.sell_pool);
+        let _entered = span.enter();
+        // the USDC the wallet held before, plus the required profit
+        let check = balances
+            .wallet_token(&quote_mint)
+            .map(|usdc| MinOutputCheck::gain(user, quote_mint, usdc, opp.min_proceeds - opp.cost));
+        let swaps = opp.instructions(buy, sell, user);
+        let Some(transaction) = assemble(swaps, check, user, &mut tables, config) else {
+            continue;
+        };
+        info!(
+            size = opp.base_amount,
+            cost = opp.cost,
+            proceeds = opp.proceeds,
+            spread = opp.spread,
+            min_proceeds = opp.min_proceeds,
+            slot = opp.slot,
+            tx_bytes = transaction.size,
+            accounts = transaction.accounts(),
+            detect_us,
+            "🔀 Cross-DEX arb found"
+        );
+        candidates.push(opp);
+    }
+    candidates
+}
+
+/// Searches routes after every pool update, until cancelled
+#[instrument(name = "routes", skip_all, fields(pool = %config.pool))]
+pub async fn run_routes(
+    state: SharedState,
+    config: Config,
+    token: CancellationToken,
+) -> anyhow::Result<()> {
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => return Ok(()),
+            _ = state.market.pool_updated() => {}
+        }
+        route_pass(&state, &config).await;
+    }
+}
+
+/// Looks for profitable cycles through the configured and tracked pools. Routes that fit in one
+/// transaction are logged, they aren't executed yet.
+pub async fn route_pass(state: &SharedState, config: &Config) -> Vec<Route> {
+    let Ok(pool_id) = Pubkey::from_str(&config.pool) else {
+        return Vec::new();
+    };
+    let snapshot = state.market.load();
+    let pools = tracked_pools(&snapshot, pool_id);
+    let started = Instant::now();
+    let result = route::search_all(&pools, &config.routes, config.min_profit_bps);
+    let search_us = started.elapsed().as_micros() as u64;
+    if !result.complete {
+        debug!(
+            quotes = result.quotes,
+            search_us, "Route search ran out of time"
+        );
+    }
+    let user = wallet_or_default(config);
+    let balances = state.balances.lock().await;
+    let mut tables = state.lookup_tables.lock().await;
+    let mut candidates = Vec::new();
+    for route in result.routes {
+        let path: Vec<String> = route.hops.iter().map(|hop| hop.pool.to_string()).collect();
+        let span = info_span!("route", token = %route.token(), path = path.join(" > "));
+        let _entered = span.enter();
+        let min_amount_out = route.min_amount_out(config.min_profit_bps);
+        let check = balances.wallet_token(&route.token()).map(|balance| {
+            MinOutputCheck::gain(
+                user,
+                route.token(),
+                balance,
+                min_amount_out - route.amount_in(),
+            )
+        });
+        let swaps = route.instructions(&pools, user, min_amount_out);
+        let Some(transaction) = assemble(swaps, check, user, &mut tables, config) else {
+            continue;
+        };
+        info!(
+            hops = route.hops.len(),
+            amount_in = route.amount_in(),
+            amount_out = route.amount_out(),
+            profit = route.profit(),
+            quotes = result.quotes,
+            pruned = result.pruned,
+            tx_bytes = transaction.size,
+            accounts = transaction.accounts(),
+            search_us,
+            "🔺 Route found"
+        );
+        candidates.push(route);
+    }
+    candidates
+}
+
+/// Compiles built swaps into the atomic transaction candidate with the lookup tables covering
+/// them best, dropping the ones that fail to build or don't fit in a transaction. Their accounts
+/// count towards the tables either way, so a route too large today can fit once they're added.
+fn assemble(
+    swaps: anyhow::Result<Vec<Instruction>>,
+    check: Option<MinOutputCheck>,
+    user: Pubkey,
+    tables: &mut LookupTableManager,
+    config: &Config,
+) -> Option<ArbTransaction> {
+    let swaps = swaps.map_err(|e| warn!("Error building swaps: {e}")).ok()?;
+    tables.record(&swaps);
+    let selected = tables.select(&swaps, config.lookup_tables.max_tables);
+    // the blockhash doesn't change the size, it's fetched when sending
+    tx_builder::build(
+        &user,
+        &swaps,
+        check.as_ref(),
+        &selected,
+        Hash::default(),
+        &config.transaction,
+    )
+    .map_err(|e| warn!(swaps = swaps.len(), "Transaction rejected: {e}"))
+    .ok()
+}
+
+/// Signer of the DEX transactions. Without a wallet candidates are still built, to report their
+/// shape.
+fn wallet_or_default(config: &Config) -> Pubkey {
+    config
+        .wallet
+        .as_deref()
+        .and_then(|wallet| Pubkey::from_str(wallet).ok())
+        .unwrap_or_default()
+}
+
+/// The configured pool followed by every other tracked pool of `snapshot`
+fn tracked_pools(snapshot: &Snapshot, pool_id: Pubkey) -> Vec<(Pubkey, &DexStruct)> {
+    let others = snapshot.pools.iter().filter(|(id, _)| **id != pool_id);
+    std::iter::once((pool_id, &*snapshot.dex))
+        .chain(others.map(|(id, dex)| (*id, &**dex)))
+        .collect()
+}
+
+fn alert_decision(alerts: &AlertDispatcher, opp: &Opportunity, decision: &Decision) {
+    let summary = format!(
+        "{} {:?}: {} lamports, cost {} proceeds {} net profit {} (slot {})",
+        arb::PAIR,
+        opp.direction,
+        opp.base_amount,
+        opp.cost,
+        opp.proceeds,
+        opp.net_profit(),
+        opp.slot
+    );
+    let alert = match decision {
+        Decision::Logged | Decision::Executed { .. } => Alert::new(
+            Severity::Info,
+            format!("opportunity:{}:{:?}", arb::PAIR, opp.direction),
+            "Arb opportunity",
+        )
+        .message(summary),
+        Decision::Rejected { reason } => Alert::new(
+            Severity::Warning,
+            format!("risk:{reason:?}"),
+            format!("Risk limit hit: {reason}"),
+        )
+        .message(summary),
+        Decision::Failed { error } => Alert::new(
+            Severity::Critical,
+            format!("failed:{}", arb::PAIR),
+            "Leg failed",
+        )
+        .message(format!("{summary}\n{error}")),
+    };
+    alerts.send(alert);
+}
+
+/// Raises an outage alert for a feed that hasn't updated in `alerts.feed_stale_secs`
+async fn check_feeds(state: &SharedState, config: &Config, started_us: u64) {
+    let now_us = ledger::unix_time_us();
+    let stale_us = config.alerts.feed_stale_secs * 1_000_000;
+    let snapshot = state.market.load();
+    let dex_us = snapshot.dex.received_us.max(started_us);
+    let cex_us = snapshot.cex.received_us.max(started_us);
+    for (feed, received_us) in [("dex", dex_us), ("cex", cex_us)] {
+        let silent_us = now_us.saturating_sub(received_us);
+        if silent_us > stale_us {
+            state.alerts.send(
+                Alert::new(
+                    Severity::Critical,
+                    format!("feed:{feed}"),
+                    format!("{} feed down", feed.to_uppercase()),
+                )
+                .message(format!("No update for {}s", silent_us / 1_000_000)),
+            );
+        }
+    }
+}
+
+/// Loads the accounts of `pools` and publishes them, logging what failed. The stream only sends
+/// accounts when they change, so each pool starts from an RPC snapshot.
+async fn bootstrap_pools(
+    feed: &mut impl DexFeed,
+    state: &SharedState,
+    pools: &mut [StreamedPool],
+    recorder: &Recorder,
+    trade_size: u64,
+) {
+    if let Err(e) = load_pools(feed, pools, recorder, trade_size).await {
+        error!("Error bootstrapping pool over RPC: {e}");
+    }
+    for pool in pools.iter() {
+        pool.publish(&state.market);
+    }
+}
+
+/// Subscribes to the accounts of all `pools` and loads them (their config, the tick arrays around
+/// the current price, their vaults). Venues that depend on accounts named in the pool account
+/// subscribe to them once it's loaded.
+async fn load_pools(
+    feed: &mut impl DexFeed,
+    pools: &mut [StreamedPool],
+    recorder: &Recorder,
+    trade_size: u64,
+) -> anyhow::Result<()> {
+    subscribe_pools(feed, pools).await?;
+    let pool_ids: Vec<Pubkey> = pools.iter().map(|pool| pool.id).collect();
+    let accounts = feed.fetch_accounts(&pool_ids).await?;
+    let mut loaded = Vec::with_capacity(pools.len());
+    for (pool, account) in pools.iter_mut().zip(accounts) {
+        let applied = match account {
+            Some(account) => {
+                recorder.account(0, pool.id.as_ref(), 0, account.lamports, &account.data);
+                Arc::make_mut(&mut pool.dex).apply_account(&pool.id, &pool.id, 0, &account.data)
+            }
+            None => Err(anyhow::Error::msg("Error: pool account not found")),
+        };
+        if let Err(e) = &applied {
+            error!(pool = %pool.id, "Error bootstrapping pool over RPC: {e}");
+        }
+        loaded.push(applied.is_ok());
+    }
+    subscribe_pools(feed, pools).await?;
+
+    for (pool, loaded) in pools.iter_mut().zip(loaded) {
+        if !loaded {
+            continue;
+        }
+        if let Err(e) = refresh_accounts(feed, &pool.id, &mut pool.dex, recorder).await {
+            error!(pool = %pool.id, "Error fetching pool accounts: {e}");
+        }
+        refresh_quote(Arc::make_mut(&mut pool.dex), trade_size);
+    }
+    Ok(())
+}
+
+/// Streams the union of the accounts `pools` subscribe to, refreshing each pool's filters
+async fn subscribe_pools(
+    feed: &mut impl DexFeed,
+    pools: &mut [StreamedPool],
+) -> anyhow::Result<()> {
+    for pool in pools.iter_mut() {
+        pool.filters = pool.dex.subscriptions(&pool.id);
+    }
+    let filters: Vec<AccountFilter> = pools
+        .iter()
+        .flat_map(|pool| pool.filters.iter().cloned())
+        .collect();
+    feed.subscribe(&filters).await
+}
+
+/// Loads the current wallet balances, later changes come from the stream
+async fn bootstrap_wallet(
+    feed: &mut impl DexFeed,
+    wallet: &WalletAccounts,
+    balances: &Arc<Mutex<Balances>>,
+) -> anyhow::Result<()> {
+    let pubkeys = wallet.pubkeys();
+    let accounts = feed.fetch_accounts(&pubkeys).await?;
+    let mut balances = balances.lock().await;
+    for (pubkey, account) in pubkeys.iter().zip(accounts) {
+        // a missing ATA just means a zero balance
+        let (lamports, data) = account.map(|a| (a.lamports, a.data)).unwrap_or_default();
+        wallet.apply(&mut balances, pubkey, lamports, &data);
+    }
+    Ok(())
+}
+
+/// Fetches the accounts the pool depends on that aren't cached yet, returns how many were added
+async fn refresh_accounts(
+    feed: &mut impl DexFeed,
+    pool_id: &Pubkey,
+    dex: &mut Arc<DexStruct>,
+    recorder: &Recorder,
+) -> anyhow::Result<usize> {
+    let missing = dex.missing_accounts(pool_id);
+    if missing.is_empty() {
+        return Ok(0);
+    }
+    // only copied once there's something to add
+    let dex = Arc::make_mut(dex);
+
+    let started = Instant::now();
+    let accounts = feed.fetch_accounts(&missing).await?;
+    debug!(
+        count = missing.len(),
+        fetch_ms = started.elapsed().as_millis() as u64,
+        "Fetched pool accounts"
+    );
+
+    let mut added = 0;
+    for (address, account) in missing.iter().zip(accounts) {
+        let Some(account) = account else {
+            dex.mark_absent(address);
+            continue;
+        };
+        recorder.account(0, address.as_ref(), 0, account.lamports, &account.data);
+        if dex.apply_account(pool_id, address, 0, &account.data)? {
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+/// Re-quotes the configured trade size against the latest pool state
+fn refresh_quote(dex: &mut DexStruct, trade_size: u64) {
+    match dex.simulate(trade_size, true, true) {
+        Ok(swap) => {
+            dex.token_in = swap.amount_in;
+            dex.token_out = Some(swap.amount_out);
+        }
+        Err(e) => warn!("Error simulating DEX swap: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, VecDeque},
+        str::FromStr,
+    };
+
+    use super::*;
+    use crate::{
+        arb::Direction,
+        balances::{TOKEN_PROGRAM_ID, USDC_MINT, WSOL_MINT},
+        clmm_simulator::tests::pool,
+        execution::ExecutionMode,
+        feed::{AccountUpdate, ScriptedCexFeed, ScriptedDexFeed},
+        order_book::{Level, OrderBook},
+        raydium_amm::tests::{amm_dex, amm_info, amm_pool},
+        raydium_clmm::{AmmConfig, CLMM_PROGRAM_ID, TICK_ARRAY_ACCOUNT_LEN, TickArrayState},
+        rpc::Account,
+        venue::{AccountFilter, PoolType},
+    };
+
+    fn pool_data(tick: i32, amm_config: Pubkey) -> Vec<u8> {
+        let mut pool = pool(tick, 1_000_000_000_000_000);
+        pool.amm_config = amm_config;
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(&pool).unwrap());
+        data
+    }
+
+    fn amm_config_data() -> Vec<u8> {
+        let amm_config = AmmConfig {
+            bump: 0,
+            index: 0,
+            owner: Pubkey::default(),
+            protocol_fee_rate: 0,
+            trade_fee_rate: 500,
+            tick_spacing: 1,
+            fund_fee_rate: 0,
+            padding_u32: 0,
+            fund_owner: Pubkey::default(),
+            padding: [0; 3],
+        };
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(&amm_config).unwrap());
+        data
+    }
+
+    /// An empty tick array account: discriminator, pool id, start index, zeroed ticks
+    fn tick_array_update(pool_id: &Pubkey, start: i32, slot: u64) -> AccountUpdate {
+        let mut data = vec![0; TICK_ARRAY_ACCOUNT_LEN];
+        data[8..40].copy_from_slice(pool_id.as_ref());
+        data[40..44].copy_from_slice(&start.to_le_bytes());
+        AccountUpdate {
+            slot,
+            pubkey: TickArrayState::address(pool_id, start),
+            owner: CLMM_PROGRAM_ID,
+            write_version: 0,
+            lamports: 0,
+            data,
+        }
+    }
+
+    fn token_account(amount: u64) -> Vec<u8> {
+        let mut data = vec![0; 165];
+        data[64..72].copy_from_slice(&amount.to_le_bytes());
+        data
+    }
+
+    fn book(bid: u64, ask: u64) -> OrderBook {
+        let level = |price| Level {
+            price,
+            qty: 100_000_000_000,
+        };
+        OrderBook {
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+        }
+    }
+
+    /// Drives the pipeline from scripted feeds, one step at a time
+    struct Harness {
+        state: SharedState,
+        coordinator: ExecutionCoordinator,
+        config: Config,
+        pool_id: Pubkey,
+    }
+
+    impl Harness {
+        async fn bootstrap(accounts: HashMap<Pubkey, Account>) -> Self {
+            let config = Config {
+                execution_mode: ExecutionMode::LogOnly,
+                ..Default::default()
+            };
+            let harness = Self {
+                state: SharedState::default(),
+                coordinator: ExecutionCoordinator::new(&config),
+                pool_id: Pubkey::from_str(&config.pool).unwrap(),
+                config,
+            };
+            let mut feed = ScriptedDexFeed {
+                accounts,
+                ..Default::default()
+            };
+            bootstrap_dex(
+                &mut feed,
+                &harness.state,
+                &harness.pool_id,
+                None,
+                &Recorder::default(),
+                harness.config.trade_size,
+            )
+            .await;
+            harness
+        }
+
+        async fn dex(&mut self, updates: Vec<AccountUpdate>) -> Vec<(Opportunity, Decision)> {
+            let mut feed = ScriptedDexFeed {
+                updates: VecDeque::from(updates),
+                ..Default::default()
+            };
+            stream_dex(
+                &mut feed,
+                &self.state,
+                &self.pool_id,
+                None,
+                &Recorder::default(),
+                self.config.trade_size,
+            )
+            .await;
+            detect_pass(&self.state, &mut self.coordinator, &self.config, None).await
+        }
+
+        async fn cex(&mut self, book: OrderBook) -> Vec<(Opportunity, Decision)> {
+            let feed = ScriptedCexFeed {
+                books: VecDeque::from([book]),
+            };
+            run_cex(feed, self.state.clone(), Recorder::default()).await;
+            detect_pass(&self.state, &mut self.coordinator, &self.config, None).await
+        }
+    }
+
+    #[tokio::test]
+    async fn scripted_feeds_emit_exact_opportunities() {
+        let pool_id = Pubkey::from_str(&Config::default().pool).unwrap();
+        let amm_config = Pubkey::new_unique();
+        let accounts = HashMap::from([
+            (
+                pool_id,
+                Account {
+                    lamports: 0,
+                    data: pool_data(-18_971, amm_config),
+                },
+            ),
+            (
+                amm_config,
+                Account {
+                    lamports: 0,
+                    data: amm_config_data(),
+                },
+            ),
+        ]);
+        let mut harness = Harness::bootstrap(accounts).await;
+        let dex = harness.state.market.load().dex.clone();
+        // the AMM config was loaded along with the pool
+        assert!(!dex.missing_accounts(&pool_id).contains(&amm_config));
+
+        // pool at ~150 USDC/SOL, nothing to do until its tick array arrives and the CEX quotes
+        assert!(
+            harness
+                .dex(vec![tick_array_update(&pool_id, -18_980, 1)])
+                .await
+                .is_empty()
+        );
+
+        let opps = harness.cex(book(155_000_000, 156_000_000)).await;
+        let opps: Vec<Opportunity> = opps
+            .into_iter()
+            .map(|(opp, decision)| {
+                assert!(matches!(decision, Decision::Logged));
+                opp
+            })
+            .collect();
+        assert_eq!(
+            opps,
+            vec![Opportunity {
+                direction: Direction::BuyDexSellCex,
+                base_amount: 1_000_000_000,
+                cost: 150_092_332,
+                proceeds: 155_000_000,
+                spread: 4_907_668,
+                cex_fee: 155_000,
+                tx_fee: 5_000,
+                slot: 1,
+            }]
+        );
+
+        // pool moves up to ~160 USDC/SOL
+        let opps = harness
+            .dex(vec![
+                tick_array_update(&pool_id, -18_360, 2),
+                AccountUpdate {
+                    slot: 3,
+                    pubkey: pool_id,
+                    owner: CLMM_PROGRAM_ID,
+                    write_version: 1,
+                    lamports: 0,
+                    data: pool_data(-18_326, amm_config),
+                },
+            ])
+            .await;
+        let opps: Vec<Opportunity> = opps.into_iter().map(|(opp, _)| opp).collect();
+        assert_eq!(
+            opps,
+            vec![Opportunity {
+                direction: Direction::BuyCexSellDex,
+                base_amount: 1_000_000_000,
+                cost: 156_000_000,
+                proceeds: 159_931_624,
+                spread: 3_931_624,
+                cex_fee: 156_000,
+                tx_fee: 5_000,
+                slot: 3,
+            }]
+        );
+
+        // CEX catches up, spread is inside the fees
+        assert!(harness.cex(book(159_000_000, 160_500_000)).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_snapshot_is_only_traded_once() {
+        let config = Config {
+            execution_mode: ExecutionMode::Paper,
+            ..Default::default()
+        };
+        let mut coordinator = ExecutionCoordinator::new(&config);
+        let state = SharedState::default();
+        // pool at 150 USDC/SOL, CEX bid at 155
+        let mut dex = amm_dex(
+            (WSOL_MINT, USDC_MINT),
+            (1_000_000_000_000, 150_000_000_000),
+            1,
+        );
+        dex.token_out = Some(149_475_897);
+        state.market.publish_dex(Arc::new(dex));
+        let cex = |received_us| CexStruct {
+            best_bid: 155_000_000,
+            best_ask: 156_000_000,
+            book: book(155_000_000, 156_000_000),
+            received_us,
+        };
+        state.market.publish_cex(cex(1));
+
+        let first = detect_pass(&state, &mut coordinator, &config, None).await;
+        assert!(matches!(first[..], [(_, Decision::Executed { .. })]));
+        // the next pass sees the same snapshot
+        assert!(
+            detect_pass(&state, &mut coordinator, &config, None)
+                .await
+                .is_empty()
+        );
+        assert_eq!(coordinator.paper.trades.len(), 1);
+        assert_eq!(state.controls.lock().await.opportunities, first);
+
+        // a new book is traded again
+        state.market.publish_cex(cex(2));
+        let second = detect_pass(&state, &mut coordinator, &config, None).await;
+        assert!(matches!(second[..], [(_, Decision::Executed { .. })]));
+        assert_eq!(coordinator.paper.trades.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn amm_pool_quotes_from_its_streamed_vaults() {
+        let pool_id = Pubkey::new_unique();
+        let (coin_vault, pc_vault) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = |data| Account { lamports: 0, data };
+        let mut feed = ScriptedDexFeed {
+            accounts: HashMap::from([
+                (
+                    pool_id,
+                    account(bincode::serialize(&amm_info(coin_vault, pc_vault)).unwrap()),
+                ),
+                (coin_vault, account(token_account(1_000_001_000_000))),
+                (pc_vault, account(token_account(150_002_000_000))),
+            ]),
+            ..Default::default()
+        };
+        let state = SharedState::new(
+            PoolType::RaydiumAmm,
+            WSOL_MINT,
+            Balances::default(),
+            AlertDispatcher::default(),
+        );
+        let recorder = Recorder::default();
+        bootstrap_dex(&mut feed, &state, &pool_id, None, &recorder, 1_000_000_000).await;
+        assert_eq!(
+            feed.subscriptions,
+            vec![
+                AccountFilter::Account(pool_id),
+                AccountFilter::Account(coin_vault),
+                AccountFilter::Account(pc_vault),
+            ]
+        );
+        assert_eq!(state.market.load().dex.token_out, Some(149_475_897));
+
+        // a swap drains USDC from the pool
+        feed.updates.push_back(AccountUpdate {
+            slot: 7,
+            pubkey: pc_vault,
+            owner: TOKEN_PROGRAM_ID,
+            write_version: 1,
+            lamports: 0,
+            data: token_account(140_002_000_000),
+        });
+        stream_dex(&mut feed, &state, &pool_id, None, &recorder, 1_000_000_000).await;
+        let dex = state.market.load().dex.clone();
+        assert_eq!(dex.slot, 7);
+        assert_eq!(dex.token_out, Some(139_510_837));
+    }
+
+    #[tokio::test]
+    async fn tracked_pools_share_one_stream() {
+        let (first_id, other_id) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = |data| Account { lamports: 0, data };
+        // AMM v4 pools of 1000 SOL, at 150 and 153 USDC, served by one feed
+        let mut accounts = HashMap::new();
+        let mut add_pool = |pool_id: Pubkey, usdc: u64| {
+            let info = amm_pool((WSOL_MINT, USDC_MINT));
+            accounts.insert(pool_id, account(bincode::serialize(&info).unwrap()));
+            accounts.insert(info.coin_vault, account(token_account(1_000_000_000_000)));
+            accounts.insert(info.pc_vault, account(token_account(usdc)));
+            info.pc_vault
+        };
+        let first_vault = add_pool(first_id, 150_000_000_000);
+        add_pool(other_id, 153_000_000_000);
+        let mut feed = ScriptedDexFeed {
+            accounts,
+            ..Default::default()
+        };
+        // the stream drains USDC from the first pool only
+        feed.updates.push_back(AccountUpdate {
+            slot: 7,
+            pubkey: first_vault,
+            owner: TOKEN_PROGRAM_ID,
+            write_version: 1,
+            lamports: 0,
+            data: token_account(140_000_000_000),
+        });
+        let state = SharedState::new(
+            PoolType::RaydiumAmm,
+            WSOL_MINT,
+            Balances::default(),
+            AlertDispatcher::default(),
+        );
+        let pools = vec![
+            (first_id, PoolType::RaydiumAmm),
+            (other_id, PoolType::RaydiumAmm),
+        ];
+        run_pools(
+            feed,
+            state.clone(),
+            pools,
+            Recorder::default(),
+            1_000_000_000,
+        )
+        .await;
+
+        let snapshot = state.market.load();
+        assert_eq!(snapshot.pools[&first_id].slot, 7);
+        assert_eq!(snapshot.pools[&first_id].token_out, Some(139_510_837));
+        assert_eq!(snapshot.pools[&other_id].slot, 0);
+        assert!(snapshot.pools[&other_id].token_out.is_some());
+    }
+
+    #[tokio::test]
+    async fn tracked_pools_feed_cross_dex_detection() {
+        let config = Config {
+            min_profit_bps: 10,
+            ..Default::default()
+        };
+        let pool_id = Pubkey::from_str(&config.pool).unwrap();
+        let other_id = Pubkey::new_unique();
+        let account = |data| Account { lamports: 0, data };
+        // AMM v4 pools of 1000 SOL, at 150 and 153 USDC, SOL first or second
+        let amm_feed = |pool_id: Pubkey, usdc: u64, sol_first: bool| {
+            let (mints, reserves) = if sol_first {
+                ((WSOL_MINT, USDC_MINT), (1_000_000_000_000, usdc))
+            } else {
+                ((USDC_MINT, WSOL_MINT), (usdc, 1_000_000_000_000))
+            };
+            let info = amm_pool(mints);
+            ScriptedDexFeed {
+                accounts: HashMap::from([
+                    (pool_id, account(bincode::serialize(&info).unwrap())),
+                    (info.coin_vault, account(token_account(reserves.0))),
+                    (info.pc_vault, account(token_account(reserves.1))),
+                ]),
+                ..Default::default()
+            }
+        };
+        let state = SharedState::new(
+            PoolType::RaydiumAmm,
+            WSOL_MINT,
+            Balances::default(),
+            AlertDispatcher::default(),
+        );
+        let recorder = Recorder::default();
+        let mut feed = amm_feed(pool_id, 150_000_000_000, true);
+        bootstrap_dex(&mut feed, &state, &pool_id, None, &recorder, 1_000_000_000).await;
+        // the tracked pool alone isn't enough to compare
+        assert!(cross_dex_pass(&state, &config).await.is_empty());
+
+        // the other pool lists USDC first
+        let feed = amm_feed(other_id, 153_000_000_000, false);
+        run_pools(
+            feed,
+            state.clone(),
+            vec![(other_id, PoolType::RaydiumAmm)],
+            recorder,
+            1_000_000_000,
+        )
+        .await;
+        assert!(state.market.load().pools[&other_id].token_out.is_some());
+        let found = cross_dex_pass(&state, &config).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!((found[0].buy_pool, found[0].sell_pool), (pool_id, other_id));
+        assert!(found[0].spread > 0);
+        // buying SOL pays in token 1 of the first pool, selling it pays in token 1 of the other
+        let snapshot = state.market.load();
+        let [buy, sell] = found[0].swaps(
+            &snapshot.dex,
+            &snapshot.pools[&other_id],
+            Pubkey::new_unique(),
+        );
+        assert!(!buy.zero_for_one && !sell.zero_for_one);
+    }
+}
@@ -0,0 +1,427 @@
+//! Raydium AMM v4 (legacy constant product) account layout and quotes. The pool account only
+//! holds the fees and the PnL owed to the protocol, reserves are the balances of its two SPL
+//! token vaults minus that PnL.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    balances::{TOKEN_PROGRAM_ID, associated_token_address, token_account_amount},
+    clmm_simulator::{PoolPrice, SwapResult},
+    constant_product::{self, amount_out},
+    venue::{self, AccountFilter, DexVenue, PoolType, SwapParams},
+};
+
+pub const AMM_V4_PROGRAM_ID: Pubkey = pubkey!("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8");
+/// Seed of the pool authority, with the pool's nonce as bump
+const AMM_AUTHORITY_SEED: &[u8] = b"amm authority";
+/// Instruction tags of the swaps that don't take the OpenBook accounts
+const SWAP_BASE_IN_V2: u8 = 16;
+const SWAP_BASE_OUT_V2: u8 = 17;
+
+/// Size of an AMM v4 pool account, there is no anchor discriminator
+pub const AMM_INFO_LEN: usize = 752;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AmmInfo {
+    pub status: u64,
+    pub nonce: u64,
+    pub order_num: u64,
+    pub depth: u64,
+    /// Decimals of the coin (first) token
+    pub coin_decimals: u64,
+    /// Decimals of the pc (second) token
+    pub pc_decimals: u64,
+    pub state: u64,
+    pub reset_flag: u64,
+    pub min_size: u64,
+    pub vol_max_cut_ratio: u64,
+    pub amount_wave: u64,
+    pub coin_lot_size: u64,
+    pub pc_lot_size: u64,
+    pub min_price_multiplier: u64,
+    pub max_price_multiplier: u64,
+    pub sys_decimal_value: u64,
+    pub fees: AmmFees,
+    pub state_data: AmmStateData,
+    pub coin_vault: Pubkey,
+    pub pc_vault: Pubkey,
+    pub coin_vault_mint: Pubkey,
+    pub pc_vault_mint: Pubkey,
+    pub lp_mint: Pubkey,
+    pub open_orders: Pubkey,
+    pub market: Pubkey,
+    pub market_program: Pubkey,
+    pub target_orders: Pubkey,
+    pub padding1: [u64; 8],
+    pub amm_owner: Pubkey,
+    pub lp_amount: u64,
+    pub client_order_id: u64,
+    pub recent_epoch: u64,
+    pub padding2: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AmmFees {
+    pub min_separate_numerator: u64,
+    pub min_separate_denominator: u64,
+    pub trade_fee_numerator: u64,
+    pub trade_fee_denominator: u64,
+    pub pnl_numerator: u64,
+    pub pnl_denominator: u64,
+    /// Fee taken from the input of a swap, 25 / 10_000 on most pools
+    pub swap_fee_numerator: u64,
+    pub swap_fee_denominator: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AmmStateData {
+    /// Vault balance owed to the protocol, not part of the reserves
+    pub need_take_pnl_coin: u64,
+    pub need_take_pnl_pc: u64,
+    pub total_pnl_pc: u64,
+    pub total_pnl_coin: u64,
+    pub pool_open_time: u64,
+    pub padding: [u64; 2],
+    pub orderbook_to_init_time: u64,
+    pub swap_coin_in_amount: u128,
+    pub swap_pc_out_amount: u128,
+    pub swap_acc_pc_fee: u64,
+    pub swap_pc_in_amount: u128,
+    pub swap_coin_out_amount: u128,
+    pub swap_acc_coin_fee: u64,
+}
+
+impl AmmInfo {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != AMM_INFO_LEN {
+            return Err(anyhow::Error::msg("Error: invalid AMM v4 account size"));
+        }
+        Ok(bincode::deserialize(data)?)
+    }
+
+    /// Coin and pc reserves given the balances of the two vaults
+    pub fn reserves(&self, coin_vault: u64, pc_vault: u64) -> (u64, u64) {
+        (
+            coin_vault.saturating_sub(self.state_data.need_take_pnl_coin),
+            pc_vault.saturating_sub(self.state_data.need_take_pnl_pc),
+        )
+    }
+
+    /// Quotes a swap like the `swap_base_in` / `swap_base_out` instructions: the fee is taken
+    /// from the input, rounded up. `coin_to_pc` sells the coin (first) token.
+    pub fn quote(
+        &self,
+        coin_reserve: u64,
+        pc_reserve: u64,
+        amount_specified: u64,
+        coin_to_pc: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<SwapResult> {
+        let (reserve_in, reserve_out) = if coin_to_pc {
+            (coin_reserve, pc_reserve)
+        } else {
+            (pc_reserve, coin_reserve)
+        };
+        let numerator = u128::from(self.fees.swap_fee_numerator);
+        let denominator = u128::from(self.fees.swap_fee_denominator);
+        if denominator == 0 || numerator >= denominator {
+            return Err(anyhow::Error::msg("Error: invalid AMM swap fee"));
+        }
+
+        let (amount_in, amount_out, fee_amount) = if is_base_input {
+            let fee = (u128::from(amount_specified) * numerator).div_ceil(denominator) as u64;
+            let out = amount_out(amount_specified - fee, reserve_in, reserve_out);
+            (amount_specified, out, fee)
+        } else {
+            let before_fee = constant_product::amount_in(amount_specified, reserve_in, reserve_out)
+                .ok_or_else(|| anyhow::Error::msg("Error: swap exceeds the pool reserves"))?;
+            let with_fee = (u128::from(before_fee) * denominator).div_ceil(denominator - numerator);
+            let with_fee = u64::try_from(with_fee)
+                .map_err(|_| anyhow::Error::msg("Error: swap exceeds the pool reserves"))?;
+            (with_fee, amount_specified, with_fee - before_fee)
+        };
+
+        // the fee stays in the pool
+        let (Some(in_after), Some(out_after)) = (
+            reserve_in.checked_add(amount_in),
+            reserve_out.checked_sub(amount_out),
+        ) else {
+            return Err(anyhow::Error::msg(
+                "Error: swap overflows the pool reserves",
+            ));
+        };
+        let (coin_after, pc_after) = if coin_to_pc {
+            (in_after, out_after)
+        } else {
+            (out_after, in_after)
+        };
+        let price = constant_product::price(coin_after, pc_after).unwrap_or_default();
+        Ok(SwapResult {
+            amount_in,
+            amount_out,
+            fee_amount,
+            sqrt_price_x64: price.sqrt_price_x64,
+            tick: price.tick,
+            ticks_crossed: 0,
+            complete: true,
+        })
+    }
+}
+
+/// Raydium AMM v4 pool and the balances of its token vaults
+#[derive(Debug, Clone, Default)]
+pub struct AmmV4Venue {
+    pub pool: Option<AmmInfo>,
+    /// `None` until the vault account is loaded
+    pub coin_vault: Option<u64>,
+    pub pc_vault: Option<u64>,
+}
+
+impl AmmV4Venue {
+    /// Coin and pc reserves, `None` until the pool and both vaults are loaded
+    fn reserves(&self) -> Option<(u64, u64)> {
+        let pool = self.pool.as_ref()?;
+        Some(pool.reserves(self.coin_vault?, self.pc_vault?))
+    }
+
+    fn quote(
+        &self,
+        amount: u64,
+        coin_to_pc: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<SwapResult> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let (coin, pc) = self
+            .reserves()
+            .ok_or_else(|| anyhow::Error::msg("Error: no vault balances yet"))?;
+        pool.quote(coin, pc, amount, coin_to_pc, is_base_input)
+    }
+}
+
+impl DexVenue for AmmV4Venue {
+    fn pool_type(&self) -> PoolType {
+        PoolType::RaydiumAmm
+    }
+
+    /// The vaults are only known once the pool account is loaded
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        let vaults = self
+            .pool
+            .as_ref()
+            .map(|pool| vec![pool.coin_vault, pool.pc_vault])
+            .unwrap_or_default();
+        std::iter::once(*pool_id)
+            .chain(vaults)
+            .map(AccountFilter::Account)
+            .collect()
+    }
+
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        if pubkey == pool_id {
+            self.pool = Some(AmmInfo::decode(data)?);
+            return Ok(true);
+        }
+        let Some(pool) = &self.pool else {
+            return Ok(false);
+        };
+        let vault = if *pubkey == pool.coin_vault {
+            &mut self.coin_vault
+        } else if *pubkey == pool.pc_vault {
+            &mut self.pc_vault
+        } else {
+            return Ok(false);
+        };
+        *vault = Some(
+            token_account_amount(data)
+                .ok_or_else(|| anyhow::Error::msg("Error: invalid token account"))?,
+        );
+        Ok(true)
+    }
+
+    fn missing_accounts(&self, _pool_id: &Pubkey) -> Vec<Pubkey> {
+        let Some(pool) = &self.pool else {
+            return Vec::new();
+        };
+        [
+            self.coin_vault.is_none().then_some(pool.coin_vault),
+            self.pc_vault.is_none().then_some(pool.pc_vault),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn price(&self) -> Option<PoolPrice> {
+        let (coin, pc) = self.reserves()?;
+        constant_product::price(coin, pc)
+    }
+
+    fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        let pool = self.pool.as_ref()?;
+        Some((pool.coin_vault_mint, pool.pc_vault_mint))
+    }
+
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        coin_to_pc: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_in, coin_to_pc, true)
+    }
+
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        coin_to_pc: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_out, coin_to_pc, false)
+    }
+
+    /// `swap_base_in_v2` / `swap_base_out_v2`: the amount comes first, then the threshold on
+    /// exact in swaps and the other way around on exact out ones
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let authority = Pubkey::create_program_address(
+            &[AMM_AUTHORITY_SEED, &[pool.nonce as u8]],
+            &AMM_V4_PROGRAM_ID,
+        )?;
+        let (source_mint, destination_mint) = if swap.zero_for_one {
+            (pool.coin_vault_mint, pool.pc_vault_mint)
+        } else {
+            (pool.pc_vault_mint, pool.coin_vault_mint)
+        };
+        let accounts = vec![
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new(*pool_id, false),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new(pool.coin_vault, false),
+            AccountMeta::new(pool.pc_vault, false),
+            AccountMeta::new(associated_token_address(&swap.user, &source_mint), false),
+            AccountMeta::new(
+                associated_token_address(&swap.user, &destination_mint),
+                false,
+            ),
+            AccountMeta::new_readonly(swap.user, true),
+        ];
+
+        let (tag, first, second) = if swap.is_base_input {
+            (SWAP_BASE_IN_V2, swap.amount, swap.other_amount_threshold)
+        } else {
+            (SWAP_BASE_OUT_V2, swap.other_amount_threshold, swap.amount)
+        };
+        let mut data = vec![tag];
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&second.to_le_bytes());
+        Ok(Instruction {
+            program_id: AMM_V4_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn DexVenue> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{
+        balances::{USDC_MINT, WSOL_MINT},
+        market::DexStruct,
+    };
+
+    pub(crate) fn amm_info(coin_vault: Pubkey, pc_vault: Pubkey) -> AmmInfo {
+        AmmInfo {
+            coin_decimals: 9,
+            pc_decimals: 6,
+            fees: AmmFees {
+                swap_fee_numerator: 25,
+                swap_fee_denominator: 10_000,
+                ..Default::default()
+            },
+            state_data: AmmStateData {
+                need_take_pnl_coin: 1_000_000,
+                need_take_pnl_pc: 2_000_000,
+                ..Default::default()
+            },
+            coin_vault,
+            pc_vault,
+            coin_vault_mint: WSOL_MINT,
+            pc_vault_mint: USDC_MINT,
+            ..Default::default()
+        }
+    }
+
+    /// Pool of `mints` with no PnL to take, so its vaults hold exactly its reserves, and a valid
+    /// authority nonce so its swaps build
+    pub(crate) fn amm_pool(mints: (Pubkey, Pubkey)) -> AmmInfo {
+        let mut info = amm_info(Pubkey::new_unique(), Pubkey::new_unique());
+        info.state_data.need_take_pnl_coin = 0;
+        info.state_data.need_take_pnl_pc = 0;
+        info.nonce =
+            Pubkey::find_program_address(&[AMM_AUTHORITY_SEED], &AMM_V4_PROGRAM_ID).1 as u64;
+        (info.coin_vault_mint, info.pc_vault_mint) = mints;
+        info
+    }
+
+    /// `amm_pool` holding `reserves` of `mints` at `slot`, 25 bps fee
+    pub(crate) fn amm_dex(mints: (Pubkey, Pubkey), reserves: (u64, u64), slot: u64) -> DexStruct {
+        DexStruct {
+            pool: Box::new(AmmV4Venue {
+                pool: Some(amm_pool(mints)),
+                coin_vault: Some(reserves.0),
+                pc_vault: Some(reserves.1),
+            }),
+            slot,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn decodes_and_quotes_with_the_swap_fee() {
+        let info = amm_info(Pubkey::new_unique(), Pubkey::new_unique());
+        let data = bincode::serialize(&info).unwrap();
+        assert_eq!(data.len(), AMM_INFO_LEN);
+        let info = AmmInfo::decode(&data).unwrap();
+        assert_eq!(info.fees.swap_fee_numerator, 25);
+
+        let (coin, pc) = info.reserves(1_000_001_000_000, 150_002_000_000);
+        assert_eq!((coin, pc), (1_000_000_000_000, 150_000_000_000));
+        let sell = info.quote(coin, pc, 1_000_000_000, true, true).unwrap();
+        assert_eq!(sell.fee_amount, 2_500_000);
+        assert_eq!(sell.amount_out, 149_475_897);
+
+        // exact out gives back at least the exact in input for the same output
+        let buy = info.quote(coin, pc, sell.amount_out, true, false).unwrap();
+        assert_eq!(buy.amount_out, sell.amount_out);
+        assert!(buy.amount_in >= 999_999_990 && buy.amount_in <= 1_000_000_000);
+        assert!(info.quote(coin, pc, pc, true, false).is_err());
+    }
+
+    #[test]
+    fn quote_rejects_inputs_that_overflow_the_reserves() {
+        let info = amm_info(Pubkey::new_unique(), Pubkey::new_unique());
+        let (coin, pc) = (u64::MAX - 1_000, 150_000_000_000);
+        assert!(info.quote(coin, pc, 1_000_000_000, true, true).is_err());
+        assert!(info.quote(pc, coin, 1_000_000_000, false, true).is_err());
+        assert!(info.quote(coin, pc, u64::MAX, true, true).is_err());
+
+        // the largest reserves still quote
+        let sell = info.quote(u64::MAX / 2, u64::MAX / 2, u64::MAX / 4, true, true);
+        assert!(sell.unwrap().amount_out > 0);
+    }
+}
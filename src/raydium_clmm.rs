@@ -1,7 +1,29 @@
 use serde::{Deserialize, Serialize};
-use solana_sdk::pubkey::Pubkey;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    balances::{TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID, associated_token_address},
+    clmm_simulator::{self, PoolPrice, SwapResult, TickArrayCache, missing_tick_arrays},
+    raydium_math::tick_array_bit_map::{TICK_ARRAY_BITMAP_SIZE, TICK_ARRAY_SIZE},
+    venue::{self, AccountFilter, DexVenue, PoolType, SwapParams},
+};
+
+pub const CLMM_PROGRAM_ID: Pubkey = pubkey!("CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK");
+/// Taken by `swap_v2` to support Token-2022 transfer memos
+const MEMO_PROGRAM_ID: Pubkey = pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+pub const TICK_ARRAY_SEED: &[u8] = b"tick_array";
+/// How many tick arrays on each side of the current one are kept cached
+const TICK_ARRAY_WINDOW: i32 = 5;
+/// Size of a tick array account, including the 8 byte anchor discriminator
+pub const TICK_ARRAY_ACCOUNT_LEN: usize = 10240;
+/// Size of a packed `TickState` inside a tick array account
+const TICK_STATE_LEN: usize = 168;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PoolState {
     /// Bump to identify PDA
     pub bump: [u8; 1],
@@ -73,6 +95,102 @@ pub struct PoolState {
     pub padding2: [u64; 32],
 }
 
+impl PoolState {
+    /// Whether the tick array starting at `start_index` holds any initialized tick, according
+    /// to the pool's default bitmap. Returns `None` when the array is outside the range covered
+    /// by the bitmap (the bitmap extension account is not tracked).
+    pub fn is_tick_array_initialized(&self, start_index: i32) -> Option<bool> {
+        let multiplier = TickArrayState::tick_count(self.tick_spacing);
+        let bit_pos = start_index.div_euclid(multiplier) + TICK_ARRAY_BITMAP_SIZE;
+        if !(0..TICK_ARRAY_BITMAP_SIZE * 2).contains(&bit_pos) {
+            return None;
+        }
+        let word = self.tick_array_bitmap[bit_pos as usize / 64];
+        Some(word & (1 << (bit_pos % 64)) != 0)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AmmConfig {
+    /// Bump to identify PDA
+    pub bump: u8,
+    pub index: u16,
+    /// Address of the protocol owner
+    pub owner: Pubkey,
+    /// The protocol fee
+    pub protocol_fee_rate: u32,
+    /// The trade fee, denominated in hundredths of a bip (10^-6)
+    pub trade_fee_rate: u32,
+    /// The tick spacing
+    pub tick_spacing: u16,
+    /// The fund fee, denominated in hundredths of a bip (10^-6)
+    pub fund_fee_rate: u32,
+    pub padding_u32: u32,
+    pub fund_owner: Pubkey,
+    pub padding: [u64; 3],
+}
+
+impl AmmConfig {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() < 8 {
+            return Err(anyhow::Error::msg("Error: AmmConfig account too short"));
+        }
+        Ok(bincode::deserialize(&data[8..])?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TickArrayState {
+    pub pool_id: Pubkey,
+    pub start_tick_index: i32,
+    pub ticks: Vec<TickState>,
+}
+
+impl TickArrayState {
+    /// Decodes a raw tick array account. The on-chain struct is packed, so the ticks are read
+    /// one by one instead of through serde (which can't derive for `[TickState; 60]`).
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != TICK_ARRAY_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: invalid tick array account size"));
+        }
+        let pool_id = Pubkey::try_from(&data[8..40])?;
+        let start_tick_index = i32::from_le_bytes(data[40..44].try_into()?);
+        let ticks_end = 44 + TICK_ARRAY_SIZE as usize * TICK_STATE_LEN;
+        let ticks = data[44..ticks_end]
+            .chunks_exact(TICK_STATE_LEN)
+            .map(bincode::deserialize::<TickState>)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self {
+            pool_id,
+            start_tick_index,
+            ticks,
+        })
+    }
+
+    /// Number of ticks covered by one tick array
+    pub fn tick_count(tick_spacing: u16) -> i32 {
+        TICK_ARRAY_SIZE * i32::from(tick_spacing)
+    }
+
+    /// Start index of the tick array containing `tick_index`
+    pub fn get_array_start_index(tick_index: i32, tick_spacing: u16) -> i32 {
+        let ticks_in_array = Self::tick_count(tick_spacing);
+        tick_index.div_euclid(ticks_in_array) * ticks_in_array
+    }
+
+    pub fn address(pool_id: &Pubkey, start_tick_index: i32) -> Pubkey {
+        Pubkey::find_program_address(
+            &[
+                TICK_ARRAY_SEED,
+                pool_id.as_ref(),
+                &start_tick_index.to_be_bytes(),
+            ],
+            &CLMM_PROGRAM_ID,
+        )
+        .0
+    }
+}
+
 #[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct RewardInfo {
     pub reward_state: u8,
@@ -118,3 +236,211 @@ pub enum RewardState {
     Opening,
     Ended,
 }
+
+/// Raydium CLMM pool, its fee rate from the AMM config and cached tick arrays
+#[derive(Debug, Clone, Default)]
+pub struct ClmmVenue {
+    pub pool: Option<PoolState>,
+    pub tick_arrays: TickArrayCache,
+    /// `None` until the AMM config is loaded
+    pub fee_rate: Option<u32>,
+}
+
+impl ClmmVenue {
+    fn quote(
+        &self,
+        amount: u64,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<SwapResult> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let fee_rate = self
+            .fee_rate
+            .ok_or_else(|| anyhow::Error::msg("Error: no AMM config yet"))?;
+        clmm_simulator::simulate_swap(
+            pool,
+            &self.tick_arrays,
+            fee_rate,
+            amount,
+            zero_for_one,
+            is_base_input,
+        )
+    }
+
+    /// Start indexes of the initialized tick arrays a swap ending at `end_tick` goes through,
+    /// plus the next one in case the price moves before it lands
+    fn swap_tick_arrays(pool: &PoolState, end_tick: i32, zero_for_one: bool) -> Vec<i32> {
+        let ticks_in_array = TickArrayState::tick_count(pool.tick_spacing);
+        let step = if zero_for_one {
+            -ticks_in_array
+        } else {
+            ticks_in_array
+        };
+        let current = TickArrayState::get_array_start_index(pool.tick_current, pool.tick_spacing);
+        let end = TickArrayState::get_array_start_index(end_tick, pool.tick_spacing);
+        let mut starts: Vec<i32> = (0..=TICK_ARRAY_WINDOW)
+            .map(|offset| current + offset * step)
+            .filter(|start| pool.is_tick_array_initialized(*start) == Some(true))
+            .collect();
+        let reached = starts
+            .iter()
+            .filter(|start| {
+                if zero_for_one {
+                    **start >= end
+                } else {
+                    **start <= end
+                }
+            })
+            .count();
+        starts.truncate(reached + 1);
+        starts
+    }
+}
+
+impl DexVenue for ClmmVenue {
+    fn pool_type(&self) -> PoolType {
+        PoolType::RaydiumClmm
+    }
+
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        vec![
+            AccountFilter::Account(*pool_id),
+            AccountFilter::PoolArrays {
+                owner: CLMM_PROGRAM_ID,
+                data_size: TICK_ARRAY_ACCOUNT_LEN,
+                pool_offset: 8,
+                pool: *pool_id,
+            },
+        ]
+    }
+
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        if pubkey == pool_id {
+            let pool: PoolState = bincode::deserialize(
+                data.get(8..)
+                    .ok_or_else(|| anyhow::Error::msg("Error: pool account too short"))?,
+            )?;
+            self.pool = Some(pool);
+        } else if self.pool.as_ref().is_some_and(|p| p.amm_config == *pubkey) {
+            self.fee_rate = Some(AmmConfig::decode(data)?.trade_fee_rate);
+        } else if data.len() == TICK_ARRAY_ACCOUNT_LEN {
+            let tick_array = TickArrayState::decode(data)?;
+            if tick_array.pool_id != *pool_id {
+                return Ok(false);
+            }
+            self.tick_arrays
+                .insert(tick_array.start_tick_index, tick_array);
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn missing_accounts(&self, pool_id: &Pubkey) -> Vec<Pubkey> {
+        let Some(pool) = &self.pool else {
+            return Vec::new();
+        };
+        let amm_config = self.fee_rate.is_none().then_some(pool.amm_config);
+        let tick_arrays = missing_tick_arrays(pool, &self.tick_arrays, TICK_ARRAY_WINDOW)
+            .into_iter()
+            .map(|start| TickArrayState::address(pool_id, start));
+        amm_config.into_iter().chain(tick_arrays).collect()
+    }
+
+    fn price(&self) -> Option<PoolPrice> {
+        self.pool.as_ref().map(|pool| PoolPrice {
+            sqrt_price_x64: pool.sqrt_price_x64,
+            liquidity: pool.liquidity,
+            tick: pool.tick_current,
+        })
+    }
+
+    fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        let pool = self.pool.as_ref()?;
+        Some((pool.token_mint0, pool.token_mint1))
+    }
+
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        zero_for_one: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_in, zero_for_one, true)
+    }
+
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        zero_for_one: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_out, zero_for_one, false)
+    }
+
+    /// `swap_v2`, the tick arrays go in the remaining accounts. The user's token accounts are
+    /// the SPL Token ATAs, like the SOL and USDC mints.
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let end_tick = self
+            .quote(swap.amount, swap.zero_for_one, swap.is_base_input)?
+            .tick;
+        let (input_vault, output_vault, input_mint, output_mint) = if swap.zero_for_one {
+            (
+                pool.token_vault0,
+                pool.token_vault1,
+                pool.token_mint0,
+                pool.token_mint1,
+            )
+        } else {
+            (
+                pool.token_vault1,
+                pool.token_vault0,
+                pool.token_mint1,
+                pool.token_mint0,
+            )
+        };
+
+        let mut accounts = vec![
+            AccountMeta::new_readonly(swap.user, true),
+            AccountMeta::new_readonly(pool.amm_config, false),
+            AccountMeta::new(*pool_id, false),
+            AccountMeta::new(associated_token_address(&swap.user, &input_mint), false),
+            AccountMeta::new(associated_token_address(&swap.user, &output_mint), false),
+            AccountMeta::new(input_vault, false),
+            AccountMeta::new(output_vault, false),
+            AccountMeta::new(pool.observation_key, false),
+            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
+            AccountMeta::new_readonly(TOKEN_2022_PROGRAM_ID, false),
+            AccountMeta::new_readonly(MEMO_PROGRAM_ID, false),
+            AccountMeta::new_readonly(input_mint, false),
+            AccountMeta::new_readonly(output_mint, false),
+        ];
+        accounts.extend(
+            Self::swap_tick_arrays(pool, end_tick, swap.zero_for_one)
+                .into_iter()
+                .map(|start| AccountMeta::new(TickArrayState::address(pool_id, start), false)),
+        );
+
+        let mut data = venue::anchor_discriminator("swap_v2").to_vec();
+        data.extend_from_slice(&swap.amount.to_le_bytes());
+        data.extend_from_slice(&swap.other_amount_threshold.to_le_bytes());
+        // no price limit, the threshold protects the swap
+        data.extend_from_slice(&0u128.to_le_bytes());
+        data.push(u8::from(swap.is_base_input));
+        Ok(Instruction {
+            program_id: CLMM_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn DexVenue> {
+        Box::new(self.clone())
+    }
+}
@@ -0,0 +1,724 @@
+//! Raydium CPMM (constant product, Token-2022 capable) account layouts and quotes. Reserves are
+//! the vault balances minus the protocol, fund and creator fees they hold; the trade fee rate
+//! comes from the pool's AMM config. Token-2022 mints are streamed too, their transfer fees are
+//! taken from what the pool receives and what it pays out.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey,
+    pubkey::Pubkey,
+};
+
+use crate::{
+    balances::{TOKEN_2022_PROGRAM_ID, token_account_amount},
+    clmm_simulator::{PoolPrice, SwapResult},
+    constant_product,
+    transfer_fee::{TransferFee, TransferFeeConfig},
+    venue::{self, AccountFilter, DexVenue, PoolType, SwapParams},
+};
+
+pub const CPMM_PROGRAM_ID: Pubkey = pubkey!("CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C");
+/// Seed of the authority owning the vaults of every pool
+const AUTHORITY_SEED: &[u8] = b"vault_and_lp_mint_auth_seed";
+
+/// Size of a CPMM pool account, including the 8 byte anchor discriminator
+pub const POOL_ACCOUNT_LEN: usize = 637;
+/// Size of a CPMM AMM config account, including the 8 byte anchor discriminator
+pub const AMM_CONFIG_ACCOUNT_LEN: usize = 236;
+/// Fee rates are in hundredths of a bip (10^-6)
+pub const FEE_RATE_DENOMINATOR: u64 = 1_000_000;
+/// Bit of `CpmmPoolState::status` that disables swaps
+const STATUS_SWAP_DISABLED: u8 = 1 << 2;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CpmmPoolState {
+    pub amm_config: Pubkey,
+    pub pool_creator: Pubkey,
+    pub token_0_vault: Pubkey,
+    pub token_1_vault: Pubkey,
+    pub lp_mint: Pubkey,
+    /// Token pair of the pool, where token_0_mint address < token_1_mint address
+    pub token_0_mint: Pubkey,
+    pub token_1_mint: Pubkey,
+    pub token_0_program: Pubkey,
+    pub token_1_program: Pubkey,
+    pub observation_key: Pubkey,
+    pub auth_bump: u8,
+    /// Bit 0 disables deposits, bit 1 withdrawals, bit 2 swaps
+    pub status: u8,
+    pub lp_mint_decimals: u8,
+    pub mint_0_decimals: u8,
+    pub mint_1_decimals: u8,
+    pub lp_supply: u64,
+    /// Fees held in the vaults that aren't part of the reserves
+    pub protocol_fees_token_0: u64,
+    pub protocol_fees_token_1: u64,
+    pub fund_fees_token_0: u64,
+    pub fund_fees_token_1: u64,
+    pub open_time: u64,
+    pub recent_epoch: u64,
+    /// Token the creator fee is taken in: 0 the input token, 1 only token 0, 2 only token 1
+    pub creator_fee_on: u8,
+    pub enable_creator_fee: bool,
+    pub padding1: [u8; 6],
+    pub creator_fees_token_0: u64,
+    pub creator_fees_token_1: u64,
+    pub padding: [u64; 28],
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CpmmConfig {
+    pub bump: u8,
+    pub disable_create_pool: bool,
+    pub index: u16,
+    /// Fee taken from every swap, of which the protocol and fund take their share
+    pub trade_fee_rate: u64,
+    /// Share of the trade fee, in hundredths of a bip of the fee
+    pub protocol_fee_rate: u64,
+    pub fund_fee_rate: u64,
+    pub create_pool_fee: u64,
+    pub protocol_owner: Pubkey,
+    pub fund_owner: Pubkey,
+    /// Fee paid to the pool creator on top of the trade fee, on pools that enable it
+    pub creator_fee_rate: u64,
+    pub padding: [u64; 15],
+}
+
+/// Fees of a swap. The protocol and fund fees are taken out of the trade fee.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CpmmFees {
+    pub trade_fee: u64,
+    pub protocol_fee: u64,
+    pub fund_fee: u64,
+    pub creator_fee: u64,
+}
+
+impl CpmmPoolState {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != POOL_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg("Error: invalid CPMM pool account size"));
+        }
+        Ok(bincode::deserialize(&data[8..])?)
+    }
+
+    /// Token 0 and token 1 reserves given the balances of the two vaults
+    pub fn reserves(&self, vault_0: u64, vault_1: u64) -> (u64, u64) {
+        (
+            vault_0
+                .saturating_sub(self.protocol_fees_token_0)
+                .saturating_sub(self.fund_fees_token_0)
+                .saturating_sub(self.creator_fees_token_0),
+            vault_1
+                .saturating_sub(self.protocol_fees_token_1)
+                .saturating_sub(self.fund_fees_token_1)
+                .saturating_sub(self.creator_fees_token_1),
+        )
+    }
+
+    fn is_creator_fee_on_input(&self, zero_for_one: bool) -> bool {
+        match self.creator_fee_on {
+            1 => zero_for_one,
+            2 => !zero_for_one,
+            _ => true,
+        }
+    }
+
+    /// Quotes a swap like the `swap_base_input` / `swap_base_output` instructions. The trade fee
+    /// is taken from the input, the creator fee from the input or the output depending on the
+    /// pool. `zero_for_one` sells token 0.
+    pub fn quote(
+        &self,
+        config: &CpmmConfig,
+        reserve_0: u64,
+        reserve_1: u64,
+        amount_specified: u64,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<(SwapResult, CpmmFees)> {
+        if self.status & STATUS_SWAP_DISABLED != 0 {
+            return Err(anyhow::Error::msg("Error: swaps are disabled on the pool"));
+        }
+        let (reserve_in, reserve_out) = if zero_for_one {
+            (reserve_0, reserve_1)
+        } else {
+            (reserve_1, reserve_0)
+        };
+        let trade_fee_rate = config.trade_fee_rate;
+        let creator_fee_rate = if self.enable_creator_fee {
+            config.creator_fee_rate
+        } else {
+            0
+        };
+        if trade_fee_rate + creator_fee_rate >= FEE_RATE_DENOMINATOR {
+            return Err(anyhow::Error::msg("Error: invalid CPMM fee rates"));
+        }
+        let on_input = self.is_creator_fee_on_input(zero_for_one);
+        let too_large = || anyhow::Error::msg("Error: swap exceeds the pool reserves");
+
+        let (amount_in, amount_out, trade_fee, creator_fee) = if is_base_input {
+            let amount_in = amount_specified;
+            let trade_fee = fee(amount_in, trade_fee_rate);
+            if on_input {
+                let creator_fee = fee(amount_in, creator_fee_rate);
+                let less_fees = amount_in
+                    .checked_sub(trade_fee + creator_fee)
+                    .ok_or_else(too_large)?;
+                let out = constant_product::amount_out(less_fees, reserve_in, reserve_out);
+                (amount_in, out, trade_fee, creator_fee)
+            } else {
+                let swapped =
+                    constant_product::amount_out(amount_in - trade_fee, reserve_in, reserve_out);
+                let creator_fee = fee(swapped, creator_fee_rate);
+                (amount_in, swapped - creator_fee, trade_fee, creator_fee)
+            }
+        } else if on_input {
+            let swapped = constant_product::amount_in(amount_specified, reserve_in, reserve_out)
+                .ok_or_else(too_large)?;
+            let amount_in =
+                pre_fee_amount(swapped, trade_fee_rate + creator_fee_rate).ok_or_else(too_large)?;
+            let (trade_fee, creator_fee) = (
+                fee(amount_in, trade_fee_rate),
+                fee(amount_in, creator_fee_rate),
+            );
+            (amount_in, amount_specified, trade_fee, creator_fee)
+        } else {
+            let swapped_out =
+                pre_fee_amount(amount_specified, creator_fee_rate).ok_or_else(too_large)?;
+            let swapped = constant_product::amount_in(swapped_out, reserve_in, reserve_out)
+                .ok_or_else(too_large)?;
+            let amount_in = pre_fee_amount(swapped, trade_fee_rate).ok_or_else(too_large)?;
+            let trade_fee = fee(amount_in, trade_fee_rate);
+            (
+                amount_in,
+                amount_specified,
+                trade_fee,
+                swapped_out - amount_specified,
+            )
+        };
+
+        let fees = CpmmFees {
+            trade_fee,
+            protocol_fee: split(trade_fee, config.protocol_fee_rate),
+            fund_fee: split(trade_fee, config.fund_fee_rate),
+            creator_fee,
+        };
+        // what the vaults gain minus the fees set aside, the LP share of the trade fee stays in
+        let in_after = reserve_in + amount_in
+            - fees.protocol_fee
+            - fees.fund_fee
+            - if on_input { creator_fee } else { 0 };
+        let out_after = reserve_out - amount_out - if on_input { 0 } else { creator_fee };
+        let (reserve_0, reserve_1) = if zero_for_one {
+            (in_after, out_after)
+        } else {
+            (out_after, in_after)
+        };
+        let price = constant_product::price(reserve_0, reserve_1).unwrap_or_default();
+        let result = SwapResult {
+            amount_in,
+            amount_out,
+            fee_amount: trade_fee + creator_fee,
+            sqrt_price_x64: price.sqrt_price_x64,
+            tick: price.tick,
+            ticks_crossed: 0,
+            complete: true,
+        };
+        Ok((result, fees))
+    }
+}
+
+impl CpmmConfig {
+    pub fn decode(data: &[u8]) -> anyhow::Result<Self> {
+        if data.len() != AMM_CONFIG_ACCOUNT_LEN {
+            return Err(anyhow::Error::msg(
+                "Error: invalid CPMM config account size",
+            ));
+        }
+        Ok(bincode::deserialize(&data[8..])?)
+    }
+}
+
+/// Fee at `rate` on `amount`, rounded up
+fn fee(amount: u64, rate: u64) -> u64 {
+    (u128::from(amount) * u128::from(rate)).div_ceil(u128::from(FEE_RATE_DENOMINATOR)) as u64
+}
+
+/// Share at `rate` of a fee, rounded down
+fn split(fee: u64, rate: u64) -> u64 {
+    (u128::from(fee) * u128::from(rate) / u128::from(FEE_RATE_DENOMINATOR)) as u64
+}
+
+/// Amount that leaves `post_fee` once a fee at `rate` is taken, rounded up
+fn pre_fee_amount(post_fee: u64, rate: u64) -> Option<u64> {
+    let denominator = u128::from(FEE_RATE_DENOMINATOR - rate);
+    u64::try_from((u128::from(post_fee) * u128::from(FEE_RATE_DENOMINATOR)).div_ceil(denominator))
+        .ok()
+}
+
+/// Raydium CPMM pool, its AMM config, the balances of its token vaults and the transfer fees of
+/// its Token-2022 mints
+#[derive(Debug, Clone, Default)]
+pub struct CpmmVenue {
+    pub pool: Option<CpmmPoolState>,
+    /// `None` until the AMM config is loaded
+    pub config: Option<CpmmConfig>,
+    /// `None` until the vault account is loaded
+    pub vault_0: Option<u64>,
+    pub vault_1: Option<u64>,
+    /// `None` until a Token-2022 mint is loaded, never set for SPL token mints
+    pub transfer_fee_0: Option<TransferFeeConfig>,
+    pub transfer_fee_1: Option<TransferFeeConfig>,
+}
+
+impl CpmmVenue {
+    /// Token-2022 mints of the pool, the ones that can charge transfer fees
+    fn token_2022_mints(pool: &CpmmPoolState) -> impl Iterator<Item = Pubkey> {
+        [
+            (pool.token_0_mint, pool.token_0_program),
+            (pool.token_1_mint, pool.token_1_program),
+        ]
+        .into_iter()
+        .filter(|(_, program)| *program == TOKEN_2022_PROGRAM_ID)
+        .map(|(mint, _)| mint)
+    }
+
+    /// Transfer fees of token 0 and token 1 in the pool's epoch, zero for SPL token mints.
+    /// The epoch of the pool's latest swap stands in for the cluster's.
+    fn transfer_fees(&self, pool: &CpmmPoolState) -> anyhow::Result<(TransferFee, TransferFee)> {
+        let fee = |config: &Option<TransferFeeConfig>, program: &Pubkey| {
+            if *program != TOKEN_2022_PROGRAM_ID {
+                return Ok(TransferFee::default());
+            }
+            config
+                .map(|config| *config.epoch_fee(pool.recent_epoch))
+                .ok_or_else(|| anyhow::Error::msg("Error: no Token-2022 mint yet"))
+        };
+        Ok((
+            fee(&self.transfer_fee_0, &pool.token_0_program)?,
+            fee(&self.transfer_fee_1, &pool.token_1_program)?,
+        ))
+    }
+
+    /// Token 0 and token 1 reserves, `None` until the pool and both vaults are loaded
+    fn reserves(&self) -> Option<(u64, u64)> {
+        let pool = self.pool.as_ref()?;
+        Some(pool.reserves(self.vault_0?, self.vault_1?))
+    }
+
+    fn quote(
+        &self,
+        amount: u64,
+        zero_for_one: bool,
+        is_base_input: bool,
+    ) -> anyhow::Result<SwapResult> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let config = self
+            .config
+            .as_ref()
+            .ok_or_else(|| anyhow::Error::msg("Error: no AMM config yet"))?;
+        let (reserve_0, reserve_1) = self
+            .reserves()
+            .ok_or_else(|| anyhow::Error::msg("Error: no vault balances yet"))?;
+        let (fee_0, fee_1) = self.transfer_fees(pool)?;
+        let (fee_in, fee_out) = if zero_for_one {
+            (fee_0, fee_1)
+        } else {
+            (fee_1, fee_0)
+        };
+        let too_large = || anyhow::Error::msg("Error: swap exceeds the pool reserves");
+
+        // the pool swaps what reaches its vault, the user gets what's left of the pool's output
+        let (mut swap, _) = if is_base_input {
+            let received = amount - fee_in.fee(amount);
+            pool.quote(config, reserve_0, reserve_1, received, zero_for_one, true)?
+        } else {
+            let sent = fee_out.pre_fee_amount(amount).ok_or_else(too_large)?;
+            pool.quote(config, reserve_0, reserve_1, sent, zero_for_one, false)?
+        };
+        let transfer_fees = if is_base_input {
+            let (fee_in, fee_out) = (fee_in.fee(amount), fee_out.fee(swap.amount_out));
+            swap.amount_in = amount;
+            swap.amount_out -= fee_out;
+            fee_in + fee_out
+        } else {
+            let paid = fee_in
+                .pre_fee_amount(swap.amount_in)
+                .ok_or_else(too_large)?;
+            let fees = (paid - swap.amount_in) + (swap.amount_out - amount);
+            swap.amount_in = paid;
+            swap.amount_out = amount;
+            fees
+        };
+        swap.fee_amount += transfer_fees;
+        Ok(swap)
+    }
+}
+
+impl DexVenue for CpmmVenue {
+    fn pool_type(&self) -> PoolType {
+        PoolType::RaydiumCpmm
+    }
+
+    /// The vaults and mints are only known once the pool account is loaded
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter> {
+        let vaults = self
+            .pool
+            .as_ref()
+            .map(|pool| {
+                let mut accounts = vec![pool.token_0_vault, pool.token_1_vault];
+                accounts.extend(Self::token_2022_mints(pool));
+                accounts
+            })
+            .unwrap_or_default();
+        std::iter::once(*pool_id)
+            .chain(vaults)
+            .map(AccountFilter::Account)
+            .collect()
+    }
+
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool> {
+        if pubkey == pool_id {
+            self.pool = Some(CpmmPoolState::decode(data)?);
+            return Ok(true);
+        }
+        let Some(pool) = &self.pool else {
+            return Ok(false);
+        };
+        let vault = if *pubkey == pool.amm_config {
+            self.config = Some(CpmmConfig::decode(data)?);
+            return Ok(true);
+        } else if Self::token_2022_mints(pool).any(|mint| mint == *pubkey) {
+            let config = Some(TransferFeeConfig::decode_mint(data)?);
+            if *pubkey == pool.token_0_mint {
+                self.transfer_fee_0 = config;
+            } else {
+                self.transfer_fee_1 = config;
+            }
+            return Ok(true);
+        } else if *pubkey == pool.token_0_vault {
+            &mut self.vault_0
+        } else if *pubkey == pool.token_1_vault {
+            &mut self.vault_1
+        } else {
+            return Ok(false);
+        };
+        // Token-2022 vaults share the base token account layout
+        *vault = Some(
+            token_account_amount(data)
+                .ok_or_else(|| anyhow::Error::msg("Error: invalid token account"))?,
+        );
+        Ok(true)
+    }
+
+    fn missing_accounts(&self, _pool_id: &Pubkey) -> Vec<Pubkey> {
+        let Some(pool) = &self.pool else {
+            return Vec::new();
+        };
+        let mints = Self::token_2022_mints(pool).filter(|mint| {
+            if *mint == pool.token_0_mint {
+                self.transfer_fee_0.is_none()
+            } else {
+                self.transfer_fee_1.is_none()
+            }
+        });
+        [
+            self.config.is_none().then_some(pool.amm_config),
+            self.vault_0.is_none().then_some(pool.token_0_vault),
+            self.vault_1.is_none().then_some(pool.token_1_vault),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(mints)
+        .collect()
+    }
+
+    fn price(&self) -> Option<PoolPrice> {
+        let (reserve_0, reserve_1) = self.reserves()?;
+        constant_product::price(reserve_0, reserve_1)
+    }
+
+    fn mints(&self) -> Option<(Pubkey, Pubkey)> {
+        let pool = self.pool.as_ref()?;
+        Some((pool.token_0_mint, pool.token_1_mint))
+    }
+
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        zero_for_one: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_in, zero_for_one, true)
+    }
+
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        zero_for_one: bool,
+        _now: i64,
+    ) -> anyhow::Result<SwapResult> {
+        self.quote(amount_out, zero_for_one, false)
+    }
+
+    /// `swap_base_input` / `swap_base_output`, the user's token accounts are the ATAs under
+    /// each mint's token program
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction> {
+        let pool = self.pool.as_ref().ok_or_else(venue::no_pool_state)?;
+        let authority = Pubkey::find_program_address(&[AUTHORITY_SEED], &CPMM_PROGRAM_ID).0;
+        let token_0 = (pool.token_0_vault, pool.token_0_mint, pool.token_0_program);
+        let token_1 = (pool.token_1_vault, pool.token_1_mint, pool.token_1_program);
+        let ((input_vault, input_mint, input_program), (output_vault, output_mint, output_program)) =
+            if swap.zero_for_one {
+                (token_0, token_1)
+            } else {
+                (token_1, token_0)
+            };
+        let accounts = vec![
+            AccountMeta::new_readonly(swap.user, true),
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(pool.amm_config, false),
+            AccountMeta::new(*pool_id, false),
+            AccountMeta::new(
+                venue::user_token_account(&swap.user, &input_mint, &input_program),
+                false,
+            ),
+            AccountMeta::new(
+                venue::user_token_account(&swap.user, &output_mint, &output_program),
+                false,
+            ),
+            AccountMeta::new(input_vault, false),
+            AccountMeta::new(output_vault, false),
+            AccountMeta::new_readonly(input_program, false),
+            AccountMeta::new_readonly(output_program, false),
+            AccountMeta::new_readonly(input_mint, false),
+            AccountMeta::new_readonly(output_mint, false),
+            AccountMeta::new(pool.observation_key, false),
+        ];
+
+        let (name, first, second) = if swap.is_base_input {
+            ("swap_base_input", swap.amount, swap.other_amount_threshold)
+        } else {
+            ("swap_base_output", swap.other_amount_threshold, swap.amount)
+        };
+        let mut data = venue::anchor_discriminator(name).to_vec();
+        data.extend_from_slice(&first.to_le_bytes());
+        data.extend_from_slice(&second.to_le_bytes());
+        Ok(Instruction {
+            program_id: CPMM_PROGRAM_ID,
+            accounts,
+            data,
+        })
+    }
+
+    fn clone_box(&self) -> Box<dyn DexVenue> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn cpmm_pool(amm_config: Pubkey, vaults: (Pubkey, Pubkey)) -> CpmmPoolState {
+        CpmmPoolState {
+            amm_config,
+            token_0_vault: vaults.0,
+            token_1_vault: vaults.1,
+            mint_0_decimals: 9,
+            mint_1_decimals: 6,
+            protocol_fees_token_0: 1_000_000,
+            fund_fees_token_1: 2_000_000,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn cpmm_config(trade_fee_rate: u64, creator_fee_rate: u64) -> CpmmConfig {
+        CpmmConfig {
+            trade_fee_rate,
+            protocol_fee_rate: 120_000,
+            fund_fee_rate: 40_000,
+            creator_fee_rate,
+            ..Default::default()
+        }
+    }
+
+    /// Raw account as stored on chain, behind an anchor discriminator
+    pub(crate) fn account_data<T: Serialize>(account: &T) -> Vec<u8> {
+        let mut data = vec![0; 8];
+        data.extend(bincode::serialize(account).unwrap());
+        data
+    }
+
+    #[test]
+    fn quotes_split_the_fees() {
+        let pool = CpmmPoolState::decode(&account_data(&cpmm_pool(
+            Pubkey::new_unique(),
+            (Pubkey::new_unique(), Pubkey::new_unique()),
+        )))
+        .unwrap();
+        let config = CpmmConfig::decode(&account_data(&cpmm_config(2_500, 0))).unwrap();
+        let (sol, usdc) = pool.reserves(1_000_001_000_000, 150_002_000_000);
+        assert_eq!((sol, usdc), (1_000_000_000_000, 150_000_000_000));
+
+        let (sell, fees) = pool
+            .quote(&config, sol, usdc, 1_000_000_000, true, true)
+            .unwrap();
+        assert_eq!(sell.amount_out, 149_475_897);
+        assert_eq!(
+            fees,
+            CpmmFees {
+                trade_fee: 2_500_000,
+                protocol_fee: 300_000,
+                fund_fee: 100_000,
+                creator_fee: 0,
+            }
+        );
+
+        // creator fee taken from the USDC output when only token 1 pays it
+        let pool = CpmmPoolState {
+            enable_creator_fee: true,
+            creator_fee_on: 2,
+            ..pool
+        };
+        let config = cpmm_config(2_500, 1_000);
+        let (sell, fees) = pool
+            .quote(&config, sol, usdc, 1_000_000_000, true, true)
+            .unwrap();
+        assert_eq!(fees.creator_fee, 149_476);
+        assert_eq!(sell.amount_out, 149_475_897 - 149_476);
+        let (buy, _) = pool
+            .quote(&config, sol, usdc, sell.amount_out, true, false)
+            .unwrap();
+        assert_eq!(buy.amount_out, sell.amount_out);
+        assert!(buy.amount_in >= 999_999_990 && buy.amount_in <= 1_000_000_000);
+
+        // selling USDC pays it on the input
+        let (_, fees) = pool
+            .quote(&config, sol, usdc, 150_000_000, false, true)
+            .unwrap();
+        assert_eq!((fees.trade_fee, fees.creator_fee), (375_000, 150_000));
+
+        let disabled = CpmmPoolState {
+            status: STATUS_SWAP_DISABLED,
+            ..pool
+        };
+        assert!(disabled.quote(&config, sol, usdc, 1, true, true).is_err());
+    }
+
+    fn cpmm_venue(pool: CpmmPoolState) -> CpmmVenue {
+        CpmmVenue {
+            pool: Some(pool),
+            config: Some(cpmm_config(2_500, 0)),
+            vault_0: Some(1_000_001_000_000),
+            vault_1: Some(150_002_000_000),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn venue_quotes_both_directions() {
+        let venue = cpmm_venue(cpmm_pool(
+            Pubkey::new_unique(),
+            (Pubkey::new_unique(), Pubkey::new_unique()),
+        ));
+        assert!(venue.missing_accounts(&Pubkey::new_unique()).is_empty());
+
+        // selling SOL for USDC and buying it back from the other side
+        let sell = venue.quote_exact_in(1_000_000_000, true, 0).unwrap();
+        assert_eq!(sell.amount_out, 149_475_897);
+        let buy = venue.quote_exact_out(sell.amount_out, true, 0).unwrap();
+        assert_eq!(buy.amount_out, sell.amount_out);
+        assert!(buy.amount_in >= 999_999_990 && buy.amount_in <= 1_000_000_000);
+
+        // selling USDC for SOL
+        let sell = venue.quote_exact_in(150_000_000, false, 0).unwrap();
+        assert_eq!(sell.amount_in, 150_000_000);
+        assert_eq!(sell.fee_amount, 375_000);
+        assert!(sell.amount_out > 990_000_000 && sell.amount_out < 1_000_000_000);
+        let buy = venue.quote_exact_out(sell.amount_out, false, 0).unwrap();
+        assert_eq!(buy.amount_out, sell.amount_out);
+        assert!(buy.amount_in >= 149_999_990 && buy.amount_in <= 150_000_000);
+        // the pool moves the other way
+        assert!(sell.sqrt_price_x64 > venue.price().unwrap().sqrt_price_x64);
+
+        assert!(venue.quote_exact_out(150_000_000_000, true, 0).is_err());
+        assert!(
+            CpmmVenue::default()
+                .quote_exact_in(1_000_000_000, true, 0)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn token_2022_transfer_fees_come_off_both_legs() {
+        let (pool_id, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let pool = CpmmPoolState {
+            token_1_mint: mint,
+            token_1_program: TOKEN_2022_PROGRAM_ID,
+            recent_epoch: 700,
+            ..cpmm_pool(
+                Pubkey::new_unique(),
+                (Pubkey::new_unique(), Pubkey::new_unique()),
+            )
+        };
+        // the same pool without the fee
+        let plain = cpmm_venue(CpmmPoolState {
+            token_1_program: Pubkey::default(),
+            ..pool.clone()
+        });
+        let mut venue = cpmm_venue(pool.clone());
+        // the mint is needed before quoting
+        assert_eq!(venue.missing_accounts(&pool_id), vec![mint]);
+        assert!(venue.quote_exact_in(1_000_000_000, true, 0).is_err());
+
+        let fee = TransferFee {
+            epoch: 600,
+            maximum_fee: 1_000_000,
+            basis_points: 100,
+        };
+        let config = TransferFeeConfig {
+            older: TransferFee::default(),
+            newer: fee,
+        };
+        let data = crate::transfer_fee::tests::mint_data(&config);
+        assert!(venue.apply_account(&pool_id, &mint, &data).unwrap());
+        assert!(venue.missing_accounts(&pool_id).is_empty());
+        assert!(
+            venue
+                .subscriptions(&pool_id)
+                .contains(&AccountFilter::Account(mint))
+        );
+
+        // selling SOL: the USDC paid out is charged on its way to the user
+        let raw = plain.quote_exact_in(1_000_000_000, true, 0).unwrap();
+        let sell = venue.quote_exact_in(1_000_000_000, true, 0).unwrap();
+        assert_eq!(sell.amount_out, raw.amount_out - fee.fee(raw.amount_out));
+        assert_eq!(sell.fee_amount, raw.fee_amount + fee.fee(raw.amount_out));
+        // buying exactly that much makes the pool send the fee on top
+        let buy = venue.quote_exact_out(sell.amount_out, true, 0).unwrap();
+        assert_eq!(buy.amount_out, sell.amount_out);
+        assert!(buy.amount_in >= 999_999_990 && buy.amount_in <= 1_000_000_000);
+
+        // selling USDC: the pool only swaps what reaches its vault
+        let sell = venue.quote_exact_in(150_000_000, false, 0).unwrap();
+        let received = 150_000_000 - fee.fee(150_000_000);
+        let raw = plain.quote_exact_in(received, false, 0).unwrap();
+        assert_eq!(sell.amount_in, 150_000_000);
+        assert_eq!(sell.amount_out, raw.amount_out);
+        let buy = venue.quote_exact_out(sell.amount_out, false, 0).unwrap();
+        assert!(buy.amount_in >= 149_999_990 && buy.amount_in <= 150_000_000);
+
+        // before the fee's epoch the older (zero) one applies
+        venue.pool.as_mut().unwrap().recent_epoch = 599;
+        assert_eq!(
+            venue.quote_exact_in(150_000_000, false, 0).unwrap(),
+            plain.quote_exact_in(150_000_000, false, 0).unwrap()
+        );
+    }
+}
@@ -17,20 +17,13 @@ use {
 /// * `y` - The delta (ΔL) by which liquidity should be changed
 ///
 pub fn add_delta(x: u128, y: i128) -> anyhow::Result<u128> {
-    let z: u128;
-    if y < 0 {
-        z = x - u128::try_from(-y).unwrap();
-        if x > z {
-            return Err(anyhow::Error::msg("Error: Liquidity Sub Value"));
-        }
-        // require_gt!(x, z, ErrorCode::LiquiditySubValueErr);
+    let z = if y < 0 {
+        x.checked_sub(y.unsigned_abs())
+            .ok_or_else(|| anyhow::Error::msg("Error: Liquidity Sub Value"))?
     } else {
-        z = x + u128::try_from(y).unwrap();
-        if z > u128::MAX {
-            return Err(anyhow::Error::msg("Error: Liquidity Add Value"));
-        }
-        // require_gte!(z, x, ErrorCode::LiquidityAddValueErr);
-    }
+        x.checked_add(y.unsigned_abs())
+            .ok_or_else(|| anyhow::Error::msg("Error: Liquidity Add Value"))?
+    };
 
     Ok(z)
 }
@@ -322,3 +315,30 @@ pub fn get_delta_amounts_signed(
     }
     Ok((amount_0, amount_1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add_delta_adds_and_subtracts() {
+        assert_eq!(add_delta(100, 20).unwrap(), 120);
+        assert_eq!(add_delta(100, -20).unwrap(), 80);
+        assert_eq!(add_delta(100, -100).unwrap(), 0);
+    }
+
+    #[test]
+    fn add_delta_overflow() {
+        assert_eq!(add_delta(u128::MAX - 1, 1).unwrap(), u128::MAX);
+        assert!(add_delta(u128::MAX, 1).is_err());
+        assert!(add_delta(u128::MAX, i128::MAX).is_err());
+    }
+
+    #[test]
+    fn add_delta_underflow() {
+        assert!(add_delta(0, -1).is_err());
+        assert!(add_delta(5, -6).is_err());
+        assert!(add_delta(u128::MAX, i128::MIN).is_ok());
+        assert!(add_delta(0, i128::MIN).is_err());
+    }
+}
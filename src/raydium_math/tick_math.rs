@@ -34,7 +34,7 @@ const BIT_PRECISION: u32 = 16;
 pub fn get_sqrt_price_at_tick(tick: i32) -> Result<u128, anyhow::Error> {
     let abs_tick = tick.unsigned_abs();
 
-    if abs_tick <= MAX_TICK as u32 {
+    if abs_tick > MAX_TICK as u32 {
         return Err(Error::msg("Unable to init tick"));
     }
 
@@ -134,7 +134,7 @@ pub fn get_sqrt_price_at_tick(tick: i32) -> Result<u128, anyhow::Error> {
 pub fn get_tick_at_sqrt_price(sqrt_price_x64: u128) -> Result<i32, Error> {
     // second inequality must be < because the price can never reach the price at
     // the max tick
-    if (MIN_SQRT_PRICE_X64..MAX_SQRT_PRICE_X64).contains(&sqrt_price_x64) {
+    if !(MIN_SQRT_PRICE_X64..MAX_SQRT_PRICE_X64).contains(&sqrt_price_x64) {
         return Err(Error::msg("Error: the max tick reached"));
     }
 
@@ -188,3 +188,47 @@ pub fn get_tick_at_sqrt_price(sqrt_price_x64: u128) -> Result<i32, Error> {
         tick_low
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqrt_price_at_tick_bounds() {
+        assert_eq!(
+            get_sqrt_price_at_tick(MIN_TICK).unwrap(),
+            MIN_SQRT_PRICE_X64
+        );
+        assert_eq!(
+            get_sqrt_price_at_tick(MAX_TICK).unwrap(),
+            MAX_SQRT_PRICE_X64
+        );
+        assert_eq!(get_sqrt_price_at_tick(0).unwrap(), 1u128 << 64);
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_out_of_range() {
+        assert!(get_sqrt_price_at_tick(MIN_TICK - 1).is_err());
+        assert!(get_sqrt_price_at_tick(MAX_TICK + 1).is_err());
+    }
+
+    #[test]
+    fn tick_at_sqrt_price_bounds() {
+        assert_eq!(
+            get_tick_at_sqrt_price(MIN_SQRT_PRICE_X64).unwrap(),
+            MIN_TICK
+        );
+        assert_eq!(
+            get_tick_at_sqrt_price(MAX_SQRT_PRICE_X64 - 1).unwrap(),
+            MAX_TICK - 1
+        );
+        assert_eq!(get_tick_at_sqrt_price(1u128 << 64).unwrap(), 0);
+    }
+
+    #[test]
+    fn tick_at_sqrt_price_out_of_range() {
+        assert!(get_tick_at_sqrt_price(MIN_SQRT_PRICE_X64 - 1).is_err());
+        // the max tick's price is exclusive
+        assert!(get_tick_at_sqrt_price(MAX_SQRT_PRICE_X64).is_err());
+    }
+}
@@ -0,0 +1,358 @@
+//! Inventory rebalancing. One-directional arbs drain one venue of quote and the other of base;
+//! the planner watches the wallet/CEX split of each asset and proposes transfers back toward the
+//! target split once it drifts past a threshold.
+
+use std::{collections::HashMap, time::Instant};
+
+use serde::Deserialize;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::Keypair,
+    signer::Signer,
+    transaction::Transaction,
+};
+use tracing::info;
+
+use crate::{
+    balances::{Asset, Balances, TOKEN_PROGRAM_ID, USDC_MINT, Venue, associated_token_address},
+    cex::CexVenue,
+    rpc,
+};
+
+const LAMPORTS_PER_SOL: u128 = 1_000_000_000;
+/// SPL token `Transfer` instruction tag
+const SPL_TRANSFER: u8 = 3;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RebalanceConfig {
+    /// Execute planned transfers instead of only logging them
+    pub enabled: bool,
+    /// Share of each asset that should sit in the wallet, the rest on the CEX
+    pub target_wallet_share: f64,
+    /// How far the wallet share may drift from the target before rebalancing
+    pub threshold: f64,
+    /// Minimum time between two transfers of the same asset
+    pub cooldown_secs: u64,
+    pub interval_secs: u64,
+    pub sol_withdrawal_fee: u64,  // lamports charged by the CEX
+    pub usdc_withdrawal_fee: u64, // micro-USDC charged by the CEX
+    pub tx_fee: u64,              // lamports per on-chain transfer
+    /// Our deposit address on the CEX
+    pub cex_deposit_address: Option<String>,
+}
+
+impl Default for RebalanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target_wallet_share: 0.5,
+            threshold: 0.2,
+            cooldown_secs: 600,
+            interval_secs: 30,
+            sol_withdrawal_fee: 2_000_000,
+            usdc_withdrawal_fee: 1_000_000,
+            tx_fee: 5_000,
+            cex_deposit_address: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebalanceAction {
+    /// On-chain transfer from the wallet to the CEX deposit address
+    Deposit { asset: Asset, amount: u64 },
+    /// CEX withdrawal to the wallet
+    Withdraw { asset: Asset, amount: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rebalance {
+    pub action: RebalanceAction,
+    /// Estimated cost in micro-USDC
+    pub cost: u64,
+}
+
+pub struct RebalancePlanner {
+    config: RebalanceConfig,
+    last_transfer: HashMap<Asset, Instant>,
+}
+
+impl RebalancePlanner {
+    pub fn new(config: RebalanceConfig) -> Self {
+        Self {
+            config,
+            last_transfer: HashMap::new(),
+        }
+    }
+
+    /// Transfers needed to bring each asset back to the target split. `sol_price` is in
+    /// micro-USDC per SOL and is only used for the cost estimate.
+    pub fn plan(&self, balances: &Balances, sol_price: u64, now: Instant) -> Vec<Rebalance> {
+        let mut planned = Vec::new();
+        for asset in [Asset::Sol, Asset::Usdc] {
+            let (Some(wallet), Some(cex)) = (
+                balances.available(Venue::Wallet, asset),
+                balances.available(Venue::Cex, asset),
+            ) else {
+                continue;
+            };
+            let total = wallet + cex;
+            if total == 0 {
+                continue;
+            }
+            if let Some(last) = self.last_transfer.get(&asset)
+                && now.duration_since(*last).as_secs() < self.config.cooldown_secs
+            {
+                continue;
+            }
+
+            let share = wallet as f64 / total as f64;
+            if (share - self.config.target_wallet_share).abs() <= self.config.threshold {
+                continue;
+            }
+            let target = (total as f64 * self.config.target_wallet_share) as u64;
+            let action = if wallet > target {
+                // SOL deposits are system transfers, which can't touch the wrapped balance
+                let amount = match asset {
+                    Asset::Sol => (wallet - target).min(balances.wallet_native_sol().unwrap_or(0)),
+                    Asset::Usdc => wallet - target,
+                };
+                if amount == 0 {
+                    continue;
+                }
+                RebalanceAction::Deposit { asset, amount }
+            } else {
+                RebalanceAction::Withdraw {
+                    asset,
+                    amount: target - wallet,
+                }
+            };
+            planned.push(Rebalance {
+                action,
+                cost: self.action_cost(&action, sol_price),
+            });
+        }
+        planned
+    }
+
+    /// Starts the cooldown for the asset moved by `rebalance`
+    pub fn record(&mut self, rebalance: &Rebalance, now: Instant) {
+        let asset = match rebalance.action {
+            RebalanceAction::Deposit { asset, .. } | RebalanceAction::Withdraw { asset, .. } => {
+                asset
+            }
+        };
+        self.last_transfer.insert(asset, now);
+    }
+
+    fn action_cost(&self, action: &RebalanceAction, sol_price: u64) -> u64 {
+        let lamports_to_usdc =
+            |lamports: u64| (lamports as u128 * sol_price as u128 / LAMPORTS_PER_SOL) as u64;
+        match action {
+            RebalanceAction::Deposit { .. } => lamports_to_usdc(self.config.tx_fee),
+            RebalanceAction::Withdraw {
+                asset: Asset::Sol, ..
+            } => lamports_to_usdc(self.config.sol_withdrawal_fee),
+            RebalanceAction::Withdraw {
+                asset: Asset::Usdc, ..
+            } => self.config.usdc_withdrawal_fee,
+        }
+    }
+}
+
+/// Rebalancing cost amortized over the notional traded between two rebalances, in bps.
+///
+/// A full cycle moves SOL one way and USDC the other (one withdrawal plus one deposit), and it
+/// is triggered after roughly `threshold` of the inventory has drifted, i.e. after that much
+/// notional has been traded. Returns 0 while the inventory isn't fully tracked.
+pub fn cost_bps(config: &RebalanceConfig, balances: &Balances, sol_price: u64) -> u64 {
+    let mut total_notional: u128 = 0;
+    for venue in [Venue::Wallet, Venue::Cex] {
+        let (Some(sol), Some(usdc)) = (
+            balances.available(venue, Asset::Sol),
+            balances.available(venue, Asset::Usdc),
+        ) else {
+            return 0;
+        };
+        total_notional += sol as u128 * sol_price as u128 / LAMPORTS_PER_SOL + usdc as u128;
+    }
+    let drift_notional = total_notional as f64 * config.threshold;
+    if drift_notional < 1.0 {
+        return 0;
+    }
+
+    let planner = RebalancePlanner::new(config.clone());
+    let cycle_cost = |withdraw: Asset, deposit: Asset| {
+        planner.action_cost(
+            &RebalanceAction::Withdraw {
+                asset: withdraw,
+                amount: 0,
+            },
+            sol_price,
+        ) + planner.action_cost(
+            &RebalanceAction::Deposit {
+                asset: deposit,
+                amount: 0,
+            },
+            sol_price,
+        )
+    };
+    let cost = cycle_cost(Asset::Sol, Asset::Usdc).max(cycle_cost(Asset::Usdc, Asset::Sol));
+    (cost as f64 * 10_000.0 / drift_notional).ceil() as u64
+}
+
+/// Carries out `rebalance`: CEX withdrawals go through `venue`'s API, deposits are signed and
+/// sent from the wallet keypair
+pub async fn execute(
+    rebalance: &Rebalance,
+    config: &RebalanceConfig,
+    rpc_url: &str,
+    keypair: &Keypair,
+    venue: &impl CexVenue,
+) -> anyhow::Result<()> {
+    match rebalance.action {
+        RebalanceAction::Withdraw { asset, amount } => {
+            venue
+                .withdraw(asset, amount, &keypair.pubkey().to_string())
+                .await
+        }
+        RebalanceAction::Deposit { asset, amount } => {
+            let deposit_address: Pubkey = config
+                .cex_deposit_address
+                .as_deref()
+                .ok_or_else(|| anyhow::Error::msg("Error: no CEX deposit address configured"))?
+                .parse()?;
+            let instruction = match asset {
+                Asset::Sol => solana_system_interface::instruction::transfer(
+                    &keypair.pubkey(),
+                    &deposit_address,
+                    amount,
+                ),
+                Asset::Usdc => spl_transfer(
+                    &associated_token_address(&keypair.pubkey(), &USDC_MINT),
+                    &associated_token_address(&deposit_address, &USDC_MINT),
+                    &keypair.pubkey(),
+                    amount,
+                ),
+            };
+            let blockhash = rpc::get_latest_blockhash(rpc_url).await?;
+            let transaction = Transaction::new_signed_with_payer(
+                &[instruction],
+                Some(&keypair.pubkey()),
+                &[keypair],
+                blockhash,
+            );
+            let signature = rpc::send_transaction(rpc_url, &transaction).await?;
+            info!(%signature, ?asset, amount, "Deposit sent");
+            Ok(())
+        }
+    }
+}
+
+pub fn spl_transfer(
+    source: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let mut data = vec![SPL_TRANSFER];
+    data.extend_from_slice(&amount.to_le_bytes());
+    Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![
+            AccountMeta::new(*source, false),
+            AccountMeta::new(*destination, false),
+            AccountMeta::new_readonly(*owner, true),
+        ],
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn balances(wallet_sol: u64, cex_sol: u64, wallet_usdc: u64, cex_usdc: u64) -> Balances {
+        let mut balances = Balances::default();
+        balances.set(Venue::Wallet, Asset::Sol, wallet_sol);
+        balances.set(Venue::Cex, Asset::Sol, cex_sol);
+        balances.set(Venue::Wallet, Asset::Usdc, wallet_usdc);
+        balances.set(Venue::Cex, Asset::Usdc, cex_usdc);
+        balances
+    }
+
+    #[test]
+    fn plans_transfers_past_threshold_and_respects_cooldown() {
+        let mut planner = RebalancePlanner::new(RebalanceConfig::default());
+        let now = Instant::now();
+        // SOL drained from the wallet, USDC piled up in it
+        let balances = balances(1_000_000_000, 9_000_000_000, 900_000_000, 100_000_000);
+
+        let planned = planner.plan(&balances, 150_000_000, now);
+        assert_eq!(
+            planned.iter().map(|r| r.action).collect::<Vec<_>>(),
+            vec![
+                RebalanceAction::Withdraw {
+                    asset: Asset::Sol,
+                    amount: 4_000_000_000
+                },
+                RebalanceAction::Deposit {
+                    asset: Asset::Usdc,
+                    amount: 400_000_000
+                },
+            ]
+        );
+        assert_eq!(planned[0].cost, 300_000);
+
+        planner.record(&planned[0], now);
+        let planned = planner.plan(&balances, 150_000_000, now);
+        assert_eq!(planned.len(), 1);
+    }
+
+    #[test]
+    fn sol_deposit_is_capped_at_native_lamports() {
+        let planner = RebalancePlanner::new(RebalanceConfig::default());
+        let mut balances = balances(0, 1_000_000_000, 500_000_000, 500_000_000);
+        balances.set_wallet_native_sol(2_000_000_000);
+        balances.set_wallet_wrapped_sol(7_000_000_000);
+
+        // 4 SOL over target, but only 2 SOL sit outside the wSOL account
+        let planned = planner.plan(&balances, 150_000_000, Instant::now());
+        assert_eq!(
+            planned.iter().map(|r| r.action).collect::<Vec<_>>(),
+            vec![RebalanceAction::Deposit {
+                asset: Asset::Sol,
+                amount: 2_000_000_000
+            }]
+        );
+
+        balances.set_wallet_native_sol(0);
+        assert!(
+            planner
+                .plan(&balances, 150_000_000, Instant::now())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn no_plan_within_threshold() {
+        let planner = RebalancePlanner::new(RebalanceConfig::default());
+        let balances = balances(4_000_000_000, 6_000_000_000, 500_000_000, 500_000_000);
+        assert!(
+            planner
+                .plan(&balances, 150_000_000, Instant::now())
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn cost_is_amortized_over_drift() {
+        let config = RebalanceConfig::default();
+        let balances = balances(5_000_000_000, 5_000_000_000, 750_000_000, 750_000_000);
+        // 3000 USDC of inventory rebalanced every 600 USDC of drift, a cycle costs ~1 USDC
+        assert_eq!(cost_bps(&config, &balances, 150_000_000), 17);
+        assert_eq!(cost_bps(&config, &Balances::default(), 150_000_000), 0);
+    }
+}
@@ -0,0 +1,519 @@
+//! Raw market data recorder. Every account update and CEX book snapshot is stamped with its
+//! receive time and appended to hourly files of length-prefixed bincode frames. Each data file
+//! has a sidecar index of `(timestamp, offset)` pairs so a reader can seek to a time without
+//! decoding everything before it.
+
+use std::{
+    collections::VecDeque,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, TrySendError},
+    },
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use tracing::{error, warn};
+
+use crate::{
+    ledger::unix_time_us,
+    order_book::{Level, OrderBook},
+};
+
+const FILE_PREFIX: &str = "market-";
+const DATA_EXTENSION: &str = "bin";
+const INDEX_EXTENSION: &str = "idx";
+const INDEX_ENTRY_LEN: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RecorderConfig {
+    pub enabled: bool,
+    pub dir: String,
+    /// A new file is started every this many seconds
+    pub rotate_secs: u64,
+    /// Min time between two index entries
+    pub index_interval_ms: u64,
+    /// Records waiting for the writer at most, more are dropped until it catches up
+    pub max_pending: usize,
+}
+
+impl Default for RecorderConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: "market_data".to_string(),
+            rotate_secs: 3600,
+            index_interval_ms: 1000,
+            max_pending: 65_536,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    /// Account update from the gRPC stream. Accounts bootstrapped over RPC are recorded with a
+    /// zero write version.
+    Account {
+        slot: u64,
+        pubkey: [u8; 32],
+        write_version: u64,
+        lamports: u64,
+        data: Vec<u8>,
+    },
+    /// Full CEX order book snapshot
+    Book { bids: Vec<Level>, asks: Vec<Level> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Record {
+    pub recv_us: u64, // unix microseconds
+    pub event: Event,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.event {
+            Event::Account {
+                slot,
+                pubkey,
+                write_version,
+                lamports,
+                data,
+            } => write!(
+                f,
+                "{} account {} slot {slot} version {write_version}: {lamports} lamports, {} bytes",
+                self.recv_us,
+                Pubkey::new_from_array(*pubkey),
+                data.len()
+            ),
+            Event::Book { bids, asks } => write!(
+                f,
+                "{} book: {} bids {} asks, best {:?} / {:?}",
+                self.recv_us,
+                bids.len(),
+                asks.len(),
+                bids.first().map(|l| l.price),
+                asks.first().map(|l| l.price)
+            ),
+        }
+    }
+}
+
+/// Handle used by the streaming tasks, cheap to clone. Does nothing when recording is disabled.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    tx: Option<mpsc::SyncSender<Record>>,
+    /// Records dropped since the writer last reported it
+    dropped: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Starts the writer thread if recording is enabled. File writes block, so they stay off the
+    /// runtime the feeds run on.
+    pub fn spawn(config: &RecorderConfig) -> anyhow::Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        let mut writer = RecordWriter::new(config)?;
+        let (tx, rx) = mpsc::sync_channel::<Record>(config.max_pending);
+        let recorder = Self {
+            tx: Some(tx),
+            dropped: Arc::default(),
+        };
+        let dropped = recorder.dropped.clone();
+        thread::Builder::new()
+            .name("recorder".to_string())
+            .spawn(move || {
+                while let Ok(record) = rx.recv() {
+                    let dropped = dropped.swap(0, Ordering::Relaxed);
+                    if dropped > 0 {
+                        warn!(dropped, "Recorder fell behind, market data was dropped");
+                    }
+                    if let Err(e) = writer.write(&record) {
+                        error!("Error recording market data: {e}");
+                    }
+                }
+                if let Err(e) = writer.flush() {
+                    error!("Error flushing market data: {e}");
+                }
+            })?;
+        Ok(recorder)
+    }
+
+    pub fn account(
+        &self,
+        slot: u64,
+        pubkey: &[u8],
+        write_version: u64,
+        lamports: u64,
+        data: &[u8],
+    ) {
+        let Ok(pubkey) = pubkey.try_into() else {
+            return;
+        };
+        self.send(Event::Account {
+            slot,
+            pubkey,
+            write_version,
+            lamports,
+            data: data.to_vec(),
+        });
+    }
+
+    pub fn book(&self, book: &OrderBook) {
+        self.send(Event::Book {
+            bids: book.bids.clone(),
+            asks: book.asks.clone(),
+        });
+    }
+
+    /// Queues `event` for the writer, never waits for it. Dropped when the queue is full.
+    fn send(&self, event: Event) {
+        if let Some(tx) = &self.tx {
+            let record = Record {
+                recv_us: unix_time_us(),
+                event,
+            };
+            if let Err(TrySendError::Full(_)) = tx.try_send(record) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+pub struct RecordWriter {
+    dir: PathBuf,
+    rotate_us: u64,
+    index_interval_us: u64,
+    data: Option<BufWriter<File>>,
+    index: Option<File>,
+    file_start_us: u64,
+    last_index_us: Option<u64>,
+    offset: u64,
+}
+
+impl RecordWriter {
+    pub fn new(config: &RecorderConfig) -> anyhow::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        Ok(Self {
+            dir: PathBuf::from(&config.dir),
+            rotate_us: config.rotate_secs.max(1) * 1_000_000,
+            index_interval_us: config.index_interval_ms * 1000,
+            data: None,
+            index: None,
+            file_start_us: 0,
+            last_index_us: None,
+            offset: 0,
+        })
+    }
+
+    pub fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        if self.data.is_none() || record.recv_us >= self.file_start_us + self.rotate_us {
+            self.rotate(record.recv_us)?;
+        }
+        let due = self
+            .last_index_us
+            .is_none_or(|last| record.recv_us >= last + self.index_interval_us);
+        if due {
+            // data must be on disk before the index points at it
+            self.flush()?;
+            if let Some(index) = self.index.as_mut() {
+                index.write_all(&record.recv_us.to_le_bytes())?;
+                index.write_all(&self.offset.to_le_bytes())?;
+            }
+            self.last_index_us = Some(record.recv_us);
+        }
+
+        let frame = bincode::serialize(record)?;
+        if let Some(data) = self.data.as_mut() {
+            data.write_all(&(frame.len() as u32).to_le_bytes())?;
+            data.write_all(&frame)?;
+        }
+        self.offset += 4 + frame.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(data) = self.data.as_mut() {
+            data.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, start_us: u64) -> anyhow::Result<()> {
+        self.flush()?;
+        let stem = format!("{FILE_PREFIX}{start_us:017}");
+        let data = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(format!("{stem}.{DATA_EXTENSION}")))?;
+        self.offset = data.metadata()?.len();
+        self.data = Some(BufWriter::new(data));
+        self.index = Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.dir.join(format!("{stem}.{INDEX_EXTENSION}")))?,
+        );
+        self.file_start_us = start_us;
+        self.last_index_us = None;
+        Ok(())
+    }
+}
+
+/// Reads records back in the order they were received, optionally limited to `[from_us, to_us)`
+pub struct RecordReader {
+    files: VecDeque<PathBuf>,
+    current: Option<BufReader<File>>,
+    from_us: Option<u64>,
+    to_us: Option<u64>,
+}
+
+impl RecordReader {
+    /// Opens `dir` limited by `--from` / `--to` (unix microseconds) command line flags
+    pub fn from_args(dir: impl AsRef<Path>, args: &[String]) -> anyhow::Result<Self> {
+        let (mut from_us, mut to_us) = (None, None);
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::Error::msg(format!("Error: missing value for {flag}")))?;
+            match flag.as_str() {
+                "--from" => from_us = Some(value.parse()?),
+                "--to" => to_us = Some(value.parse()?),
+                _ => return Err(anyhow::Error::msg(format!("Error: unknown flag {flag}"))),
+            }
+        }
+        Self::open(dir, from_us, to_us)
+    }
+
+    pub fn open(
+        dir: impl AsRef<Path>,
+        from_us: Option<u64>,
+        to_us: Option<u64>,
+    ) -> anyhow::Result<Self> {
+        let mut files: Vec<(u64, PathBuf)> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == DATA_EXTENSION))
+            .filter_map(|path| {
+                let start = path
+                    .file_stem()?
+                    .to_str()?
+                    .strip_prefix(FILE_PREFIX)?
+                    .parse()
+                    .ok()?;
+                Some((start, path))
+            })
+            .collect();
+        files.sort();
+
+        // only the last file starting at or before `from_us` can hold earlier records we need
+        let first = from_us
+            .and_then(|from| files.iter().rposition(|(start, _)| *start <= from))
+            .unwrap_or(0);
+        let files = files
+            .into_iter()
+            .skip(first)
+            .take_while(|(start, _)| to_us.is_none_or(|to| *start < to))
+            .map(|(_, path)| path)
+            .collect();
+        Ok(Self {
+            files,
+            current: None,
+            from_us,
+            to_us,
+        })
+    }
+
+    /// Opens the next file, seeking past index entries that are entirely before `from_us`
+    fn open_next(&mut self) -> anyhow::Result<bool> {
+        let Some(path) = self.files.pop_front() else {
+            return Ok(false);
+        };
+        let mut file = File::open(&path)?;
+        if let Some(from) = self.from_us {
+            let offset = seek_offset(&path.with_extension(INDEX_EXTENSION), from)?;
+            file.seek(SeekFrom::Start(offset))?;
+        }
+        self.current = Some(BufReader::new(file));
+        Ok(true)
+    }
+
+    fn read_frame(&mut self) -> anyhow::Result<Option<Record>> {
+        let Some(reader) = self.current.as_mut() else {
+            return Ok(None);
+        };
+        let mut len = [0u8; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut frame = vec![0u8; u32::from_le_bytes(len) as usize];
+        match reader.read_exact(&mut frame) {
+            Ok(()) => Ok(Some(bincode::deserialize(&frame)?)),
+            // a frame still being written
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Iterator for RecordReader {
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.read_frame() {
+                Ok(Some(record)) => {
+                    if self.to_us.is_some_and(|to| record.recv_us >= to) {
+                        self.files.clear();
+                        self.current = None;
+                        return None;
+                    }
+                    if self.from_us.is_some_and(|from| record.recv_us < from) {
+                        continue;
+                    }
+                    return Some(Ok(record));
+                }
+                Ok(None) => match self.open_next() {
+                    Ok(true) => continue,
+                    Ok(false) => return None,
+                    Err(e) => return Some(Err(e)),
+                },
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Offset of the last index entry at or before `from_us`, 0 without one
+fn seek_offset(index_path: &Path, from_us: u64) -> anyhow::Result<u64> {
+    let index = match fs::read(index_path) {
+        Ok(index) => index,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut offset = 0;
+    for entry in index.chunks_exact(INDEX_ENTRY_LEN) {
+        let timestamp = u64::from_le_bytes(entry[..8].try_into()?);
+        if timestamp > from_us {
+            break;
+        }
+        offset = u64::from_le_bytes(entry[8..].try_into()?);
+    }
+    Ok(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book(recv_us: u64) -> Record {
+        Record {
+            recv_us,
+            event: Event::Book {
+                bids: vec![Level {
+                    price: 150_000_000,
+                    qty: recv_us,
+                }],
+                asks: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn replays_in_order_across_files_and_seeks_by_time() {
+        let dir = std::env::temp_dir().join(format!("recorder-test-{}", uuid::Uuid::new_v4()));
+        let mut writer = RecordWriter::new(&RecorderConfig {
+            enabled: true,
+            dir: dir.to_string_lossy().into_owned(),
+            rotate_secs: 1,
+            index_interval_ms: 0,
+            ..Default::default()
+        })
+        .unwrap();
+        // three files: [0, 1s), [1s, 2s), [2.5s, ..)
+        let times = [0, 400_000, 900_000, 1_000_000, 1_500_000, 2_500_000];
+        for recv_us in times {
+            writer.write(&book(recv_us)).unwrap();
+        }
+        writer
+            .write(&Record {
+                recv_us: 2_600_000,
+                event: Event::Account {
+                    slot: 7,
+                    pubkey: [1; 32],
+                    write_version: 3,
+                    lamports: 10,
+                    data: vec![1, 2, 3],
+                },
+            })
+            .unwrap();
+        writer.flush().unwrap();
+
+        let all: Vec<u64> = RecordReader::open(&dir, None, None)
+            .unwrap()
+            .map(|r| r.unwrap().recv_us)
+            .collect();
+        assert_eq!(all, [times.as_slice(), &[2_600_000]].concat());
+
+        let window: Vec<Record> = RecordReader::open(&dir, Some(900_000), Some(2_600_000))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        assert_eq!(
+            window.iter().map(|r| r.recv_us).collect::<Vec<_>>(),
+            vec![900_000, 1_000_000, 1_500_000, 2_500_000]
+        );
+        assert_eq!(window[0], book(900_000));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+    #[test]
+    fn full_queue_drops_records() {
+        let (tx, rx) = mpsc::sync_channel(2);
+        let recorder = Recorder {
+            tx: Some(tx),
+            dropped: Arc::default(),
+        };
+        let book = OrderBook::default();
+        for _ in 0..5 {
+            recorder.book(&book);
+        }
+        assert_eq!(recorder.dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(rx.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn spawned_recorder_writes_from_its_thread() {
+        let dir = std::env::temp_dir().join(format!("recorder-test-{}", uuid::Uuid::new_v4()));
+        let recorder = Recorder::spawn(&RecorderConfig {
+            enabled: true,
+            dir: dir.to_string_lossy().into_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        recorder.book(&OrderBook::default());
+        // the writer flushes once every handle is gone
+        drop(recorder);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+        let records = loop {
+            let records = RecordReader::open(&dir, None, None).unwrap().count();
+            if records > 0 || std::time::Instant::now() > deadline {
+                break records;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(records, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,369 @@
+//! Pre-trade risk checks. Every opportunity goes through `RiskEngine::check` before it is
+//! executed; rejections carry a reason that is logged and counted.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{arb::Opportunity, balances::Asset, ledger::SECS_PER_DAY};
+
+const RATE_WINDOW_US: u64 = 60_000_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RiskLimits {
+    /// Stops all trading when set
+    pub kill_switch: bool,
+    /// Max cost of the buy leg, in micro-USDC
+    pub max_notional: u64,
+    /// Max exposure per asset, in base units: the open leg imbalance plus what a trade puts at
+    /// stake while its legs are in flight. Realized PnL only counts towards `max_daily_loss`.
+    pub max_position: HashMap<Asset, u64>,
+    /// Trading stops for the (UTC) day once realized PnL drops below minus this, in micro-USDC
+    pub max_daily_loss: u64,
+    pub max_open_legs: u32,
+    pub max_trades_per_minute: u32,
+    /// Min profit after all known fees, in bps of the buy leg
+    pub min_net_profit_bps: u64,
+}
+
+impl Default for RiskLimits {
+    fn default() -> Self {
+        Self {
+            kill_switch: false,
+            max_notional: 1_000_000_000,
+            max_position: HashMap::from([
+                (Asset::Sol, 5_000_000_000),
+                (Asset::Usdc, 1_000_000_000),
+            ]),
+            max_daily_loss: 100_000_000,
+            max_open_legs: 2,
+            max_trades_per_minute: 10,
+            min_net_profit_bps: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    KillSwitch,
+    MaxNotional,
+    MaxPosition(Asset),
+    MaxDailyLoss,
+    MaxOpenLegs,
+    MaxTradesPerMinute,
+    MinNetProfit,
+}
+
+impl RejectReason {
+    /// Stable snake_case identifier, used as the metrics label
+    pub fn id(&self) -> &'static str {
+        match self {
+            RejectReason::KillSwitch => "kill_switch",
+            RejectReason::MaxNotional => "max_notional",
+            RejectReason::MaxPosition(Asset::Sol) => "max_position_sol",
+            RejectReason::MaxPosition(Asset::Usdc) => "max_position_usdc",
+            RejectReason::MaxDailyLoss => "max_daily_loss",
+            RejectReason::MaxOpenLegs => "max_open_legs",
+            RejectReason::MaxTradesPerMinute => "max_trades_per_minute",
+            RejectReason::MinNetProfit => "min_net_profit",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::KillSwitch => write!(f, "kill switch engaged"),
+            RejectReason::MaxNotional => write!(f, "max notional per trade exceeded"),
+            RejectReason::MaxPosition(asset) => {
+                write!(f, "max {} position exceeded", asset.symbol())
+            }
+            RejectReason::MaxDailyLoss => write!(f, "max daily loss reached"),
+            RejectReason::MaxOpenLegs => write!(f, "max open legs reached"),
+            RejectReason::MaxTradesPerMinute => write!(f, "max trades per minute reached"),
+            RejectReason::MinNetProfit => write!(f, "net profit below minimum"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RiskEngine {
+    limits: RiskLimits,
+    /// Start times of recent trades, unix microseconds
+    trades: VecDeque<u64>,
+    day: u64,
+    daily_pnl: i64,
+    open_legs: u32,
+    /// Net position left open by filled trades whose legs didn't match, per asset
+    imbalances: HashMap<Asset, i64>,
+    pub rejections: HashMap<RejectReason, u64>,
+}
+
+impl RiskEngine {
+    pub fn new(limits: RiskLimits) -> Self {
+        Self {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn limits(&self) -> &RiskLimits {
+        &self.limits
+    }
+
+    /// Swaps in new limits, state (PnL, positions, rate window) is kept
+    pub fn set_limits(&mut self, limits: RiskLimits) {
+        self.limits = limits;
+    }
+
+    /// Checks `opp`, traded in `legs` legs, against every limit at `now_us` (unix microseconds).
+    /// `exposure` is what the trade puts at stake per asset while its legs are in flight.
+    /// Rejections are counted.
+    pub fn check(
+        &mut self,
+        opp: &Opportunity,
+        legs: u32,
+        exposure: &[(Asset, u64)],
+        now_us: u64,
+    ) -> Result<(), RejectReason> {
+        let result = self.evaluate(opp, legs, exposure, now_us);
+        if let Err(reason) = result {
+            *self.rejections.entry(reason).or_default() += 1;
+        }
+        result
+    }
+
+    fn evaluate(
+        &mut self,
+        opp: &Opportunity,
+        legs: u32,
+        exposure: &[(Asset, u64)],
+        now_us: u64,
+    ) -> Result<(), RejectReason> {
+        self.roll_day(now_us);
+        while let Some(first) = self.trades.front() {
+            if now_us.saturating_sub(*first) < RATE_WINDOW_US {
+                break;
+            }
+            self.trades.pop_front();
+        }
+
+        if self.limits.kill_switch {
+            return Err(RejectReason::KillSwitch);
+        }
+        if self.daily_pnl <= -(self.limits.max_daily_loss as i64) {
+            return Err(RejectReason::MaxDailyLoss);
+        }
+        if opp.cost > self.limits.max_notional {
+            return Err(RejectReason::MaxNotional);
+        }
+        for (asset, at_stake) in exposure {
+            let imbalance = self.imbalances.get(asset).copied().unwrap_or_default();
+            let position = imbalance.unsigned_abs().saturating_add(*at_stake);
+            let max = self
+                .limits
+                .max_position
+                .get(asset)
+                .copied()
+                .unwrap_or(u64::MAX);
+            if position > max {
+                return Err(RejectReason::MaxPosition(*asset));
+            }
+        }
+        if self.open_legs + legs > self.limits.max_open_legs {
+            return Err(RejectReason::MaxOpenLegs);
+        }
+        if self.trades.len() >= self.limits.max_trades_per_minute as usize {
+            return Err(RejectReason::MaxTradesPerMinute);
+        }
+        if (opp.net_profit() as i128) * 10_000
+            < opp.cost as i128 * self.limits.min_net_profit_bps as i128
+        {
+            return Err(RejectReason::MinNetProfit);
+        }
+        Ok(())
+    }
+
+    /// Marks `legs` as in flight and counts the trade towards the rate limit
+    pub fn open_legs(&mut self, legs: u32, now_us: u64) {
+        self.open_legs += legs;
+        self.trades.push_back(now_us);
+    }
+
+    pub fn close_legs(&mut self, legs: u32) {
+        self.open_legs = self.open_legs.saturating_sub(legs);
+    }
+
+    /// Records the outcome of a filled trade: its realized PnL and the position its legs left open
+    pub fn on_fill(&mut self, pnl: i64, imbalances: &[(Asset, i64)], now_us: u64) {
+        self.roll_day(now_us);
+        self.daily_pnl += pnl;
+        for (asset, imbalance) in imbalances {
+            *self.imbalances.entry(*asset).or_default() += imbalance;
+        }
+    }
+
+    /// Resets the daily PnL when `now_us` falls on a new UTC day
+    fn roll_day(&mut self, now_us: u64) {
+        let day = now_us / 1_000_000 / SECS_PER_DAY;
+        if day != self.day {
+            self.day = day;
+            self.daily_pnl = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arb::Direction;
+
+    fn opp(cost: u64, proceeds: u64) -> Opportunity {
+        Opportunity {
+            direction: Direction::BuyCexSellDex,
+            base_amount: 1_000_000_000,
+            cost,
+            proceeds,
+            spread: proceeds as i64 - cost as i64,
+            cex_fee: 0,
+            tx_fee: 0,
+            slot: 0,
+        }
+    }
+
+    #[test]
+    fn rejects_with_reason_and_counts() {
+        let mut risk = RiskEngine::new(RiskLimits::default());
+        let now = 1_700_000_000_000_000;
+        assert_eq!(
+            risk.check(&opp(150_000_000, 150_200_000), 2, &[], now),
+            Ok(())
+        );
+        assert_eq!(
+            risk.check(&opp(150_000_000, 150_010_000), 2, &[], now),
+            Err(RejectReason::MinNetProfit)
+        );
+        assert_eq!(
+            risk.check(&opp(2_000_000_000, 2_100_000_000), 2, &[], now),
+            Err(RejectReason::MaxNotional)
+        );
+        assert_eq!(
+            risk.check(
+                &opp(150_000_000, 150_200_000),
+                2,
+                &[(Asset::Sol, 6_000_000_000)],
+                now
+            ),
+            Err(RejectReason::MaxPosition(Asset::Sol))
+        );
+        assert_eq!(risk.rejections[&RejectReason::MinNetProfit], 1);
+        assert_eq!(risk.rejections.values().sum::<u64>(), 3);
+    }
+
+    #[test]
+    fn transaction_fee_counts_towards_net_profit() {
+        let mut risk = RiskEngine::new(RiskLimits::default());
+        let now = 1_700_000_000_000_000;
+        // 5.07 bps before the transaction fee, the 5 bps minimum is 75_000
+        let opp = opp(150_000_000, 150_076_000);
+        assert_eq!(risk.check(&opp, 2, &[], now), Ok(()));
+
+        // 10_000 lamports at 150 USDC/SOL take 1_500 off the profit
+        let with_fee = Opportunity {
+            tx_fee: 10_000,
+            ..opp
+        };
+        assert_eq!(with_fee.tx_fee_quote(), 1_500);
+        assert_eq!(with_fee.net_profit(), 74_500);
+        assert_eq!(
+            risk.check(&with_fee, 2, &[], now),
+            Err(RejectReason::MinNetProfit)
+        );
+    }
+
+    #[test]
+    fn rate_limit_open_legs_and_daily_loss() {
+        let mut risk = RiskEngine::new(RiskLimits {
+            max_trades_per_minute: 1,
+            ..Default::default()
+        });
+        let now = 1_700_000_000_000_000;
+        let good = opp(150_000_000, 150_200_000);
+
+        risk.open_legs(2, now);
+        assert_eq!(
+            risk.check(&good, 2, &[], now),
+            Err(RejectReason::MaxOpenLegs)
+        );
+        risk.close_legs(2);
+        assert_eq!(
+            risk.check(&good, 2, &[], now),
+            Err(RejectReason::MaxTradesPerMinute)
+        );
+        assert_eq!(risk.check(&good, 2, &[], now + 61_000_000), Ok(()));
+
+        risk.on_fill(-100_000_000, &[], now + 61_000_000);
+        assert_eq!(
+            risk.check(&good, 2, &[], now + 61_000_000),
+            Err(RejectReason::MaxDailyLoss)
+        );
+    }
+
+    #[test]
+    fn profits_never_count_as_exposure() {
+        let mut risk = RiskEngine::new(RiskLimits {
+            max_trades_per_minute: u32::MAX,
+            ..Default::default()
+        });
+        let now = 1_700_000_000_000_000;
+        let good = opp(150_000_000, 150_200_000);
+        // 20 fills of 100 USDC each, twice the default USDC limit
+        for i in 0..20 {
+            assert_eq!(risk.check(&good, 2, &good.exposure(), now + i), Ok(()));
+            risk.on_fill(100_000_000, &[(Asset::Sol, 0)], now + i);
+        }
+        assert_eq!(risk.check(&good, 2, &good.exposure(), now + 20), Ok(()));
+
+        // leg imbalances do add up: 4.5 SOL left open plus 1 SOL in flight
+        risk.on_fill(0, &[(Asset::Sol, -4_500_000_000)], now + 20);
+        assert_eq!(
+            risk.check(&good, 2, &good.exposure(), now + 21),
+            Err(RejectReason::MaxPosition(Asset::Sol))
+        );
+        // three legs in flight don't fit the default two
+        assert_eq!(
+            risk.check(&good, 3, &[], now + 21),
+            Err(RejectReason::MaxOpenLegs)
+        );
+    }
+
+    #[test]
+    fn daily_loss_resets_on_new_day() {
+        let mut risk = RiskEngine::new(RiskLimits::default());
+        let good = opp(150_000_000, 150_200_000);
+        let day_us = SECS_PER_DAY * 1_000_000;
+        risk.on_fill(-100_000_000, &[], day_us);
+        assert_eq!(
+            risk.check(&good, 2, &[], day_us + 1),
+            Err(RejectReason::MaxDailyLoss)
+        );
+        assert_eq!(risk.check(&good, 2, &[], 2 * day_us), Ok(()));
+    }
+
+    #[test]
+    fn kill_switch_wins_and_limits_reload() {
+        let mut risk = RiskEngine::new(RiskLimits {
+            kill_switch: true,
+            ..Default::default()
+        });
+        let good = opp(150_000_000, 150_200_000);
+        assert_eq!(risk.check(&good, 2, &[], 0), Err(RejectReason::KillSwitch));
+        risk.set_limits(RiskLimits::default());
+        assert_eq!(risk.check(&good, 2, &[], 0), Ok(()));
+    }
+}
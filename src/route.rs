@@ -0,0 +1,491 @@
+//! Multi-hop route search. The tracked pools form a token graph, each pool an edge both ways
+//! between its two mints. Routes are cycles of 2 to `max_hops` pools that start and end in the
+//! same token, simulated hop by hop with each venue's quoter.
+//!
+//! The search is pruned with spot rates: a swap never pays out more than its input at the pool's
+//! current price, so the best product of spot rates back to the start token bounds what a partial
+//! route can still return. Branches that can't beat their input are dropped before quoting.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{instruction::Instruction, pubkey::Pubkey};
+
+use crate::{
+    balances::{USDC_MINT, WSOL_MINT},
+    market::DexStruct,
+    venue::SwapParams,
+};
+
+/// Headroom on the spot rate bound for the rounding of the published prices
+const BOUND_SLACK: f64 = 1.0001;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RouteConfig {
+    /// Search routes after every pool update
+    pub enabled: bool,
+    /// Tokens routes start and end in, with the amount each route trades
+    pub starts: Vec<RouteStart>,
+    /// Longest cycle searched, 2 to 4 pools
+    pub max_hops: usize,
+    /// Time allowed for one search over all starts, routes not reached by then are skipped
+    pub budget_us: u64,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            starts: vec![
+                RouteStart {
+                    mint: WSOL_MINT.to_string(),
+                    amount: 1_000_000_000,
+                },
+                RouteStart {
+                    mint: USDC_MINT.to_string(),
+                    amount: 150_000_000,
+                },
+            ],
+            max_hops: 3,
+            budget_us: 3_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RouteStart {
+    pub mint: String,
+    /// Base units of `mint` put into the first hop
+    pub amount: u64,
+}
+
+/// One swap of a route
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hop {
+    pub pool: Pubkey,
+    /// Sells the pool's first token
+    pub zero_for_one: bool,
+    pub token_in: Pubkey,
+    pub token_out: Pubkey,
+    pub amount_in: u64,
+    pub amount_out: u64,
+}
+
+/// A cycle of swaps, each one spending everything the previous one returned
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub hops: Vec<Hop>,
+}
+
+impl Route {
+    pub fn token(&self) -> Pubkey {
+        self.hops[0].token_in
+    }
+
+    pub fn amount_in(&self) -> u64 {
+        self.hops[0].amount_in
+    }
+
+    pub fn amount_out(&self) -> u64 {
+        self.hops[self.hops.len() - 1].amount_out
+    }
+
+    /// Gain in base units of the start token
+    pub fn profit(&self) -> i64 {
+        self.amount_out() as i64 - self.amount_in() as i64
+    }
+
+    /// Least output of the last swap that still clears `min_profit_bps` of the input
+    pub fn min_amount_out(&self, min_profit_bps: u64) -> u64 {
+        let amount_in = self.amount_in();
+        amount_in + (amount_in as u128 * min_profit_bps as u128).div_ceil(10_000) as u64
+    }
+
+    /// The swaps signed by `user`, all exact in. Each one but the last must return what the next
+    /// one spends, the last one at least `min_amount_out`.
+    pub fn swaps(&self, user: Pubkey, min_amount_out: u64) -> Vec<SwapParams> {
+        let last = self.hops.len() - 1;
+        self.hops
+            .iter()
+            .enumerate()
+            .map(|(i, hop)| SwapParams {
+                user,
+                amount: hop.amount_in,
+                other_amount_threshold: if i == last {
+                    min_amount_out
+                } else {
+                    hop.amount_out
+                },
+                zero_for_one: hop.zero_for_one,
+                is_base_input: true,
+            })
+            .collect()
+    }
+
+    /// Instructions of the atomic transaction candidate, one swap per hop through `pools`
+    pub fn instructions(
+        &self,
+        pools: &[(Pubkey, &DexStruct)],
+        user: Pubkey,
+        min_amount_out: u64,
+    ) -> anyhow::Result<Vec<Instruction>> {
+        self.hops
+            .iter()
+            .zip(self.swaps(user, min_amount_out))
+            .map(|(hop, swap)| {
+                let (_, dex) = pools
+                    .iter()
+                    .find(|(id, _)| *id == hop.pool)
+                    .ok_or_else(|| {
+                        anyhow::Error::msg(format!("Error: route pool {} isn't tracked", hop.pool))
+                    })?;
+                dex.pool.swap_instruction(&hop.pool, &swap)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Edge<'a> {
+    pool: Pubkey,
+    dex: &'a DexStruct,
+    zero_for_one: bool,
+    token_out: Pubkey,
+    /// Output per unit of input at the pool's current price, before fees
+    rate: f64,
+}
+
+/// Loaded pools by the token they take in
+pub struct TokenGraph<'a> {
+    edges: HashMap<Pubkey, Vec<Edge<'a>>>,
+}
+
+impl<'a> TokenGraph<'a> {
+    /// Graph of `pools`, skipping the ones that aren't loaded yet
+    pub fn new(pools: &[(Pubkey, &'a DexStruct)]) -> Self {
+        let mut edges: HashMap<Pubkey, Vec<Edge<'a>>> = HashMap::new();
+        for &(pool, dex) in pools {
+            let (Some((mint_0, mint_1)), Some(price)) = (dex.mints(), dex.price()) else {
+                continue;
+            };
+            let sqrt_price = price.sqrt_price_x64 as f64 / 2f64.powi(64);
+            let rate = sqrt_price * sqrt_price;
+            if rate <= 0.0 || !rate.is_finite() {
+                continue;
+            }
+            let edge = |zero_for_one, token_out, rate| Edge {
+                pool,
+                dex,
+                zero_for_one,
+                token_out,
+                rate,
+            };
+            edges
+                .entry(mint_0)
+                .or_default()
+                .push(edge(true, mint_1, rate));
+            edges
+                .entry(mint_1)
+                .or_default()
+                .push(edge(false, mint_0, 1.0 / rate));
+        }
+        Self { edges }
+    }
+
+    /// Best product of spot rates from each token back to `start` in at most `hops` swaps, for
+    /// every `hops` up to `max_hops`
+    fn bounds(&self, start: &Pubkey, max_hops: usize) -> Vec<HashMap<Pubkey, f64>> {
+        let mut bounds = vec![HashMap::from([(*start, 1.0)])];
+        for hops in 1..=max_hops {
+            let previous = &bounds[hops - 1];
+            let mut bound = previous.clone();
+            for (token, edges) in &self.edges {
+                for edge in edges {
+                    let Some(rest) = previous.get(&edge.token_out) else {
+                        continue;
+                    };
+                    let best: &mut f64 = bound.entry(*token).or_default();
+                    *best = best.max(edge.rate * rest);
+                }
+            }
+            bounds.push(bound);
+        }
+        bounds
+    }
+}
+
+/// Routes found by one search, and how much of the graph it covered
+#[derive(Debug, Clone, Default)]
+pub struct SearchResult {
+    /// Profitable routes, most profitable first
+    pub routes: Vec<Route>,
+    /// Hops simulated
+    pub quotes: usize,
+    /// Branches dropped by the spot rate bound
+    pub pruned: usize,
+    /// False when the time budget ran out first
+    pub complete: bool,
+}
+
+struct Search<'g, 'a> {
+    graph: &'g TokenGraph<'a>,
+    start: Pubkey,
+    amount: u64,
+    max_hops: usize,
+    deadline: Instant,
+    bounds: Vec<HashMap<Pubkey, f64>>,
+    result: SearchResult,
+}
+
+impl Search<'_, '_> {
+    /// Extends `hops`, which end in `token` with `amount` of it, by every pool not used yet
+    fn extend(
+        &mut self,
+        hops: &mut Vec<Hop>,
+        visited: &mut HashSet<Pubkey>,
+        token: Pubkey,
+        amount: u64,
+    ) {
+        let remaining = self.max_hops - hops.len() - 1;
+        let Some(edges) = self.graph.edges.get(&token) else {
+            return;
+        };
+        for edge in edges {
+            if hops.iter().any(|hop| hop.pool == edge.pool) {
+                continue;
+            }
+            let closes = edge.token_out == self.start;
+            if closes && hops.is_empty() {
+                continue;
+            }
+            if !closes && (remaining == 0 || visited.contains(&edge.token_out)) {
+                continue;
+            }
+            // what this branch could return at best, in the start token
+            let back = if closes {
+                1.0
+            } else {
+                self.bounds[remaining]
+                    .get(&edge.token_out)
+                    .copied()
+                    .unwrap_or_default()
+            };
+            if amount as f64 * edge.rate * back * BOUND_SLACK <= self.amount as f64 {
+                self.result.pruned += 1;
+                continue;
+            }
+            if Instant::now() >= self.deadline {
+                self.result.complete = false;
+                return;
+            }
+            self.result.quotes += 1;
+            let now = (edge.dex.received_us / 1_000_000) as i64;
+            let Ok(swap) = edge.dex.pool.quote_exact_in(amount, edge.zero_for_one, now) else {
+                continue;
+            };
+            if !swap.complete || swap.amount_out == 0 {
+                continue;
+            }
+            hops.push(Hop {
+                pool: edge.pool,
+                zero_for_one: edge.zero_for_one,
+                token_in: token,
+                token_out: edge.token_out,
+                amount_in: amount,
+                amount_out: swap.amount_out,
+            });
+            if closes {
+                if swap.amount_out > self.amount {
+                    self.result.routes.push(Route { hops: hops.clone() });
+                }
+            } else if swap.amount_out as f64 * back * BOUND_SLACK > self.amount as f64 {
+                visited.insert(edge.token_out);
+                self.extend(hops, visited, edge.token_out, swap.amount_out);
+                visited.remove(&edge.token_out);
+            } else {
+                self.result.pruned += 1;
+            }
+            hops.pop();
+        }
+    }
+}
+
+/// Profitable cycles through `start` trading `amount` of it, of 2 to `max_hops` pools. Stops
+/// quoting at `deadline`.
+pub fn search(
+    graph: &TokenGraph,
+    start: Pubkey,
+    amount: u64,
+    max_hops: usize,
+    deadline: Instant,
+) -> SearchResult {
+    let max_hops = max_hops.clamp(2, 4);
+    let mut search = Search {
+        graph,
+        start,
+        amount,
+        max_hops,
+        deadline,
+        bounds: graph.bounds(&start, max_hops),
+        result: SearchResult {
+            complete: true,
+            ..Default::default()
+        },
+    };
+    search.extend(&mut Vec::new(), &mut HashSet::from([start]), start, amount);
+    let mut result = search.result;
+    result
+        .routes
+        .sort_by_key(|route| std::cmp::Reverse(route.profit()));
+    result
+}
+
+/// Searches every configured start within `config.budget_us`, routes whose profit clears
+/// `min_profit_bps` of their input, best first per start
+pub fn search_all(
+    pools: &[(Pubkey, &DexStruct)],
+    config: &RouteConfig,
+    min_profit_bps: u64,
+) -> SearchResult {
+    let graph = TokenGraph::new(pools);
+    let deadline = Instant::now() + Duration::from_micros(config.budget_us);
+    let mut total = SearchResult {
+        complete: true,
+        ..Default::default()
+    };
+    for start in &config.starts {
+        let Ok(mint) = start.mint.parse::<Pubkey>() else {
+            continue;
+        };
+        let result = search(&graph, mint, start.amount, config.max_hops, deadline);
+        total.quotes += result.quotes;
+        total.pruned += result.pruned;
+        total.complete &= result.complete;
+        total
+            .routes
+            .extend(result.routes.into_iter().filter(|route| {
+                route.profit() as i128 * 10_000
+                    >= route.amount_in() as i128 * min_profit_bps as i128
+            }));
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raydium_amm::tests::amm_dex;
+
+    /// SOL at 150 USDC on two pools and at 153 USDT on one, USDC/USDT at par
+    fn market() -> Vec<(Pubkey, DexStruct)> {
+        let usdt = Pubkey::new_unique();
+        let msol = Pubkey::new_unique();
+        vec![
+            (
+                Pubkey::new_unique(),
+                amm_dex(
+                    (WSOL_MINT, USDC_MINT),
+                    (10_000_000_000_000, 1_500_000_000_000),
+                    0,
+                ),
+            ),
+            (
+                Pubkey::new_unique(),
+                amm_dex(
+                    (WSOL_MINT, USDC_MINT),
+                    (10_000_000_000_000, 1_501_000_000_000),
+                    0,
+                ),
+            ),
+            (
+                Pubkey::new_unique(),
+                amm_dex(
+                    (WSOL_MINT, usdt),
+                    (10_000_000_000_000, 1_530_000_000_000),
+                    0,
+                ),
+            ),
+            (
+                Pubkey::new_unique(),
+                amm_dex((USDC_MINT, usdt), (1_000_000_000_000, 1_000_000_000_000), 0),
+            ),
+            // a pool nothing profitable goes through
+            (
+                Pubkey::new_unique(),
+                amm_dex((msol, WSOL_MINT), (1_000_000_000_000, 1_200_000_000_000), 0),
+            ),
+        ]
+    }
+
+    #[test]
+    fn finds_the_triangle_and_prunes_the_rest() {
+        let market = market();
+        let pools: Vec<(Pubkey, &DexStruct)> = market.iter().map(|(id, dex)| (*id, dex)).collect();
+        let graph = TokenGraph::new(&pools);
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let result = search(&graph, USDC_MINT, 150_000_000, 3, deadline);
+        assert!(result.complete);
+        assert!(result.pruned > 0);
+
+        // USDC -> SOL on either SOL/USDC pool, SOL -> USDT, USDT -> USDC, and the reverse
+        // directions lose the 2% twice
+        assert_eq!(result.routes.len(), 2);
+        let best = &result.routes[0];
+        let path: Vec<Pubkey> = best.hops.iter().map(|hop| hop.pool).collect();
+        assert_eq!(path, vec![pools[0].0, pools[2].0, pools[3].0]);
+        assert_eq!(best.token(), USDC_MINT);
+        assert!(
+            best.hops
+                .windows(2)
+                .all(|w| w[0].amount_out == w[1].amount_in)
+        );
+        assert!(!best.hops[0].zero_for_one && best.hops[1].zero_for_one);
+        // ~1.3% after three 25 bps fees
+        assert!((1_500_000..2_500_000).contains(&best.profit()));
+        // each swap must return what the next one spends, the last one the required profit
+        let thresholds: Vec<u64> = best
+            .swaps(Pubkey::new_unique(), best.min_amount_out(10))
+            .iter()
+            .map(|swap| swap.other_amount_threshold)
+            .collect();
+        assert_eq!(
+            thresholds,
+            vec![
+                best.hops[0].amount_out,
+                best.hops[1].amount_out,
+                150_150_000
+            ]
+        );
+
+        // two SOL/USDC pools 7 bps apart don't pay two fees
+        let result = search(&graph, USDC_MINT, 150_000_000, 2, deadline);
+        assert!(result.routes.is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_deadline() {
+        let market = market();
+        let pools: Vec<(Pubkey, &DexStruct)> = market.iter().map(|(id, dex)| (*id, dex)).collect();
+        let graph = TokenGraph::new(&pools);
+        let result = search(&graph, WSOL_MINT, 1_000_000_000, 4, Instant::now());
+        assert!(!result.complete);
+        assert_eq!(result.quotes, 0);
+
+        let config = RouteConfig {
+            max_hops: 4,
+            budget_us: 1_000_000,
+            ..Default::default()
+        };
+        let result = search_all(&pools, &config, 10);
+        assert!(result.complete);
+        // the triangle from both ends: USDC, and SOL through USDT then USDC
+        assert_eq!(
+            result.routes.iter().map(Route::token).collect::<Vec<_>>(),
+            vec![WSOL_MINT, WSOL_MINT, USDC_MINT, USDC_MINT]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! Minimal Solana JSON-RPC helpers, used to bootstrap accounts the gRPC stream only reports on change
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use serde::{Deserialize, de::DeserializeOwned};
+use solana_sdk::{hash::Hash, pubkey::Pubkey, transaction::Transaction};
+
+#[derive(Debug, Deserialize)]
+struct RpcResponse<T> {
+    result: Option<T>,
+    error: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithContext<T> {
+    value: T,
+}
+
+#[derive(Debug, Deserialize)]
+struct LatestBlockhash {
+    blockhash: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RpcAccount {
+    lamports: u64,
+    data: (String, String),
+}
+
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+}
+
+async fn call<T: DeserializeOwned>(
+    rpc_url: &str,
+    method: &str,
+    params: serde_json::Value,
+) -> anyhow::Result<T> {
+    let body = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params,
+    });
+    let resp: RpcResponse<T> = reqwest::Client::new()
+        .post(rpc_url)
+        .json(&body)
+        .send()
+        .await?
+        .json()
+        .await?;
+    if let Some(error) = resp.error {
+        return Err(anyhow::Error::msg(format!("RPC error: {error}")));
+    }
+    resp.result
+        .ok_or_else(|| anyhow::Error::msg("RPC error: empty result"))
+}
+
+/// Fetches `pubkeys`, `None` for accounts that don't exist
+pub async fn get_multiple_accounts(
+    rpc_url: &str,
+    pubkeys: &[Pubkey],
+) -> anyhow::Result<Vec<Option<Account>>> {
+    let keys: Vec<String> = pubkeys.iter().map(|k| k.to_string()).collect();
+    let accounts: WithContext<Vec<Option<RpcAccount>>> = call(
+        rpc_url,
+        "getMultipleAccounts",
+        serde_json::json!([keys, { "encoding": "base64" }]),
+    )
+    .await?;
+    accounts
+        .value
+        .into_iter()
+        .map(|account| {
+            account
+                .map(|a| {
+                    Ok(Account {
+                        lamports: a.lamports,
+                        data: STANDARD.decode(a.data.0)?,
+                    })
+                })
+                .transpose()
+        })
+        .collect()
+}
+
+pub async fn get_latest_blockhash(rpc_url: &str) -> anyhow::Result<Hash> {
+    let latest: WithContext<LatestBlockhash> = call(
+        rpc_url,
+        "getLatestBlockhash",
+        serde_json::json!([{ "commitment": "confirmed" }]),
+    )
+    .await?;
+    Ok(latest.value.blockhash.parse()?)
+}
+
+/// Latest finalized slot
+pub async fn get_slot(rpc_url: &str) -> anyhow::Result<u64> {
+    call(
+        rpc_url,
+        "getSlot",
+        serde_json::json!([{ "commitment": "finalized" }]),
+    )
+    .await
+}
+
+/// Sends a signed transaction, returns its signature
+pub async fn send_transaction(rpc_url: &str, transaction: &Transaction) -> anyhow::Result<String> {
+    let encoded = STANDARD.encode(bincode::serialize(transaction)?);
+    call(
+        rpc_url,
+        "sendTransaction",
+        serde_json::json!([encoded, { "encoding": "base64" }]),
+    )
+    .await
+}
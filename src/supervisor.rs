@@ -0,0 +1,250 @@
+//! Task supervision and graceful shutdown. Every long running task is restarted with exponential
+//! backoff when it fails, panics or returns. On SIGINT/SIGTERM the shared cancellation token is
+//! cancelled and tasks get `shutdown_timeout_secs` to finish what they're doing before being
+//! aborted.
+
+use std::{future::Future, time::Duration};
+
+use serde::Deserialize;
+use tokio::{
+    task::{AbortHandle, JoinSet},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::metrics::METRICS;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SupervisorConfig {
+    pub backoff_min_ms: u64,
+    pub backoff_max_ms: u64,
+    /// A task that ran this long before failing restarts without waiting
+    pub backoff_reset_secs: u64,
+    pub shutdown_timeout_secs: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            backoff_min_ms: 500,
+            backoff_max_ms: 30_000,
+            backoff_reset_secs: 60,
+            shutdown_timeout_secs: 10,
+        }
+    }
+}
+
+/// Exponential backoff between restarts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Self {
+            min,
+            max,
+            next: min,
+        }
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        delay
+    }
+
+    pub fn reset(&mut self) {
+        self.next = self.min;
+    }
+}
+
+/// Aborts a task once dropped
+struct AbortOnDrop(AbortHandle);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+pub struct Supervisor {
+    config: SupervisorConfig,
+    token: CancellationToken,
+    tasks: JoinSet<()>,
+}
+
+impl Supervisor {
+    pub fn new(config: &SupervisorConfig) -> Self {
+        Self {
+            config: config.clone(),
+            token: CancellationToken::new(),
+            tasks: JoinSet::new(),
+        }
+    }
+
+    /// Runs the task built by `task` until shutdown, restarting it whenever it ends. Tasks are
+    /// handed the cancellation token and are expected to return once it's cancelled.
+    pub fn spawn<F, Fut>(&mut self, name: &'static str, mut task: F)
+    where
+        F: FnMut(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let token = self.token.clone();
+        let reset_after = Duration::from_secs(self.config.backoff_reset_secs);
+        let mut backoff = Backoff::new(
+            Duration::from_millis(self.config.backoff_min_ms),
+            Duration::from_millis(self.config.backoff_max_ms),
+        );
+        self.tasks.spawn(async move {
+            loop {
+                let started = Instant::now();
+                // spawned on its own so a panic ends up here instead of taking down the loop, and
+                // aborted along with the loop on a shutdown timeout
+                let handle = tokio::spawn(task(token.clone()));
+                let _abort = AbortOnDrop(handle.abort_handle());
+                let result = handle.await;
+                if token.is_cancelled() {
+                    break;
+                }
+                match result {
+                    Ok(Ok(())) => warn!(task = name, "Task exited, restarting"),
+                    Ok(Err(e)) => error!(task = name, "Task failed, restarting: {e}"),
+                    Err(e) => error!(task = name, "Task panicked, restarting: {e}"),
+                }
+                METRICS.task_restarts.with_label_values(&[name]).inc();
+                if started.elapsed() >= reset_after {
+                    backoff.reset();
+                }
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = tokio::time::sleep(backoff.next_delay()) => {}
+                }
+            }
+        });
+    }
+
+    /// Waits for SIGINT or SIGTERM, then shuts down
+    pub async fn run(self) -> anyhow::Result<()> {
+        wait_for_signal().await?;
+        info!("Shutdown requested");
+        self.shutdown().await;
+        Ok(())
+    }
+
+    /// Cancels every task and waits for them to finish, aborting those still running after the
+    /// shutdown timeout
+    pub async fn shutdown(mut self) {
+        self.token.cancel();
+        let timeout = Duration::from_secs(self.config.shutdown_timeout_secs);
+        let drained = tokio::time::timeout(timeout, async {
+            while self.tasks.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                remaining = self.tasks.len(),
+                "Tasks still running after the shutdown timeout, aborting"
+            );
+            self.tasks.shutdown().await;
+        }
+        info!("Shutdown complete");
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() -> anyhow::Result<()> {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() -> anyhow::Result<()> {
+    tokio::signal::ctrl_c().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut backoff = Backoff::new(Duration::from_millis(100), Duration::from_millis(300));
+        let delays: Vec<u128> = (0..4).map(|_| backoff.next_delay().as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 300, 300]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay().as_millis(), 100);
+    }
+
+    #[tokio::test]
+    async fn restarts_failed_tasks_and_waits_for_them_on_shutdown() {
+        let mut supervisor = Supervisor::new(&SupervisorConfig {
+            backoff_min_ms: 1,
+            backoff_max_ms: 5,
+            shutdown_timeout_secs: 1,
+            ..Default::default()
+        });
+        let attempts = Arc::new(AtomicU32::new(0));
+        let finished = Arc::new(AtomicU32::new(0));
+        let (counter, done) = (attempts.clone(), finished.clone());
+        supervisor.spawn("flaky", move |token| {
+            let (counter, done) = (counter.clone(), done.clone());
+            async move {
+                match counter.fetch_add(1, Ordering::SeqCst) {
+                    0 => anyhow::bail!("Error: first attempt"),
+                    1 => panic!("second attempt"),
+                    _ => {}
+                }
+                token.cancelled().await;
+                // in-flight work finishes after cancellation
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                done.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        // a task that ignores the token is aborted once the shutdown times out
+        struct Dropped(Arc<AtomicU32>);
+        impl Drop for Dropped {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let aborted = Arc::new(AtomicU32::new(0));
+        let dropped = aborted.clone();
+        supervisor.spawn("stubborn", move |_token| {
+            let dropped = Dropped(dropped.clone());
+            async move {
+                let _dropped = dropped;
+                std::future::pending::<()>().await;
+                Ok(())
+            }
+        });
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert_eq!(aborted.load(Ordering::SeqCst), 0);
+        supervisor.shutdown().await;
+        assert_eq!(finished.load(Ordering::SeqCst), 1);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        // the abort reaches the task on its next poll
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(aborted.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Token-2022 transfer fees. A mint with the `TransferFeeConfig` extension withholds part of every
+//! transfer at the destination, so a pool receives less than the user sends and the user less
+//! than the pool sends. The math follows the token program: the fee is rounded up and capped at
+//! `maximum_fee`.
+
+/// Size of a base token account, mints are padded to it when they carry extensions
+const BASE_ACCOUNT_LEN: usize = 165;
+/// `AccountType::Mint`, the byte after the padding
+const ACCOUNT_TYPE_MINT: u8 = 1;
+/// `ExtensionType::TransferFeeConfig`
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+/// Two authorities and the withheld amount come before the two fees
+const TRANSFER_FEES_OFFSET: usize = 72;
+const TRANSFER_FEE_LEN: usize = 18;
+const ONE_IN_BASIS_POINTS: u128 = 10_000;
+
+/// Transfer fee in effect from `epoch` on
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferFee {
+    pub epoch: u64,
+    pub maximum_fee: u64,
+    pub basis_points: u16,
+}
+
+impl TransferFee {
+    fn decode(data: &[u8]) -> Option<Self> {
+        Some(Self {
+            epoch: u64::from_le_bytes(data.get(0..8)?.try_into().ok()?),
+            maximum_fee: u64::from_le_bytes(data.get(8..16)?.try_into().ok()?),
+            basis_points: u16::from_le_bytes(data.get(16..18)?.try_into().ok()?),
+        })
+    }
+
+    /// Fee withheld from a transfer of `amount`
+    pub fn fee(&self, amount: u64) -> u64 {
+        if self.basis_points == 0 || amount == 0 {
+            return 0;
+        }
+        let fee =
+            (u128::from(amount) * u128::from(self.basis_points)).div_ceil(ONE_IN_BASIS_POINTS);
+        fee.min(u128::from(self.maximum_fee)) as u64
+    }
+
+    /// Amount to transfer so that `post_fee` arrives, `None` when it doesn't fit a u64
+    pub fn pre_fee_amount(&self, post_fee: u64) -> Option<u64> {
+        let basis_points = u128::from(self.basis_points);
+        match (basis_points, post_fee) {
+            (0, _) => Some(post_fee),
+            (_, 0) => Some(0),
+            (ONE_IN_BASIS_POINTS, _) => post_fee.checked_add(self.maximum_fee),
+            _ => {
+                let pre_fee = (u128::from(post_fee) * ONE_IN_BASIS_POINTS)
+                    .div_ceil(ONE_IN_BASIS_POINTS - basis_points);
+                if pre_fee - u128::from(post_fee) >= u128::from(self.maximum_fee) {
+                    post_fee.checked_add(self.maximum_fee)
+                } else {
+                    u64::try_from(pre_fee).ok()
+                }
+            }
+        }
+    }
+}
+
+/// The older and newer transfer fee of a mint. Mints without the extension have neither.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransferFeeConfig {
+    pub older: TransferFee,
+    pub newer: TransferFee,
+}
+
+impl TransferFeeConfig {
+    /// Reads the extension from a Token-2022 mint account, the default (no fee) when the mint
+    /// doesn't have it
+    pub fn decode_mint(data: &[u8]) -> anyhow::Result<Self> {
+        let invalid = || anyhow::Error::msg("Error: invalid Token-2022 mint");
+        if data.len() <= BASE_ACCOUNT_LEN {
+            return Ok(Self::default());
+        }
+        if data[BASE_ACCOUNT_LEN] != ACCOUNT_TYPE_MINT {
+            return Err(invalid());
+        }
+        let mut tlv = &data[BASE_ACCOUNT_LEN + 1..];
+        while tlv.len() >= 4 {
+            let extension = u16::from_le_bytes([tlv[0], tlv[1]]);
+            let len = usize::from(u16::from_le_bytes([tlv[2], tlv[3]]));
+            let value = tlv.get(4..4 + len).ok_or_else(invalid)?;
+            if extension == EXTENSION_TRANSFER_FEE_CONFIG {
+                let fees = value.get(TRANSFER_FEES_OFFSET..).ok_or_else(invalid)?;
+                return Ok(Self {
+                    older: TransferFee::decode(fees).ok_or_else(invalid)?,
+                    newer: TransferFee::decode(fees.get(TRANSFER_FEE_LEN..).ok_or_else(invalid)?)
+                        .ok_or_else(invalid)?,
+                });
+            }
+            tlv = &tlv[4 + len..];
+        }
+        Ok(Self::default())
+    }
+
+    /// Fee in effect during `epoch`
+    pub fn epoch_fee(&self, epoch: u64) -> &TransferFee {
+        if epoch >= self.newer.epoch {
+            &self.newer
+        } else {
+            &self.older
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Token-2022 mint account carrying `config`
+    pub(crate) fn mint_data(config: &TransferFeeConfig) -> Vec<u8> {
+        let mut data = vec![0; BASE_ACCOUNT_LEN];
+        data.push(ACCOUNT_TYPE_MINT);
+        // an unrelated extension first, `MintCloseAuthority`
+        data.extend_from_slice(&3u16.to_le_bytes());
+        data.extend_from_slice(&32u16.to_le_bytes());
+        data.extend_from_slice(&[7; 32]);
+        data.extend_from_slice(&EXTENSION_TRANSFER_FEE_CONFIG.to_le_bytes());
+        data.extend_from_slice(&108u16.to_le_bytes());
+        data.extend_from_slice(&[0; TRANSFER_FEES_OFFSET]);
+        for fee in [config.older, config.newer] {
+            data.extend_from_slice(&fee.epoch.to_le_bytes());
+            data.extend_from_slice(&fee.maximum_fee.to_le_bytes());
+            data.extend_from_slice(&fee.basis_points.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn decodes_the_extension() {
+        let config = TransferFeeConfig {
+            older: TransferFee {
+                epoch: 500,
+                maximum_fee: 1_000,
+                basis_points: 50,
+            },
+            newer: TransferFee {
+                epoch: 600,
+                maximum_fee: 5_000,
+                basis_points: 100,
+            },
+        };
+        assert_eq!(
+            TransferFeeConfig::decode_mint(&mint_data(&config)).unwrap(),
+            config
+        );
+        assert_eq!(config.epoch_fee(599).basis_points, 50);
+        assert_eq!(config.epoch_fee(600).basis_points, 100);
+
+        // a base mint, or one without the extension, charges nothing
+        assert_eq!(
+            TransferFeeConfig::decode_mint(&[0; 82]).unwrap(),
+            TransferFeeConfig::default()
+        );
+        let mut data = mint_data(&config);
+        data.truncate(BASE_ACCOUNT_LEN + 1 + 36);
+        assert_eq!(
+            TransferFeeConfig::decode_mint(&data).unwrap(),
+            TransferFeeConfig::default()
+        );
+        data.truncate(BASE_ACCOUNT_LEN + 1 + 20);
+        assert!(TransferFeeConfig::decode_mint(&data).is_err());
+    }
+
+    #[test]
+    fn fee_is_rounded_up_and_capped() {
+        let fee = TransferFee {
+            epoch: 0,
+            maximum_fee: 5_000,
+            basis_points: 100,
+        };
+        assert_eq!(fee.fee(0), 0);
+        assert_eq!(fee.fee(1), 1);
+        assert_eq!(fee.fee(100_000), 1_000);
+        assert_eq!(fee.fee(10_000_000), 5_000);
+
+        // the pre fee amount leaves at least the post fee amount once the fee is taken
+        for post_fee in [1, 99, 100_000, 494_999, 10_000_000] {
+            let pre_fee = fee.pre_fee_amount(post_fee).unwrap();
+            assert!(pre_fee - fee.fee(pre_fee) >= post_fee);
+            assert!(pre_fee - 1 - fee.fee(pre_fee - 1) < post_fee);
+        }
+        assert_eq!(fee.pre_fee_amount(10_000_000), Some(10_005_000));
+        assert_eq!(TransferFee::default().pre_fee_amount(42), Some(42));
+    }
+}
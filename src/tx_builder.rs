@@ -0,0 +1,242 @@
+//! Atomic arb transactions: the swaps of a cross-DEX round trip or a route chained into one v0
+//! transaction, with address lookup tables to fit their accounts. The transaction either lands
+//! whole or not at all, and its size is checked before anything is sent.
+
+use serde::Deserialize;
+use solana_compute_budget_interface::ComputeBudgetInstruction;
+use solana_sdk::{
+    hash::Hash,
+    instruction::Instruction,
+    message::{AddressLookupTableAccount, VersionedMessage, v0},
+    packet::PACKET_DATA_SIZE,
+    pubkey::Pubkey,
+    signature::Signature,
+    transaction::VersionedTransaction,
+};
+
+use crate::{balances::associated_token_address, ledger::SIGNATURE_FEE, rebalance::spl_transfer};
+
+/// Accounts one transaction may lock, lookups included
+pub const MAX_ACCOUNT_LOCKS: usize = 64;
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TransactionConfig {
+    /// Compute units requested, enough for four swaps crossing a few tick arrays each
+    pub compute_unit_limit: u32,
+    /// Priority fee, micro-lamports per compute unit
+    pub compute_unit_price: u64,
+}
+
+impl TransactionConfig {
+    /// Lamports a transaction with one signature pays: the base fee plus the priority fee on
+    /// the requested compute units
+    pub fn fee(&self) -> u64 {
+        let priority_fee = (u128::from(self.compute_unit_limit)
+            * u128::from(self.compute_unit_price))
+        .div_ceil(MICRO_LAMPORTS_PER_LAMPORT);
+        SIGNATURE_FEE + priority_fee as u64
+    }
+}
+
+impl Default for TransactionConfig {
+    fn default() -> Self {
+        Self {
+            compute_unit_limit: 800_000,
+            compute_unit_price: 0,
+        }
+    }
+}
+
+/// Final check that `owner`'s token account of `mint` holds at least `min_balance` once every
+/// swap has run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinOutputCheck {
+    pub owner: Pubkey,
+    pub mint: Pubkey,
+    pub min_balance: u64,
+}
+
+impl MinOutputCheck {
+    /// Check that the account gained at least `min_gain` over `balance`, its amount before the
+    /// transaction
+    pub fn gain(owner: Pubkey, mint: Pubkey, balance: u64, min_gain: u64) -> Self {
+        Self {
+            owner,
+            mint,
+            min_balance: balance.saturating_add(min_gain),
+        }
+    }
+
+    /// A token transfer of `min_balance` from the account to itself. It moves nothing, but the
+    /// token program rejects it, and with it the transaction, when the balance is lower.
+    pub fn instruction(&self) -> Instruction {
+        let account = associated_token_address(&self.owner, &self.mint);
+        spl_transfer(&account, &account, &self.owner, self.min_balance)
+    }
+}
+
+/// A compiled arb transaction, not signed yet
+#[derive(Debug, Clone)]
+pub struct ArbTransaction {
+    pub message: VersionedMessage,
+    /// Serialized size once signed, in bytes
+    pub size: usize,
+}
+
+impl ArbTransaction {
+    /// Accounts the transaction locks, the ones loaded from lookup tables included
+    pub fn accounts(&self) -> usize {
+        self.message.static_account_keys().len() + self.looked_up()
+    }
+
+    /// Accounts loaded from lookup tables
+    pub fn looked_up(&self) -> usize {
+        self.message.address_table_lookups().map_or(0, |lookups| {
+            lookups
+                .iter()
+                .map(|lookup| lookup.writable_indexes.len() + lookup.readonly_indexes.len())
+                .sum()
+        })
+    }
+}
+
+/// Compiles `swaps` into one v0 transaction paid by `payer`: compute budget first, then the swaps
+/// in order, then `check`. Accounts found in `tables` are loaded from them. Fails when the
+/// transaction would exceed the packet size or the account lock limit.
+pub fn build(
+    payer: &Pubkey,
+    swaps: &[Instruction],
+    check: Option<&MinOutputCheck>,
+    tables: &[AddressLookupTableAccount],
+    blockhash: Hash,
+    config: &TransactionConfig,
+) -> anyhow::Result<ArbTransaction> {
+    let mut instructions = vec![ComputeBudgetInstruction::set_compute_unit_limit(
+        config.compute_unit_limit,
+    )];
+    if config.compute_unit_price > 0 {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+            config.compute_unit_price,
+        ));
+    }
+    instructions.extend_from_slice(swaps);
+    instructions.extend(check.map(MinOutputCheck::instruction));
+
+    let message = v0::Message::try_compile(payer, &instructions, tables, blockhash)
+        .map_err(|e| anyhow::Error::msg(format!("Error: unable to compile transaction: {e}")))?;
+    let message = VersionedMessage::V0(message);
+    let signatures = message.header().num_required_signatures as usize;
+    let size = bincode::serialized_size(&VersionedTransaction {
+        signatures: vec![Signature::default(); signatures],
+        message: message.clone(),
+    })? as usize;
+    let transaction = ArbTransaction { message, size };
+    if size > PACKET_DATA_SIZE {
+        return Err(anyhow::Error::msg(format!(
+            "Error: transaction of {size} bytes exceeds the {PACKET_DATA_SIZE} byte limit"
+        )));
+    }
+    if transaction.accounts() > MAX_ACCOUNT_LOCKS {
+        return Err(anyhow::Error::msg(format!(
+            "Error: transaction locks {} accounts, more than {MAX_ACCOUNT_LOCKS}",
+            transaction.accounts()
+        )));
+    }
+    Ok(transaction)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::instruction::AccountMeta;
+
+    use super::*;
+    use crate::balances::{TOKEN_PROGRAM_ID, USDC_MINT};
+
+    /// Swap-like instruction touching `accounts` fresh accounts besides `shared`
+    fn swap(program_id: Pubkey, shared: &[Pubkey], accounts: usize) -> Instruction {
+        let metas = shared
+            .iter()
+            .copied()
+            .chain((0..accounts).map(|_| Pubkey::new_unique()))
+            .map(|pubkey| AccountMeta::new(pubkey, false))
+            .collect();
+        Instruction::new_with_bytes(program_id, &[9; 17], metas)
+    }
+
+    #[test]
+    fn chains_swaps_and_checks_the_output_last() {
+        let payer = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let swaps = [swap(program_id, &[], 12), swap(program_id, &[], 12)];
+        let check = MinOutputCheck::gain(payer, USDC_MINT, 1_000_000_000, 150_000);
+        let config = TransactionConfig {
+            compute_unit_price: 1_000,
+            ..Default::default()
+        };
+        let tx = build(&payer, &swaps, Some(&check), &[], Hash::default(), &config).unwrap();
+        let VersionedMessage::V0(message) = &tx.message else {
+            panic!("legacy message");
+        };
+        let programs: Vec<Pubkey> = message
+            .instructions
+            .iter()
+            .map(|ix| message.account_keys[ix.program_id_index as usize])
+            .collect();
+        assert_eq!(
+            programs,
+            vec![
+                solana_compute_budget_interface::id(),
+                solana_compute_budget_interface::id(),
+                program_id,
+                program_id,
+                TOKEN_PROGRAM_ID
+            ]
+        );
+        // self transfer of the minimum balance, owner signing
+        let transfer = &message.instructions[4];
+        assert_eq!(transfer.accounts[0], transfer.accounts[1]);
+        assert_eq!(transfer.data[1..], 1_000_150_000u64.to_le_bytes());
+        assert_eq!(message.account_keys[transfer.accounts[2] as usize], payer);
+        assert_eq!(tx.looked_up(), 0);
+        assert!(tx.size <= PACKET_DATA_SIZE);
+    }
+
+    #[test]
+    fn lookup_tables_fit_routes_that_are_too_large_otherwise() {
+        let payer = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let shared = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let swaps: Vec<Instruction> = (0..3).map(|_| swap(program_id, &shared, 14)).collect();
+        let config = TransactionConfig::default();
+        let error = build(&payer, &swaps, None, &[], Hash::default(), &config).unwrap_err();
+        assert!(error.to_string().contains("byte limit"), "{error}");
+
+        // every swap account except the program in one table
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: swaps
+                .iter()
+                .flat_map(|ix| ix.accounts.iter().map(|meta| meta.pubkey))
+                .collect(),
+        };
+        let tx = build(&payer, &swaps, None, &[table], Hash::default(), &config).unwrap();
+        assert_eq!(tx.looked_up(), 2 + 3 * 14);
+        // payer, compute budget and swap programs
+        assert_eq!(tx.accounts(), 3 + 2 + 3 * 14);
+        assert!(tx.size <= PACKET_DATA_SIZE);
+
+        // lookups don't lift the lock limit
+        let swaps: Vec<Instruction> = (0..4).map(|_| swap(program_id, &shared, 16)).collect();
+        let table = AddressLookupTableAccount {
+            key: Pubkey::new_unique(),
+            addresses: swaps
+                .iter()
+                .flat_map(|ix| ix.accounts.iter().map(|meta| meta.pubkey))
+                .collect(),
+        };
+        let error = build(&payer, &swaps, None, &[table], Hash::default(), &config).unwrap_err();
+        assert!(error.to_string().contains("locks"), "{error}");
+    }
+}
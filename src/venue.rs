@@ -0,0 +1,247 @@
+//! DEX venues. Every supported pool program implements `DexVenue`: the accounts to stream, how
+//! their updates change the pool state, exact in / exact out quotes and the swap instruction.
+//! The feed and the pipeline only go through the trait, so a new pool type is an
+//! implementation plus a `PoolType` variant.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::{hash, instruction::Instruction, pubkey::Pubkey};
+
+use crate::{
+    balances::ASSOCIATED_TOKEN_PROGRAM_ID,
+    clmm_simulator::{PoolPrice, SwapResult},
+    meteora_dlmm::DlmmVenue,
+    orca_whirlpool::WhirlpoolVenue,
+    raydium_amm::AmmV4Venue,
+    raydium_clmm::ClmmVenue,
+    raydium_cpmm::CpmmVenue,
+};
+
+/// Program of the configured pool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PoolType {
+    #[default]
+    RaydiumClmm,
+    OrcaWhirlpool,
+    RaydiumAmm,
+    RaydiumCpmm,
+    MeteoraDlmm,
+}
+
+impl PoolType {
+    /// Empty venue for this pool type, filled by the updates of its accounts
+    pub fn venue(self) -> Box<dyn DexVenue> {
+        match self {
+            PoolType::RaydiumClmm => Box::<ClmmVenue>::default(),
+            PoolType::OrcaWhirlpool => Box::<WhirlpoolVenue>::default(),
+            PoolType::RaydiumAmm => Box::<AmmV4Venue>::default(),
+            PoolType::RaydiumCpmm => Box::<CpmmVenue>::default(),
+            PoolType::MeteoraDlmm => Box::<DlmmVenue>::default(),
+        }
+    }
+}
+
+/// Accounts a venue needs streamed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccountFilter {
+    Account(Pubkey),
+    /// Every account of `owner` with `data_size` bytes and `pool` at `pool_offset`: the tick or
+    /// bin arrays of the pool, including ones created later
+    PoolArrays {
+        owner: Pubkey,
+        data_size: usize,
+        pool_offset: usize,
+        pool: Pubkey,
+    },
+}
+
+impl AccountFilter {
+    /// Whether the streamed account `pubkey`, owned by `owner`, is one this filter selects
+    pub fn matches(&self, pubkey: &Pubkey, owner: &Pubkey, data: &[u8]) -> bool {
+        match self {
+            AccountFilter::Account(account) => account == pubkey,
+            AccountFilter::PoolArrays {
+                owner: program,
+                data_size,
+                pool_offset,
+                pool,
+            } => {
+                program == owner
+                    && data.len() == *data_size
+                    && data.get(*pool_offset..*pool_offset + 32) == Some(pool.as_ref())
+            }
+        }
+    }
+}
+
+/// A swap to build an instruction for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwapParams {
+    /// Signs the swap, its associated token accounts of both mints are used
+    pub user: Pubkey,
+    pub amount: u64,
+    /// Minimum output of an exact in swap, maximum input of an exact out one
+    pub other_amount_threshold: u64,
+    /// Sells the pool's first token
+    pub zero_for_one: bool,
+    pub is_base_input: bool,
+}
+
+pub trait DexVenue: fmt::Debug + Send + Sync {
+    fn pool_type(&self) -> PoolType;
+
+    /// Accounts to stream for `pool_id`. Venues that depend on accounts named in the pool
+    /// account add them once it's loaded.
+    fn subscriptions(&self, pool_id: &Pubkey) -> Vec<AccountFilter>;
+
+    /// Applies a raw account update: the pool itself or one of the accounts it depends on.
+    /// Returns false for accounts that don't belong to `pool_id`.
+    fn apply_account(
+        &mut self,
+        pool_id: &Pubkey,
+        pubkey: &Pubkey,
+        data: &[u8],
+    ) -> anyhow::Result<bool>;
+
+    /// Accounts needed for quoting that aren't cached yet, fetched over RPC
+    fn missing_accounts(&self, pool_id: &Pubkey) -> Vec<Pubkey>;
+
+    /// Records that an account from `missing_accounts` doesn't exist on chain
+    fn mark_absent(&mut self, _pubkey: &Pubkey) {}
+
+    /// Current price, `None` until the pool is loaded
+    fn price(&self) -> Option<PoolPrice>;
+
+    /// Mints of the pool's first and second token, `None` until the pool is loaded
+    fn mints(&self) -> Option<(Pubkey, Pubkey)>;
+
+    /// Quotes selling `amount_in`, `zero_for_one` sells the pool's first token. `now` (unix
+    /// seconds) matters for venues with time dependent fees.
+    fn quote_exact_in(
+        &self,
+        amount_in: u64,
+        zero_for_one: bool,
+        now: i64,
+    ) -> anyhow::Result<SwapResult>;
+
+    /// Quotes buying `amount_out`
+    fn quote_exact_out(
+        &self,
+        amount_out: u64,
+        zero_for_one: bool,
+        now: i64,
+    ) -> anyhow::Result<SwapResult>;
+
+    /// Swap instruction of the program for `swap`, with the tick or bin arrays the swap needs
+    /// at the current price
+    fn swap_instruction(&self, pool_id: &Pubkey, swap: &SwapParams) -> anyhow::Result<Instruction>;
+
+    fn clone_box(&self) -> Box<dyn DexVenue>;
+}
+
+impl Clone for Box<dyn DexVenue> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+impl Default for Box<dyn DexVenue> {
+    fn default() -> Self {
+        PoolType::default().venue()
+    }
+}
+
+/// Anchor instruction discriminator: the first 8 bytes of sha256("global:<name>")
+pub fn anchor_discriminator(name: &str) -> [u8; 8] {
+    let hash = hash::hash(format!("global:{name}").as_bytes());
+    hash.to_bytes()[..8].try_into().unwrap()
+}
+
+/// Associated token account of `user` for `mint`, owned by `token_program`
+pub fn user_token_account(user: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[user.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &ASSOCIATED_TOKEN_PROGRAM_ID,
+    )
+    .0
+}
+
+/// Error for venues used before their pool account is loaded
+pub fn no_pool_state() -> anyhow::Error {
+    anyhow::Error::msg("Error: no pool state yet")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        balances::{TOKEN_PROGRAM_ID, USDC_MINT, associated_token_address},
+        clmm_simulator::tests::{pool, tick_array},
+        raydium_clmm::{CLMM_PROGRAM_ID, TickArrayState},
+    };
+
+    #[test]
+    fn derives_discriminators_and_token_accounts() {
+        // known discriminator of Raydium CLMM's swap_v2
+        assert_eq!(
+            anchor_discriminator("swap_v2"),
+            [43, 4, 237, 11, 26, 201, 30, 98]
+        );
+        let user = Pubkey::new_unique();
+        assert_eq!(
+            user_token_account(&user, &USDC_MINT, &TOKEN_PROGRAM_ID),
+            associated_token_address(&user, &USDC_MINT)
+        );
+        assert_eq!(
+            PoolType::MeteoraDlmm.venue().pool_type(),
+            PoolType::MeteoraDlmm
+        );
+    }
+
+    #[test]
+    fn clmm_swap_takes_the_tick_arrays_it_reaches() {
+        // arrays of 60 ticks: the current one, the next one down, then a gap
+        let mut state = pool(-18_971, 1_000_000_000_000_000);
+        for start in [-19_020, -19_080, -19_200] {
+            let bit = start / 60 + 512;
+            state.tick_array_bitmap[bit as usize / 64] |= 1 << (bit % 64);
+        }
+        let venue = ClmmVenue {
+            pool: Some(state),
+            tick_arrays: [(-19_020, tick_array(-19_020, &[]))].into(),
+            fee_rate: Some(500),
+        };
+        let pool_id = Pubkey::new_unique();
+        let swap = SwapParams {
+            user: Pubkey::new_unique(),
+            amount: 1_000_000_000,
+            other_amount_threshold: 149_000_000,
+            zero_for_one: true,
+            is_base_input: true,
+        };
+        let instruction = venue.swap_instruction(&pool_id, &swap).unwrap();
+        assert_eq!(instruction.program_id, CLMM_PROGRAM_ID);
+        assert!(instruction.accounts[0].is_signer);
+        assert_eq!(instruction.accounts[0].pubkey, swap.user);
+        assert_eq!(instruction.accounts[2].pubkey, pool_id);
+        // the swap stays in the current array, the next one covers the price moving
+        let tick_arrays: Vec<Pubkey> = instruction.accounts[13..]
+            .iter()
+            .map(|meta| meta.pubkey)
+            .collect();
+        assert_eq!(
+            tick_arrays,
+            vec![
+                TickArrayState::address(&pool_id, -19_020),
+                TickArrayState::address(&pool_id, -19_080),
+            ]
+        );
+        assert_eq!(instruction.data.len(), 41);
+        assert_eq!(instruction.data[..8], anchor_discriminator("swap_v2"));
+        assert_eq!(instruction.data[8..16], 1_000_000_000u64.to_le_bytes());
+        assert_eq!(instruction.data[16..24], 149_000_000u64.to_le_bytes());
+        assert_eq!(instruction.data[40], 1);
+    }
+}
@@ -0,0 +1,207 @@
+//! Orca Whirlpool swap simulation on the shared concentrated liquidity loop. Only the tick lookup
+//! differs from Raydium: there is no tick array bitmap, and a swap can only traverse the tick
+//! array holding the current tick plus the next two in its direction.
+
+use std::collections::BTreeMap;
+
+use crate::{
+    clmm_simulator::{NextTick, PoolPrice, SwapResult, walk_ticks},
+    orca_whirlpool::{Whirlpool, WhirlpoolTickArray},
+    raydium_math::tick_math::{MAX_TICK, MIN_TICK},
+};
+
+/// Tick arrays of a whirlpool keyed by their start tick index
+pub type WhirlpoolTickArrays = BTreeMap<i32, WhirlpoolTickArray>;
+
+/// Tick arrays a single swap instruction can traverse
+pub const MAX_TICK_ARRAYS: i32 = 3;
+
+/// Simulates a swap against `pool` using the ticks in `tick_arrays`, `a_to_b` sells token A.
+/// Swaps that would leave the cached arrays (or the three the instruction takes) are
+/// incomplete.
+pub fn simulate_swap(
+    pool: &Whirlpool,
+    tick_arrays: &WhirlpoolTickArrays,
+    amount_specified: u64,
+    a_to_b: bool,
+    is_base_input: bool,
+) -> anyhow::Result<SwapResult> {
+    let price = PoolPrice {
+        sqrt_price_x64: pool.sqrt_price,
+        liquidity: pool.liquidity,
+        tick: pool.tick_current_index,
+    };
+    let first =
+        WhirlpoolTickArray::get_array_start_index(pool.tick_current_index, pool.tick_spacing);
+    walk_ticks(
+        price,
+        u32::from(pool.fee_rate),
+        amount_specified,
+        a_to_b,
+        is_base_input,
+        |tick| next_tick(pool, tick_arrays, first, tick, a_to_b),
+    )
+}
+
+/// Start indexes of the tick arrays a swap in either direction could use that aren't cached
+/// yet. Some of them may not exist on chain.
+pub fn missing_tick_arrays(pool: &Whirlpool, tick_arrays: &WhirlpoolTickArrays) -> Vec<i32> {
+    let ticks_in_array = WhirlpoolTickArray::tick_count(pool.tick_spacing);
+    let current =
+        WhirlpoolTickArray::get_array_start_index(pool.tick_current_index, pool.tick_spacing);
+    (1 - MAX_TICK_ARRAYS..MAX_TICK_ARRAYS)
+        .map(|offset| current + offset * ticks_in_array)
+        .filter(|start| *start + ticks_in_array > MIN_TICK && *start <= MAX_TICK)
+        .filter(|start| !tick_arrays.contains_key(start))
+        .collect()
+}
+
+/// Finds the next initialized tick in the swap direction, or the edge of the first tick array
+/// that isn't cached or out of the swap's reach
+fn next_tick(
+    pool: &Whirlpool,
+    tick_arrays: &WhirlpoolTickArrays,
+    first: i32,
+    tick_current: i32,
+    a_to_b: bool,
+) -> Option<NextTick> {
+    let spacing = i32::from(pool.tick_spacing);
+    let ticks_in_array = WhirlpoolTickArray::tick_count(pool.tick_spacing);
+    let reach = (MAX_TICK_ARRAYS - 1) * ticks_in_array;
+    let mut start_index =
+        WhirlpoolTickArray::get_array_start_index(tick_current, pool.tick_spacing);
+
+    loop {
+        if start_index + ticks_in_array <= MIN_TICK || start_index > MAX_TICK {
+            return None;
+        }
+        let in_reach = if a_to_b {
+            start_index >= first - reach
+        } else {
+            start_index <= first + reach
+        };
+        match tick_arrays.get(&start_index).filter(|_| in_reach) {
+            Some(tick_array) => {
+                let initialized = tick_array
+                    .ticks
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, t)| t.initialized)
+                    .map(|(i, t)| (start_index + i as i32 * spacing, t.liquidity_net));
+                let found = if a_to_b {
+                    initialized
+                        .filter(|(tick, _)| *tick <= tick_current)
+                        .max_by_key(|(tick, _)| *tick)
+                } else {
+                    initialized
+                        .filter(|(tick, _)| *tick > tick_current)
+                        .min_by_key(|(tick, _)| *tick)
+                };
+                if let Some((tick, liquidity_net)) = found {
+                    return Some(NextTick {
+                        tick,
+                        liquidity_net: Some(liquidity_net),
+                    });
+                }
+            }
+            None => {
+                // the swap can't go past the edge of an array it doesn't have
+                let edge = if a_to_b {
+                    start_index + ticks_in_array
+                } else {
+                    start_index
+                };
+                if (a_to_b && edge > tick_current) || (!a_to_b && edge <= tick_current) {
+                    return None;
+                }
+                return Some(NextTick {
+                    tick: edge.clamp(MIN_TICK, MAX_TICK),
+                    liquidity_net: None,
+                });
+            }
+        }
+        start_index += if a_to_b {
+            -ticks_in_array
+        } else {
+            ticks_in_array
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_sdk::pubkey::Pubkey;
+
+    use super::*;
+    use crate::{
+        clmm_simulator::{
+            self, TickArrayCache,
+            tests::{pool, tick_array},
+        },
+        orca_whirlpool::tests::{tick_array_data, whirlpool},
+    };
+
+    fn cached(ticks: &[(i32, &[(usize, i128)])]) -> WhirlpoolTickArrays {
+        let id = Pubkey::default();
+        ticks
+            .iter()
+            .map(|(start, ticks)| {
+                let data = tick_array_data(&id, *start, ticks);
+                (*start, WhirlpoolTickArray::decode(&data).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quotes_match_raydium_for_the_same_liquidity() {
+        // same liquidity on both pools, half of it ends at tick -19_000
+        let whirlpool = whirlpool(-18_971, 1_000_000_000_000_000, 400);
+        let tick_arrays = cached(&[
+            (-19_008, &[(8, 500_000_000_000_000)]),
+            (-18_920, &[(20, -1_000_000_000_000_000)]),
+        ]);
+        let raydium = pool(-18_971, 1_000_000_000_000_000);
+        let mut cache = TickArrayCache::new();
+        cache.insert(
+            -19_020,
+            tick_array(-19_020, &[(-19_000, 500_000_000_000_000)]),
+        );
+        cache.insert(-18_960, tick_array(-18_960, &[]));
+        cache.insert(
+            -18_900,
+            tick_array(-18_900, &[(-18_900, -1_000_000_000_000_000)]),
+        );
+
+        for (amount, a_to_b, exact_in) in [
+            (1_000_000_000, true, true),
+            (4_000_000_000_000, true, true),
+            (150_000_000, false, true),
+            (1_000_000_000, false, false),
+        ] {
+            let orca = simulate_swap(&whirlpool, &tick_arrays, amount, a_to_b, exact_in).unwrap();
+            let ray =
+                clmm_simulator::simulate_swap(&raydium, &cache, 400, amount, a_to_b, exact_in)
+                    .unwrap();
+            assert!(orca.complete);
+            assert_eq!(orca, ray);
+            assert_eq!(orca.ticks_crossed, u32::from(amount > 1_000_000_000_000));
+        }
+    }
+
+    #[test]
+    fn stops_at_uncached_arrays_and_the_three_array_limit() {
+        let pool = whirlpool(10, 1_000_000, 0);
+        // liquidity runs out past the current array, only one more array is cached
+        let tick_arrays = cached(&[(0, &[(0, 1_000_000)]), (-88, &[])]);
+        let swap = simulate_swap(&pool, &tick_arrays, u64::MAX / 2, true, true).unwrap();
+        assert_eq!(swap.ticks_crossed, 1);
+        assert!(!swap.complete);
+        assert_eq!(swap.tick, -89);
+
+        let tick_arrays = cached(&[(0, &[]), (88, &[]), (176, &[]), (264, &[(0, -1)])]);
+        let swap = simulate_swap(&pool, &tick_arrays, u64::MAX / 2, false, true).unwrap();
+        assert!(!swap.complete);
+        assert_eq!(swap.tick, 264);
+        assert_eq!(missing_tick_arrays(&pool, &tick_arrays), vec![-176, -88]);
+    }
+}